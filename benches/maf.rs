@@ -0,0 +1,152 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use maf_stream::alphabet::Nucleotides;
+use maf_stream::coverage::{coverage, CoverageOptions};
+use maf_stream::dup_blocks::{output_merged_consensus_blocks, ConsensusMode};
+use maf_stream::synthetic::{LengthDistribution, SyntheticMaf};
+use maf_stream::{MAFOutputStream, OutputOptions};
+use multiple_alignment_format::parser::{next_maf_item, next_maf_item_slice};
+use multiple_alignment_format::MAFItem;
+use std::io::{sink, Write};
+
+fn datasets() -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "many_small_blocks",
+            SyntheticMaf {
+                num_species: 5,
+                num_blocks: 20_000,
+                block_length: LengthDistribution::Uniform { min: 10, max: 50 },
+                ..Default::default()
+            },
+        ),
+        (
+            "few_huge_blocks",
+            SyntheticMaf {
+                num_species: 5,
+                num_blocks: 4,
+                block_length: LengthDistribution::Uniform {
+                    min: 200_000,
+                    max: 250_000,
+                },
+                ..Default::default()
+            },
+        ),
+        (
+            "400_species",
+            SyntheticMaf {
+                num_species: 400,
+                num_blocks: 100,
+                block_length: LengthDistribution::Uniform { min: 100, max: 300 },
+                indel_rate: 0.02,
+                // Give merge_dups something to do.
+                duplication_rate: 0.05,
+                ..Default::default()
+            },
+        ),
+    ]
+    .into_iter()
+    .map(|(name, synthetic)| (name, synthetic.to_maf_string()))
+    .collect()
+}
+
+fn bench_maf(c: &mut Criterion) {
+    let datasets = datasets();
+
+    let mut group = c.benchmark_group("next_maf_item");
+    for (name, maf) in datasets.iter() {
+        group.throughput(Throughput::Bytes(maf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), maf, |b, maf| {
+            b.iter(|| {
+                let mut input = maf.as_bytes();
+                let mut num_blocks = 0;
+                while let Ok(item) = next_maf_item(&mut input) {
+                    if let MAFItem::Block(_) = item {
+                        num_blocks += 1;
+                    }
+                }
+                num_blocks
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("next_maf_item_slice");
+    for (name, maf) in datasets.iter() {
+        group.throughput(Throughput::Bytes(maf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), maf, |b, maf| {
+            b.iter(|| {
+                let mut input = maf.as_bytes();
+                let mut num_blocks = 0;
+                while let Ok(item) = next_maf_item_slice(&mut input) {
+                    if let MAFItem::Block(_) = item {
+                        num_blocks += 1;
+                    }
+                }
+                num_blocks
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("display");
+    for (name, maf) in datasets.iter() {
+        let mut input = maf.as_bytes();
+        let mut blocks = vec![];
+        while let Ok(item) = next_maf_item(&mut input) {
+            if let MAFItem::Block(block) = item {
+                blocks.push(block);
+            }
+        }
+        group.throughput(Throughput::Bytes(maf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &blocks, |b, blocks| {
+            b.iter(|| {
+                let mut output = sink();
+                for block in blocks.iter() {
+                    write!(output, "{}", block).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("coverage");
+    for (name, maf) in datasets.iter() {
+        group.throughput(Throughput::Bytes(maf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), maf, |b, maf| {
+            b.iter(|| {
+                coverage(
+                    &mut maf.as_bytes(),
+                    &mut sink(),
+                    "species0",
+                    CoverageOptions::default(),
+                )
+            })
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("merge_dups");
+    for (name, maf) in datasets.iter() {
+        group.throughput(Throughput::Bytes(maf.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), maf, |b, maf| {
+            b.iter(|| {
+                output_merged_consensus_blocks(
+                    &mut maf.as_bytes(),
+                    &mut MAFOutputStream::new(sink(), OutputOptions::default()),
+                    ConsensusMode::Consensus,
+                    None,
+                    false,
+                    &Nucleotides,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_maf
+}
+criterion_main!(benches);
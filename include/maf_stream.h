@@ -0,0 +1,111 @@
+#ifndef MAF_STREAM_H
+#define MAF_STREAM_H
+
+/* Generated by cbindgen from src/capi.rs; don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A parsed alignment block.
+typedef struct MafBlock MafBlock;
+
+// An open MAF file.
+typedef struct MafReader MafReader;
+
+// Open a MAF file for reading. Returns NULL if it can't be opened.
+//
+// # Safety
+// `path` must be a valid NUL-terminated string.
+struct MafReader *maf_open(const char *path);
+
+// Read the next block, skipping comments. Returns NULL at the end of
+// the file or on a parse error; `maf_error` tells the two apart. The
+// block must be freed with `maf_block_free`.
+//
+// # Safety
+// `reader` must come from `maf_open`.
+struct MafBlock *maf_next_block(struct MafReader *reader);
+
+// The error that stopped the last `maf_next_block` call, or NULL if
+// there wasn't one. Owned by the reader.
+//
+// # Safety
+// `reader` must come from `maf_open`.
+const char *maf_error(const struct MafReader *reader);
+
+// Close a reader opened with `maf_open`.
+//
+// # Safety
+// `reader` must come from `maf_open` (or be NULL), and not be used
+// afterwards.
+void maf_free(struct MafReader *reader);
+
+// Free a block returned by `maf_next_block`.
+//
+// # Safety
+// `block` must come from `maf_next_block` (or be NULL), and not be
+// used afterwards.
+void maf_block_free(struct MafBlock *block);
+
+// Number of aligned (`s` line) rows in the block. Row 0 is the
+// reference.
+//
+// # Safety
+// `block` must be a live block.
+size_t maf_block_num_rows(const struct MafBlock *block);
+
+// Number of alignment columns in the block.
+//
+// # Safety
+// `block` must be a live block.
+size_t maf_block_num_columns(const struct MafBlock *block);
+
+// The value of a key in the block's `a` line (e.g. "score"), or NULL
+// if it's not present.
+//
+// # Safety
+// `block` must be a live block and `key` a valid NUL-terminated
+// string.
+const char *maf_block_metadata(const struct MafBlock *block, const char *key);
+
+// Sequence name of a row. Aborts if `row` is out of range, as do the
+// other row accessors.
+//
+// # Safety
+// `block` must be a live block.
+const char *maf_block_row_seq(const struct MafBlock *block, size_t row);
+
+// Start of a row, on its own strand.
+//
+// # Safety
+// `block` must be a live block.
+uint64_t maf_block_row_start(const struct MafBlock *block, size_t row);
+
+// Number of bases in a row.
+//
+// # Safety
+// `block` must be a live block.
+uint64_t maf_block_row_size(const struct MafBlock *block, size_t row);
+
+// Strand of a row: '+' or '-'.
+//
+// # Safety
+// `block` must be a live block.
+char maf_block_row_strand(const struct MafBlock *block, size_t row);
+
+// Total length of a row's sequence.
+//
+// # Safety
+// `block` must be a live block.
+uint64_t maf_block_row_src_size(const struct MafBlock *block, size_t row);
+
+// Aligned text of a row, gaps included.
+//
+// # Safety
+// `block` must be a live block.
+const char *maf_block_row_text(const struct MafBlock *block, size_t row);
+
+#endif /* MAF_STREAM_H */
@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use multiple_alignment_format::parser::{next_maf_item_slice, MAFParseError};
+use multiple_alignment_format::MAFItem;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = data;
+    loop {
+        match next_maf_item_slice(&mut input) {
+            Ok(MAFItem::Block(block)) => {
+                // Printing a parsed block shouldn't panic either.
+                let _ = block.to_string();
+            }
+            Ok(MAFItem::Comment(_)) => {}
+            Err(MAFParseError::EOF) | Err(MAFParseError::IOError(_)) => break,
+            // Keep going after bad lines, like a lenient reader would.
+            Err(_) => {}
+        }
+    }
+});
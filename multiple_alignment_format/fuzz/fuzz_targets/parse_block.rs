@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use multiple_alignment_format::parser::parse_block;
+use std::io::BufRead;
+
+fuzz_target!(|data: &[u8]| {
+    let mut lines = data.lines();
+    let header = match lines.next() {
+        Some(Ok(header)) => header,
+        _ => return,
+    };
+    if let Ok(block) = parse_block(header, lines) {
+        let _ = block.to_string();
+    }
+});
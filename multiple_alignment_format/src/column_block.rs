@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use crate::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry};
+
+/// A block with its alignment stored column-major, so that per-column
+/// work (consensus, conservation, gap counting) reads contiguous
+/// memory instead of striding across every row.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ColumnBlock {
+    /// The block's entries, in their original order. The alignment
+    /// text of aligned entries is moved into `bases`, leaving it empty
+    /// here.
+    pub entries: Vec<MAFBlockEntry>,
+    pub metadata: BTreeMap<String, String>,
+    /// Index into `entries` of each aligned row.
+    row_entries: Vec<usize>,
+    num_columns: usize,
+    /// Column-major bases: column i is bases[i * num_rows..(i + 1) * num_rows].
+    bases: Vec<u8>,
+}
+
+impl ColumnBlock {
+    pub fn num_rows(&self) -> usize { self.row_entries.len() }
+
+    pub fn num_columns(&self) -> usize { self.num_columns }
+
+    /// The bases of every row in a column.
+    pub fn column(&self, column: usize) -> &[u8] {
+        let n = self.num_rows();
+        &self.bases[column * n..(column + 1) * n]
+    }
+
+    pub fn column_mut(&mut self, column: usize) -> &mut [u8] {
+        let n = self.num_rows();
+        &mut self.bases[column * n..(column + 1) * n]
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item=&[u8]> {
+        (0..self.num_columns).map(move |i| self.column(i))
+    }
+
+    /// The entry for a row (with empty alignment text).
+    pub fn row_entry(&self, row: usize) -> &MAFBlockAlignedEntry {
+        match &self.entries[self.row_entries[row]] {
+            MAFBlockEntry::AlignedEntry(a) => a,
+            MAFBlockEntry::UnalignedEntry(_) => unreachable!(),
+        }
+    }
+
+    pub fn row_entries(&self) -> impl Iterator<Item=&MAFBlockAlignedEntry> {
+        (0..self.num_rows()).map(move |row| self.row_entry(row))
+    }
+}
+
+impl From<MAFBlock> for ColumnBlock {
+    fn from(block: MAFBlock) -> ColumnBlock {
+        let mut entries = block.entries;
+        let row_entries: Vec<usize> = entries.iter().enumerate()
+            .filter(|(_, e)| matches!(e, MAFBlockEntry::AlignedEntry(_)))
+            .map(|(i, _)| i)
+            .collect();
+        let num_rows = row_entries.len();
+        let num_columns = row_entries.first().map_or(0, |i| match &entries[*i] {
+            MAFBlockEntry::AlignedEntry(a) => a.alignment.len(),
+            MAFBlockEntry::UnalignedEntry(_) => 0,
+        });
+        let mut bases = vec![b'-'; num_rows * num_columns];
+        for (row, i) in row_entries.iter().enumerate() {
+            if let MAFBlockEntry::AlignedEntry(a) = &mut entries[*i] {
+                // Free each row's text as it's copied in.
+                for (column, base) in std::mem::take(&mut a.alignment).into_iter().enumerate().take(num_columns) {
+                    bases[column * num_rows + row] = base;
+                }
+            }
+        }
+        ColumnBlock { entries, metadata: block.metadata, row_entries, num_columns, bases }
+    }
+}
+
+impl From<ColumnBlock> for MAFBlock {
+    fn from(block: ColumnBlock) -> MAFBlock {
+        let num_rows = block.row_entries.len();
+        let mut entries = block.entries;
+        for (row, i) in block.row_entries.iter().enumerate() {
+            if let MAFBlockEntry::AlignedEntry(a) = &mut entries[*i] {
+                a.alignment = block.bases.iter().skip(row).step_by(num_rows).copied().collect();
+            }
+        }
+        MAFBlock { entries, metadata: block.metadata }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::next_maf_item;
+    use crate::MAFItem;
+
+    #[test]
+    fn round_trip() {
+        let maf = "a score=2
+s hg38.chr1 10 3 + 100 AC-G
+e mm39.chr2 0 5 + 10 I
+s rn7.chr1 0 4 - 10 aTTG
+";
+        let block = match next_maf_item(&mut maf.as_bytes()).unwrap() {
+            MAFItem::Block(block) => block,
+            item => panic!("Got unexpected maf item {:?}", item),
+        };
+        let expected = format!("{}", block);
+        let mut columns = ColumnBlock::from(block);
+        assert_eq!(columns.num_rows(), 2);
+        assert_eq!(columns.num_columns(), 4);
+        assert_eq!(columns.column(2), b"-T");
+        assert_eq!(columns.row_entry(1).seq, "rn7.chr1");
+        columns.column_mut(0)[1] = b'A';
+        let block = MAFBlock::from(columns);
+        assert_eq!(format!("{}", block), expected.replace("aTTG", "ATTG"));
+    }
+}
@@ -1,13 +1,24 @@
-#[cfg(test)]
-#[macro_use]
-extern crate maplit;
 
 pub mod parser;
 pub mod output;
+pub mod column_block;
+#[cfg(feature = "packed")]
+pub mod packed;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Whether an alignment character is a gap. Both '-' and '.' are
+/// accepted as gaps.
+pub fn is_gap(c: u8) -> bool {
+    c == b'-' || c == b'.'
+}
 
 /// Structure representing a MAF item (comment or block).
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum MAFItem {
     Block(MAFBlock),
     Comment(String),
@@ -15,12 +26,14 @@ pub enum MAFItem {
 
 /// A MAF alignment block.
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MAFBlock {
     pub entries: Vec<MAFBlockEntry>,
     pub metadata: BTreeMap<String, String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum MAFBlockEntry {
     AlignedEntry(MAFBlockAlignedEntry),
     UnalignedEntry(MAFBlockUnalignedEntry),
@@ -29,8 +42,10 @@ pub enum MAFBlockEntry {
 /// An alignment entry within a MAF block. Corresponds to the "s"
 /// line, as well as the "i" and "q" lines if they are present.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MAFBlockAlignedEntry {
     /// Actual sequence of bases/amino acids, including gaps.
+    #[cfg_attr(feature = "serde", serde(with = "serde_text"))]
     pub alignment: Vec<u8>,
     /// The sequence name.
     pub seq: String,
@@ -47,13 +62,15 @@ pub struct MAFBlockAlignedEntry {
     /// within this sequence.
     pub context: Option<AlignedContext>,
     /// Optional scores indicating the alignment quality for each
-    /// base, ranging from 0-100. No one uses this as far as I can
-    /// tell.
+    /// column, as given in the "q" line: '0'-'9' (the quality divided
+    /// by 5, capped at 9), 'F' for finished sequence, or '-' for a gap.
+    #[cfg_attr(feature = "serde", serde(default, with = "serde_text_opt"))]
     pub qualities: Option<Vec<u8>>,
 }
 
 /// Indicates one of the two strands.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Strand {
     Positive,
     Negative,
@@ -61,6 +78,7 @@ pub enum Strand {
 
 /// Corresponds to the "i" line.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AlignedContext {
     pub left_status: AlignedContextStatus,
     pub left_count: u64,
@@ -69,6 +87,8 @@ pub struct AlignedContext {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum AlignedContextStatus {
     /// Corresponds to "C" in the original MAF line. "The sequence
     /// before or after is contiguous with this block."
@@ -98,6 +118,7 @@ pub enum AlignedContextStatus {
 /// "bridging" two alignment blocks on either side. Corresponds to the
 /// "e" line.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MAFBlockUnalignedEntry {
     pub seq: String,
     /// Start of the unaligned region.
@@ -114,6 +135,8 @@ pub struct MAFBlockUnalignedEntry {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
 pub enum UnalignedContextStatus {
     /// "C" in the original MAF line. "The sequence before and after
     /// is contiguous implying that this region was either deleted in
@@ -147,9 +170,252 @@ impl MAFBlock {
             .filter_map(|e| match e { MAFBlockEntry::AlignedEntry(a) => Some(a), _ => None })
     }
 
+    pub fn unaligned_entries(&self) -> impl Iterator<Item=&MAFBlockUnalignedEntry> {
+        self.entries.iter()
+            .filter_map(|e| match e { MAFBlockEntry::UnalignedEntry(u) => Some(u), _ => None })
+    }
+
+    /// Aligned entries grouped by species. Iteration order is
+    /// arbitrary; use `entries_by_species` where the order can reach
+    /// the output.
     pub fn entries_as_hash(&self) -> HashMap<&str, Vec<&MAFBlockAlignedEntry>> {
         self.aligned_entries()
             .map(|a| (a.seq.split('.').next().unwrap(), a))
-            .fold(HashMap::new(), |mut acc: HashMap<&str, Vec<&MAFBlockAlignedEntry>>, (species, a)| { acc.entry(species).or_insert_with(Vec::new).push(a); acc })
+            .fold(HashMap::new(), |mut acc: HashMap<&str, Vec<&MAFBlockAlignedEntry>>, (species, a)| { acc.entry(species).or_default().push(a); acc })
+    }
+
+    /// Aligned entries grouped by species, in species name order.
+    pub fn entries_by_species(&self) -> BTreeMap<&str, Vec<&MAFBlockAlignedEntry>> {
+        let mut entries: BTreeMap<&str, Vec<&MAFBlockAlignedEntry>> = BTreeMap::new();
+        for a in self.aligned_entries() {
+            entries.entry(a.seq.split('.').next().unwrap()).or_default().push(a);
+        }
+        entries
+    }
+
+    /// A block made of the alignment columns in [start, end), with row
+    /// coordinates adjusted to match, or None if the reference row has
+    /// no bases there. "i" lines no longer hold once a row is cut, so
+    /// they're dropped.
+    ///
+    /// A row with no bases in the slice but bases on both sides of it
+    /// becomes a zero-length "e" line, since the slice sits in a
+    /// deletion in that sequence; other rows with no bases are dropped.
+    /// The block's own "e" lines cover every column, so each slice
+    /// keeps them.
+    pub fn slice_columns(&self, start: usize, end: usize) -> Option<MAFBlock> {
+        let is_base = |c: &u8| !is_gap(*c);
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let a = match entry {
+                MAFBlockEntry::AlignedEntry(a) => a,
+                MAFBlockEntry::UnalignedEntry(_) => {
+                    entries.push(entry.clone());
+                    continue;
+                }
+            };
+            match a.slice_columns(start, end) {
+                Some(sliced) => entries.push(MAFBlockEntry::AlignedEntry(sliced)),
+                None if entries.iter().all(|e| matches!(e, MAFBlockEntry::UnalignedEntry(_))) => return None,
+                None if a.alignment[..start].iter().any(is_base) && a.alignment[end..].iter().any(is_base) => {
+                    entries.push(MAFBlockEntry::UnalignedEntry(MAFBlockUnalignedEntry {
+                        seq: a.seq.clone(),
+                        start: a.start + a.alignment[..start].iter().filter(|c| is_base(c)).count() as u64,
+                        size: 0,
+                        strand: a.strand,
+                        sequence_size: a.sequence_size,
+                        status: UnalignedContextStatus::Deletion,
+                    }));
+                }
+                None => (),
+            }
+        }
+        Some(MAFBlock { entries, metadata: self.metadata.clone() })
+    }
+}
+
+impl MAFBlockAlignedEntry {
+    /// The part of this row in the alignment columns [start, end), or
+    /// None if it has no bases there.
+    pub fn slice_columns(&self, start: usize, end: usize) -> Option<MAFBlockAlignedEntry> {
+        let is_base = |c: &&u8| !is_gap(**c);
+        let skipped = self.alignment[..start].iter().filter(is_base).count() as u64;
+        let aligned_length = self.alignment[start..end].iter().filter(is_base).count() as u64;
+        if aligned_length == 0 {
+            return None;
+        }
+        Some(MAFBlockAlignedEntry {
+            alignment: self.alignment[start..end].to_vec(),
+            seq: self.seq.clone(),
+            start: self.start + skipped,
+            aligned_length,
+            sequence_size: self.sequence_size,
+            strand: self.strand,
+            context: None,
+            qualities: self.qualities.as_ref().map(|q| q[start..end].to_vec()),
+        })
+    }
+
+    /// The position (on this entry's strand) of the base in each
+    /// alignment column, or None if the column is a gap.
+    pub fn column_positions<'a>(&'a self) -> impl Iterator<Item=Option<u64>> + 'a {
+        let mut pos = self.start;
+        self.alignment.iter().map(move |c| {
+            if is_gap(*c) {
+                None
+            } else {
+                pos += 1;
+                Some(pos - 1)
+            }
+        })
+    }
+
+    /// The quality of the base in a column, from the "q" line, if there
+    /// is one: 0-9 as in the line, with finished sequence ('F') as 10.
+    /// None for gaps.
+    pub fn quality(&self, column: usize) -> Option<u8> {
+        match self.qualities.as_ref()?.get(column)? {
+            b'F' => Some(10),
+            q if q.is_ascii_digit() => Some(q - b'0'),
+            _ => None,
+        }
+    }
+
+    /// Convert a position on this entry's strand to a position on the
+    /// positive strand.
+    pub fn forward_position(&self, pos: u64) -> u64 {
+        match self.strand {
+            Strand::Positive => pos,
+            Strand::Negative => self.sequence_size - 1 - pos,
+        }
+    }
+
+    /// The half-open interval this entry covers on the positive strand.
+    pub fn forward_interval(&self) -> (u64, u64) {
+        let start = match self.strand {
+            Strand::Positive => self.start,
+            Strand::Negative => self.sequence_size - self.start - self.aligned_length,
+        };
+        (start, start + self.aligned_length)
+    }
+}
+
+/// (De)serialize alignment text as a string rather than a list of
+/// bytes.
+#[cfg(feature = "serde")]
+mod serde_text {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(text: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(text))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(String::deserialize(deserializer)?.into_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_text_opt {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(text: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match text {
+            Some(text) => super::serde_text::serialize(text, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?.map(String::into_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_positions() {
+        let entry = MAFBlockAlignedEntry {
+            seq: "baboon".to_owned(),
+            start: 10,
+            aligned_length: 3,
+            sequence_size: 100,
+            strand: Strand::Negative,
+            alignment: "g-c.a".as_bytes().to_vec(),
+            context: None,
+            qualities: None,
+        };
+        assert_eq!(entry.column_positions().collect::<Vec<_>>(),
+                   vec![Some(10), None, Some(11), None, Some(12)]);
+        assert_eq!(entry.forward_position(10), 89);
+        assert_eq!(entry.forward_interval(), (87, 90));
+    }
+
+    #[test]
+    fn slice_columns() {
+        let entry = MAFBlockAlignedEntry {
+            seq: "baboon".to_owned(),
+            start: 10,
+            aligned_length: 4,
+            sequence_size: 100,
+            strand: Strand::Negative,
+            alignment: "g-cat".as_bytes().to_vec(),
+            context: None,
+            qualities: Some("9-F12".as_bytes().to_vec()),
+        };
+        let sliced = entry.slice_columns(1, 4).unwrap();
+        assert_eq!(sliced.start, 11);
+        assert_eq!(sliced.aligned_length, 2);
+        assert_eq!(sliced.alignment, "-ca".as_bytes().to_vec());
+        assert_eq!(sliced.qualities, Some("-F1".as_bytes().to_vec()));
+        assert_eq!(entry.slice_columns(1, 2), None);
+    }
+
+    #[test]
+    fn slice_block_columns() {
+        let row = |seq: &str, start: u64, alignment: &str| MAFBlockEntry::AlignedEntry(MAFBlockAlignedEntry {
+            seq: seq.to_owned(),
+            start,
+            aligned_length: alignment.bytes().filter(|c| !is_gap(*c)).count() as u64,
+            sequence_size: 100,
+            strand: Strand::Positive,
+            alignment: alignment.as_bytes().to_vec(),
+            context: None,
+            qualities: None,
+        });
+        let bridge = MAFBlockEntry::UnalignedEntry(MAFBlockUnalignedEntry {
+            seq: "gorilla".to_owned(),
+            start: 5,
+            size: 20,
+            strand: Strand::Positive,
+            sequence_size: 100,
+            status: UnalignedContextStatus::Insertion,
+        });
+        let block = MAFBlock {
+            entries: vec![row("human", 10, "ac-gt"), row("chimp", 10, "a--gt"), row("baboon", 10, "ac---"), bridge.clone()],
+            metadata: BTreeMap::new(),
+        };
+        // Only the reference's gap: nothing to keep.
+        assert_eq!(block.slice_columns(2, 3), None);
+        let sliced = block.slice_columns(1, 3).unwrap();
+        assert_eq!(sliced.entries, vec![
+            row("human", 11, "c-"),
+            MAFBlockEntry::UnalignedEntry(MAFBlockUnalignedEntry {
+                seq: "chimp".to_owned(),
+                start: 11,
+                size: 0,
+                strand: Strand::Positive,
+                sequence_size: 100,
+                status: UnalignedContextStatus::Deletion,
+            }),
+            row("baboon", 11, "c-"),
+            bridge.clone(),
+        ]);
+        // Baboon has no bases after the slice, so it's just dropped.
+        let sliced = block.slice_columns(3, 5).unwrap();
+        assert_eq!(sliced.entries.len(), 3);
+        assert_eq!(sliced.entries[2], bridge);
     }
 }
@@ -1,13 +1,126 @@
-use MAFBlock;
-use MAFBlockEntry;
-use Strand;
-use AlignedContextStatus;
-use UnalignedContextStatus;
+use crate::is_gap;
+use crate::MAFBlock;
+use crate::MAFBlockEntry;
+use crate::Strand;
+use crate::AlignedContextStatus;
+use crate::UnalignedContextStatus;
 use std::str;
+use std::error;
 use std::fmt;
+use std::io;
+
+/// How the fields of "s", "i" and "e" lines are laid out.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[non_exhaustive]
+pub enum OutputStyle {
+    /// A single space between fields.
+    #[default]
+    Compact,
+    /// Fields lined up in columns within each block, with sequence
+    /// names left-justified and numbers right-justified (like UCSC's
+    /// tools write them).
+    Padded,
+}
+
+/// Writes blocks in a chosen style.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct MAFWriter {
+    pub style: OutputStyle,
+    /// Check every block with `validate` before writing it, and refuse
+    /// to write an invalid one.
+    pub strict: bool,
+}
+
+impl MAFWriter {
+    /// Write a block to `output` in the writer's style, unless it's
+    /// strict and the block is invalid. Like the rest of the output,
+    /// I/O errors are left to the caller to notice.
+    pub fn write_block<W: io::Write + ?Sized>(&self, output: &mut W, block: &MAFBlock) -> Result<(), MAFValidationError> {
+        if self.strict {
+            block.validate()?;
+        }
+        match self.style {
+            OutputStyle::Compact => write!(output, "{}", block).ok(),
+            OutputStyle::Padded => write!(output, "{:#}", block).ok(),
+        };
+        Ok(())
+    }
+}
+
+/// Why a block isn't valid MAF.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MAFValidationError {
+    /// The sequence and start of the block's first "s" line, if it has
+    /// one, to tell which block it is.
+    pub location: Option<(String, u64)>,
+    pub reason: String,
+}
+
+impl fmt::Display for MAFValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some((seq, start)) => write!(f, "invalid block at {}:{}: {}", seq, start, self.reason),
+            None => write!(f, "invalid block: {}", self.reason),
+        }
+    }
+}
+
+impl error::Error for MAFValidationError {}
+
+impl MAFBlock {
+    /// Check that the block is valid MAF: it has an "s" line, its rows
+    /// (and their qualities) are all the same length, each row's size is
+    /// its number of bases, no row runs past the end of its sequence,
+    /// and no names or metadata have whitespace that would split their
+    /// fields. (Status codes are always valid, since they're enums.)
+    pub fn validate(&self) -> Result<(), MAFValidationError> {
+        self.invalid_reason().map_err(|reason| MAFValidationError {
+            location: self.aligned_entries().next().map(|e| (e.seq.clone(), e.start)),
+            reason,
+        })
+    }
+
+    fn invalid_reason(&self) -> Result<(), String> {
+        for (key, value) in self.metadata.iter() {
+            if key.is_empty() || key.contains(|c: char| c == '=' || c.is_whitespace())
+                || value.contains(char::is_whitespace) {
+                return Err(format!("invalid metadata {}={}", key, value));
+            }
+        }
+        let mut width = None;
+        for entry in self.entries.iter() {
+            let (seq, start, size, sequence_size) = match entry {
+                MAFBlockEntry::AlignedEntry(e) => (&e.seq, e.start, e.aligned_length, e.sequence_size),
+                MAFBlockEntry::UnalignedEntry(e) => (&e.seq, e.start, e.size, e.sequence_size),
+            };
+            if seq.is_empty() || seq.contains(char::is_whitespace) {
+                return Err(format!("invalid sequence name \"{}\"", seq));
+            }
+            if start + size > sequence_size {
+                return Err(format!("{} {}-{} runs past the end of the sequence ({})", seq, start, start + size, sequence_size));
+            }
+            if let MAFBlockEntry::AlignedEntry(e) = entry {
+                let num_bases = e.alignment.iter().filter(|c| !is_gap(**c)).count() as u64;
+                if num_bases != e.aligned_length {
+                    return Err(format!("{} has {} bases but a size of {}", seq, num_bases, e.aligned_length));
+                }
+                if *width.get_or_insert(e.alignment.len()) != e.alignment.len() {
+                    return Err(format!("{} is {} columns long, but the first row is {}", seq, e.alignment.len(), width.unwrap()));
+                }
+                if e.qualities.as_ref().is_some_and(|q| q.len() != e.alignment.len()) {
+                    return Err(format!("{} has qualities of a different length to its row", seq));
+                }
+            }
+        }
+        if width.is_none() {
+            return Err("block has no s lines".to_owned());
+        }
+        Ok(())
+    }
+}
 
 fn aligned_context_status_char(status: &AlignedContextStatus) -> &'static str {
-    use AlignedContextStatus::*;
+    use crate::AlignedContextStatus::*;
     match status {
         Contiguous => "C",
         Insertion => "I",
@@ -19,7 +132,7 @@ fn aligned_context_status_char(status: &AlignedContextStatus) -> &'static str {
 }
 
 fn unaligned_context_status_char(status: &UnalignedContextStatus) -> &'static str {
-    use UnalignedContextStatus::*;
+    use crate::UnalignedContextStatus::*;
     match status {
         Deletion => "C",
         Insertion => "I",
@@ -29,8 +142,39 @@ fn unaligned_context_status_char(status: &UnalignedContextStatus) -> &'static st
     }
 }
 
+/// Widths of the fields of the "s" and "e" lines in a block, for
+/// lining them up in columns.
+#[derive(Default)]
+struct FieldWidths {
+    seq: usize,
+    start: usize,
+    size: usize,
+    sequence_size: usize,
+}
+
+impl FieldWidths {
+    fn for_block(block: &MAFBlock) -> FieldWidths {
+        let mut widths = FieldWidths::default();
+        for entry in block.entries.iter() {
+            let (seq, start, size, sequence_size) = match entry {
+                MAFBlockEntry::AlignedEntry(e) => (&e.seq, e.start, e.aligned_length, e.sequence_size),
+                MAFBlockEntry::UnalignedEntry(e) => (&e.seq, e.start, e.size, e.sequence_size),
+            };
+            widths.seq = widths.seq.max(seq.len());
+            widths.start = widths.start.max(start.to_string().len());
+            widths.size = widths.size.max(size.to_string().len());
+            widths.sequence_size = widths.sequence_size.max(sequence_size.to_string().len());
+        }
+        widths
+    }
+}
+
+/// Blocks are displayed compact, or padded in the alternate form
+/// (`{:#}`). `MAFWriter` writes them in either style.
 impl fmt::Display for MAFBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let padded = f.alternate();
+        let w = if padded { FieldWidths::for_block(self) } else { FieldWidths::default() };
         write!(f, "a")?;
         for (key, value) in self.metadata.iter() {
             write!(f, " {}={}", key, value)?;
@@ -39,26 +183,39 @@ impl fmt::Display for MAFBlock {
         for entry in self.entries.iter() {
             match entry {
                 MAFBlockEntry::AlignedEntry(e) => {
-                    writeln!(f, "s {} {} {} {} {} {}",
+                    writeln!(f, "s {:<seq_w$} {:>start_w$} {:>size_w$} {} {:>sequence_size_w$} {}",
                            e.seq, e.start, e.aligned_length,
                            if e.strand == Strand::Positive { "+" } else { "-" },
                            e.sequence_size,
-                           str::from_utf8(&e.alignment).expect("alignment not utf8 compatible"))?;
+                           str::from_utf8(&e.alignment).expect("alignment not utf8 compatible"),
+                           seq_w = w.seq, start_w = w.start, size_w = w.size,
+                           sequence_size_w = w.sequence_size)?;
+                    if let Some(ref qualities) = e.qualities {
+                        // Line the qualities up with the alignment.
+                        let text_w = if padded { w.start + w.size + w.sequence_size + 5 } else { 0 };
+                        writeln!(f, "q {:<seq_w$} {:>text_w$}{}",
+                               e.seq, "",
+                               str::from_utf8(qualities).expect("qualities not utf8 compatible"),
+                               seq_w = w.seq, text_w = text_w)?;
+                    }
                     if let Some(ref context) = e.context {
-                        writeln!(f, "i {} {} {} {} {}",
+                        writeln!(f, "i {:<seq_w$} {} {} {} {}",
                                e.seq,
                                aligned_context_status_char(&context.left_status),
                                context.left_count,
                                aligned_context_status_char(&context.right_status),
-                               context.right_count)?;
+                               context.right_count,
+                               seq_w = w.seq)?;
                     }
                 },
                 MAFBlockEntry::UnalignedEntry(e) => {
-                    writeln!(f, "e {} {} {} {} {} {}",
+                    writeln!(f, "e {:<seq_w$} {:>start_w$} {:>size_w$} {} {:>sequence_size_w$} {}",
                     e.seq, e.start, e.size,
                     if e.strand == Strand::Positive { "+" } else { "-" },
                     e.sequence_size,
-                    unaligned_context_status_char(&e.status))?;
+                    unaligned_context_status_char(&e.status),
+                    seq_w = w.seq, start_w = w.start, size_w = w.size,
+                    sequence_size_w = w.sequence_size)?;
                 },
             }
         }
@@ -68,14 +225,20 @@ impl fmt::Display for MAFBlock {
 
 #[cfg(test)]
 mod tests {
-    use MAFBlock;
-    use MAFBlockEntry;
-    use MAFBlockAlignedEntry;
-    use MAFBlockUnalignedEntry;
-    use Strand;
-    use AlignedContext;
-    use AlignedContextStatus;
-    use UnalignedContextStatus;
+    use crate::MAFBlock;
+    use crate::MAFBlockEntry;
+    use crate::MAFBlockAlignedEntry;
+    use crate::MAFBlockUnalignedEntry;
+    use crate::Strand;
+    use crate::AlignedContext;
+    use crate::AlignedContextStatus;
+    use crate::UnalignedContextStatus;
+    use crate::MAFItem;
+    use crate::parser::next_maf_item;
+    use super::{MAFWriter, OutputStyle};
+    use maplit::btreemap;
+    use proptest::prelude::*;
+
     #[test]
     fn display_block() {
         let block = MAFBlock {
@@ -132,4 +295,169 @@ e mm4.chr6 53310102 13 + 151104725 I
 ");
     }
 
+    #[test]
+    fn display_block_padded() {
+        let block_str = "a score=1
+s hg16.chr7 27707221 13 + 158545518 gcagctgaaaaca
+i hg16.chr7 N 0 C 0
+s baboon 249182 12 - 4622798 gcagctgaa-aca
+q baboon 99999999F-999
+e mm4.chr6 53310102 13 + 151104725 I
+";
+        let block = match next_maf_item(&mut block_str.as_bytes()) {
+            Ok(MAFItem::Block(block)) => block,
+            other => panic!("Got unexpected maf item {:?}", other),
+        };
+        let padded = "a score=1
+s hg16.chr7 27707221 13 + 158545518 gcagctgaaaaca
+i hg16.chr7 N 0 C 0
+s baboon      249182 12 -   4622798 gcagctgaa-aca
+q baboon                            99999999F-999
+e mm4.chr6  53310102 13 + 151104725 I
+
+";
+        assert_eq!(format!("{:#}", block), padded);
+        let mut output = vec![];
+        let writer = MAFWriter { style: OutputStyle::Padded, strict: true };
+        writer.write_block(&mut output, &block).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), padded);
+        let mut output = vec![];
+        MAFWriter::default().write_block(&mut output, &block).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), block.to_string());
+    }
+
+    #[test]
+    fn validate_block() {
+        let block_str = "a score=1
+s hg16.chr7 27707221 13 + 158545518 gcagctgaaaaca
+s baboon 249182 12 - 4622798 gcagctgaa-aca
+q baboon 99999999F-999
+e mm4.chr6 53310102 13 + 151104725 I
+";
+        let mut block = match next_maf_item(&mut block_str.as_bytes()) {
+            Ok(MAFItem::Block(block)) => block,
+            other => panic!("Got unexpected maf item {:?}", other),
+        };
+        assert_eq!(block.validate(), Ok(()));
+        if let MAFBlockEntry::AlignedEntry(e) = &mut block.entries[1] {
+            e.alignment.pop();
+        }
+        let error = block.validate().unwrap_err();
+        assert_eq!(error.to_string(), "invalid block at hg16.chr7:27707221: baboon has 11 bases but a size of 12");
+        let mut output = vec![];
+        assert_eq!(MAFWriter { strict: true, ..MAFWriter::default() }.write_block(&mut output, &block), Err(error));
+        assert!(output.is_empty());
+        if let MAFBlockEntry::AlignedEntry(e) = &mut block.entries[1] {
+            e.aligned_length = 11;
+        }
+        assert_eq!(block.validate().unwrap_err().reason, "baboon is 12 columns long, but the first row is 13");
+        if let MAFBlockEntry::AlignedEntry(e) = &mut block.entries[0] {
+            e.sequence_size = 27707230;
+        }
+        assert_eq!(block.validate().unwrap_err().reason, "hg16.chr7 27707221-27707234 runs past the end of the sequence (27707230)");
+        block.metadata.insert("note".to_owned(), "two words".to_owned());
+        assert_eq!(block.validate().unwrap_err().reason, "invalid metadata note=two words");
+    }
+
+    const BASES: &[u8] = b"ACGTNacgtn";
+
+    fn arb_strand() -> impl Strategy<Value = Strand> {
+        prop_oneof![Just(Strand::Positive), Just(Strand::Negative)]
+    }
+
+    fn arb_aligned_context_status() -> impl Strategy<Value = AlignedContextStatus> {
+        use crate::AlignedContextStatus::*;
+        prop_oneof![Just(Contiguous), Just(Insertion), Just(FirstInSequence),
+                    Just(FirstInSequenceBridged), Just(MissingData), Just(AlreadyUsed)]
+    }
+
+    fn arb_unaligned_context_status() -> impl Strategy<Value = UnalignedContextStatus> {
+        use crate::UnalignedContextStatus::*;
+        prop_oneof![Just(Deletion), Just(Insertion), Just(MissingData), Just(NewSequence),
+                    Just(AlreadyUsed)]
+    }
+
+    fn arb_context() -> impl Strategy<Value = Option<AlignedContext>> {
+        proptest::option::of((arb_aligned_context_status(), any::<u64>(),
+                              arb_aligned_context_status(), any::<u64>())
+            .prop_map(|(left_status, left_count, right_status, right_count)| AlignedContext {
+                left_status, left_count, right_status, right_count,
+            }))
+    }
+
+    /// An "s" line with `columns` columns, where the start and
+    /// sequence size are consistent with the number of bases.
+    fn arb_aligned_entry(columns: usize) -> impl Strategy<Value = MAFBlockEntry> {
+        ("[A-Za-z0-9_]{1,8}(\\.[A-Za-z0-9_]{1,8})?",
+         proptest::collection::vec(proptest::option::weighted(0.8, proptest::sample::select(BASES)), columns),
+         0..1_000_000_000u64, 0..1_000_000u64, arb_strand(), arb_context(),
+         proptest::option::of(proptest::collection::vec(proptest::sample::select(b"0123456789F".to_vec()), columns)))
+            .prop_map(|(seq, alignment, start, extra_size, strand, context, qualities)| {
+                let alignment: Vec<u8> = alignment.into_iter().map(|b| b.unwrap_or(b'-')).collect();
+                let aligned_length = alignment.iter().filter(|b| **b != b'-').count() as u64;
+                let qualities = qualities.map(|q| q.into_iter().zip(alignment.iter())
+                                              .map(|(q, b)| if *b == b'-' { b'-' } else { q })
+                                              .collect());
+                MAFBlockEntry::AlignedEntry(MAFBlockAlignedEntry {
+                    seq, start, aligned_length, strand, alignment, context, qualities,
+                    sequence_size: start + aligned_length + extra_size,
+                })
+            })
+    }
+
+    fn arb_unaligned_entry() -> impl Strategy<Value = MAFBlockEntry> {
+        ("[A-Za-z0-9_]{1,8}(\\.[A-Za-z0-9_]{1,8})?", 0..1_000_000_000u64, 0..1_000_000u64,
+         0..1_000_000u64, arb_strand(), arb_unaligned_context_status())
+            .prop_map(|(seq, start, size, extra_size, strand, status)| {
+                MAFBlockEntry::UnalignedEntry(MAFBlockUnalignedEntry {
+                    seq, start, size, strand, status,
+                    sequence_size: start + size + extra_size,
+                })
+            })
+    }
+
+    fn arb_block() -> impl Strategy<Value = MAFBlock> {
+        (1..50usize).prop_flat_map(|columns| {
+            (proptest::collection::btree_map("[a-z]{1,6}", "[A-Za-z0-9.]{0,6}", 0..3),
+             proptest::collection::vec(arb_aligned_entry(columns), 1..6),
+             proptest::collection::vec(arb_unaligned_entry(), 0..3))
+                .prop_map(|(metadata, mut entries, unaligned)| {
+                    entries.extend(unaligned);
+                    MAFBlock { metadata, entries }
+                })
+        })
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let maf_str = "a score=1\ns hg16.chr7 0 4 + 10 ACGT\ni hg16.chr7 N 0 C 0\ne mm4.chr6 5 4 - 100 I\n";
+        let block = match next_maf_item(&mut maf_str.as_bytes()).unwrap() {
+            MAFItem::Block(block) => block,
+            item => panic!("Got unexpected maf item {:?}", item),
+        };
+        let json = serde_json::to_string(&block).unwrap();
+        assert!(json.contains("\"alignment\":\"ACGT\""));
+        assert_eq!(serde_json::from_str::<MAFBlock>(&json).unwrap(), block);
+    }
+
+    proptest! {
+        #[test]
+        fn display_parse_roundtrip(block in arb_block()) {
+            let displayed = block.to_string();
+            match next_maf_item(&mut displayed.as_bytes()) {
+                Ok(MAFItem::Block(parsed)) => prop_assert_eq!(parsed, block),
+                other => panic!("Got unexpected maf item {:?}", other),
+            }
+        }
+
+        #[test]
+        fn padded_display_parse_roundtrip(block in arb_block()) {
+            let displayed = format!("{:#}", block);
+            match next_maf_item(&mut displayed.as_bytes()) {
+                Ok(MAFItem::Block(parsed)) => prop_assert_eq!(parsed, block),
+                other => panic!("Got unexpected maf item {:?}", other),
+            }
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::io;
+use crate::parser::{next_maf_item, MAFParseError};
+use crate::{MAFBlock, MAFBlockEntry, MAFItem};
+
+/// 4-bit codes: the base in the low 3 bits, and the case in the top bit.
+const SYMBOLS: &[u8; 6] = b"-ACGTN";
+const LOWERCASE: u8 = 8;
+
+fn encode(base: u8) -> Option<u8> {
+    let upper = base.to_ascii_uppercase();
+    let code = SYMBOLS.iter().position(|s| *s == upper)? as u8;
+    Some(if base.is_ascii_lowercase() { code | LOWERCASE } else { code })
+}
+
+fn decode(code: u8) -> u8 {
+    let base = SYMBOLS[(code & !LOWERCASE) as usize];
+    if code & LOWERCASE != 0 { base.to_ascii_lowercase() } else { base }
+}
+
+/// Alignment text packed two bases to a byte, for text made only of
+/// A, C, G, T, N (in either case) and gaps. Anything else (IUPAC
+/// codes, proteins) is kept as it is.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PackedText {
+    Packed { len: usize, nibbles: Vec<u8> },
+    Raw(Vec<u8>),
+}
+
+impl PackedText {
+    pub fn pack(text: &[u8]) -> PackedText {
+        let mut nibbles = Vec::with_capacity(text.len().div_ceil(2));
+        for pair in text.chunks(2) {
+            let low = encode(pair[0]);
+            let high = pair.get(1).map_or(Some(0), |b| encode(*b));
+            match (low, high) {
+                (Some(low), Some(high)) => nibbles.push(low | high << 4),
+                _ => return PackedText::Raw(text.to_vec()),
+            }
+        }
+        PackedText::Packed { len: text.len(), nibbles }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            PackedText::Packed { len, .. } => *len,
+            PackedText::Raw(text) => text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    pub fn get(&self, i: usize) -> u8 {
+        match self {
+            PackedText::Packed { nibbles, .. } => decode((nibbles[i / 2] >> (4 * (i % 2))) & 0xf),
+            PackedText::Raw(text) => text[i],
+        }
+    }
+
+    pub fn unpack(&self) -> Vec<u8> {
+        match self {
+            PackedText::Packed { len, .. } => (0..*len).map(|i| self.get(i)).collect(),
+            PackedText::Raw(text) => text.clone(),
+        }
+    }
+}
+
+/// A block with its alignment text packed, to cut the
+/// memory used by blocks that are held on to. Nothing packs blocks
+/// unless asked to; convert to and from `MAFBlock` to work with it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PackedBlock {
+    /// The block's entries, with the text of aligned entries moved
+    /// into `texts`.
+    pub entries: Vec<MAFBlockEntry>,
+    pub metadata: BTreeMap<String, String>,
+    /// The packed text of each entry, None for "e" lines.
+    texts: Vec<Option<PackedText>>,
+}
+
+impl From<MAFBlock> for PackedBlock {
+    fn from(block: MAFBlock) -> PackedBlock {
+        let mut entries = block.entries;
+        let texts = entries.iter_mut().map(|e| match e {
+            MAFBlockEntry::AlignedEntry(a) => {
+                let text = PackedText::pack(&a.alignment);
+                a.alignment = vec![];
+                a.alignment.shrink_to_fit();
+                Some(text)
+            }
+            MAFBlockEntry::UnalignedEntry(_) => None,
+        }).collect();
+        PackedBlock { entries, metadata: block.metadata, texts }
+    }
+}
+
+impl From<PackedBlock> for MAFBlock {
+    fn from(block: PackedBlock) -> MAFBlock {
+        let mut entries = block.entries;
+        for (entry, text) in entries.iter_mut().zip(block.texts) {
+            if let (MAFBlockEntry::AlignedEntry(a), Some(text)) = (entry, text) {
+                a.alignment = match text {
+                    PackedText::Raw(text) => text,
+                    packed => packed.unpack(),
+                };
+            }
+        }
+        MAFBlock { entries, metadata: block.metadata }
+    }
+}
+
+/// Like `MAFItem`, with blocks packed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PackedItem {
+    Block(PackedBlock),
+    Comment(String),
+}
+
+/// Get the next item out of the input, with blocks packed as they're
+/// read.
+pub fn next_packed_item<T: io::BufRead + ?Sized>(input: &mut T) -> Result<PackedItem, MAFParseError> {
+    Ok(match next_maf_item(input)? {
+        MAFItem::Block(block) => PackedItem::Block(block.into()),
+        MAFItem::Comment(comment) => PackedItem::Comment(comment),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_text() {
+        let packed = PackedText::pack(b"ACgt-Nn");
+        assert!(matches!(packed, PackedText::Packed { len: 7, ref nibbles } if nibbles.len() == 4));
+        assert_eq!(packed.get(2), b'g');
+        assert_eq!(packed.unpack(), b"ACgt-Nn".to_vec());
+        assert_eq!(PackedText::pack(b"ACRT"), PackedText::Raw(b"ACRT".to_vec()));
+    }
+
+    #[test]
+    fn packed_block_round_trip() {
+        let maf = "a score=2
+s hg38.chr1 10 3 + 100 AC-G
+e mm39.chr2 0 5 + 10 I
+s rn7.chr1 0 4 - 10 aTRG
+
+";
+        let block = match next_packed_item(&mut maf.as_bytes()).unwrap() {
+            PackedItem::Block(block) => MAFBlock::from(block),
+            item => panic!("Got unexpected item {:?}", item),
+        };
+        assert_eq!(format!("{}", block), maf);
+    }
+}
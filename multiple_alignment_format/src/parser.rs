@@ -1,83 +1,334 @@
-use MAFItem;
-use MAFBlock;
-use MAFBlockEntry;
-use MAFBlockAlignedEntry;
-use MAFBlockUnalignedEntry;
-use AlignedContext;
-use AlignedContextStatus;
-use UnalignedContextStatus;
-use Strand;
+use crate::MAFItem;
+use crate::MAFBlock;
+use crate::MAFBlockEntry;
+use crate::MAFBlockAlignedEntry;
+use crate::MAFBlockUnalignedEntry;
+use crate::AlignedContext;
+use crate::AlignedContextStatus;
+use crate::UnalignedContextStatus;
+use crate::Strand;
+use crate::is_gap;
 use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
 use std::io;
+use std::str;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 
 
-pub struct LinesRef<'a, B: 'a> {
+/// How much of the next chunk of input belongs to the current line, and
+/// whether that finishes it. Lines can end in "\n", "\r\n", or a lone
+/// "\r" (old Mac files), mixed in any way; `after_cr` is whether the
+/// line so far ends in a "\r", which ends it unless a "\n" follows.
+fn line_end(chunk: &[u8], after_cr: bool) -> (usize, bool) {
+    if after_cr {
+        return (usize::from(chunk.first() == Some(&b'\n')), true);
+    }
+    match chunk.iter().position(|c| *c == b'\n' || *c == b'\r') {
+        Some(i) if chunk[i] == b'\n' => (i + 1, true),
+        Some(i) if i + 1 == chunk.len() => (i + 1, false),
+        Some(i) if chunk[i + 1] == b'\n' => (i + 2, true),
+        Some(i) => (i + 1, true),
+        None => (chunk.len(), false),
+    }
+}
+
+/// The line ending of a raw line, removed.
+fn trim_line_end(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+/// The line ending the raw text of an item uses first, or "\n" if it
+/// has none.
+fn first_line_end(raw: &str) -> &'static str {
+    match raw.find(['\n', '\r']) {
+        Some(i) if raw[i..].starts_with("\r\n") => "\r\n",
+        Some(i) if raw[i..].starts_with('\r') => "\r",
+        _ => "\n",
+    }
+}
+
+/// A line without the byte order mark that files saved by some Windows
+/// editors start with.
+fn strip_bom(line: &str) -> &str {
+    line.strip_prefix('\u{feff}').unwrap_or(line)
+}
+
+pub struct LinesRef<'a, B: 'a + ?Sized> {
     buf: &'a mut B,
+    /// If present, the original text of every line read (line endings
+    /// included) is appended here.
+    raw: Option<&'a mut String>,
 }
 
-impl<'a, B: io::BufRead> Iterator for LinesRef<'a, B> {
+impl<'a, B: io::BufRead + ?Sized> Iterator for LinesRef<'a, B> {
     type Item = io::Result<String>;
 
     fn next(&mut self) -> Option<io::Result<String>> {
-        let mut buf = String::new();
-        match self.buf.read_line(&mut buf) {
-            Ok(0) => None,
-            Ok(_n) => {
-                if buf.ends_with('\n') {
-                    buf.pop();
-                    if buf.ends_with('\r') {
-                        buf.pop();
-                    }
-                }
-                Some(Ok(buf))
+        let mut line = vec![];
+        loop {
+            let chunk = match self.buf.fill_buf() {
+                Ok(chunk) => chunk,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Some(Err(e)),
+            };
+            if chunk.is_empty() {
+                break;
             }
-            Err(e) => Some(Err(e))
+            let (len, done) = line_end(chunk, line.last() == Some(&b'\r'));
+            line.extend_from_slice(&chunk[..len]);
+            self.buf.consume(len);
+            if done {
+                break;
+            }
+        }
+        if line.is_empty() {
+            return None;
+        }
+        let mut buf = match String::from_utf8(line) {
+            Ok(buf) => buf,
+            Err(e) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+        };
+        if let Some(ref mut raw) = self.raw {
+            raw.push_str(&buf);
         }
+        buf.truncate(trim_line_end(&buf).len());
+        Some(Ok(buf))
     }
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum MAFParseError {
     IOError(io::Error),
     UnexpectedLine(String),
     BadMetadata,
     BadLineType(String),
     Misc(&'static str),
+    /// The input ended partway through a block, as when a download was
+    /// cut off: there was no blank line after the block, and either it
+    /// couldn't be parsed, or it has no "s" lines, or its last row has a
+    /// different number of bases than its size. Holds the block's "a"
+    /// line.
+    Truncated(String),
     EOF,
 }
 
+impl fmt::Display for MAFParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MAFParseError::IOError(e) => write!(f, "I/O error: {}", e),
+            MAFParseError::UnexpectedLine(line) => write!(f, "Unexpected line: {}", line),
+            MAFParseError::BadMetadata => write!(f, "Bad metadata in block header"),
+            MAFParseError::BadLineType(line_type) => write!(f, "Bad line type: {}", line_type),
+            MAFParseError::Misc(msg) => write!(f, "{}", msg),
+            MAFParseError::Truncated(header) => write!(f, "Input ends partway through the block starting {:?}", header),
+            MAFParseError::EOF => write!(f, "End of file"),
+        }
+    }
+}
+
+impl error::Error for MAFParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MAFParseError::IOError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl From<io::Error> for MAFParseError {
     fn from(err: io::Error) -> Self {
         MAFParseError::IOError(err)
     }
 }
 
-/// Get the next MAFItem out of the input.
-pub fn next_maf_item<T: io::BufRead + ?Sized>(mut input: &mut T) -> Result<MAFItem, MAFParseError> {
-    let mut header: Option<String> = None;
-    {
-        let lines = LinesRef { buf: &mut input };
-        for line_res in lines {
-            let line: String = line_res?;
-            if line.trim().is_empty() {
-                // Blank line
-                continue;
+/// Get the next MAFItem out of the input. When the whole item is
+/// already in the input's buffer (as it always is for a memory-mapped
+/// file), it's parsed in place, as `next_maf_item_slice` does;
+/// otherwise it's read line by line.
+pub fn next_maf_item<T: io::BufRead + ?Sized>(input: &mut T) -> Result<MAFItem, MAFParseError> {
+    let buf = match input.fill_buf() {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => return next_item(input, None),
+        Err(e) => return Err(e.into()),
+    };
+    // An item is only known to be whole if something follows it in the
+    // buffer, which for a block means a blank line.
+    if buf.windows(2).any(|w| matches!(w, b"\n\n" | b"\n\r" | b"\r\r")) {
+        let mut rest = buf;
+        if let Ok(item) = next_maf_item_slice(&mut rest) {
+            if !rest.is_empty() {
+                let len = buf.len() - rest.len();
+                input.consume(len);
+                return Ok(item);
+            }
+        }
+    }
+    next_item(input, None)
+}
+
+/// Get the next MAFItem out of the input, along with the original text
+/// it was parsed from (including any blank lines before it), so that
+/// items that aren't modified can be written back out byte-for-byte.
+/// The text of a block always ends with a blank line, even if the
+/// input didn't have one after the last block.
+pub fn next_maf_item_raw<T: io::BufRead + ?Sized>(input: &mut T) -> Result<(MAFItem, String), MAFParseError> {
+    let mut raw = String::new();
+    let item = next_item(input, Some(&mut raw))?;
+    let line_end = first_line_end(&raw);
+    if !raw.ends_with(['\n', '\r']) {
+        raw.push_str(line_end);
+    }
+    if let MAFItem::Block(_) = item {
+        let body = raw.strip_suffix('\n').unwrap_or(&raw);
+        let body = body.strip_suffix('\r').unwrap_or(body);
+        let last_line = body.rsplit(['\n', '\r']).next().unwrap();
+        if !last_line.trim().is_empty() {
+            raw.push_str(line_end);
+        }
+    }
+    Ok((item, raw))
+}
+
+fn next_item<T: io::BufRead + ?Sized>(input: &mut T, raw: Option<&mut String>) -> Result<MAFItem, MAFParseError> {
+    item_from_lines(&mut LinesRef { buf: input, raw })
+}
+
+/// Lines of a byte slice, borrowed rather than copied. The slice is
+/// advanced past each line as it's read.
+struct SliceLines<'a: 'b, 'b> {
+    input: &'b mut &'a [u8],
+}
+
+impl<'a, 'b> Iterator for SliceLines<'a, 'b> {
+    type Item = io::Result<&'a str>;
+
+    fn next(&mut self) -> Option<io::Result<&'a str>> {
+        if self.input.is_empty() {
+            return None;
+        }
+        let input: &'a [u8] = self.input;
+        let (len, _) = line_end(input, false);
+        *self.input = &input[len..];
+        Some(str::from_utf8(&input[..len])
+             .map(trim_line_end)
+             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+/// Get the next MAFItem out of an in-memory MAF, advancing the slice
+/// past it. Lines are parsed in place without being copied first.
+pub fn next_maf_item_slice(input: &mut &[u8]) -> Result<MAFItem, MAFParseError> {
+    item_from_lines(&mut SliceLines { input })
+}
+
+/// Get the next MAFItem out of an async input. Each block is read into
+/// memory before it's parsed.
+#[cfg(feature = "async")]
+pub async fn next_maf_item_async<T: AsyncBufRead + Unpin + ?Sized>(input: &mut T) -> Result<MAFItem, MAFParseError> {
+    let mut lines = vec![];
+    loop {
+        let mut line = vec![];
+        loop {
+            let chunk = input.fill_buf().await?;
+            if chunk.is_empty() {
+                break;
             }
-            if line.starts_with('#') {
-                // MAF comment
-                return Ok(MAFItem::Comment(line.chars().skip(1).collect()));
-            } else if line.starts_with('a') {
-                // Start of a block
-                header = Some(line);
+            let (len, done) = line_end(chunk, line.last() == Some(&b'\r'));
+            line.extend_from_slice(&chunk[..len]);
+            input.consume(len);
+            if done {
                 break;
-            } else {
-                // Shouldn't see this.
-                return Err(MAFParseError::UnexpectedLine(line))
             }
-        };
+        }
+        if line.is_empty() {
+            break;
+        }
+        let line = String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let line = strip_bom(trim_line_end(&line)).to_string();
+        if line.trim().is_empty() || is_track_line(&line) {
+            if lines.is_empty() {
+                continue;
+            }
+            // Ending the block as the blank line would, so that it isn't
+            // taken to run to the end of the input.
+            lines.push(String::new());
+            break;
+        }
+        let starts_block = line.starts_with('a');
+        lines.push(line);
+        if lines.len() == 1 && !starts_block {
+            // A comment or a bad line, either of which stands alone.
+            break;
+        }
     }
-    let block = parse_block(header.ok_or(MAFParseError::EOF)?, LinesRef { buf: &mut input })?;
-    Ok(MAFItem::Block(block))
+    item_from_lines(&mut lines.into_iter().map(Ok::<_, io::Error>))
+}
+
+/// Whether a line is a UCSC "track" or "browser" line, which
+/// concatenated MAFs sometimes have between blocks. They aren't part of
+/// the MAF, so they're skipped (though the raw text of the next item
+/// keeps them).
+fn is_track_line(line: &str) -> bool {
+    ["track", "browser"].iter().any(|keyword| {
+        line.strip_prefix(keyword)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+    })
+}
+
+fn item_from_lines<L: AsRef<str>>(lines: &mut impl Iterator<Item = io::Result<L>>) -> Result<MAFItem, MAFParseError> {
+    let mut header: Option<String> = None;
+    for line_res in lines.by_ref() {
+        let line = line_res?;
+        let line = strip_bom(line.as_ref());
+        if line.trim().is_empty() || is_track_line(line) {
+            // Blank line
+            continue;
+        }
+        if line.starts_with('#') {
+            // MAF comment
+            return Ok(MAFItem::Comment(line.chars().skip(1).collect()));
+        } else if line.starts_with('a') {
+            // Start of a block
+            header = Some(line.to_string());
+            break;
+        } else {
+            // Shouldn't see this.
+            return Err(MAFParseError::UnexpectedLine(line.to_string()))
+        }
+    };
+    let header = header.ok_or(MAFParseError::EOF)?;
+    // Whether the block runs to the end of the input, with no blank
+    // line after it.
+    let mut at_end = false;
+    let block_lines = std::iter::from_fn(|| {
+        let line = lines.next();
+        at_end = line.is_none();
+        line
+    });
+    match parse_block(header.clone(), block_lines) {
+        Ok(block) if at_end && cut_off(&block) => Err(MAFParseError::Truncated(header)),
+        Ok(block) => Ok(MAFItem::Block(block)),
+        Err(MAFParseError::IOError(e)) => Err(MAFParseError::IOError(e)),
+        // A block cut off by the end of the input fails on its last
+        // line; otherwise the error is in the middle of the input, and
+        // the line after it is lost along with the rest of the block.
+        Err(e) => match lines.next() {
+            None => Err(MAFParseError::Truncated(header)),
+            Some(_) => Err(e),
+        },
+    }
+}
+
+/// Whether a block that parsed, but ran to the end of the input, was
+/// cut off: it has no rows yet, or its last row ends partway through
+/// its text.
+fn cut_off(block: &MAFBlock) -> bool {
+    block.aligned_entries().next().is_none()
+        || matches!(block.entries.last(), Some(MAFBlockEntry::AlignedEntry(entry))
+                    if entry.alignment.iter().filter(|c| !is_gap(**c)).count() as u64 != entry.aligned_length)
 }
 
 // Go from "key=value" to "(key, value)".
@@ -102,6 +353,9 @@ fn parse_strand(strand: &str) -> Result<Strand, MAFParseError> {
 }
 
 fn update_from_s_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEntry>) -> Result<(), MAFParseError> {
+    if fields.len() != 7 {
+        return Err(MAFParseError::Misc("s line has wrong number of fields"));
+    }
     let alignment = fields.pop()
         .ok_or(MAFParseError::Misc("s line incomplete"))?;
     let sequence_size = fields.pop()
@@ -118,6 +372,17 @@ fn update_from_s_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEn
         .and_then(|s| s.parse::<u64>().map_err(|_| MAFParseError::Misc("invalid start")))?;
     let seq = fields.pop()
         .ok_or(MAFParseError::Misc("s line incomplete"))?;
+    if start.checked_add(aligned_length).is_none_or(|end| end > sequence_size) {
+        return Err(MAFParseError::Misc("s line extends past the end of the sequence"));
+    }
+    if let Some(first) = block_entries.iter().filter_map(|e| match e {
+        MAFBlockEntry::AlignedEntry(e) => Some(e),
+        MAFBlockEntry::UnalignedEntry(_) => None,
+    }).next() {
+        if first.alignment.len() != alignment.len() {
+            return Err(MAFParseError::Misc("s lines in block have different alignment lengths"));
+        }
+    }
     block_entries.push(MAFBlockEntry::AlignedEntry(MAFBlockAlignedEntry {
         alignment: alignment.as_bytes().to_vec(),
         seq: seq.to_string(),
@@ -132,7 +397,7 @@ fn update_from_s_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEn
 }
 
 fn parse_aligned_context_status(status: &str) -> Result<AlignedContextStatus, MAFParseError> {
-    use AlignedContextStatus::*;
+    use crate::AlignedContextStatus::*;
     match status {
         "C" => Ok(Contiguous),
         "I" => Ok(Insertion),
@@ -144,7 +409,33 @@ fn parse_aligned_context_status(status: &str) -> Result<AlignedContextStatus, MA
     }
 }
 
+fn update_from_q_line(fields: &mut Vec<&str>, block_entries: &mut [MAFBlockEntry]) -> Result<(), MAFParseError> {
+    if fields.len() != 3 {
+        return Err(MAFParseError::Misc("q line has wrong number of fields"));
+    }
+    let qualities = fields.pop()
+        .ok_or(MAFParseError::Misc("q line incomplete"))?;
+    let seq = fields.pop()
+        .ok_or(MAFParseError::Misc("q line incomplete"))?;
+    if !qualities.bytes().all(|q| q.is_ascii_digit() || q == b'F' || q == b'-') {
+        return Err(MAFParseError::Misc("invalid quality value"));
+    }
+    match block_entries.last_mut() {
+        Some(MAFBlockEntry::AlignedEntry(e)) if e.seq == seq => {
+            if qualities.len() != e.alignment.len() {
+                return Err(MAFParseError::Misc("q line length doesn't match alignment length"));
+            }
+            e.qualities = Some(qualities.as_bytes().to_vec());
+            Ok(())
+        },
+        _ => Err(MAFParseError::UnexpectedLine("q line must follow a corresponding s line".to_owned())),
+    }
+}
+
 fn update_from_i_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEntry>) -> Result<(), MAFParseError> {
+    if fields.len() != 6 {
+        return Err(MAFParseError::Misc("i line has wrong number of fields"));
+    }
     let right_count = fields.pop()
         .ok_or(MAFParseError::Misc("i line incomplete"))
         .and_then(|s| s.parse::<u64>().map_err(|_| MAFParseError::Misc("invalid right count")))?;
@@ -183,6 +474,9 @@ fn update_from_i_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEn
 }
 
 fn update_from_e_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEntry>) -> Result<(), MAFParseError> {
+    if fields.len() != 7 {
+        return Err(MAFParseError::Misc("e line has wrong number of fields"));
+    }
     let status_char = fields.pop()
         .ok_or(MAFParseError::Misc("e line incomplete"))?;
     let sequence_size = fields.pop()
@@ -199,6 +493,9 @@ fn update_from_e_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEn
         .and_then(|s| s.parse::<u64>().map_err(|_| MAFParseError::Misc("invalid start")))?;
     let seq = fields.pop()
         .ok_or(MAFParseError::Misc("e line incomplete"))?;
+    if start.checked_add(unaligned_length).is_none_or(|end| end > sequence_size) {
+        return Err(MAFParseError::Misc("e line extends past the end of the sequence"));
+    }
     let status = match status_char {
         "C" => UnalignedContextStatus::Deletion,
         "I" => UnalignedContextStatus::Insertion,
@@ -219,23 +516,25 @@ fn update_from_e_line(fields: &mut Vec<&str>, block_entries: &mut Vec<MAFBlockEn
 }
 
 
-pub fn parse_block(header: String, iter: impl Iterator<Item = Result<String, io::Error>>) -> Result<MAFBlock, MAFParseError> {
+pub fn parse_block<L: AsRef<str>>(header: String, iter: impl Iterator<Item = Result<L, io::Error>>) -> Result<MAFBlock, MAFParseError> {
     let mut block_entries: Vec<MAFBlockEntry> = vec![];
     let block_metadata = metadata_from_header(&header)?;
  
     for line_res in iter {
-        let line: String = line_res?;
-        if line.is_empty() {
-            // Blank lines terminate the "paragraph".
-            break;
-        }
+        let line = line_res?;
+        let line = line.as_ref();
         let mut fields: Vec<_> = line.split_whitespace().collect();
-        match fields[0] {
+        let line_type = match fields.first() {
+            Some(line_type) => *line_type,
+            // Blank (or whitespace-only) lines terminate the "paragraph".
+            None => break,
+        };
+        match line_type {
             "s" => update_from_s_line(&mut fields, &mut block_entries)?,
             "i" => update_from_i_line(&mut fields, &mut block_entries)?,
             "e" => update_from_e_line(&mut fields, &mut block_entries)?,
-//            "q" => update_from_q_line(&mut fields, &mut block_entries)?,
-            _ => return Err(MAFParseError::BadLineType(fields[0].to_string())),
+            "q" => update_from_q_line(&mut fields, &mut block_entries)?,
+            _ => return Err(MAFParseError::BadLineType(line_type.to_string())),
         };
     }
     Ok(MAFBlock {
@@ -247,13 +546,14 @@ pub fn parse_block(header: String, iter: impl Iterator<Item = Result<String, io:
 #[cfg(test)]
 mod tests {
     use super::*;
+    use maplit::btreemap;
     use std::io::{BufRead, BufReader};
 
     #[test]
     fn metadata_from_header_filled() {
         let header = "a score=23262.0 pass=2";
         match metadata_from_header(header) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, btreemap!{"score".to_owned() => "23262.0".to_owned(),
                                                  "pass".to_owned() => "2".to_owned()}),
         }
@@ -263,7 +563,7 @@ mod tests {
     fn metadata_from_header_blank() {
         let header = "a";
         match metadata_from_header(header) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, btreemap!{}),
         }
     }
@@ -279,7 +579,7 @@ this line is a canary to ensure it stops after a 'paragraph'";
         let mut lines = BufReader::new(block_str.as_bytes()).lines();
         let header = lines.next().unwrap().unwrap();
         match parse_block(header, lines) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, MAFBlock {
                 metadata: btreemap!{"meta1".to_owned() => "val1".to_owned(),
                                     "meta2".to_owned() => "val2".to_owned()},
@@ -329,7 +629,7 @@ i baboon       I 234 n 19";
         let mut lines = BufReader::new(block_str.as_bytes()).lines();
         let header = lines.next().unwrap().unwrap();
         match parse_block(header, lines) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, MAFBlock {
                 metadata: btreemap!{},
                 entries: vec![
@@ -376,7 +676,7 @@ e mm4.chr6     53310102 13 + 151104725 I";
         let mut lines = BufReader::new(block_str.as_bytes()).lines();
         let header = lines.next().unwrap().unwrap();
         match parse_block(header, lines) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, MAFBlock {
                 metadata: btreemap!{},
                 entries: vec![
@@ -404,12 +704,243 @@ e mm4.chr6     53310102 13 + 151104725 I";
     }
 
 
+    #[test]
+    fn parse_block_whitespace_line_ends_block() {
+        let block_str = "a
+s hg16.chr7    27707221 13 + 158545518 gcagctgaaaaca
+   \t
+s mm4.chr6     53310102 12 + 151104725 ACAGCTGA-AATA";
+        let mut lines = BufReader::new(block_str.as_bytes()).lines();
+        let header = lines.next().unwrap().unwrap();
+        match parse_block(header, lines) {
+            Err(e) => panic!("got error {:?}", e),
+            Ok(val) => assert_eq!(val.entries.len(), 1),
+        }
+    }
+
+    #[test]
+    fn parse_block_malformed_lines() {
+        let bad_lines = [
+            // Too few fields
+            "s 1 2 + 3 ACGT",
+            // Too many fields
+            "s hg16.chr7 0 4 + 10 ACGT ACGT",
+            // Past the end of the sequence
+            "s hg16.chr7 8 4 + 10 ACGT",
+            "s hg16.chr7 18446744073709551615 4 + 10 ACGT",
+            "e hg16.chr7 8 4 + 10 I",
+            // Different alignment length from the first row
+            "s mm4.chr6 0 3 + 10 ACG",
+            "i hg16.chr7 N 0",
+        ];
+        for bad_line in bad_lines.iter() {
+            let block_str = format!("a\ns hg16.chr7 0 4 + 10 ACGT\n{}", bad_line);
+            let mut lines = BufReader::new(block_str.as_bytes()).lines();
+            let header = lines.next().unwrap().unwrap();
+            assert!(parse_block(header, lines).is_err(), "parsed {:?}", bad_line);
+        }
+    }
+
+    #[test]
+    fn parse_raw() {
+        let maf_str = "##maf version=1\r\n\na  score=1\ns hg16.chr7  0 4 + 10 ACGT\n\n\na\ns hg16.chr7 4 4 + 10 ACGT";
+        let mut buf_reader = BufReader::new(maf_str.as_bytes());
+        let mut raws = vec![];
+        while let Ok((_, raw)) = next_maf_item_raw(&mut buf_reader) {
+            raws.push(raw);
+        }
+        assert_eq!(raws, vec!["##maf version=1\r\n",
+                              "\na  score=1\ns hg16.chr7  0 4 + 10 ACGT\n\n",
+                              "\na\ns hg16.chr7 4 4 + 10 ACGT\n\n"]);
+    }
+
+    #[test]
+    fn parse_slice() {
+        let maf_str = "##maf version=1\r\na score=1\r\ns hg16.chr7 0 4 + 10 ACGT\r\n\r\na\ns hg16.chr7 4 4 + 10 ACGT";
+        let mut from_slice = vec![];
+        let mut input = maf_str.as_bytes();
+        while let Ok(item) = next_maf_item_slice(&mut input) {
+            from_slice.push(item);
+        }
+        let mut from_reader = vec![];
+        let mut buf_reader = BufReader::new(maf_str.as_bytes());
+        while let Ok(item) = next_maf_item(&mut buf_reader) {
+            from_reader.push(item);
+        }
+        assert_eq!(from_slice.len(), 3);
+        assert_eq!(from_slice, from_reader);
+    }
+
+    #[test]
+    fn parse_line_endings_and_bom() {
+        let lf = "##maf version=1\na score=1\ns hg16.chr7 0 4 + 10 ACGT\n\na\ns hg16.chr7 4 4 + 10 ACGT\n";
+        let mut expected = vec![];
+        let mut buf_reader = BufReader::new(lf.as_bytes());
+        while let Ok(item) = next_maf_item(&mut buf_reader) {
+            expected.push(item);
+        }
+        assert_eq!(expected.len(), 3);
+        let crlf = lf.replace('\n', "\r\n");
+        let cr = lf.replace('\n', "\r");
+        let mixed = "##maf version=1\ra score=1\r\ns hg16.chr7 0 4 + 10 ACGT\r\n\ra\ns hg16.chr7 4 4 + 10 ACGT";
+        for maf_str in [format!("\u{feff}{}", lf), crlf, format!("\u{feff}{}", cr), mixed.to_string()].iter() {
+            // A reader with a tiny buffer, so that "\r\n" gets split
+            // across reads.
+            let mut buf_reader = BufReader::with_capacity(3, maf_str.as_bytes());
+            let mut items = vec![];
+            while let Ok(item) = next_maf_item(&mut buf_reader) {
+                items.push(item);
+            }
+            assert_eq!(items, expected, "{:?}", maf_str);
+            let mut input = maf_str.as_bytes();
+            let mut items = vec![];
+            while let Ok(item) = next_maf_item_slice(&mut input) {
+                items.push(item);
+            }
+            assert_eq!(items, expected, "{:?}", maf_str);
+        }
+
+        // The raw text is kept as it was, and blocks are finished with
+        // the file's own line ending.
+        let mut buf_reader = BufReader::new("\u{feff}##maf version=1\ra\rs hg16.chr7 0 4 + 10 ACGT".as_bytes());
+        let mut raws = vec![];
+        while let Ok((_, raw)) = next_maf_item_raw(&mut buf_reader) {
+            raws.push(raw);
+        }
+        assert_eq!(raws, vec!["\u{feff}##maf version=1\r", "a\rs hg16.chr7 0 4 + 10 ACGT\r\r"]);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let maf_str = "##maf version=1\na score=1\ns hg16.chr7 0 4 + 10 ACGT\n\na score=2\ns hg16.chr7 4 4 + 10 ACGT\ns mm4.chr6 0 4 + 10 AC";
+        let mut buf_reader = BufReader::new(maf_str.as_bytes());
+        assert!(matches!(next_maf_item(&mut buf_reader), Ok(MAFItem::Comment(_))));
+        assert!(matches!(next_maf_item(&mut buf_reader), Ok(MAFItem::Block(_))));
+        match next_maf_item(&mut buf_reader) {
+            Err(MAFParseError::Truncated(header)) => assert_eq!(header, "a score=2"),
+            other => panic!("Expected a truncated block, got {:?}", other),
+        }
+        // Cut off in the middle of a name.
+        let mut input = &maf_str.as_bytes()[..maf_str.find("mm4").unwrap() + 5];
+        assert!(next_maf_item_slice(&mut input).is_ok());
+        assert!(next_maf_item_slice(&mut input).is_ok());
+        assert!(matches!(next_maf_item_slice(&mut input), Err(MAFParseError::Truncated(_))));
+
+        // Blocks that parse, but are cut off partway through a row's
+        // text, or just after their "a" line. A last block without a
+        // blank line after it is fine if its rows are whole.
+        for (maf_str, truncated) in [("a score=1\ns hg16.chr7 0 4 + 10 ACGT\n\na score=2\ns hg38.chr1 4 6 + 100 AC", true),
+                                     ("a score=1\ns hg16.chr7 0 4 + 10 ACGT\n\na score=2\n", true),
+                                     ("a score=1\ns hg16.chr7 0 4 + 10 ACGT\n\na score=2\ns hg38.chr1 4 2 + 100 A-C", false)].iter() {
+            let mut buf_reader = BufReader::new(maf_str.as_bytes());
+            let mut input = maf_str.as_bytes();
+            assert!(next_maf_item(&mut buf_reader).is_ok());
+            assert!(next_maf_item_slice(&mut input).is_ok());
+            for last in [next_maf_item(&mut buf_reader), next_maf_item_slice(&mut input)].iter() {
+                match last {
+                    Err(MAFParseError::Truncated(header)) => assert!(*truncated && header == "a score=2", "{:?}", maf_str),
+                    Ok(MAFItem::Block(_)) => assert!(!truncated, "{:?}", maf_str),
+                    other => panic!("{:?} gave {:?}", maf_str, other),
+                }
+            }
+        }
+
+        // The same bad line isn't truncation if the block ends properly,
+        // or isn't at the end of the input.
+        for maf_str in ["a\ns hg16.chr7 0 4 +\n\n", "a\ns hg16.chr7 0 4 +\na\n"].iter() {
+            let mut buf_reader = BufReader::new(maf_str.as_bytes());
+            match next_maf_item(&mut buf_reader) {
+                Err(MAFParseError::Truncated(_)) | Ok(_) => panic!("{:?} isn't truncated", maf_str),
+                Err(_) => (),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn parse_async() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Waker};
+        let maf_str = "\u{feff}##maf version=1\r\n\na score=1\rs hg16.chr7 0 4 + 10 ACGT\n\n\na\ns hg16.chr7 4 4 + 10 ACGT";
+        let mut input = maf_str.as_bytes();
+        let mut items = vec![];
+        // Reading from a slice never blocks, so there's no need for a
+        // real executor.
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            match pin!(next_maf_item_async(&mut input)).as_mut().poll(&mut cx) {
+                Poll::Ready(Ok(item)) => items.push(item),
+                Poll::Ready(Err(_)) => break,
+                Poll::Pending => panic!("Reading from a slice shouldn't block"),
+            }
+        }
+        let mut expected = vec![];
+        let mut buf_reader = BufReader::new(maf_str.as_bytes());
+        while let Ok(item) = next_maf_item(&mut buf_reader) {
+            expected.push(item);
+        }
+        assert_eq!(items.len(), 3);
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn parse_error_display() {
+        assert_eq!(MAFParseError::BadLineType("x".to_owned()).to_string(), "Bad line type: x");
+        let err: Box<dyn error::Error> = Box::new(MAFParseError::from(io::Error::other("oops")));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn parse_buffered() {
+        let maf_str = "##maf version=1\na score=1\ns hg16.chr7 0 4 + 10 ACGT\n\na\r\ns hg16.chr7 4 4 + 12 ACGT\r\n\r\na\ns hg16.chr7 8 4 + 12 ACGT\n";
+        let mut expected = vec![];
+        let mut input = maf_str.as_bytes();
+        while let Ok(item) = next_maf_item_slice(&mut input) {
+            expected.push(item);
+        }
+        assert_eq!(expected.len(), 4);
+        // Whole items in the buffer, and items split across reads.
+        for capacity in [maf_str.len(), 7].iter() {
+            let mut buf_reader = BufReader::with_capacity(*capacity, maf_str.as_bytes());
+            let mut items = vec![];
+            while let Ok(item) = next_maf_item(&mut buf_reader) {
+                items.push(item);
+            }
+            assert_eq!(items, expected);
+        }
+    }
+
+    #[test]
+    fn parse_q_line() {
+        let block_str = "a
+s hg16.chr7 0 4 + 10 AC-GT
+q hg16.chr7 9F-01
+i hg16.chr7 N 0 C 0
+";
+        match next_maf_item(&mut block_str.as_bytes()) {
+            Ok(MAFItem::Block(block)) => {
+                let entry = block.aligned_entries().next().unwrap();
+                assert_eq!(entry.qualities, Some(b"9F-01".to_vec()));
+                assert_eq!((0..5).map(|c| entry.quality(c)).collect::<Vec<_>>(),
+                           vec![Some(9), Some(10), None, Some(0), Some(1)]);
+                assert!(entry.context.is_some());
+                assert_eq!(block.to_string(), block_str.to_owned() + "\n");
+            },
+            other => panic!("Got unexpected maf item {:?}", other),
+        }
+        for bad in ["q hg16.chr7 9F-0", "q hg16.chr7 9X-01", "q mm4.chr6 9F-01"].iter() {
+            let maf = format!("a\ns hg16.chr7 0 4 + 10 AC-GT\n{}\n", bad);
+            assert!(next_maf_item(&mut maf.as_bytes()).is_err(), "{}", bad);
+        }
+    }
+
     #[test]
     fn parse_comment() {
         let comment_str = "##maf version=1";
         let mut buf_reader = BufReader::new(comment_str.as_bytes());
         match next_maf_item(&mut buf_reader) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, MAFItem::Comment("#maf version=1".to_owned())),
         }
     }
@@ -419,8 +950,39 @@ e mm4.chr6     53310102 13 + 151104725 I";
         let comment_str = "#";
         let mut buf_reader = BufReader::new(comment_str.as_bytes());
         match next_maf_item(&mut buf_reader) {
-            Err(e) => assert!(false, "got error {:?}", e),
+            Err(e) => panic!("got error {:?}", e),
             Ok(val) => assert_eq!(val, MAFItem::Comment("".to_owned())),
         }
     }
+
+    #[test]
+    fn parse_track_lines() {
+        let maf = "track name=first
+##maf version=1
+a
+s hg16.chr7 0 2 + 10 AC
+
+browser position chr7:1-2
+track name=second
+##maf version=1
+a
+s hg16.chr7 2 2 + 10 GT
+";
+        let mut input = maf.as_bytes();
+        let mut items = vec![];
+        while let Ok(item) = next_maf_item(&mut input) {
+            items.push(item);
+        }
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[2], MAFItem::Comment("#maf version=1".to_owned()));
+        // The raw text keeps them.
+        let mut input = maf.as_bytes();
+        let mut raw = String::new();
+        while let Ok((_, item_raw)) = next_maf_item_raw(&mut input) {
+            raw.push_str(&item_raw);
+        }
+        assert_eq!(raw, maf.to_owned() + "\n");
+        // "trackName" isn't a track line.
+        assert!(next_maf_item(&mut "trackName\n".as_bytes()).is_err());
+    }
 }
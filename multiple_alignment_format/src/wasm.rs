@@ -0,0 +1,103 @@
+//! wasm-bindgen API for parsing MAFs in the browser. Build with
+//! `wasm-pack build --target web -- --features wasm`.
+//!
+//! Positions are exposed as JS numbers rather than BigInts; every
+//! realistic sequence is well under 2^53 bases long.
+use wasm_bindgen::prelude::*;
+
+use crate::parser::{next_maf_item_slice, MAFParseError};
+use crate::MAFBlock;
+use crate::MAFBlockAlignedEntry;
+use crate::MAFItem;
+use crate::Strand;
+
+/// An aligned (`s` line) row of a block.
+#[wasm_bindgen]
+pub struct Row {
+    entry: MAFBlockAlignedEntry,
+}
+
+#[wasm_bindgen]
+impl Row {
+    #[wasm_bindgen(getter)]
+    pub fn seq(&self) -> String { self.entry.seq.clone() }
+
+    #[wasm_bindgen(getter)]
+    pub fn start(&self) -> f64 { self.entry.start as f64 }
+
+    #[wasm_bindgen(getter)]
+    pub fn size(&self) -> f64 { self.entry.aligned_length as f64 }
+
+    #[wasm_bindgen(getter)]
+    pub fn strand(&self) -> String {
+        match self.entry.strand {
+            Strand::Positive => "+".to_owned(),
+            Strand::Negative => "-".to_owned(),
+        }
+    }
+
+    #[wasm_bindgen(getter, js_name = srcSize)]
+    pub fn src_size(&self) -> f64 { self.entry.sequence_size as f64 }
+
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> String { String::from_utf8_lossy(&self.entry.alignment).into_owned() }
+}
+
+/// An alignment block.
+#[wasm_bindgen]
+pub struct Block {
+    block: MAFBlock,
+}
+
+#[wasm_bindgen]
+impl Block {
+    /// The aligned rows, in order; the first is the reference.
+    #[wasm_bindgen(getter)]
+    pub fn rows(&self) -> Vec<Row> {
+        self.block.aligned_entries().map(|e| Row { entry: e.clone() }).collect()
+    }
+
+    /// The value of a key in the block's `a` line, if present.
+    pub fn metadata(&self, key: &str) -> Option<String> {
+        self.block.metadata.get(key).cloned()
+    }
+
+    /// The block as MAF text.
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_maf_string(&self) -> String { self.block.to_string() }
+}
+
+fn parse(text: &str) -> Result<Vec<Block>, MAFParseError> {
+    let mut input = text.as_bytes();
+    let mut blocks = vec![];
+    loop {
+        match next_maf_item_slice(&mut input) {
+            Ok(MAFItem::Block(block)) => blocks.push(Block { block }),
+            Ok(MAFItem::Comment(_)) => continue,
+            Err(MAFParseError::EOF) => return Ok(blocks),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Parse every block out of MAF text, skipping comments.
+#[wasm_bindgen(js_name = parseBlocks)]
+pub fn parse_blocks(text: &str) -> Result<Vec<Block>, JsError> {
+    parse(text).map_err(|e| JsError::new(&format!("{:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_for_wasm() {
+        let blocks = parse("##maf version=1\na score=2\ns hg38.chr1 10 4 + 100 AC-GT\ns mm39.chr2 20 5 - 200 ACTGT\n\na\ns hg38.chr1 14 1 + 100 A\n").unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].metadata("score"), Some("2".to_owned()));
+        let rows = blocks[0].rows();
+        assert_eq!((rows[1].seq(), rows[1].start(), rows[1].strand()), ("mm39.chr2".to_owned(), 20.0, "-".to_owned()));
+        assert_eq!(rows[0].text(), "AC-GT");
+        assert_eq!(blocks[1].to_maf_string(), "a\ns hg38.chr1 14 1 + 100 A\n\n");
+    }
+}
@@ -0,0 +1,229 @@
+//! The residues alignments are made of, so that consensus and identity
+//! work the same way for nucleotide and protein alignments.
+pub use multiple_alignment_format::is_gap;
+
+pub trait Alphabet: Sync {
+    /// The residues, uppercase, in the order they're counted in.
+    fn residues(&self) -> &'static [u8];
+
+    /// The residue written when none can be called (N or X).
+    fn unknown(&self) -> u8;
+
+    /// Index of a residue (in either case) in `residues`, or None if it
+    /// isn't one.
+    fn index(&self, residue: u8) -> Option<usize> {
+        let residue = residue.to_ascii_uppercase();
+        self.residues().iter().position(|r| *r == residue)
+    }
+
+    /// Whether a character is a residue or the unknown residue, rather
+    /// than a gap or anything else.
+    fn is_aligned(&self, c: u8) -> bool {
+        self.index(c).is_some() || c.to_ascii_uppercase() == self.unknown()
+    }
+
+    /// The residue all of some calls agree on, or the unknown residue
+    /// if they disagree (or there are none).
+    fn unanimous(&self, chars: &[u8]) -> u8 {
+        ResidueCounts::count(self, chars.iter()).unanimous(self)
+    }
+
+    /// The ambiguity code standing for every residue among some calls,
+    /// or None if the alphabet has no ambiguity codes.
+    fn ambiguity_code(&self, _chars: &[u8]) -> Option<u8> {
+        None
+    }
+}
+
+/// IUPAC nucleotide codes, indexed by the set of bases they stand for
+/// (A = 1, C = 2, G = 4, T = 8).
+const IUPAC_CODES: &[u8; 16] = b"NACMGRSVTWYHKDBN";
+
+/// The set of bases an IUPAC code (in either case) stands for, or None
+/// if it isn't one.
+pub fn iupac_bases(code: u8) -> Option<u8> {
+    let code = code.to_ascii_uppercase();
+    IUPAC_CODES[1..]
+        .iter()
+        .position(|c| *c == code)
+        .map(|i| i as u8 + 1)
+}
+
+/// Counts of each IUPAC code among some calls, so that ambiguous calls
+/// aren't just thrown away.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CodeCounts([usize; 16]);
+
+impl CodeCounts {
+    pub fn count<'a>(chars: impl Iterator<Item = &'a u8>) -> Self {
+        let mut counts = [0; 16];
+        for bases in chars.filter_map(|c| iupac_bases(*c)) {
+            counts[bases as usize] += 1;
+        }
+        CodeCounts(counts)
+    }
+
+    fn present(&self) -> impl Iterator<Item = u8> + '_ {
+        (1..16u8).filter(move |bases| self.0[*bases as usize] > 0)
+    }
+
+    /// The code for the bases every call is compatible with, so that
+    /// A and R agree on A and R and S agree on G, while N agrees with
+    /// anything. N if there are no calls or they're incompatible.
+    pub fn unanimous(&self) -> u8 {
+        let bases = self.present().fold(15, |acc, bases| acc & bases);
+        IUPAC_CODES[bases as usize]
+    }
+
+    /// The code for every base called, so that A and G make R. Ns are
+    /// ignored unless there's nothing else.
+    pub fn mixture(&self) -> u8 {
+        let bases = self
+            .present()
+            .filter(|bases| *bases != 15)
+            .fold(0, |acc, bases| acc | bases);
+        IUPAC_CODES[bases as usize]
+    }
+}
+
+pub struct Nucleotides;
+
+impl Alphabet for Nucleotides {
+    fn residues(&self) -> &'static [u8] {
+        b"ACGT"
+    }
+
+    fn unknown(&self) -> u8 {
+        b'N'
+    }
+
+    fn unanimous(&self, chars: &[u8]) -> u8 {
+        CodeCounts::count(chars.iter()).unanimous()
+    }
+
+    fn ambiguity_code(&self, chars: &[u8]) -> Option<u8> {
+        Some(CodeCounts::count(chars.iter()).mixture())
+    }
+}
+
+pub struct AminoAcids;
+
+impl Alphabet for AminoAcids {
+    fn residues(&self) -> &'static [u8] {
+        b"ACDEFGHIKLMNPQRSTVWY"
+    }
+
+    fn unknown(&self) -> u8 {
+        b'X'
+    }
+}
+
+/// The alphabet of protein alignments, or else of nucleotide ones.
+pub fn alphabet(protein: bool) -> &'static dyn Alphabet {
+    if protein {
+        &AminoAcids
+    } else {
+        &Nucleotides
+    }
+}
+
+/// Nucleotides, for options that default to them.
+impl Default for &'static dyn Alphabet {
+    fn default() -> Self {
+        &Nucleotides
+    }
+}
+
+/// Counts of each residue of an alphabet, in `residues` order.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ResidueCounts(pub Vec<usize>);
+
+impl ResidueCounts {
+    /// Count the residues among some characters, ignoring case and
+    /// skipping gaps and anything not in the alphabet.
+    pub fn count<'a, A: Alphabet + ?Sized>(
+        alphabet: &A,
+        chars: impl Iterator<Item = &'a u8>,
+    ) -> Self {
+        let mut counts = vec![0; alphabet.residues().len()];
+        for c in chars {
+            if let Some(i) = alphabet.index(*c) {
+                counts[i] += 1;
+            }
+        }
+        ResidueCounts(counts)
+    }
+
+    /// The only residue counted, or the unknown residue if there are
+    /// none or several.
+    pub fn unanimous<A: Alphabet + ?Sized>(&self, alphabet: &A) -> u8 {
+        let mut present = self.0.iter().enumerate().filter(|(_, n)| **n > 0);
+        match (present.next(), present.next()) {
+            (Some((i, _)), None) => alphabet.residues()[i],
+            _ => alphabet.unknown(),
+        }
+    }
+
+    /// Narrow down `possible` to the possible residues with the highest
+    /// count.
+    fn keep_max(&self, possible: &mut [bool]) {
+        let max = self
+            .0
+            .iter()
+            .zip(possible.iter())
+            .filter(|(_, p)| **p)
+            .map(|(n, _)| *n)
+            .max()
+            .unwrap_or(0);
+        for (n, p) in self.0.iter().zip(possible.iter_mut()) {
+            *p = *p && *n == max;
+        }
+    }
+
+    /// The most common residue, breaking ties with the counts in
+    /// `tie_breaker`, or the unknown residue if it's still tied.
+    pub fn consensus<A: Alphabet + ?Sized>(&self, tie_breaker: &ResidueCounts, alphabet: &A) -> u8 {
+        let mut possible = vec![true; self.0.len()];
+        self.keep_max(&mut possible);
+        tie_breaker.keep_max(&mut possible);
+        let mut remaining = possible.iter().enumerate().filter(|(_, p)| **p);
+        match (remaining.next(), remaining.next()) {
+            (Some((i, _)), None) => alphabet.residues()[i],
+            _ => alphabet.unknown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amino_acid_consensus() {
+        let column = b"MmK-X.LL";
+        let counts = ResidueCounts::count(&AminoAcids, column.iter());
+        assert_eq!(counts.0.iter().sum::<usize>(), 5);
+        let tie_breaker = ResidueCounts::count(&AminoAcids, b"M".iter());
+        assert_eq!(counts.consensus(&tie_breaker, &AminoAcids), b'M');
+        assert_eq!(counts.unanimous(&AminoAcids), b'X');
+        assert!(AminoAcids.is_aligned(b'x'));
+        assert!(!AminoAcids.is_aligned(b'.'));
+        assert!(is_gap(b'.'));
+    }
+
+    #[test]
+    fn test_iupac() {
+        assert_eq!(iupac_bases(b'r'), Some(5));
+        assert_eq!(iupac_bases(b'-'), None);
+        assert_eq!(Nucleotides.unanimous(b"AaNR"), b'A');
+        assert_eq!(Nucleotides.unanimous(b"RS"), b'G');
+        assert_eq!(Nucleotides.unanimous(b"RR"), b'R');
+        assert_eq!(Nucleotides.unanimous(b"AC"), b'N');
+        assert_eq!(Nucleotides.unanimous(b"--"), b'N');
+        assert_eq!(Nucleotides.ambiguity_code(b"AGN"), Some(b'R'));
+        assert_eq!(Nucleotides.ambiguity_code(b"CTY"), Some(b'Y'));
+        assert_eq!(Nucleotides.ambiguity_code(b"AKC"), Some(b'N'));
+        assert_eq!(Nucleotides.ambiguity_code(b"ACG"), Some(b'V'));
+        assert_eq!(AminoAcids.ambiguity_code(b"MK"), None);
+    }
+}
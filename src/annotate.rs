@@ -0,0 +1,399 @@
+use maf_stream::{
+    block_id, block_identity, chrom_part, for_each_block, MAFItems, MAFOutput, BLOCK_ID_KEY,
+};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem};
+use std::collections::{BTreeMap, HashSet};
+use std::io::BufRead;
+
+/// A value computed from the block itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Computed {
+    /// Fraction of aligned base pairs between the reference and the
+    /// other rows that are identical.
+    Identity,
+    /// Number of distinct species in the block.
+    SpeciesCount,
+    /// The block's 1-based number in the input, as a stable ID.
+    Id,
+}
+
+impl Computed {
+    pub fn from_name(name: &str) -> Computed {
+        match name {
+            "identity" => Computed::Identity,
+            "species_count" => Computed::SpeciesCount,
+            "id" => Computed::Id,
+            _ => panic!("Unknown computed value {}", name),
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match self {
+            Computed::Identity => "identity",
+            Computed::SpeciesCount => "species_count",
+            Computed::Id => BLOCK_ID_KEY,
+        }
+    }
+
+    fn value(&self, block: &MAFBlock, number: usize) -> Option<String> {
+        match self {
+            Computed::Identity => {
+                let identity = block_identity(block, block.aligned_entries().next()?)?;
+                Some(format!("{:.4}", identity))
+            }
+            Computed::SpeciesCount => {
+                let species: HashSet<_> = block
+                    .aligned_entries()
+                    .map(|e| e.seq.split('.').next().unwrap())
+                    .collect();
+                Some(species.len().to_string())
+            }
+            Computed::Id => Some(number.to_string()),
+        }
+    }
+}
+
+/// Values keyed by reference interval, from a BED file whose name
+/// column holds the value.
+pub struct IntervalLookup {
+    key: String,
+    /// Chromosome -> (start, end, value), sorted by start.
+    intervals: BTreeMap<String, Vec<(u64, u64, String)>>,
+    /// The length of the longest interval, which bounds how far before
+    /// a position an interval overlapping it can start.
+    max_length: u64,
+}
+
+impl IntervalLookup {
+    fn new(key: &str, mut intervals: BTreeMap<String, Vec<(u64, u64, String)>>) -> Self {
+        for chrom_intervals in intervals.values_mut() {
+            chrom_intervals.sort();
+        }
+        let max_length = intervals
+            .values()
+            .flatten()
+            .map(|(start, end, _)| end.saturating_sub(*start))
+            .max()
+            .unwrap_or(0);
+        IntervalLookup {
+            key: key.to_string(),
+            intervals,
+            max_length,
+        }
+    }
+
+    pub fn from_bed(key: &str, bed: impl BufRead) -> IntervalLookup {
+        let mut intervals: BTreeMap<String, Vec<(u64, u64, String)>> = BTreeMap::new();
+        for line in bed.lines() {
+            let line = line.expect("Can't read line");
+            let fields: Vec<_> = line.split('\t').collect();
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") {
+                continue;
+            }
+            if fields.len() < 4 {
+                panic!("Lookup BED needs a name column holding the value");
+            }
+            let start = fields[1].parse().expect("Can't parse start position");
+            let end = fields[2].parse().expect("Can't parse end position");
+            if fields[3].contains(char::is_whitespace) || fields[3].contains('=') {
+                panic!("Value {:?} can't go in a block header", fields[3]);
+            }
+            intervals.entry(fields[0].to_string()).or_default().push((
+                start,
+                end,
+                fields[3].to_string(),
+            ));
+        }
+        IntervalLookup::new(key, intervals)
+    }
+
+    /// Lookup values from the genes in a GFF3 or GTF file, taking each
+    /// "gene" line's Name (or gene_name, gene_id, or ID) as its value.
+    pub fn from_gff(key: &str, gff: impl BufRead) -> IntervalLookup {
+        let mut intervals: BTreeMap<String, Vec<(u64, u64, String)>> = BTreeMap::new();
+        for line in gff.lines() {
+            let line = line.expect("Can't read line");
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split('\t').collect();
+            if fields.len() < 9 {
+                panic!("GFF line has too few fields: {}", line);
+            }
+            if fields[2] != "gene" {
+                continue;
+            }
+            let start: u64 = fields[3].parse().expect("Can't parse start position");
+            let end = fields[4].parse().expect("Can't parse end position");
+            let name = gene_name(fields[8]).expect("Gene line has no name or ID");
+            intervals
+                .entry(fields[0].to_string())
+                .or_default()
+                .push((start - 1, end, name));
+        }
+        IntervalLookup::new(key, intervals)
+    }
+
+    /// The forward-strand reference interval of a block.
+    fn ref_interval(block: &MAFBlock) -> Option<(String, u64, u64)> {
+        let ref_entry = block.aligned_entries().next()?;
+        let (start, end) = ref_entry.forward_interval();
+        Some((chrom_part(&ref_entry.seq), start, end))
+    }
+
+    /// The values of every interval overlapping a reference interval.
+    fn values(&self, chrom: &str, start: u64, end: u64) -> Vec<&str> {
+        let intervals = match self.intervals.get(chrom) {
+            Some(intervals) => intervals,
+            None => return vec![],
+        };
+        let first = intervals.partition_point(|(s, _, _)| s + self.max_length <= start);
+        let last = intervals.partition_point(|(s, _, _)| *s < end);
+        intervals[first..last.max(first)]
+            .iter()
+            .filter(|(_, e, _)| *e > start)
+            .map(|(_, _, value)| value.as_str())
+            .collect()
+    }
+
+    /// The values of every interval overlapping the block's reference
+    /// row, comma-separated.
+    fn value(&self, block: &MAFBlock) -> Option<String> {
+        let (chrom, start, end) = IntervalLookup::ref_interval(block)?;
+        let values = self.values(&chrom, start, end);
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(","))
+        }
+    }
+}
+
+/// Get a gene's name from its GFF3 Name/ID or GTF gene_name/gene_id
+/// attributes, preferring names to IDs.
+fn gene_name(attributes: &str) -> Option<String> {
+    let mut name = None;
+    let mut id = None;
+    for attribute in attributes.split(';').map(|a| a.trim()) {
+        if let Some(value) = attribute
+            .strip_prefix("Name=")
+            .or_else(|| attribute.strip_prefix("gene_name "))
+        {
+            name = Some(value.trim_matches('"').to_string());
+        } else if let Some(value) = attribute
+            .strip_prefix("ID=")
+            .or_else(|| attribute.strip_prefix("gene_id "))
+        {
+            id = Some(value.trim_matches('"').to_string());
+        }
+    }
+    name.or(id)
+}
+
+/// Edits to make to each block's "a" line metadata. Keys are removed
+/// first, then computed and looked-up values are added, then fixed
+/// values, so each step can overwrite the last.
+#[derive(Default)]
+pub struct Annotator {
+    pub remove: Vec<String>,
+    pub compute: Vec<Computed>,
+    pub lookup: Option<IntervalLookup>,
+    pub set: Vec<(String, String)>,
+}
+
+impl Annotator {
+    /// Edit a block, given its 1-based number in the input.
+    fn annotate_block(&self, block: &mut MAFBlock, number: usize) {
+        for key in self.remove.iter() {
+            block.metadata.remove(key);
+        }
+        for computed in self.compute.iter() {
+            if let Some(value) = computed.value(block, number) {
+                block.metadata.insert(computed.key().to_string(), value);
+            }
+        }
+        if let Some(lookup) = self.lookup.as_ref() {
+            if let Some(value) = lookup.value(block) {
+                block.metadata.insert(lookup.key.clone(), value);
+            }
+        }
+        for (key, value) in self.set.iter() {
+            block.metadata.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+/// Parse a "key=value" pair to set in block headers.
+pub fn parse_key_value(pair: &str) -> (String, String) {
+    let mut split = pair.splitn(2, '=');
+    match (split.next(), split.next()) {
+        (Some(key), Some(value)) if !key.is_empty() && !value.contains(char::is_whitespace) => {
+            (key.to_string(), value.to_string())
+        }
+        _ => panic!("Expected key=value, got {:?}", pair),
+    }
+}
+
+pub fn annotate(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    annotator: &Annotator,
+) -> Result<(), MAFParseError> {
+    let mut num_blocks = 0;
+    for_each_block(input, output, |mut block, output| {
+        num_blocks += 1;
+        annotator.annotate_block(&mut block, num_blocks);
+        output.write_block(&block);
+    })
+}
+
+/// For each block, write the genes overlapping its reference row: either
+/// into its header under the lookup's key, or, with `tsv`, as a TSV row
+/// of the block's ID (see `block_id`) and forward-strand reference
+/// interval for every overlapping gene. Returns the number of blocks overlapping a gene.
+pub fn annotate_genes(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    genes: &IntervalLookup,
+    tsv: bool,
+) -> Result<usize, MAFParseError> {
+    let mut num_overlapping = 0;
+    if tsv {
+        writeln!(output, "#id\tchrom\tstart\tend\tgene").ok();
+        let mut num_blocks = 0;
+        let mut items = MAFItems::new(input);
+        for item in items.by_ref() {
+            let block = match item {
+                MAFItem::Block(block) => block,
+                MAFItem::Comment(_) => continue,
+            };
+            num_blocks += 1;
+            let (chrom, start, end) = match IntervalLookup::ref_interval(&block) {
+                Some(interval) => interval,
+                None => continue,
+            };
+            let values = genes.values(&chrom, start, end);
+            if !values.is_empty() {
+                num_overlapping += 1;
+            }
+            let id = block_id(&block, num_blocks);
+            for gene in values {
+                writeln!(output, "{}\t{}\t{}\t{}\t{}", id, chrom, start, end, gene).ok();
+            }
+        }
+        items.finish()?;
+    } else {
+        for_each_block(input, output, |mut block, output| {
+            block.metadata.remove(&genes.key);
+            if let Some(value) = genes.value(&block) {
+                block.metadata.insert(genes.key.clone(), value);
+                num_overlapping += 1;
+            }
+            output.write_block(&block);
+        })?;
+    }
+    Ok(num_overlapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate() {
+        let maf = "a score=10 pass=2
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGA
+s mm39.chr3 10 2 + 200 -cg-
+
+a score=5
+s hg38.chr1 80 2 - 100 AC
+";
+        let bed = "chr1\t0\t5\tpromoter\nchr1\t12\t13\tenhancer\nchr1\t13\t20\texon1\n";
+        let annotator = Annotator {
+            remove: vec!["score".to_string()],
+            compute: vec![Computed::Identity, Computed::SpeciesCount, Computed::Id],
+            lookup: Some(IntervalLookup::from_bed("feature", bed.as_bytes())),
+            set: vec![parse_key_value("source=multiz")],
+        };
+        let mut output = vec![];
+        annotate(&mut maf.as_bytes(), &mut output, &annotator).unwrap();
+        // The second block's reference row is at 18-20 on the forward
+        // strand.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a feature=enhancer,exon1 id=1 identity=0.8333 pass=2 source=multiz species_count=2
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGA
+s mm39.chr3 10 2 + 200 -cg-
+
+a feature=exon1 id=2 source=multiz species_count=1
+s hg38.chr1 80 2 - 100 AC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_interval_values() {
+        // A long interval starting well before shorter ones still
+        // overlaps positions after them.
+        let bed = "chr1\t0\t1000\tlong\nchr1\t10\t20\ta\nchr1\t30\t40\tb\nchr1\t500\t510\tc\n";
+        let lookup = IntervalLookup::from_bed("feature", bed.as_bytes());
+        assert_eq!(lookup.values("chr1", 35, 36), vec!["long", "b"]);
+        assert_eq!(lookup.values("chr1", 20, 30), vec!["long"]);
+        assert_eq!(lookup.values("chr1", 1000, 2000), Vec::<&str>::new());
+        assert_eq!(lookup.values("chr2", 0, 10), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_annotate_genes() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGA
+
+a
+s hg38.chr1 80 2 - 100 AC
+
+a
+s hg38.chr2 0 2 + 100 AC
+";
+        let gff = "##gff-version 3
+chr1\tsrc\tgene\t1\t12\t.\t+\t.\tID=gene0;Name=ABC1
+chr1\tsrc\tmRNA\t1\t12\t.\t+\t.\tID=tx0;Parent=gene0
+chr1\tsrc\tgene\t14\t20\t.\t-\t.\tgene_id \"XYZ2\"; gene_name \"XYZ2\";
+chr1\tsrc\tgene\t19\t30\t.\t-\t.\tID=gene2
+";
+        let genes = IntervalLookup::from_gff("genes", gff.as_bytes());
+        let mut output = vec![];
+        let num_overlapping =
+            annotate_genes(&mut maf.as_bytes(), &mut output, &genes, false).unwrap();
+        assert_eq!(num_overlapping, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a genes=ABC1,XYZ2
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGA
+
+a genes=XYZ2,gene2
+s hg38.chr1 80 2 - 100 AC
+
+a
+s hg38.chr2 0 2 + 100 AC
+
+"
+        );
+        let mut output = vec![];
+        annotate_genes(&mut maf.as_bytes(), &mut output, &genes, true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#id\tchrom\tstart\tend\tgene
+1\tchr1\t10\t14\tABC1
+1\tchr1\t10\t14\tXYZ2
+2\tchr1\t18\t20\tXYZ2
+2\tchr1\t18\t20\tgene2
+"
+        );
+    }
+}
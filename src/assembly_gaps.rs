@@ -0,0 +1,172 @@
+use maf_stream::{
+    chrom_part, for_each_block, merge_ranges, overlapping_ranges, range_contains_pos, MAFOutput,
+    Range,
+};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFBlockAlignedEntry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+
+/// A genome's assembly gaps, and the same gaps widened by the flank.
+struct GenomeGaps {
+    gaps: BTreeSet<Range>,
+    flanked: BTreeSet<Range>,
+}
+
+/// How much of a genome's alignment is in or next to its gaps.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GapCounts {
+    pub aligned_bases: u64,
+    pub in_gap_bases: u64,
+    /// Bases within the flank of a gap, but not in one.
+    pub near_gap_bases: u64,
+    /// Rows whose interval overlaps a gap.
+    pub crossing_rows: u64,
+}
+
+pub struct GapChecker {
+    genomes: HashMap<String, GenomeGaps>,
+    counts: BTreeMap<String, GapCounts>,
+}
+
+impl GapChecker {
+    /// Takes each genome's gaps, and how many bases on either side of
+    /// a gap count as next to it.
+    pub fn new(gaps: HashMap<String, BTreeSet<Range>>, flank: u64) -> Self {
+        let genomes = gaps
+            .into_iter()
+            .map(|(genome, gaps)| {
+                let gaps = merge_ranges(gaps);
+                let flanked = merge_ranges(gaps.iter().map(|r| Range {
+                    seq: r.seq.clone(),
+                    start: r.start.saturating_sub(flank),
+                    end: r.end + flank,
+                }));
+                (genome, GenomeGaps { gaps, flanked })
+            })
+            .collect();
+        GapChecker {
+            genomes,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Count a row's bases in and near gaps, returning whether the row
+    /// crosses a gap.
+    fn add_row(&mut self, entry: &MAFBlockAlignedEntry) -> bool {
+        let genome = entry.seq.split('.').next().unwrap();
+        let genome_gaps = match self.genomes.get(genome) {
+            Some(genome_gaps) => genome_gaps,
+            None => return false,
+        };
+        let chrom = chrom_part(&entry.seq);
+        let counts = self.counts.entry(genome.to_string()).or_default();
+        for pos in entry.column_positions().flatten() {
+            let pos = entry.forward_position(pos);
+            counts.aligned_bases += 1;
+            if range_contains_pos(&genome_gaps.gaps, &chrom, pos) {
+                counts.in_gap_bases += 1;
+            } else if range_contains_pos(&genome_gaps.flanked, &chrom, pos) {
+                counts.near_gap_bases += 1;
+            }
+        }
+        let (start, end) = entry.forward_interval();
+        let row_range = Range {
+            seq: chrom,
+            start,
+            end,
+        };
+        let crosses = overlapping_ranges(&genome_gaps.gaps, &row_range).any(|gap| {
+            gap.seq == row_range.seq && gap.start < row_range.end && gap.end > row_range.start
+        });
+        if crosses {
+            counts.crossing_rows += 1;
+        }
+        crosses
+    }
+}
+
+/// Pass the alignment through, adding an "assembly_gaps" key to the
+/// header of every block that has rows crossing an assembly gap,
+/// listing those rows' sequences. Then write a TSV to `report` of how
+/// many of each genome's aligned bases are in gaps or near them, and
+/// how many of its rows cross them. Genomes without gaps given are left
+/// out.
+pub fn assembly_gaps(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    checker: &mut GapChecker,
+    report: &mut dyn Write,
+) -> Result<(), MAFParseError> {
+    for_each_block(input, output, |mut block, output| {
+        let mut crossing: Vec<&str> = vec![];
+        for entry in block.aligned_entries() {
+            if checker.add_row(entry) && !crossing.contains(&entry.seq.as_str()) {
+                crossing.push(&entry.seq);
+            }
+        }
+        if !crossing.is_empty() {
+            let value = crossing.join(",");
+            block.metadata.insert("assembly_gaps".to_string(), value);
+        }
+        output.write_block(&block);
+    })?;
+    writeln!(report, "#genome\taligned\tin_gap\tnear_gap\tcrossing_rows").ok();
+    for (genome, counts) in checker.counts.iter() {
+        writeln!(
+            report,
+            "{}\t{}\t{}\t{}\t{}",
+            genome,
+            counts.aligned_bases,
+            counts.in_gap_bases,
+            counts.near_gap_bases,
+            counts.crossing_rows
+        )
+        .ok();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::parse_bed;
+
+    #[test]
+    fn test_assembly_gaps() {
+        let maf = "a score=1
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 5 4 - 20 ACNN
+
+a
+s hg38.chr1 14 2 + 100 AC
+s mm39.chr2 0 2 + 20 AC
+";
+        let mut gaps = HashMap::new();
+        // mm39's first row covers forward positions 11-14.
+        let bed = "chr2\t13\t14\nchr2\t3\t4\n";
+        gaps.insert("mm39".to_string(), parse_bed(bed.as_bytes()));
+        let mut checker = GapChecker::new(gaps, 1);
+        let mut output = vec![];
+        let mut report = vec![];
+        assembly_gaps(&mut maf.as_bytes(), &mut output, &mut checker, &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a assembly_gaps=mm39.chr2 score=1
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 5 4 - 20 ACNN
+
+a
+s hg38.chr1 14 2 + 100 AC
+s mm39.chr2 0 2 + 20 AC
+
+"
+        );
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "#genome\taligned\tin_gap\tnear_gap\tcrossing_rows
+mm39\t6\t1\t2\t1
+"
+        );
+    }
+}
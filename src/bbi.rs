@@ -0,0 +1,1025 @@
+//! Writing bigWig and bigBed files, the indexed binary track formats
+//! genome browsers read, converted from the wiggle or BED text other
+//! subcommands write. No zoom levels are written, so browsers always
+//! read the full-resolution data.
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use tempfile::tempfile;
+
+const BIGWIG_MAGIC: u32 = 0x888F_FC26;
+const BIGBED_MAGIC: u32 = 0x8789_F2EB;
+const CHROM_TREE_MAGIC: u32 = 0x78CA_8C91;
+const R_TREE_MAGIC: u32 = 0x2468_ACE0;
+/// Children of each node of the index.
+const R_TREE_BLOCK_SIZE: usize = 256;
+/// Most children of each node of the chromosome tree.
+const CHROM_TREE_BLOCK_SIZE: usize = 256;
+/// Values in each compressed section of a bigWig.
+const BIGWIG_SECTION_ITEMS: usize = 1024;
+/// Records in each compressed block of a bigBed.
+const BIGBED_BLOCK_ITEMS: usize = 256;
+
+fn put_u16(buf: &mut Vec<u8>, n: u16) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, n: u64) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+/// The chromosomes of the chrom sizes, with the ids data refers to them
+/// by, checking that data stays within them.
+struct Chroms {
+    sizes: HashMap<String, u32>,
+    ids: HashMap<String, u32>,
+}
+
+impl Chroms {
+    fn new(sizes: HashMap<String, u64>) -> Self {
+        Chroms {
+            sizes: sizes
+                .into_iter()
+                .map(|(chrom, size)| {
+                    let size = u32::try_from(size)
+                        .unwrap_or_else(|_| panic!("{} is too long for a bigWig or bigBed", chrom));
+                    (chrom, size)
+                })
+                .collect(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// The id of a chromosome, giving it the next one if it hasn't
+    /// been seen yet.
+    fn id(&mut self, chrom: &str, end: u32) -> u32 {
+        let size = *self
+            .sizes
+            .get(chrom)
+            .unwrap_or_else(|| panic!("{} isn't in the chrom sizes", chrom));
+        if end > size {
+            panic!(
+                "{}:{} is past the end of {} ({} bases)",
+                chrom, end, chrom, size
+            );
+        }
+        let next_id = self.ids.len() as u32;
+        *self.ids.entry(chrom.to_string()).or_insert(next_id)
+    }
+
+    /// The name, id, and size of every chromosome there was data on,
+    /// sorted by name.
+    fn finish(self) -> Vec<(String, u32, u32)> {
+        let sizes = self.sizes;
+        let mut chroms: Vec<(String, u32, u32)> = self
+            .ids
+            .into_iter()
+            .map(|(name, id)| {
+                let size = sizes[&name];
+                (name, id, size)
+            })
+            .collect();
+        chroms.sort();
+        chroms
+    }
+}
+
+/// Bounds of data in the index: start chromosome id and base, and end
+/// chromosome id and base.
+type Bounds = (u32, u32, u32, u32);
+
+fn merge_bounds(a: Bounds, b: Bounds) -> Bounds {
+    let (start_chrom, start) = (a.0, a.1).min((b.0, b.1));
+    let (end_chrom, end) = (a.2, a.3).max((b.2, b.3));
+    (start_chrom, start, end_chrom, end)
+}
+
+/// Compressed sections of data, spooled to a temporary file until the
+/// parts of the file before them can be written.
+struct Sections {
+    file: File,
+    /// Bounds, offset within the file, and size of each section.
+    index: Vec<(Bounds, u64, u64)>,
+    max_uncompressed: usize,
+}
+
+impl Sections {
+    fn new() -> Self {
+        Sections {
+            file: tempfile().expect("Couldn't create temporary file"),
+            index: vec![],
+            max_uncompressed: 0,
+        }
+    }
+
+    fn add(&mut self, bounds: Bounds, data: &[u8]) {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let offset = self.file.stream_position().unwrap();
+        self.file
+            .write_all(&compressed)
+            .expect("Couldn't write temporary file");
+        self.index.push((bounds, offset, compressed.len() as u64));
+        self.max_uncompressed = self.max_uncompressed.max(data.len());
+    }
+}
+
+/// The B+ tree from chromosome names (sorted) to ids and sizes, to be
+/// written at `offset`. As in UCSC's tools, every node has room for the
+/// same number of items, padded out with zeros, and each item of a node
+/// above the leaves is the first name under a child and its offset.
+fn chrom_tree(chroms: &[(String, u32, u32)], offset: u64) -> Vec<u8> {
+    let key_size = chroms
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(1);
+    let block_size = chroms.len().clamp(1, CHROM_TREE_BLOCK_SIZE);
+    // Keys are followed by an id and size in leaves, and by an offset
+    // in the nodes above them, 8 bytes either way.
+    let item_size = key_size + 8;
+    let node_size = (4 + block_size * item_size) as u64;
+    // levels[0] is the number of leaves, and each level above the
+    // number of nodes pointing to the one below, up to the root.
+    let mut levels = vec![chroms.len().div_ceil(block_size).max(1)];
+    while *levels.last().unwrap() > 1 {
+        levels.push(levels.last().unwrap().div_ceil(block_size));
+    }
+    let mut buf = vec![];
+    put_u32(&mut buf, CHROM_TREE_MAGIC);
+    put_u32(&mut buf, block_size as u32);
+    put_u32(&mut buf, key_size as u32);
+    put_u32(&mut buf, 8);
+    put_u64(&mut buf, chroms.len() as u64);
+    put_u64(&mut buf, 0);
+    let put_key = |buf: &mut Vec<u8>, name: &str| {
+        buf.extend_from_slice(name.as_bytes());
+        buf.resize(buf.len() + key_size - name.len(), 0);
+    };
+    // Nodes are written from the root down.
+    let mut level_offset = offset + 32;
+    for level in (0..levels.len()).rev() {
+        let below_offset = level_offset + levels[level] as u64 * node_size;
+        let num_children = if level == 0 {
+            chroms.len()
+        } else {
+            levels[level - 1]
+        };
+        for node in 0..levels[level] {
+            let first = node * block_size;
+            let count = num_children.saturating_sub(first).min(block_size);
+            buf.push((level == 0) as u8);
+            buf.push(0);
+            put_u16(&mut buf, count as u16);
+            for child in first..first + count {
+                if level == 0 {
+                    let (name, id, size) = &chroms[child];
+                    put_key(&mut buf, name);
+                    put_u32(&mut buf, *id);
+                    put_u32(&mut buf, *size);
+                } else {
+                    put_key(&mut buf, &chroms[child * block_size.pow(level as u32)].0);
+                    put_u64(&mut buf, below_offset + child as u64 * node_size);
+                }
+            }
+            buf.resize(buf.len() + (block_size - count) * item_size, 0);
+        }
+        level_offset = below_offset;
+    }
+    buf
+}
+
+/// The R tree indexing the sections, to be written at `offset`, just
+/// after the sections end.
+fn r_tree(
+    sections: &[(Bounds, u64, u64)],
+    data_offset: u64,
+    offset: u64,
+    items_per_slot: u32,
+) -> Vec<u8> {
+    // levels[0] has the bounds of the leaf nodes, and each level above
+    // has those of the nodes pointing to the one below, up to the root.
+    let chunk_bounds = |bounds: Vec<Bounds>| -> Vec<Bounds> {
+        bounds
+            .chunks(R_TREE_BLOCK_SIZE)
+            .map(|chunk| chunk.iter().copied().reduce(merge_bounds).unwrap())
+            .collect()
+    };
+    let mut levels = vec![chunk_bounds(sections.iter().map(|s| s.0).collect())];
+    if levels[0].is_empty() {
+        levels[0].push((0, 0, 0, 0));
+    }
+    while levels.last().unwrap().len() > 1 {
+        let above = chunk_bounds(levels.last().unwrap().clone());
+        levels.push(above);
+    }
+    let num_children = |level: usize, node: usize| {
+        let children = if level == 0 {
+            sections.len()
+        } else {
+            levels[level - 1].len()
+        };
+        children
+            .saturating_sub(node * R_TREE_BLOCK_SIZE)
+            .min(R_TREE_BLOCK_SIZE)
+    };
+    // Nodes are written from the root down.
+    let mut node_offsets = vec![vec![]; levels.len()];
+    let mut node_offset = offset + 48;
+    for level in (0..levels.len()).rev() {
+        let item_size = if level == 0 { 32 } else { 24 };
+        for node in 0..levels[level].len() {
+            node_offsets[level].push(node_offset);
+            node_offset += 4 + num_children(level, node) as u64 * item_size;
+        }
+    }
+    let root = levels.last().unwrap()[0];
+    let mut buf = vec![];
+    put_u32(&mut buf, R_TREE_MAGIC);
+    put_u32(&mut buf, R_TREE_BLOCK_SIZE as u32);
+    put_u64(&mut buf, sections.len() as u64);
+    put_u32(&mut buf, root.0);
+    put_u32(&mut buf, root.1);
+    put_u32(&mut buf, root.2);
+    put_u32(&mut buf, root.3);
+    put_u64(&mut buf, offset);
+    put_u32(&mut buf, items_per_slot);
+    put_u32(&mut buf, 0);
+    for level in (0..levels.len()).rev() {
+        for node in 0..levels[level].len() {
+            let count = num_children(level, node);
+            buf.push((level == 0) as u8);
+            buf.push(0);
+            put_u16(&mut buf, count as u16);
+            let first = node * R_TREE_BLOCK_SIZE;
+            for child in first..first + count {
+                let (bounds, child_offset, size) = if level == 0 {
+                    let (bounds, section_offset, size) = sections[child];
+                    (bounds, data_offset + section_offset, Some(size))
+                } else {
+                    (
+                        levels[level - 1][child],
+                        node_offsets[level - 1][child],
+                        None,
+                    )
+                };
+                put_u32(&mut buf, bounds.0);
+                put_u32(&mut buf, bounds.1);
+                put_u32(&mut buf, bounds.2);
+                put_u32(&mut buf, bounds.3);
+                put_u64(&mut buf, child_offset);
+                if let Some(size) = size {
+                    put_u64(&mut buf, size);
+                }
+            }
+        }
+    }
+    buf
+}
+
+/// The whole-file summary of a bigWig's values.
+#[derive(Default)]
+struct Summary {
+    bases_covered: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Summary {
+    fn add(&mut self, bases: u32, value: f32) {
+        let value = value as f64;
+        if self.bases_covered == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.bases_covered += bases as u64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value * bases as f64;
+        self.sum_squares += value * value * bases as f64;
+    }
+}
+
+/// Everything but the data of a bigWig or bigBed.
+struct BbiParts<'a> {
+    magic: u32,
+    /// Number of fields, and number of those that are standard BED
+    /// fields (both 0 for bigWig).
+    field_counts: (u16, u16),
+    auto_sql: Option<String>,
+    summary: Option<Summary>,
+    chroms: Vec<(String, u32, u32)>,
+    /// Number of bigWig sections or bigBed records.
+    data_count: u64,
+    items_per_slot: u32,
+    sections: &'a mut Sections,
+}
+
+/// Write a whole file: the header, autoSql, summary, chromosome tree,
+/// data, and index, in that order.
+fn write_bbi(output: &mut dyn Write, parts: BbiParts) {
+    let auto_sql = parts.auto_sql.map(|sql| {
+        let mut bytes = sql.into_bytes();
+        bytes.push(0);
+        bytes
+    });
+    let auto_sql_offset = 64;
+    let summary_offset = auto_sql_offset + auto_sql.as_ref().map_or(0, |sql| sql.len() as u64);
+    let chrom_tree_offset = summary_offset + parts.summary.as_ref().map_or(0, |_| 40);
+    let chrom_tree = chrom_tree(&parts.chroms, chrom_tree_offset);
+    let data_offset = chrom_tree_offset + chrom_tree.len() as u64;
+    let data_size = parts.sections.file.stream_position().unwrap();
+    let index_offset = data_offset + 8 + data_size;
+    let index = r_tree(
+        &parts.sections.index,
+        data_offset + 8,
+        index_offset,
+        parts.items_per_slot,
+    );
+
+    let mut header = vec![];
+    put_u32(&mut header, parts.magic);
+    put_u16(&mut header, 4);
+    put_u16(&mut header, 0);
+    put_u64(&mut header, chrom_tree_offset);
+    put_u64(&mut header, data_offset);
+    put_u64(&mut header, index_offset);
+    put_u16(&mut header, parts.field_counts.0);
+    put_u16(&mut header, parts.field_counts.1);
+    put_u64(
+        &mut header,
+        if auto_sql.is_some() {
+            auto_sql_offset
+        } else {
+            0
+        },
+    );
+    put_u64(
+        &mut header,
+        if parts.summary.is_some() {
+            summary_offset
+        } else {
+            0
+        },
+    );
+    put_u32(&mut header, parts.sections.max_uncompressed as u32);
+    put_u64(&mut header, 0);
+    output.write_all(&header).ok();
+    if let Some(auto_sql) = auto_sql {
+        output.write_all(&auto_sql).ok();
+    }
+    if let Some(summary) = parts.summary {
+        let mut buf = vec![];
+        put_u64(&mut buf, summary.bases_covered);
+        for value in [summary.min, summary.max, summary.sum, summary.sum_squares] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        output.write_all(&buf).ok();
+    }
+    output.write_all(&chrom_tree).ok();
+    output.write_all(&parts.data_count.to_le_bytes()).ok();
+    let file = &mut parts.sections.file;
+    file.seek(SeekFrom::Start(0)).unwrap();
+    io::copy(file, output).ok();
+    output.write_all(&index).ok();
+}
+
+/// Calls `f` with every complete line written to `pending`, keeping
+/// what's left.
+fn take_lines(pending: &mut Vec<u8>, buf: &[u8], mut f: impl FnMut(&str)) {
+    pending.extend_from_slice(buf);
+    let end = match pending.iter().rposition(|c| *c == b'\n') {
+        Some(end) => end,
+        None => return,
+    };
+    for line in pending[..end].split(|c| *c == b'\n') {
+        f(std::str::from_utf8(line)
+            .expect("Track isn't UTF-8")
+            .trim_end());
+    }
+    pending.drain(..=end);
+}
+
+/// The kind of wiggle lines being read.
+enum WigMode {
+    BedGraph,
+    /// Chromosome, next start, step, and span.
+    Fixed(String, u32, u32, u32),
+    /// Chromosome and span.
+    Variable(String, u32),
+}
+
+/// The bigWig section being built: its type (1 for bedGraph, 2 for
+/// variableStep, 3 for fixedStep), chromosome id, step, span, and
+/// items.
+struct Section {
+    kind: u8,
+    chrom_id: u32,
+    step: u32,
+    span: u32,
+    items: Vec<(u32, u32, f32)>,
+}
+
+/// Converts wiggle (fixedStep, variableStep, or bedGraph) text written
+/// to it into a bigWig, written out by `finish`. Values must come in
+/// order of position within each chromosome, with each chromosome's
+/// values together.
+pub struct BigWigWriter {
+    chroms: Chroms,
+    pending: Vec<u8>,
+    mode: WigMode,
+    section: Option<Section>,
+    /// The chromosome id and end of the last value.
+    last: Option<(u32, u32)>,
+    summary: Summary,
+    sections: Sections,
+}
+
+impl BigWigWriter {
+    pub fn new(chrom_sizes: HashMap<String, u64>) -> Self {
+        BigWigWriter {
+            chroms: Chroms::new(chrom_sizes),
+            pending: vec![],
+            mode: WigMode::BedGraph,
+            section: None,
+            last: None,
+            summary: Summary::default(),
+            sections: Sections::new(),
+        }
+    }
+
+    fn read_line(&mut self, line: &str) {
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            return;
+        }
+        let mut fields = line.split_whitespace();
+        let first = fields.next().unwrap();
+        if first == "fixedStep" || first == "variableStep" {
+            let options: HashMap<&str, &str> =
+                fields.filter_map(|field| field.split_once('=')).collect();
+            let option = |key: &str| {
+                options.get(key).map(|value| {
+                    value
+                        .parse::<u32>()
+                        .unwrap_or_else(|_| panic!("Invalid {} in {}", key, line))
+                })
+            };
+            let chrom = options
+                .get("chrom")
+                .unwrap_or_else(|| panic!("No chrom in {}", line))
+                .to_string();
+            let span = option("span").unwrap_or(1);
+            self.mode = if first == "fixedStep" {
+                let start = option("start").unwrap_or_else(|| panic!("No start in {}", line));
+                WigMode::Fixed(
+                    chrom,
+                    start.saturating_sub(1),
+                    option("step").unwrap_or(1),
+                    span,
+                )
+            } else {
+                WigMode::Variable(chrom, span)
+            };
+            return;
+        }
+        let parse_value = |value: &str| -> f32 {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid value in {}", line))
+        };
+        let parse_pos = |pos: &str| -> u32 {
+            pos.parse()
+                .unwrap_or_else(|_| panic!("Invalid position in {}", line))
+        };
+        match &mut self.mode {
+            WigMode::Fixed(chrom, start, step, span) => {
+                let (chrom, item_start, step, span) = (chrom.clone(), *start, *step, *span);
+                *start += step;
+                self.add(
+                    3,
+                    &chrom,
+                    item_start,
+                    item_start + span,
+                    parse_value(first),
+                    step,
+                    span,
+                );
+            }
+            WigMode::Variable(chrom, span) => {
+                let (chrom, span) = (chrom.clone(), *span);
+                let start = parse_pos(first).saturating_sub(1);
+                let value = parse_value(
+                    fields
+                        .next()
+                        .unwrap_or_else(|| panic!("No value in {}", line)),
+                );
+                self.add(2, &chrom, start, start + span, value, 0, span);
+            }
+            WigMode::BedGraph => {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 4 {
+                    panic!("Invalid bedGraph line {}", line);
+                }
+                let (start, end) = (parse_pos(fields[1]), parse_pos(fields[2]));
+                self.add(1, fields[0], start, end, parse_value(fields[3]), 0, 0);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add(
+        &mut self,
+        kind: u8,
+        chrom: &str,
+        start: u32,
+        end: u32,
+        value: f32,
+        step: u32,
+        span: u32,
+    ) {
+        let chrom_id = self.chroms.id(chrom, end);
+        if let Some((last_chrom_id, last_end)) = self.last {
+            if chrom_id < last_chrom_id || (chrom_id == last_chrom_id && start < last_end) {
+                panic!(
+                    "bigWig values must be in order of position, but {}:{} comes after an earlier position (sort the alignment by reference position first)",
+                    chrom, start
+                );
+            }
+        }
+        self.last = Some((chrom_id, end));
+        self.summary.add(end - start, value);
+        let continues = self.section.as_ref().is_some_and(|section| {
+            section.kind == kind
+                && section.chrom_id == chrom_id
+                && section.step == step
+                && section.span == span
+                && section.items.len() < BIGWIG_SECTION_ITEMS
+                && (kind != 3 || section.items.last().unwrap().0 + step == start)
+        });
+        if !continues {
+            self.end_section();
+        }
+        self.section
+            .get_or_insert_with(|| Section {
+                kind,
+                chrom_id,
+                step,
+                span,
+                items: vec![],
+            })
+            .items
+            .push((start, end, value));
+    }
+
+    fn end_section(&mut self) {
+        let section = match self.section.take() {
+            Some(section) => section,
+            None => return,
+        };
+        let start = section.items[0].0;
+        let end = section.items.iter().map(|item| item.1).max().unwrap();
+        let mut buf = vec![];
+        put_u32(&mut buf, section.chrom_id);
+        put_u32(&mut buf, start);
+        put_u32(&mut buf, end);
+        put_u32(&mut buf, section.step);
+        put_u32(&mut buf, section.span);
+        buf.push(section.kind);
+        buf.push(0);
+        put_u16(&mut buf, section.items.len() as u16);
+        for (item_start, item_end, value) in section.items {
+            match section.kind {
+                1 => {
+                    put_u32(&mut buf, item_start);
+                    put_u32(&mut buf, item_end);
+                }
+                2 => put_u32(&mut buf, item_start),
+                _ => {}
+            }
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        self.sections
+            .add((section.chrom_id, start, section.chrom_id, end), &buf);
+    }
+
+    /// Write the bigWig.
+    pub fn finish(mut self, output: &mut dyn Write) {
+        let rest = std::mem::take(&mut self.pending);
+        if let Ok(line) = std::str::from_utf8(&rest) {
+            self.read_line(line.trim_end());
+        }
+        self.end_section();
+        let data_count = self.sections.index.len() as u64;
+        write_bbi(
+            output,
+            BbiParts {
+                magic: BIGWIG_MAGIC,
+                field_counts: (0, 0),
+                auto_sql: None,
+                summary: Some(self.summary),
+                chroms: self.chroms.finish(),
+                data_count,
+                items_per_slot: BIGWIG_SECTION_ITEMS as u32,
+                sections: &mut self.sections,
+            },
+        );
+    }
+}
+
+impl Write for BigWigWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = vec![];
+        take_lines(&mut self.pending, buf, |line| lines.push(line.to_string()));
+        for line in lines {
+            self.read_line(&line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The autoSql describing the standard BED fields, and any extra ones.
+fn bed_auto_sql(num_fields: usize) -> String {
+    let standard = [
+        "string chrom;       \"Reference sequence chromosome or scaffold\"",
+        "uint   chromStart;  \"Start position in chromosome\"",
+        "uint   chromEnd;    \"End position in chromosome\"",
+        "string name;        \"Name of item\"",
+        "uint   score;       \"Score from 0-1000\"",
+        "char[1] strand;     \"+ or -\"",
+    ];
+    let mut sql = "table bed\n\"Browser extensible data\"\n    (\n".to_string();
+    for field in 0..num_fields {
+        match standard.get(field) {
+            Some(line) => sql.push_str(&format!("    {}\n", line)),
+            None => sql.push_str(&format!(
+                "    lstring field{};  \"Extra field\"\n",
+                field + 1
+            )),
+        }
+    }
+    sql.push_str("    )\n");
+    sql
+}
+
+/// Converts BED text written to it into a bigBed, written out by
+/// `finish`. Records are sorted before they're written, so they can come
+/// in any order, unless the writer is `presorted`; every line must have
+/// the same number of fields.
+pub struct BigBedWriter {
+    chroms: Chroms,
+    pending: Vec<u8>,
+    num_fields: Option<usize>,
+    /// The autoSql and number of standard BED fields, if the fields
+    /// aren't BED6 and extra fields.
+    format: Option<(String, u16)>,
+    /// Chromosome, start, end, and the rest of the fields of each record
+    /// held to be sorted, unless they're written as they come.
+    records: Option<Vec<(String, u32, u32, String)>>,
+    /// Chromosome id, start, end, and the rest of the fields of each
+    /// record of the block being built.
+    block: Vec<(u32, u32, u32, String)>,
+    /// The chromosome id and start of the last record.
+    last: Option<(u32, u32)>,
+    num_records: u64,
+    sections: Sections,
+}
+
+impl BigBedWriter {
+    pub fn new(chrom_sizes: HashMap<String, u64>) -> Self {
+        BigBedWriter {
+            chroms: Chroms::new(chrom_sizes),
+            pending: vec![],
+            num_fields: None,
+            format: None,
+            records: Some(vec![]),
+            block: vec![],
+            last: None,
+            num_records: 0,
+            sections: Sections::new(),
+        }
+    }
+
+    /// Write records as they come rather than holding them all to sort,
+    /// which needs them to be in order of position within each
+    /// chromosome, with each chromosome's records together.
+    pub fn presorted(mut self) -> Self {
+        self.records = None;
+        self
+    }
+
+    /// Describe the fields with this autoSql, of which the first
+    /// `num_standard_fields` are standard BED fields.
+    pub fn with_format(mut self, auto_sql: &str, num_standard_fields: u16) -> Self {
+        self.format = Some((auto_sql.to_string(), num_standard_fields));
+        self
+    }
+
+    fn read_line(&mut self, line: &str) {
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            return;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 || *self.num_fields.get_or_insert(fields.len()) != fields.len() {
+            panic!(
+                "Every BED line needs the same number of fields (at least 3): {}",
+                line
+            );
+        }
+        let parse_pos = |pos: &str| -> u32 {
+            pos.parse()
+                .unwrap_or_else(|_| panic!("Invalid position in {}", line))
+        };
+        let (start, end) = (parse_pos(fields[1]), parse_pos(fields[2]));
+        let rest = fields[3..].join("\t");
+        match self.records.as_mut() {
+            Some(records) => records.push((fields[0].to_string(), start, end, rest)),
+            None => {
+                let chrom_id = self.chroms.id(fields[0], end);
+                self.add(chrom_id, start, end, rest);
+            }
+        }
+    }
+
+    fn add(&mut self, chrom_id: u32, start: u32, end: u32, rest: String) {
+        if self.last.is_some_and(|last| (chrom_id, start) < last) {
+            panic!(
+                "bigBed records must be in order of position, but one starting at {} comes after a later one",
+                start
+            );
+        }
+        self.last = Some((chrom_id, start));
+        if self
+            .block
+            .first()
+            .is_some_and(|first| first.0 != chrom_id || self.block.len() == BIGBED_BLOCK_ITEMS)
+        {
+            self.end_block();
+        }
+        self.block.push((chrom_id, start, end, rest));
+        self.num_records += 1;
+    }
+
+    fn end_block(&mut self) {
+        if self.block.is_empty() {
+            return;
+        }
+        let mut buf = vec![];
+        for (chrom_id, start, end, rest) in self.block.iter() {
+            put_u32(&mut buf, *chrom_id);
+            put_u32(&mut buf, *start);
+            put_u32(&mut buf, *end);
+            buf.extend_from_slice(rest.as_bytes());
+            buf.push(0);
+        }
+        let (chrom_id, start) = (self.block[0].0, self.block[0].1);
+        let end = self.block.iter().map(|record| record.2).max().unwrap();
+        self.sections.add((chrom_id, start, chrom_id, end), &buf);
+        self.block.clear();
+    }
+
+    /// Write the bigBed.
+    pub fn finish(mut self, output: &mut dyn Write) {
+        let rest = std::mem::take(&mut self.pending);
+        if let Ok(line) = std::str::from_utf8(&rest) {
+            self.read_line(line.trim_end());
+        }
+        if let Some(records) = self.records.take() {
+            // Ids in order of name, so sorting by id sorts by name.
+            let mut names: Vec<String> = records.iter().map(|r| r.0.clone()).collect();
+            names.sort();
+            names.dedup();
+            for name in names.iter() {
+                self.chroms.id(name, 0);
+            }
+            let mut records: Vec<(u32, u32, u32, String)> = records
+                .into_iter()
+                .map(|(chrom, start, end, rest)| (self.chroms.id(&chrom, end), start, end, rest))
+                .collect();
+            records.sort();
+            for (chrom_id, start, end, rest) in records {
+                self.add(chrom_id, start, end, rest);
+            }
+        }
+        self.end_block();
+        let num_fields = self.num_fields.unwrap_or(3);
+        let (auto_sql, num_standard_fields) = self
+            .format
+            .take()
+            .unwrap_or_else(|| (bed_auto_sql(num_fields), num_fields.min(6) as u16));
+        write_bbi(
+            output,
+            BbiParts {
+                magic: BIGBED_MAGIC,
+                field_counts: (num_fields as u16, num_standard_fields),
+                auto_sql: Some(auto_sql),
+                summary: None,
+                chroms: self.chroms.finish(),
+                data_count: self.num_records,
+                items_per_slot: BIGBED_BLOCK_ITEMS as u32,
+                sections: &mut self.sections,
+            },
+        );
+    }
+}
+
+impl Write for BigBedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = vec![];
+        take_lines(&mut self.pending, buf, |line| lines.push(line.to_string()));
+        for line in lines {
+            self.read_line(&line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::convert::TryInto;
+    use std::io::Read;
+
+    fn chrom_sizes() -> HashMap<String, u64> {
+        vec![("chr1".to_string(), 100), ("chr2".to_string(), 50)]
+            .into_iter()
+            .collect()
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The id and size of a chromosome, looked up in the chromosome
+    /// tree at `tree` as UCSC's tools do.
+    fn find_chrom(bytes: &[u8], tree: usize, name: &str) -> Option<(u32, u32)> {
+        assert_eq!(u32_at(bytes, tree), CHROM_TREE_MAGIC);
+        let key_size = u32_at(bytes, tree + 8) as usize;
+        let mut key = name.as_bytes().to_vec();
+        key.resize(key_size, 0);
+        let mut node = tree + 32;
+        loop {
+            let count = u16::from_le_bytes([bytes[node + 2], bytes[node + 3]]) as usize;
+            let item = |i: usize| node + 4 + i * (key_size + 8);
+            let key_at = |i: usize| &bytes[item(i)..item(i) + key_size];
+            if bytes[node] == 1 {
+                return (0..count).find(|i| key_at(*i) == &key[..]).map(|i| {
+                    (
+                        u32_at(bytes, item(i) + key_size),
+                        u32_at(bytes, item(i) + key_size + 4),
+                    )
+                });
+            }
+            let child = (1..count).take_while(|i| key_at(*i) <= &key[..]).last();
+            node = u64_at(bytes, item(child.unwrap_or(0)) + key_size) as usize;
+        }
+    }
+
+    /// The decompressed data of every section, found through the
+    /// index (which must be a single leaf).
+    fn sections(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let index_offset = u64_at(bytes, 24) as usize;
+        assert_eq!(u32_at(bytes, index_offset), R_TREE_MAGIC);
+        let root = index_offset + 48;
+        assert_eq!(bytes[root], 1);
+        let count = u16::from_le_bytes([bytes[root + 2], bytes[root + 3]]) as usize;
+        (0..count)
+            .map(|i| {
+                let item = root + 4 + i * 32;
+                let offset = u64_at(bytes, item + 16) as usize;
+                let size = u64_at(bytes, item + 24) as usize;
+                let mut data = vec![];
+                ZlibDecoder::new(&bytes[offset..offset + size])
+                    .read_to_end(&mut data)
+                    .unwrap();
+                data
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bigwig() {
+        let mut sizes = chrom_sizes();
+        sizes.insert("chr3".to_string(), 10);
+        let mut writer = BigWigWriter::new(sizes);
+        write!(
+            writer,
+            "fixedStep chrom=chr2 start=11 step=1\n1.5\n2\nvariableStep chrom=chr1\n5\t3"
+        )
+        .unwrap();
+        let mut bytes = vec![];
+        writer.finish(&mut bytes);
+        assert_eq!(u32_at(&bytes, 0), BIGWIG_MAGIC);
+        // chr2 comes first, so it gets id 0, and chr1 id 1.
+        let chrom_tree = u64_at(&bytes, 8) as usize;
+        assert_eq!(u32_at(&bytes, chrom_tree), CHROM_TREE_MAGIC);
+        assert_eq!(&bytes[chrom_tree + 36..chrom_tree + 40], b"chr1");
+        assert_eq!(u32_at(&bytes, chrom_tree + 40), 1);
+        assert_eq!(u32_at(&bytes, chrom_tree + 44), 100);
+        // chr3 has no data, so it's left out.
+        assert_eq!(find_chrom(&bytes, chrom_tree, "chr1"), Some((1, 100)));
+        assert_eq!(find_chrom(&bytes, chrom_tree, "chr3"), None);
+        let sections = sections(&bytes);
+        assert_eq!(sections.len(), 2);
+        // chrom 0, 10-12, step 1, span 1, fixedStep, 2 values.
+        assert_eq!(
+            &sections[0][..20],
+            &[0, 0, 0, 0, 10, 0, 0, 0, 12, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]
+        );
+        assert_eq!(&sections[0][20..24], &[3, 0, 2, 0]);
+        assert_eq!(sections[0][24..28], 1.5f32.to_le_bytes());
+        assert_eq!(sections[0][28..32], 2f32.to_le_bytes());
+        assert_eq!(sections[1][20], 2);
+        assert_eq!(u32_at(&sections[1], 24), 4);
+        // The summary covers 3 bases, summing to 6.5.
+        let summary = u64_at(&bytes, 44) as usize;
+        assert_eq!(u64_at(&bytes, summary), 3);
+        assert_eq!(
+            f64::from_le_bytes(bytes[summary + 24..summary + 32].try_into().unwrap()),
+            6.5
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "order of position")]
+    fn test_bigwig_unsorted() {
+        let mut writer = BigWigWriter::new(chrom_sizes());
+        write!(
+            writer,
+            "fixedStep chrom=chr1 start=11 step=1\n1\nfixedStep chrom=chr1 start=5 step=1\n1\n"
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bigbed() {
+        let mut writer = BigBedWriter::new(chrom_sizes());
+        write!(
+            writer,
+            "chr2\t5\t10\ta\t0\t+\nchr1\t20\t30\tb\t0\t-\nchr1\t10\t15\tc\t0\t+\n"
+        )
+        .unwrap();
+        let mut bytes = vec![];
+        writer.finish(&mut bytes);
+        assert_eq!(u32_at(&bytes, 0), BIGBED_MAGIC);
+        assert_eq!(&bytes[32..36], &[6, 0, 6, 0]);
+        let data_offset = u64_at(&bytes, 16) as usize;
+        assert_eq!(u64_at(&bytes, data_offset), 3);
+        let sections = sections(&bytes);
+        assert_eq!(sections.len(), 2);
+        // chr1 sorts first, with its records in order.
+        assert_eq!(&sections[0][..12], &[0, 0, 0, 0, 10, 0, 0, 0, 15, 0, 0, 0]);
+        assert_eq!(&sections[0][12..20], b"c\t0\t+\0\0\0");
+        assert_eq!(u32_at(&sections[1], 0), 1);
+    }
+
+    #[test]
+    fn test_chrom_tree_levels() {
+        // More chromosomes than a node's count can hold, as in a
+        // scaffold-level assembly, need a tree three levels deep.
+        let mut chroms: Vec<(String, u32, u32)> = (0..70000)
+            .map(|i| (format!("scaffold{}", i), i, i + 1))
+            .collect();
+        chroms.sort();
+        let tree = chrom_tree(&chroms, 0);
+        assert_eq!(u32_at(&tree, 4), CHROM_TREE_BLOCK_SIZE as u32);
+        assert_eq!(u64_at(&tree, 16), 70000);
+        assert_eq!(tree[32], 0);
+        for i in [0, 1, 255, 256, 65535, 65536, 69999].iter() {
+            let name = format!("scaffold{}", i);
+            assert_eq!(find_chrom(&tree, 0, &name), Some((*i, i + 1)), "{}", name);
+        }
+        assert_eq!(find_chrom(&tree, 0, "scaffold70000"), None);
+        assert_eq!(find_chrom(&tree, 0, "chr1"), None);
+    }
+}
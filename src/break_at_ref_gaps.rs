@@ -0,0 +1,100 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use std::io::BufRead;
+
+/// The runs [start, end) of columns where a row has bases.
+fn base_runs(alignment: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs = vec![];
+    let mut run_start = None;
+    for (i, c) in alignment.iter().enumerate() {
+        match (!is_gap(*c), run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                runs.push((start, i));
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = run_start {
+        runs.push((start, alignment.len()));
+    }
+    runs
+}
+
+/// Split each block at the columns where its reference (first) row has
+/// a gap, dropping those columns, so that every block written is
+/// colinear with the reference. Returns the number of columns dropped.
+pub fn break_at_ref_gaps(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+) -> Result<usize, MAFParseError> {
+    let mut num_dropped = 0;
+    for_each_block(input, output, |block, output| {
+        let ref_alignment = match block.aligned_entries().next() {
+            Some(entry) if entry.alignment.iter().any(|c| is_gap(*c)) => &entry.alignment,
+            _ => {
+                output.write_block(&block);
+                return;
+            }
+        };
+        let runs = base_runs(ref_alignment);
+        num_dropped +=
+            ref_alignment.len() - runs.iter().map(|(start, end)| end - start).sum::<usize>();
+        for (start, end) in runs {
+            output.write_block(
+                &block
+                    .slice_columns(start, end)
+                    .expect("Run has no reference bases"),
+            );
+        }
+    })?;
+    Ok(num_dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_break_at_ref_gaps() {
+        let maf = "##maf version=1
+a score=5
+s hg38.chr1 10 5 + 100 -AC--GT-A
+s mm39.chr2 5 7 - 50 TAC-AG-CA
+e rn7.chr3 0 5 + 10 I
+
+a
+s hg38.chr1 20 2 + 100 AC
+s mm39.chr2 20 2 + 50 AC
+";
+        let mut output = vec![];
+        let num_dropped = break_at_ref_gaps(&mut maf.as_bytes(), &mut output).unwrap();
+        assert_eq!(num_dropped, 4);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a score=5
+s hg38.chr1 10 2 + 100 AC
+s mm39.chr2 6 2 - 50 AC
+e rn7.chr3 0 5 + 10 I
+
+a score=5
+s hg38.chr1 12 2 + 100 GT
+s mm39.chr2 9 1 - 50 G-
+e rn7.chr3 0 5 + 10 I
+
+a score=5
+s hg38.chr1 14 1 + 100 A
+s mm39.chr2 11 1 - 50 A
+e rn7.chr3 0 5 + 10 I
+
+a
+s hg38.chr1 20 2 + 100 AC
+s mm39.chr2 20 2 + 50 AC
+
+"
+        );
+    }
+}
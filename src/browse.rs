@@ -0,0 +1,446 @@
+use crate::view::{render_block, ViewFormat};
+use maf_stream::{chrom_part, try_parse_region, Range};
+use multiple_alignment_format::parser::next_maf_item;
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry, MAFItem};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+const HELP: &str = "n/enter: next block  p: previous block  g <chr:start-end>: jump to region
+t <species>: toggle species  /<text>: search sequence names  q: quit
+";
+
+/// Where a block starts in the file, and the (forward-strand) region
+/// its reference covers.
+#[derive(Debug, PartialEq)]
+struct IndexEntry {
+    offset: u64,
+    ref_range: Option<Range>,
+}
+
+/// Find the byte offset of every block in a MAF, along with the region
+/// covered by its reference (first) entry.
+fn index_blocks(input: &mut dyn BufRead) -> Vec<IndexEntry> {
+    let mut index: Vec<IndexEntry> = vec![];
+    let mut offset = 0;
+    let mut line = String::new();
+    let mut in_block = false;
+    loop {
+        line.clear();
+        let len = input.read_line(&mut line).expect("Couldn't read input");
+        if len == 0 {
+            break;
+        }
+        let fields: Vec<_> = line.split_whitespace().collect();
+        match fields.first() {
+            Some(&"a") => {
+                index.push(IndexEntry {
+                    offset,
+                    ref_range: None,
+                });
+                in_block = true;
+            }
+            Some(&"s") if in_block && fields.len() >= 6 => {
+                let start: u64 = fields[2].parse().expect("Couldn't parse start");
+                let length: u64 = fields[3].parse().expect("Couldn't parse length");
+                let size: u64 = fields[5].parse().expect("Couldn't parse sequence size");
+                let start = if fields[4] == "-" {
+                    size - start - length
+                } else {
+                    start
+                };
+                index.last_mut().unwrap().ref_range = Some(Range {
+                    seq: chrom_part(fields[1]),
+                    start,
+                    end: start + length,
+                });
+                in_block = false;
+            }
+            None => in_block = false,
+            _ => {}
+        }
+        offset += len as u64;
+    }
+    index
+}
+
+/// What an index was made from: the size of the input, and its ETag or
+/// modification time if known, so that a saved index can be checked
+/// against the input before it's used.
+#[derive(Debug, PartialEq)]
+struct IndexSource {
+    size: u64,
+    version: Option<String>,
+}
+
+/// Write an index as a "#source\tsize\tversion" header line (with "-"
+/// for an unknown version), then lines of each block's offset,
+/// followed by its reference region as "chrom\tstart\tend" if it has
+/// one.
+fn write_index(source: &IndexSource, index: &[IndexEntry], output: &mut dyn Write) {
+    writeln!(
+        output,
+        "#source\t{}\t{}",
+        source.size,
+        source.version.as_deref().unwrap_or("-")
+    )
+    .expect("Couldn't write index");
+    for entry in index {
+        match &entry.ref_range {
+            Some(r) => writeln!(
+                output,
+                "{}\t{}\t{}\t{}",
+                entry.offset, r.seq, r.start, r.end
+            ),
+            None => writeln!(output, "{}", entry.offset),
+        }
+        .expect("Couldn't write index");
+    }
+}
+
+/// Read an index written by `write_index`, along with what it was made
+/// from.
+fn read_index(input: &mut dyn BufRead) -> Result<(IndexSource, Vec<IndexEntry>), String> {
+    let parse = |field: &str| -> Result<u64, String> {
+        field
+            .parse()
+            .map_err(|_| format!("can't parse {:?}", field))
+    };
+    let mut lines = input.lines();
+    let header = lines
+        .next()
+        .ok_or("it's empty")?
+        .map_err(|e| e.to_string())?;
+    let source = match header.split('\t').collect::<Vec<_>>()[..] {
+        ["#source", size, version] => IndexSource {
+            size: parse(size)?,
+            version: (version != "-").then(|| version.to_string()),
+        },
+        _ => return Err("it has no source header".to_string()),
+    };
+    let index = lines
+        .map(|line| {
+            let line = line.map_err(|e| e.to_string())?;
+            let fields: Vec<_> = line.split('\t').collect();
+            let ref_range = match fields[1..] {
+                [] => None,
+                [seq, start, end] => Some(Range {
+                    seq: seq.to_string(),
+                    start: parse(start)?,
+                    end: parse(end)?,
+                }),
+                _ => return Err(format!("bad line {:?}", line)),
+            };
+            Ok(IndexEntry {
+                offset: parse(fields[0])?,
+                ref_range,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok((source, index))
+}
+
+/// The index of a MAF, read from `index_path` if it exists and was made
+/// from the same input. Otherwise the input is indexed, and the index
+/// saved to `index_path` if given, so that later sessions (over a
+/// remote MAF, say) needn't read the whole input again. Also returns
+/// why a saved index was rebuilt, if it was.
+fn load_index(
+    input: &mut dyn BufRead,
+    source: IndexSource,
+    index_path: Option<&Path>,
+) -> (Vec<IndexEntry>, Option<String>) {
+    let mut rebuilt = None;
+    if let Some(path) = index_path.filter(|path| path.exists()) {
+        let saved = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| read_index(&mut BufReader::new(file)));
+        match saved {
+            Ok((saved_source, index)) if saved_source == source => return (index, None),
+            Ok(_) => {
+                rebuilt = Some(format!(
+                    "Index {} was made from a different input, so rebuilt it",
+                    path.display()
+                ))
+            }
+            Err(e) => {
+                rebuilt = Some(format!(
+                    "Couldn't read index {} ({}), so rebuilt it",
+                    path.display(),
+                    e
+                ))
+            }
+        }
+    }
+    let index = index_blocks(input);
+    if let Some(path) = index_path {
+        let mut file = BufWriter::new(File::create(path).expect("Couldn't create index file"));
+        write_index(&source, &index, &mut file);
+    }
+    (index, rebuilt)
+}
+
+/// State of an interactive browsing session over a seekable MAF.
+pub struct Browser<R: Read + Seek> {
+    input: BufReader<R>,
+    index: Vec<IndexEntry>,
+    current: usize,
+    hidden: BTreeSet<String>,
+    width: usize,
+    message: String,
+}
+
+impl<R: Read + Seek> Browser<R> {
+    /// Start a session over `input`, with `version` (its ETag or
+    /// modification time, if known) to check a saved index against.
+    pub fn new(input: R, index_path: Option<&Path>, version: Option<String>, width: usize) -> Self {
+        let mut input = BufReader::new(input);
+        let size = input
+            .seek(SeekFrom::End(0))
+            .expect("Couldn't seek in input");
+        input
+            .seek(SeekFrom::Start(0))
+            .expect("Couldn't seek in input");
+        let (index, rebuilt) = load_index(&mut input, IndexSource { size, version }, index_path);
+        Browser {
+            input,
+            index,
+            current: 0,
+            hidden: BTreeSet::new(),
+            width,
+            message: rebuilt.unwrap_or_default(),
+        }
+    }
+
+    fn load_block(&mut self, i: usize) -> MAFBlock {
+        self.input
+            .seek(SeekFrom::Start(self.index[i].offset))
+            .expect("Couldn't seek in input");
+        match next_maf_item(&mut self.input) {
+            Ok(MAFItem::Block(block)) => block,
+            _ => panic!("Index doesn't point to a block"),
+        }
+    }
+
+    /// Render the current block, without any hidden species.
+    pub fn render(&mut self) -> String {
+        if self.index.is_empty() {
+            return "No blocks in input\n".to_string();
+        }
+        let mut block = self.load_block(self.current);
+        let hidden = &self.hidden;
+        block.entries.retain(|entry| {
+            let seq = match entry {
+                MAFBlockEntry::AlignedEntry(e) => &e.seq,
+                MAFBlockEntry::UnalignedEntry(e) => &e.seq,
+            };
+            !hidden.contains(seq.split('.').next().unwrap())
+        });
+        let mut rendered = format!("Block {}/{}", self.current + 1, self.index.len());
+        if !self.hidden.is_empty() {
+            let hidden: Vec<_> = self.hidden.iter().map(|s| s.as_str()).collect();
+            rendered.push_str(&format!(" (hidden: {})", hidden.join(", ")));
+        }
+        rendered.push('\n');
+        rendered.push_str(&render_block(&block, ViewFormat::Ansi, self.width));
+        if !self.message.is_empty() {
+            rendered.push_str(&self.message);
+            rendered.push('\n');
+        }
+        rendered
+    }
+
+    /// Find the next block (after the current one, wrapping around)
+    /// containing a sequence whose name contains `text`.
+    fn search(&mut self, text: &str) -> Option<usize> {
+        let n = self.index.len();
+        for step in 1..=n {
+            let i = (self.current + step) % n;
+            let block = self.load_block(i);
+            if block.aligned_entries().any(|e| e.seq.contains(text)) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Find the first block whose reference overlaps `region`.
+    fn find_region(&self, region: &Range) -> Option<usize> {
+        self.index.iter().position(|entry| match &entry.ref_range {
+            Some(r) => r.seq == region.seq && r.start < region.end && r.end > region.start,
+            None => false,
+        })
+    }
+
+    /// Handle one command. Returns false if the session should end.
+    pub fn command(&mut self, command: &str) -> bool {
+        self.message.clear();
+        let command = command.trim();
+        let last = self.index.len().saturating_sub(1);
+        if command.is_empty() || command == "n" {
+            if self.current < last {
+                self.current += 1;
+            } else {
+                self.message = "Already at the last block".to_string();
+            }
+        } else if command == "p" {
+            if self.current > 0 {
+                self.current -= 1;
+            } else {
+                self.message = "Already at the first block".to_string();
+            }
+        } else if command == "q" {
+            return false;
+        } else if command == "h" || command == "?" {
+            self.message = HELP.trim_end().to_string();
+        } else if let Some(region) = command.strip_prefix("g ") {
+            match try_parse_region(region.trim()).map(|range| self.find_region(&range)) {
+                Ok(Some(i)) => self.current = i,
+                Ok(None) => self.message = format!("No block overlaps {}", region.trim()),
+                Err(e) => self.message = e,
+            }
+        } else if let Some(species) = command.strip_prefix("t ") {
+            let species = species.trim().to_string();
+            if !self.hidden.remove(&species) {
+                self.hidden.insert(species);
+            }
+        } else if let Some(text) = command.strip_prefix('/') {
+            match self.search(text) {
+                Some(i) => self.current = i,
+                None => self.message = format!("No sequence name matches {}", text),
+            }
+        } else {
+            self.message = format!("Unknown command {:?} (h for help)", command);
+        }
+        true
+    }
+}
+
+/// Run an interactive session, reading commands (one per line) from
+/// `commands` and redrawing the screen on `output` after each.
+pub fn browse<R: Read + Seek>(
+    input: R,
+    index_path: Option<&Path>,
+    version: Option<String>,
+    commands: &mut dyn BufRead,
+    output: &mut dyn Write,
+    width: usize,
+) {
+    let mut browser = Browser::new(input, index_path, version, width);
+    let mut line = String::new();
+    loop {
+        write!(output, "{}{}> ", CLEAR_SCREEN, browser.render()).ok();
+        output.flush().ok();
+        line.clear();
+        if commands.read_line(&mut line).unwrap_or(0) == 0 || !browser.command(&line) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MAF: &str = "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+
+a score=2
+s hg38.chr1 10 4 - 100 ACGT
+s rn7.chr3 50 4 + 200 ACTT
+";
+
+    #[test]
+    fn test_index_blocks() {
+        assert_eq!(
+            index_blocks(&mut MAF.as_bytes()),
+            vec![
+                IndexEntry {
+                    offset: 16,
+                    ref_range: Some(Range {
+                        seq: "chr1".to_string(),
+                        start: 0,
+                        end: 4,
+                    }),
+                },
+                IndexEntry {
+                    offset: 74,
+                    ref_range: Some(Range {
+                        seq: "chr1".to_string(),
+                        start: 86,
+                        end: 90,
+                    }),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_saved_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.maf.idx");
+        let source = || IndexSource {
+            size: MAF.len() as u64,
+            version: Some("\"v1\"".to_string()),
+        };
+        let (index, rebuilt) = load_index(&mut MAF.as_bytes(), source(), Some(&path));
+        assert_eq!(index, index_blocks(&mut MAF.as_bytes()));
+        assert_eq!(rebuilt, None);
+        // Once saved, the index is read back instead of the input.
+        let (saved, rebuilt) = load_index(&mut "".as_bytes(), source(), Some(&path));
+        assert_eq!(saved, index);
+        assert_eq!(rebuilt, None);
+        // But not if the input has changed since.
+        let changed = IndexSource {
+            version: Some("\"v2\"".to_string()),
+            ..source()
+        };
+        let (rebuilt_index, rebuilt) = load_index(&mut "".as_bytes(), changed, Some(&path));
+        assert_eq!(rebuilt_index, vec![]);
+        assert!(rebuilt.unwrap().contains("different input"));
+        // Or if the saved index can't be read.
+        std::fs::write(&path, "#source\t130\t-\n16\tchr1\t0\n").unwrap();
+        assert_eq!(
+            load_index(&mut MAF.as_bytes(), source(), Some(&path)).0,
+            index
+        );
+        assert_eq!(
+            load_index(&mut "".as_bytes(), source(), Some(&path)).0,
+            index
+        );
+
+        let mut browser = Browser::new(Cursor::new(MAF), Some(&path), None, 0);
+        browser.command("g chr1:88-88");
+        assert_eq!(browser.current, 1);
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut browser = Browser::new(Cursor::new(MAF), None, None, 0);
+        assert!(browser.render().starts_with("Block 1/2\nhg38.chr1:0-4 (+)"));
+        browser.command("/rn7");
+        assert_eq!(browser.current, 1);
+        browser.command("g chr1:1-4");
+        assert_eq!(browser.current, 0);
+        browser.command("g chr1:88-88");
+        assert_eq!(browser.current, 1);
+        assert!(browser.command("g chr1:88"));
+        assert_eq!(browser.current, 1);
+        assert!(browser
+            .render()
+            .contains("Region must look like chr:start-end"));
+        browser.command("t hg38");
+        assert!(browser
+            .render()
+            .starts_with("Block 2/2 (hidden: hg38)\nrn7.chr3"));
+        assert!(browser.command("p"));
+        assert_eq!(browser.current, 0);
+        assert!(!browser.command("q"));
+    }
+}
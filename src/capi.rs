@@ -0,0 +1,298 @@
+//! C ABI for the parser. The header is generated with cbindgen (see
+//! `cbindgen.toml`) into `include/maf_stream.h`.
+//!
+//! Strings returned by the accessors are NUL-terminated and owned by
+//! the block they came from; they're valid until it's freed.
+use multiple_alignment_format::parser::{next_maf_item, MAFParseError};
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::raw::c_char;
+use std::ptr;
+
+/// Lossily convert to a C string, dropping any interior NULs.
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+/// An open MAF file.
+pub struct MafReader {
+    input: Box<dyn BufRead>,
+    error: Option<CString>,
+}
+
+struct MafRow {
+    seq: CString,
+    start: u64,
+    size: u64,
+    strand: c_char,
+    src_size: u64,
+    text: CString,
+}
+
+/// A parsed alignment block.
+pub struct MafBlock {
+    rows: Vec<MafRow>,
+    num_columns: usize,
+    metadata: Vec<(String, CString)>,
+}
+
+impl MafBlock {
+    fn new(block: &MAFBlock) -> MafBlock {
+        let rows: Vec<MafRow> = block
+            .aligned_entries()
+            .map(|e| MafRow {
+                seq: c_string(&e.seq),
+                start: e.start,
+                size: e.aligned_length,
+                strand: match e.strand {
+                    Strand::Positive => b'+' as c_char,
+                    Strand::Negative => b'-' as c_char,
+                },
+                src_size: e.sequence_size,
+                text: c_string(&String::from_utf8_lossy(&e.alignment)),
+            })
+            .collect();
+        MafBlock {
+            num_columns: rows.first().map_or(0, |r| r.text.as_bytes().len()),
+            rows,
+            metadata: block
+                .metadata
+                .iter()
+                .map(|(k, v)| (k.clone(), c_string(v)))
+                .collect(),
+        }
+    }
+
+    fn row(&self, row: usize) -> &MafRow {
+        self.rows
+            .get(row)
+            .unwrap_or_else(|| panic!("Row {} out of range", row))
+    }
+}
+
+/// Open a MAF file for reading. Returns NULL if it can't be opened.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn maf_open(path: *const c_char) -> *mut MafReader {
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return ptr::null_mut(),
+    };
+    match File::open(path) {
+        Ok(file) => Box::into_raw(Box::new(MafReader {
+            input: Box::new(BufReader::new(file)),
+            error: None,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Read the next block, skipping comments. Returns NULL at the end of
+/// the file or on a parse error; `maf_error` tells the two apart. The
+/// block must be freed with `maf_block_free`.
+///
+/// # Safety
+/// `reader` must come from `maf_open`.
+#[no_mangle]
+pub unsafe extern "C" fn maf_next_block(reader: *mut MafReader) -> *mut MafBlock {
+    let reader = &mut *reader;
+    reader.error = None;
+    loop {
+        match next_maf_item(&mut reader.input) {
+            Ok(MAFItem::Block(block)) => return Box::into_raw(Box::new(MafBlock::new(&block))),
+            Ok(MAFItem::Comment(_)) => continue,
+            Err(MAFParseError::EOF) => return ptr::null_mut(),
+            Err(e) => {
+                reader.error = Some(c_string(&format!("{:?}", e)));
+                return ptr::null_mut();
+            }
+        }
+    }
+}
+
+/// The error that stopped the last `maf_next_block` call, or NULL if
+/// there wasn't one. Owned by the reader.
+///
+/// # Safety
+/// `reader` must come from `maf_open`.
+#[no_mangle]
+pub unsafe extern "C" fn maf_error(reader: *const MafReader) -> *const c_char {
+    (*reader).error.as_ref().map_or(ptr::null(), |e| e.as_ptr())
+}
+
+/// Close a reader opened with `maf_open`.
+///
+/// # Safety
+/// `reader` must come from `maf_open` (or be NULL), and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn maf_free(reader: *mut MafReader) {
+    if !reader.is_null() {
+        drop(Box::from_raw(reader));
+    }
+}
+
+/// Free a block returned by `maf_next_block`.
+///
+/// # Safety
+/// `block` must come from `maf_next_block` (or be NULL), and not be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_free(block: *mut MafBlock) {
+    if !block.is_null() {
+        drop(Box::from_raw(block));
+    }
+}
+
+/// Number of aligned (`s` line) rows in the block. Row 0 is the
+/// reference.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_num_rows(block: *const MafBlock) -> usize {
+    (*block).rows.len()
+}
+
+/// Number of alignment columns in the block.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_num_columns(block: *const MafBlock) -> usize {
+    (*block).num_columns
+}
+
+/// The value of a key in the block's `a` line (e.g. "score"), or NULL
+/// if it's not present.
+///
+/// # Safety
+/// `block` must be a live block and `key` a valid NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_metadata(
+    block: *const MafBlock,
+    key: *const c_char,
+) -> *const c_char {
+    let key = CStr::from_ptr(key).to_string_lossy();
+    (*block)
+        .metadata
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map_or(ptr::null(), |(_, v)| v.as_ptr())
+}
+
+/// Sequence name of a row. Aborts if `row` is out of range, as do the
+/// other row accessors.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_row_seq(block: *const MafBlock, row: usize) -> *const c_char {
+    (*block).row(row).seq.as_ptr()
+}
+
+/// Start of a row, on its own strand.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_row_start(block: *const MafBlock, row: usize) -> u64 {
+    (*block).row(row).start
+}
+
+/// Number of bases in a row.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_row_size(block: *const MafBlock, row: usize) -> u64 {
+    (*block).row(row).size
+}
+
+/// Strand of a row: '+' or '-'.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_row_strand(block: *const MafBlock, row: usize) -> c_char {
+    (*block).row(row).strand
+}
+
+/// Total length of a row's sequence.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_row_src_size(block: *const MafBlock, row: usize) -> u64 {
+    (*block).row(row).src_size
+}
+
+/// Aligned text of a row, gaps included.
+///
+/// # Safety
+/// `block` must be a live block.
+#[no_mangle]
+pub unsafe extern "C" fn maf_block_row_text(block: *const MafBlock, row: usize) -> *const c_char {
+    (*block).row(row).text.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn read(s: *const c_char) -> &'static str {
+        unsafe { CStr::from_ptr(s) }.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_capi() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "##maf version=1
+a score=2
+s hg38.chr1 10 4 + 100 AC-GT
+s mm39.chr2 20 5 - 200 ACTGT
+
+a
+s hg38.chr1 10 4 + 100 ACGT
+x bogus
+"
+        )
+        .unwrap();
+        let path = c_string(file.path().to_str().unwrap());
+        unsafe {
+            let reader = maf_open(path.as_ptr());
+            assert!(!reader.is_null());
+            let block = maf_next_block(reader);
+            assert!(!block.is_null());
+            assert_eq!(maf_block_num_rows(block), 2);
+            assert_eq!(maf_block_num_columns(block), 5);
+            assert_eq!(
+                read(maf_block_metadata(block, c_string("score").as_ptr())),
+                "2"
+            );
+            assert!(maf_block_metadata(block, c_string("pass").as_ptr()).is_null());
+            assert_eq!(read(maf_block_row_seq(block, 1)), "mm39.chr2");
+            assert_eq!(maf_block_row_start(block, 1), 20);
+            assert_eq!(maf_block_row_size(block, 1), 5);
+            assert_eq!(maf_block_row_strand(block, 1), b'-' as c_char);
+            assert_eq!(maf_block_row_src_size(block, 1), 200);
+            assert_eq!(read(maf_block_row_text(block, 0)), "AC-GT");
+            maf_block_free(block);
+            assert!(maf_error(reader).is_null());
+            // The second block has a bad line.
+            assert!(maf_next_block(reader).is_null());
+            assert!(!maf_error(reader).is_null());
+            maf_free(reader);
+            assert!(maf_open(c_string("/nonexistent").as_ptr()).is_null());
+        }
+    }
+}
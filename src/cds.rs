@@ -0,0 +1,719 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::tree::Tree;
+use maf_stream::{
+    chrom_part, complement, reverse_complement, write_alignment_file, MAFItems, Range,
+};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// The coding sequence of a reference transcript.
+#[derive(Debug, PartialEq)]
+pub struct Transcript {
+    pub name: String,
+    pub strand: Strand,
+    /// CDS segments, sorted by position (regardless of strand).
+    pub cds: Vec<Range>,
+    /// Number of bases to skip at the start of the CDS (in
+    /// transcript orientation) to get to the first full codon.
+    pub phase: u64,
+}
+
+impl Transcript {
+    pub fn cds_length(&self) -> u64 {
+        self.cds.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+/// Get the transcript a CDS line belongs to, from either a GFF3
+/// "Parent=" attribute or a GTF "transcript_id" attribute.
+fn transcript_id(attributes: &str) -> Option<String> {
+    for attribute in attributes.split(';').map(|a| a.trim()) {
+        if let Some(parents) = attribute.strip_prefix("Parent=") {
+            return parents.split(',').next().map(|s| s.to_string());
+        } else if let Some(id) = attribute.strip_prefix("transcript_id") {
+            return Some(id.trim().trim_matches('"').to_string());
+        }
+    }
+    None
+}
+
+/// Get the CDS of each transcript in a GFF3 or GTF file, sorted by
+/// transcript name.
+pub fn parse_gff(gff: impl BufRead) -> Vec<Transcript> {
+    let mut segments: BTreeMap<String, (Strand, Vec<(Range, u64)>)> = BTreeMap::new();
+    for line_res in gff.lines() {
+        let line = line_res.expect("Can't read line");
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split('\t').collect();
+        if fields.len() < 9 {
+            panic!("GFF line has too few fields: {}", line);
+        }
+        if fields[2] != "CDS" {
+            continue;
+        }
+        let name = transcript_id(fields[8]).expect("CDS line has no transcript ID");
+        let start: u64 = fields[3].parse().expect("Can't parse start position");
+        let end: u64 = fields[4].parse().expect("Can't parse end position");
+        let strand = match fields[6] {
+            "-" => Strand::Negative,
+            _ => Strand::Positive,
+        };
+        let phase: u64 = fields[7].parse().unwrap_or(0);
+        let range = Range {
+            seq: fields[0].to_string(),
+            start: start - 1,
+            end,
+        };
+        segments
+            .entry(name)
+            .or_insert_with(|| (strand, vec![]))
+            .1
+            .push((range, phase));
+    }
+    segments
+        .into_iter()
+        .map(|(name, (strand, mut cds))| {
+            cds.sort();
+            let phase = match strand {
+                Strand::Positive => cds.first().unwrap().1,
+                Strand::Negative => cds.last().unwrap().1,
+            };
+            Transcript {
+                name,
+                strand,
+                cds: cds.into_iter().map(|(r, _)| r).collect(),
+                phase,
+            }
+        })
+        .collect()
+}
+
+/// Builds reference-anchored codon alignments for a set of transcripts.
+/// Columns where the reference has a gap are dropped, so every species
+/// has exactly one character per reference CDS base and the frame is
+/// always that of the reference.
+pub struct CdsExtractor {
+    pub transcripts: Vec<Transcript>,
+    /// CDS segment -> (transcript index, offset of the segment within
+    /// the forward-strand CDS).
+    segments: BTreeMap<Range, Vec<(usize, u64)>>,
+    /// Longest CDS segment, used to find segments that start before a
+    /// block but still overlap it.
+    max_segment_length: u64,
+    /// Per transcript, the forward-strand aligned bases of each species,
+    /// with UNALIGNED where it had no row.
+    alignments: Vec<BTreeMap<String, Vec<u8>>>,
+    /// Per transcript, the lengths of each species' insertions between
+    /// bases of the same CDS segment.
+    insertions: Vec<BTreeMap<String, Vec<u64>>>,
+}
+
+/// Placeholder for CDS bases a species never aligned to, so they aren't
+/// mistaken for deletions.
+const UNALIGNED: u8 = b' ';
+
+/// The standard genetic code, indexed by the bases of a codon as
+/// base-4 digits in TCAG order.
+const CODON_TABLE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+/// Translate a codon alignment into amino acids: codons that are all
+/// gaps become gaps, and those with some gaps or ambiguous bases become
+/// X.
+pub fn translate(codons: &[u8]) -> Vec<u8> {
+    codons
+        .chunks(3)
+        .map(|codon| {
+            if codon.iter().all(|b| is_gap(*b)) {
+                return b'-';
+            }
+            let mut index = 0;
+            for base in codon {
+                index = index * 4
+                    + match base.to_ascii_uppercase() {
+                        b'T' | b'U' => 0,
+                        b'C' => 1,
+                        b'A' => 2,
+                        b'G' => 3,
+                        _ => return b'X',
+                    };
+            }
+            CODON_TABLE[index]
+        })
+        .collect()
+}
+
+impl CdsExtractor {
+    pub fn new(transcripts: Vec<Transcript>) -> Self {
+        let mut segments: BTreeMap<Range, Vec<(usize, u64)>> = BTreeMap::new();
+        let mut max_segment_length = 0;
+        for (i, transcript) in transcripts.iter().enumerate() {
+            let mut offset = 0;
+            for range in transcript.cds.iter() {
+                segments.entry(range.clone()).or_default().push((i, offset));
+                offset += range.end - range.start;
+                max_segment_length = max_segment_length.max(range.end - range.start);
+            }
+        }
+        CdsExtractor {
+            alignments: transcripts.iter().map(|_| BTreeMap::new()).collect(),
+            insertions: transcripts.iter().map(|_| BTreeMap::new()).collect(),
+            transcripts,
+            segments,
+            max_segment_length,
+        }
+    }
+
+    pub fn add_block(&mut self, block: &MAFBlock) {
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let chrom = chrom_part(&ref_entry.seq);
+        let ref_positions: Vec<Option<u64>> = ref_entry
+            .column_positions()
+            .map(|p| p.map(|p| ref_entry.forward_position(p)))
+            .collect();
+        let (block_start, block_end) = ref_entry.forward_interval();
+        let query_start = Range {
+            seq: chrom.clone(),
+            start: block_start.saturating_sub(self.max_segment_length),
+            end: 0,
+        };
+        let query_end = Range {
+            seq: chrom,
+            start: block_end,
+            end: 0,
+        };
+        let hits: Vec<(&Range, usize, u64)> = self
+            .segments
+            .range(query_start..query_end)
+            .filter(|(range, _)| range.end > block_start)
+            .flat_map(|(range, ts)| ts.iter().map(move |(t, offset)| (range, *t, *offset)))
+            .collect();
+        if hits.is_empty() {
+            return;
+        }
+
+        let entries = block.entries_as_hash();
+        for (species, species_entries) in entries.iter() {
+            // Only the first entry of duplicated species is used.
+            let entry = species_entries[0];
+            for (range, t, offset) in hits.iter() {
+                let length = self.transcripts[*t].cds_length() as usize;
+                let alignment = self.alignments[*t]
+                    .entry((*species).to_string())
+                    .or_insert_with(|| vec![UNALIGNED; length]);
+                let insertions = self.insertions[*t]
+                    .entry((*species).to_string())
+                    .or_default();
+                let in_range = |pos: u64| pos >= range.start && pos < range.end;
+                let mut last_pos = None;
+                let mut inserted = 0;
+                for (i, ref_pos) in ref_positions.iter().enumerate() {
+                    if ref_pos.is_none() && !is_gap(entry.alignment[i]) {
+                        inserted += 1;
+                    }
+                    if let Some(pos) = ref_pos {
+                        if inserted > 0 && in_range(*pos) && last_pos.is_some_and(in_range) {
+                            insertions.push(inserted);
+                        }
+                        inserted = 0;
+                        last_pos = Some(*pos);
+                        if in_range(*pos) {
+                            let base = entry.alignment[i];
+                            alignment[(offset + pos - range.start) as usize] =
+                                match ref_entry.strand {
+                                    Strand::Positive => base,
+                                    Strand::Negative => complement(base),
+                                };
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// In-frame codon alignments in transcript orientation, with
+    /// UNALIGNED where a species has no row.
+    fn oriented_alignment(&self, transcript: usize) -> BTreeMap<String, Vec<u8>> {
+        let t = &self.transcripts[transcript];
+        self.alignments[transcript]
+            .iter()
+            .map(|(species, alignment)| {
+                let mut alignment = alignment.clone();
+                if t.strand == Strand::Negative {
+                    reverse_complement(&mut alignment);
+                }
+                // A CDS fragment can be shorter than its phase, leaving
+                // no full codons.
+                let mut alignment = alignment.split_off((t.phase as usize).min(alignment.len()));
+                alignment.truncate(alignment.len() - alignment.len() % 3);
+                (species.clone(), alignment)
+            })
+            .collect()
+    }
+
+    /// Get the in-frame codon alignment for each species for a
+    /// transcript, in transcript orientation. Species that never
+    /// aligned to the transcript are not included.
+    pub fn codon_alignment(&self, transcript: usize) -> BTreeMap<String, Vec<u8>> {
+        let mut alignments = self.oriented_alignment(transcript);
+        for alignment in alignments.values_mut() {
+            for base in alignment.iter_mut().filter(|b| **b == UNALIGNED) {
+                *base = b'-';
+            }
+        }
+        alignments
+    }
+
+    /// For each species aligned to a transcript, the fraction of the
+    /// transcript's CDS bases that it has a base aligned to.
+    pub fn aligned_fractions(&self, transcript: usize) -> BTreeMap<String, f64> {
+        self.alignments[transcript]
+            .iter()
+            .map(|(species, alignment)| {
+                let aligned = alignment
+                    .iter()
+                    .filter(|b| **b != UNALIGNED && !is_gap(**b))
+                    .count();
+                (species.clone(), aligned as f64 / alignment.len() as f64)
+            })
+            .collect()
+    }
+
+    /// For each species aligned to a transcript, the number of indels
+    /// that shift the frame (those whose length isn't a multiple of 3)
+    /// and the number of stop codons before the last codon.
+    pub fn disruptions(&self, transcript: usize) -> BTreeMap<String, (usize, usize)> {
+        self.oriented_alignment(transcript)
+            .iter()
+            .map(|(species, alignment)| {
+                let deletions = alignment
+                    .split(|b| !is_gap(*b))
+                    .filter(|run| run.len() % 3 != 0)
+                    .count();
+                let insertions = self.insertions[transcript]
+                    .get(species)
+                    .map_or(0, |lengths| lengths.iter().filter(|l| *l % 3 != 0).count());
+                let protein = translate(alignment);
+                let stops = protein
+                    .iter()
+                    .take(protein.len().saturating_sub(1))
+                    .filter(|aa| **aa == b'*')
+                    .count();
+                (species.clone(), (deletions + insertions, stops))
+            })
+            .collect()
+    }
+}
+
+/// What to do to each transcript's codon alignment, and what to write.
+#[derive(Default)]
+pub struct CdsOptions {
+    /// Also write protein alignments, and a TSV of frameshifts and
+    /// premature stops.
+    pub translate: bool,
+    /// Drop codons with a gap or an ambiguous base in any species.
+    pub drop_gappy_codons: bool,
+    /// Replace codons with a base in a CpG in any species with NNN in
+    /// every species.
+    pub mask_cpg: bool,
+    /// Write sequential PHYLIP, as PAML reads, rather than FASTA.
+    pub phylip: bool,
+    /// Species tree to write next to each alignment, pruned to the
+    /// species in it.
+    pub tree: Option<Tree>,
+}
+
+/// Replace every codon where any species has a C followed by a G with
+/// NNN in every species, since CpGs mutate so much faster than other
+/// sites.
+fn mask_cpg(alignment: &mut BTreeMap<String, Vec<u8>>) {
+    let mut masked = BTreeSet::new();
+    for seq in alignment.values() {
+        for (i, pair) in seq.windows(2).enumerate() {
+            if pair[0].eq_ignore_ascii_case(&b'C') && pair[1].eq_ignore_ascii_case(&b'G') {
+                masked.insert(i / 3);
+                masked.insert((i + 1) / 3);
+            }
+        }
+    }
+    for seq in alignment.values_mut() {
+        for codon in masked.iter() {
+            seq[codon * 3..codon * 3 + 3].copy_from_slice(b"NNN");
+        }
+    }
+}
+
+/// Drop every codon with a gap or an ambiguous base in any species.
+fn drop_gappy_codons(alignment: &mut BTreeMap<String, Vec<u8>>) {
+    let num_codons = alignment.values().next().map_or(0, |seq| seq.len() / 3);
+    let keep: Vec<bool> = (0..num_codons)
+        .map(|codon| {
+            alignment.values().all(|seq| {
+                seq[codon * 3..codon * 3 + 3]
+                    .iter()
+                    .all(|b| b"ACGT".contains(&b.to_ascii_uppercase()))
+            })
+        })
+        .collect();
+    for seq in alignment.values_mut() {
+        *seq = seq
+            .chunks(3)
+            .zip(keep.iter())
+            .filter(|(_, keep)| **keep)
+            .flat_map(|(codon, _)| codon.iter().copied())
+            .collect();
+    }
+}
+
+/// Write the codon alignment of each transcript to
+/// `<output_dir>/<transcript>.fa` (or `.phy`, as PHYLIP), filtered as
+/// the options say, with the tree pruned to its species in
+/// `<transcript>.nwk`, if given. With `translate`, also write its
+/// protein alignment to `<transcript>.faa`, and a TSV to `output` of the
+/// frameshifting indels and premature stops in each species' copy, to
+/// screen for pseudogenes.
+pub fn extract_cds(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    output_dir: &str,
+    gff: impl BufRead,
+    options: &CdsOptions,
+) -> Result<(), MAFParseError> {
+    let mut extractor = CdsExtractor::new(parse_gff(gff));
+
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            extractor.add_block(&block);
+        }
+    }
+
+    for (i, transcript) in extractor.transcripts.iter().enumerate() {
+        let mut alignment = extractor.codon_alignment(i);
+        if alignment.is_empty() {
+            continue;
+        }
+        if options.mask_cpg {
+            mask_cpg(&mut alignment);
+        }
+        if options.drop_gappy_codons {
+            drop_gappy_codons(&mut alignment);
+        }
+        // Transcript IDs often have a version suffix, so the extension
+        // is added rather than set.
+        let path =
+            |extension| Path::new(output_dir).join(format!("{}.{}", transcript.name, extension));
+        let extension = if options.phylip { "phy" } else { "fa" };
+        write_alignment_file(&path(extension), &alignment, options.phylip);
+        if options.translate {
+            let proteins = alignment
+                .iter()
+                .map(|(species, seq)| (species.clone(), translate(seq)))
+                .collect();
+            write_alignment_file(&path("faa"), &proteins, options.phylip);
+        }
+        if let Some(tree) = options.tree.as_ref() {
+            if let Some(pruned) = tree.prune(|name| alignment.contains_key(name)) {
+                let mut file = File::create(path("nwk")).expect("Couldn't create file");
+                writeln!(file, "{}", pruned.to_newick()).expect("failed to write");
+            }
+        }
+    }
+    if options.translate {
+        writeln!(output, "#transcript\tspecies\tframeshifts\tpremature_stops").ok();
+        for (i, transcript) in extractor.transcripts.iter().enumerate() {
+            for (species, (frameshifts, stops)) in extractor.disruptions(i) {
+                writeln!(
+                    output,
+                    "{}\t{}\t{}\t{}",
+                    transcript.name, species, frameshifts, stops
+                )
+                .ok();
+            }
+        }
+    }
+    items.finish()
+}
+
+/// Write a TSV with, for each transcript and every species in the
+/// alignment, the fraction of its CDS aligned, and the frameshifting
+/// indels and in-frame stops (before the last codon) in the aligned
+/// copy: the usual screen for gene loss. Returns the number of
+/// transcripts.
+pub fn gene_loss(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    gff: impl BufRead,
+) -> Result<usize, MAFParseError> {
+    let mut extractor = CdsExtractor::new(parse_gff(gff));
+    let mut species = BTreeSet::new();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            for entry in block.aligned_entries() {
+                species.insert(entry.seq.split('.').next().unwrap().to_string());
+            }
+            extractor.add_block(&block);
+        }
+    }
+    writeln!(
+        output,
+        "#transcript\tspecies\taligned_frac\tframeshifts\tin_frame_stops"
+    )
+    .ok();
+    for (i, transcript) in extractor.transcripts.iter().enumerate() {
+        let fractions = extractor.aligned_fractions(i);
+        let disruptions = extractor.disruptions(i);
+        for species in species.iter() {
+            let (frameshifts, stops) = disruptions.get(species).copied().unwrap_or((0, 0));
+            writeln!(
+                output,
+                "{}\t{}\t{:.4}\t{}\t{}",
+                transcript.name,
+                species,
+                fractions.get(species).copied().unwrap_or(0.0),
+                frameshifts,
+                stops
+            )
+            .ok();
+        }
+    }
+    items.finish()?;
+    Ok(extractor.transcripts.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multiple_alignment_format::parser::next_maf_item;
+    use std::fs::read_to_string;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_gff() {
+        let gff = "##gff-version 3
+chr1\tsrc\tmRNA\t1\t100\t.\t-\t.\tID=tx1
+chr1\tsrc\tCDS\t50\t60\t.\t-\t2\tParent=tx1
+chr1\tsrc\tCDS\t11\t20\t.\t-\t0\tParent=tx1
+chr2\tsrc\tCDS\t5\t10\t.\t+\t0\tgene_id \"g2\"; transcript_id \"tx2\";
+";
+        assert_eq!(
+            parse_gff(gff.as_bytes()),
+            vec![
+                Transcript {
+                    name: "tx1".to_string(),
+                    strand: Strand::Negative,
+                    cds: vec![
+                        Range {
+                            seq: "chr1".to_string(),
+                            start: 10,
+                            end: 20,
+                        },
+                        Range {
+                            seq: "chr1".to_string(),
+                            start: 49,
+                            end: 60,
+                        },
+                    ],
+                    phase: 2,
+                },
+                Transcript {
+                    name: "tx2".to_string(),
+                    strand: Strand::Positive,
+                    cds: vec![Range {
+                        seq: "chr2".to_string(),
+                        start: 4,
+                        end: 10,
+                    }],
+                    phase: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_cds() {
+        // Two exons split across two blocks, plus a reference insertion
+        // which should be dropped. The second block is on the negative
+        // strand of the reference.
+        let maf = "a
+s       hg38.chr1 10   7       +       100  ATG-CCTA
+s       mm39.chr2 30   7       +       200  ATGGCCT-
+
+a
+s       hg38.chr1 80   3       -       100  TTA
+s       mm39.chr2 50   3       +       200  TCA
+";
+        let gff = "chr1\tsrc\tCDS\t11\t16\t.\t+\t0\tParent=tx1
+chr1\tsrc\tCDS\t18\t20\t.\t+\t0\tParent=tx1
+";
+        let tempdir = TempDir::new().unwrap();
+        extract_cds(
+            &mut maf.as_bytes(),
+            &mut vec![],
+            tempdir.path().to_str().unwrap(),
+            gff.as_bytes(),
+            &CdsOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            read_to_string(tempdir.path().join("tx1.fa")).unwrap(),
+            ">hg38
+ATGCCTTAA
+>mm39
+ATGCCTTGA
+"
+        );
+    }
+
+    #[test]
+    fn test_cds_shorter_than_phase() {
+        let maf = "a
+s hg38.chr1 10 2 + 100 AT
+s mm39.chr2 30 2 + 200 AT
+";
+        let gff = "chr1\tsrc\tCDS\t11\t11\t.\t+\t2\tParent=tx1\n";
+        let transcripts = parse_gff(gff.as_bytes());
+        let mut extractor = CdsExtractor::new(transcripts);
+        if let Ok(MAFItem::Block(block)) = next_maf_item(&mut maf.as_bytes()) {
+            extractor.add_block(&block);
+        }
+        assert!(extractor.codon_alignment(0).values().all(|a| a.is_empty()));
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate(b"ATGTGGtaa---A-GNNN..."), b"MW*-XX-".to_vec());
+    }
+
+    #[test]
+    fn test_translate_cds() {
+        // rn7 has a 1-base deletion, mm39 a 2-base insertion, and galGal6
+        // a premature stop and a 3-base deletion (which keeps the frame).
+        let maf = "a
+s hg38.chr1 10 12 + 100 ATGCC--TTGCTAA
+s mm39.chr2 30 14 + 200 ATGCCAATTGCTAA
+s rn7.chr2 30 11 + 200 ATGC---TTGCTAA
+s galGal6.chr2 30 9 + 200 ATGTA--G---TAA
+";
+        let gff = "chr1\tsrc\tCDS\t11\t22\t.\t+\t0\tParent=tx1\n";
+        let tempdir = TempDir::new().unwrap();
+        let mut output = vec![];
+        extract_cds(
+            &mut maf.as_bytes(),
+            &mut output,
+            tempdir.path().to_str().unwrap(),
+            gff.as_bytes(),
+            &CdsOptions {
+                translate: true,
+                ..CdsOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            read_to_string(tempdir.path().join("tx1.faa")).unwrap(),
+            ">galGal6
+M*-*
+>hg38
+MPC*
+>mm39
+MPC*
+>rn7
+MXC*
+"
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#transcript\tspecies\tframeshifts\tpremature_stops
+tx1\tgalGal6\t0\t1
+tx1\thg38\t0\t0
+tx1\tmm39\t1\t0
+tx1\trn7\t1\t0
+"
+        );
+    }
+
+    #[test]
+    fn test_gene_loss() {
+        let maf = "a
+s hg38.chr1 10 6 + 100 ATGCCT
+s mm39.chr2 30 5 + 200 ATG-CT
+s rn7.chr2 30 3 + 200 ATG---
+
+a
+s hg38.chr1 50 3 + 100 TAA
+s mm39.chr2 30 3 + 200 TAA
+s galGal6.chr2 30 3 + 200 TAA
+";
+        let gff = "chr1\tsrc\tCDS\t11\t16\t.\t+\t0\tParent=tx1
+chr1\tsrc\tCDS\t51\t53\t.\t+\t0\tParent=tx1
+chr1\tsrc\tCDS\t71\t73\t.\t+\t0\tParent=tx2
+";
+        let mut output = vec![];
+        let num_transcripts = gene_loss(&mut maf.as_bytes(), &mut output, gff.as_bytes()).unwrap();
+        assert_eq!(num_transcripts, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#transcript\tspecies\taligned_frac\tframeshifts\tin_frame_stops
+tx1\tgalGal6\t0.3333\t0\t0
+tx1\thg38\t1.0000\t0\t0
+tx1\tmm39\t0.8889\t1\t0
+tx1\trn7\t0.3333\t0\t0
+tx2\tgalGal6\t0.0000\t0\t0
+tx2\thg38\t0.0000\t0\t0
+tx2\tmm39\t0.0000\t0\t0
+tx2\trn7\t0.0000\t0\t0
+"
+        );
+    }
+
+    #[test]
+    fn test_codon_filters() {
+        let maf = "a
+s hg38.chr1 10 12 + 100 ATGCGTAAACTT
+s mm39.chr2 30 11 + 200 ATGCATAA-CTT
+s rn7.chr2 30 12 + 200 ATGCATAAACTN
+";
+        let gff = "chr1\tsrc\tCDS\t11\t22\t.\t+\t0\tParent=tx1\n";
+        let tempdir = TempDir::new().unwrap();
+        let options = CdsOptions {
+            drop_gappy_codons: true,
+            mask_cpg: true,
+            phylip: true,
+            tree: Some(Tree::parse_newick(
+                "((hg38:0.1,galGal6:0.2):0.1,(mm39:0.1,rn7:0.1):0.2);",
+            )),
+            ..CdsOptions::default()
+        };
+        extract_cds(
+            &mut maf.as_bytes(),
+            &mut vec![],
+            tempdir.path().to_str().unwrap(),
+            gff.as_bytes(),
+            &options,
+        )
+        .unwrap();
+        // The CpG in hg38 masks the second codon, which is then dropped
+        // along with the gapped third and the ambiguous fourth.
+        assert_eq!(
+            read_to_string(tempdir.path().join("tx1.phy")).unwrap(),
+            " 3 3
+hg38  ATG
+mm39  ATG
+rn7  ATG
+"
+        );
+        assert_eq!(
+            read_to_string(tempdir.path().join("tx1.nwk")).unwrap(),
+            "(hg38:0.2,(mm39:0.1,rn7:0.1):0.2);\n"
+        );
+    }
+}
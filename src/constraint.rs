@@ -0,0 +1,198 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::tree::Tree;
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{BufRead, Write};
+
+/// Bit for each of A, C, G, T, for Fitch parsimony.
+fn base_bit(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => 1,
+        b'C' => 2,
+        b'G' => 4,
+        b'T' => 8,
+        _ => 0,
+    }
+}
+
+/// Scores columns against a neutral tree, GERP-style.
+pub struct ConstraintScorer {
+    tree: Tree,
+    /// Tree leaf of each species in the tree.
+    leaves: HashMap<String, usize>,
+    postorder: Vec<usize>,
+    /// Species seen in blocks that aren't in the tree.
+    missing: BTreeSet<String>,
+}
+
+impl ConstraintScorer {
+    pub fn new(tree: Tree) -> Self {
+        ConstraintScorer {
+            leaves: tree.leaves(),
+            postorder: tree.postorder().collect(),
+            tree,
+            missing: BTreeSet::new(),
+        }
+    }
+
+    /// Expected minus observed substitutions for a column, given the
+    /// base of each non-reference species at the leaves (0 where the
+    /// species is absent). The expected number is the neutral branch
+    /// length of the tree pruned to the species present; the observed
+    /// number is the Fitch parsimony score of their bases on that tree.
+    fn score(&self, leaf_bases: &[u8]) -> f64 {
+        let num_present = leaf_bases.iter().filter(|b| **b != 0).count();
+        if num_present < 2 {
+            return 0.0;
+        }
+        let mut present_below = vec![0; self.tree.nodes.len()];
+        let mut fitch_sets = leaf_bases.to_vec();
+        let mut expected = 0.0;
+        let mut observed = 0;
+        for &node in self.postorder.iter() {
+            if self.tree.is_leaf(node) {
+                present_below[node] = (leaf_bases[node] != 0) as usize;
+            } else {
+                let mut set = 0;
+                for &child in self.tree.nodes[node].children.iter() {
+                    present_below[node] += present_below[child];
+                    let child_set = fitch_sets[child];
+                    if child_set == 0 {
+                        continue;
+                    }
+                    if set == 0 {
+                        set = child_set;
+                    } else if set & child_set == 0 {
+                        set |= child_set;
+                        observed += 1;
+                    } else {
+                        set &= child_set;
+                    }
+                }
+                fitch_sets[node] = set;
+            }
+            // The branch above this node is in the pruned tree if it
+            // separates some present species from others.
+            if present_below[node] > 0 && present_below[node] < num_present {
+                expected += self.tree.nodes[node].branch_length;
+            }
+        }
+        expected - observed as f64
+    }
+
+    /// Score every reference base in a block, returning the reference
+    /// chromosome, the forward-strand start, and the scores in
+    /// forward-strand order.
+    fn score_block(&mut self, block: &MAFBlock) -> Option<(String, u64, Vec<f64>)> {
+        let ref_entry = block.aligned_entries().next()?;
+        let ref_species = ref_entry.seq.split('.').next().unwrap();
+        // Tree leaf of each non-reference row, taking only the first
+        // row of each species.
+        let mut seen = HashSet::new();
+        let mut rows = vec![];
+        for entry in block.aligned_entries().skip(1) {
+            let species = entry.seq.split('.').next().unwrap();
+            if species == ref_species || !seen.insert(species) {
+                continue;
+            }
+            match self.leaves.get(species) {
+                Some(leaf) => rows.push((entry, *leaf)),
+                None => {
+                    self.missing.insert(species.to_string());
+                }
+            }
+        }
+        let mut leaf_bases = vec![0; self.tree.nodes.len()];
+        let mut scores = vec![];
+        for (column, ref_base) in ref_entry.alignment.iter().enumerate() {
+            if is_gap(*ref_base) {
+                continue;
+            }
+            for (entry, leaf) in rows.iter() {
+                leaf_bases[*leaf] = base_bit(entry.alignment[column]);
+            }
+            scores.push(self.score(&leaf_bases));
+        }
+        if ref_entry.strand == Strand::Negative {
+            scores.reverse();
+        }
+        let (start, _) = ref_entry.forward_interval();
+        Some((chrom_part(&ref_entry.seq), start, scores))
+    }
+}
+
+/// Write a wiggle track of GERP-like rejected substitution scores
+/// (expected minus observed substitutions) for every reference base,
+/// one fixedStep section per block. Returns the species that were
+/// ignored for not being in the tree.
+pub fn constraint(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    tree: Tree,
+) -> Result<BTreeSet<String>, MAFParseError> {
+    let mut scorer = ConstraintScorer::new(tree);
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            if let Some((chrom, start, scores)) = scorer.score_block(&block) {
+                if scores.is_empty() {
+                    continue;
+                }
+                writeln!(
+                    output,
+                    "fixedStep chrom={} start={} step=1",
+                    chrom,
+                    start + 1
+                )
+                .ok();
+                for score in scores {
+                    writeln!(output, "{:.3}", score).ok();
+                }
+            }
+        }
+    }
+    items.finish()?;
+    Ok(scorer.missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constraint() {
+        let tree = Tree::parse_newick("(((hg38:0.1,mm39:0.2):0.1,rn7:0.3):0.1,bosTau9:0.4);");
+        let maf = "a
+s hg38.chr1 10 4 + 100 ACG-T
+s mm39.chr2 20 5 + 200 ACGAT
+s rn7.chr3 30 5 + 200 ATGAT
+s bosTau9.chr4 40 3 + 200 AT--C
+s galGal6.chr5 50 4 + 200 ACGAT
+
+a
+s hg38.chr1 90 2 - 100 AC
+s mm39.chr2 0 2 + 200 AC
+";
+        let mut output = vec![];
+        let missing = constraint(&mut maf.as_bytes(), &mut output, tree).unwrap();
+        assert_eq!(missing.into_iter().collect::<Vec<_>>(), vec!["galGal6"]);
+        // hg38 is the reference and galGal6 isn't in the tree. The
+        // expected substitutions among mm39, rn7, and bosTau9 are 0.2 +
+        // 0.1 + 0.3 + 0.1 + 0.4 = 1.1, or 0.6 where bosTau9 has a gap.
+        // The second and last bases each have one substitution.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "fixedStep chrom=chr1 start=11 step=1
+1.100
+0.100
+0.600
+0.100
+fixedStep chrom=chr1 start=9 step=1
+0.000
+0.000
+"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// How often each problem was found.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ContiguityCounts {
+    pub gaps: usize,
+    pub gap_bases: u64,
+    pub overlaps: usize,
+    pub out_of_order: usize,
+    pub revisits: usize,
+}
+
+/// Where the walk along a reference chromosome has got to.
+struct ChromState {
+    chrom: String,
+    size: u64,
+    last_start: u64,
+    /// The end of the reference covered so far.
+    covered_end: u64,
+    last_block: usize,
+}
+
+struct ContiguityChecker<'a> {
+    output: &'a mut dyn Write,
+    chroms: Vec<ChromState>,
+    chrom_idx: HashMap<String, usize>,
+    current: Option<usize>,
+    counts: ContiguityCounts,
+}
+
+impl ContiguityChecker<'_> {
+    fn report(&mut self, chrom: &str, start: u64, end: u64, kind: &str, block: usize) {
+        writeln!(
+            self.output,
+            "{}\t{}\t{}\t{}\t{}",
+            chrom, start, end, kind, block
+        )
+        .ok();
+    }
+
+    fn add_interval(&mut self, chrom: String, size: u64, start: u64, end: u64, block: usize) {
+        let idx = match self.chrom_idx.get(&chrom).copied() {
+            Some(idx) => {
+                if self.current != Some(idx) {
+                    self.counts.revisits += 1;
+                    self.report(&chrom, start, end, "revisit", block);
+                }
+                idx
+            }
+            None => {
+                self.chrom_idx.insert(chrom.clone(), self.chroms.len());
+                self.chroms.push(ChromState {
+                    chrom: chrom.clone(),
+                    size,
+                    last_start: 0,
+                    covered_end: 0,
+                    last_block: block,
+                });
+                self.chroms.len() - 1
+            }
+        };
+        self.current = Some(idx);
+        let (last_start, covered_end) = (self.chroms[idx].last_start, self.chroms[idx].covered_end);
+        if start < last_start {
+            self.counts.out_of_order += 1;
+            self.report(&chrom, start, end, "out_of_order", block);
+        } else if start < covered_end {
+            self.counts.overlaps += 1;
+            self.report(&chrom, start, end.min(covered_end), "overlap", block);
+        } else if start > covered_end {
+            self.counts.gaps += 1;
+            self.counts.gap_bases += start - covered_end;
+            self.report(&chrom, covered_end, start, "gap", block);
+        }
+        let state = &mut self.chroms[idx];
+        state.last_start = start;
+        state.covered_end = state.covered_end.max(end);
+        state.last_block = block;
+    }
+
+    /// Report the uncovered ends of the chromosomes.
+    fn finish(&mut self) {
+        let ends: Vec<_> = self
+            .chroms
+            .iter()
+            .filter(|c| c.covered_end < c.size)
+            .map(|c| (c.chrom.clone(), c.covered_end, c.size, c.last_block))
+            .collect();
+        for (chrom, start, end, block) in ends {
+            self.counts.gaps += 1;
+            self.counts.gap_bases += end - start;
+            self.report(&chrom, start, end, "gap", block);
+        }
+    }
+}
+
+/// Walk the blocks along the reference (the first row of each block)
+/// and report, as a TSV, every place where the reference coverage
+/// isn't contiguous: gaps (including the uncovered ends of each
+/// chromosome), overlaps with earlier blocks, blocks that start before
+/// the previous block on their chromosome, and chromosomes that come
+/// back after blocks on another chromosome. Each line gives the
+/// forward-strand interval involved and the index of the block where
+/// it was found. End gaps are reported at the end.
+pub fn contiguity(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> Result<ContiguityCounts, MAFParseError> {
+    writeln!(output, "#chrom\tstart\tend\tkind\tblock").ok();
+    let mut checker = ContiguityChecker {
+        output,
+        chroms: vec![],
+        chrom_idx: HashMap::new(),
+        current: None,
+        counts: ContiguityCounts::default(),
+    };
+    let mut num_blocks = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            if let Some(ref_entry) = block.aligned_entries().next() {
+                let (start, end) = ref_entry.forward_interval();
+                checker.add_interval(
+                    chrom_part(&ref_entry.seq),
+                    ref_entry.sequence_size,
+                    start,
+                    end,
+                    num_blocks,
+                );
+            }
+            num_blocks += 1;
+        }
+    }
+    checker.finish();
+    items.finish()?;
+    Ok(checker.counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contiguity() {
+        let maf = format!(
+            "a
+s hg38.chr1 0 10 + 100 ACGTACGTAC
+
+a
+s hg38.chr1 80 10 - 100 ACGTACGTAC
+
+a
+s hg38.chr1 25 3 + 100 ACG
+
+a
+s hg38.chr1 5 5 + 100 ACGTA
+
+a
+s hg38.chr2 2 8 + 12 ACGTACGT
+
+a
+s hg38.chr1 26 74 + 100 {}
+",
+            "A".repeat(74)
+        );
+        let mut output = vec![];
+        let counts = contiguity(&mut maf.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            counts,
+            ContiguityCounts {
+                gaps: 3,
+                gap_bases: 9,
+                overlaps: 1,
+                out_of_order: 1,
+                revisits: 1,
+            }
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tstart\tend\tkind\tblock
+chr1\t20\t25\tgap\t2
+chr1\t5\t10\tout_of_order\t3
+chr2\t0\t2\tgap\t4
+chr1\t26\t100\trevisit\t5
+chr1\t26\t28\toverlap\t5
+chr2\t10\t12\tgap\t4
+"
+        );
+    }
+}
@@ -1,46 +1,421 @@
-use crate::lib::{chrom_part, parse_bed, range_contains_pos, Range};
-use multiple_alignment_format::parser::next_maf_item;
-use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFItem, Strand};
-use std::collections::{BTreeSet, HashMap};
+use crate::alphabet::{is_gap, iupac_bases, Alphabet};
+use crate::tree::Tree;
+use crate::{block_identity, chrom_part, complement, MAFItems, QueryFilter, RegionFilter};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{
+    MAFBlock, MAFBlockAlignedEntry, MAFBlockUnalignedEntry, MAFItem, Strand, UnalignedContextStatus,
+};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::{BufRead, Write};
 
-struct MAFCoverage {
+/// How the coverage table is written.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CoverageFormat {
+    /// Tab-separated, with a header line of column names.
+    #[default]
+    Tsv,
+    /// A JSON array with an object per row.
+    Json,
+    /// A Markdown table, with the covered fraction rounded.
+    Markdown,
+}
+
+const COLUMNS: [&str; 7] = [
+    "reference",
+    "query",
+    "reference_length",
+    "fraction_covered",
+    "bases_covered",
+    "bases_deleted",
+    "bases_missing",
+];
+
+/// Coverage of the reference by one query genome.
+struct CoverageRow {
+    query: String,
+    reference_length: u64,
+    covered: u64,
+    deleted: u64,
+    missing: u64,
+}
+
+impl CoverageRow {
+    fn fraction_covered(&self) -> f64 {
+        self.covered as f64 / self.reference_length as f64
+    }
+}
+
+/// Running coverage totals of a reference genome by each query genome.
+pub struct MAFCoverage {
     /// Coverage by genome.
-    coverage: HashMap<String, u64>,
-    /// Optional ranges to filter on. Any alignments not within these
-    /// ranges will be ignored.
-    ranges: Option<BTreeSet<Range>>,
+    coverage: BTreeMap<String, u64>,
+    /// Bases not covered by a genome because an "e" line says they
+    /// were deleted in that genome.
+    deleted: BTreeMap<String, u64>,
+    /// Bases not covered by a genome because of a gap in its "s"
+    /// line, or because of a bridged "e" line that isn't a deletion or
+    /// missing data. Any bases that aren't covered, deleted, or gapped
+    /// are considered missing data.
+    gapped: BTreeMap<String, u64>,
+    /// Number of reference bases considered so far.
+    counted_bases: u64,
+    /// Regions to filter on. Any alignments not within these regions
+    /// will be ignored.
+    regions: RegionFilter,
+    /// Optional regions on a query genome to filter on. Any columns
+    /// where the query genome isn't aligned within these regions will
+    /// be ignored.
+    query_filter: Option<QueryFilter>,
     ref_genome: String,
     /// Sequence name -> length in reference genome. Used for
-    /// calculating the total at the end when not filtering by ranges.
+    /// calculating the total at the end when not filtering by
+    /// included ranges.
     ref_lengths: HashMap<String, u64>,
+    /// Genome -> groups it belongs to, if reporting coverage by group
+    /// rather than by genome.
+    groups: Option<HashMap<String, Vec<String>>>,
+    min_block_length: u64,
+    min_identity: f64,
+    context: Option<Dinucleotide>,
+    context_exclude: Option<Dinucleotide>,
+    alphabet: &'static dyn Alphabet,
+    /// With a context, the last block seen, which isn't counted until
+    /// the next block shows what reference base comes after it.
+    pending: Option<MAFBlock>,
+    /// The last base of each reference row of the last block counted,
+    /// with its sequence, strand, and end, to find the base before a
+    /// row continuing it.
+    last_ref_bases: Vec<(String, Strand, u64, u8)>,
+    /// Number of reference bases in the context (and regions), aligned
+    /// or not.
+    context_bases: u64,
+}
+
+/// Read a two-column TSV of genome and group names into a map from
+/// each genome to the groups it's in. A genome can be in more than
+/// one group.
+pub fn parse_groups(tsv: impl BufRead) -> HashMap<String, Vec<String>> {
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for line in tsv.lines() {
+        let line = line.expect("Can't read line");
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<_> = line.split('\t').collect();
+        if fields.len() != 2 {
+            panic!(
+                "Expected two tab-separated fields (genome, group), got {:?}",
+                line
+            );
+        }
+        groups
+            .entry(fields[0].to_string())
+            .or_default()
+            .push(fields[1].to_string());
+    }
+    groups
+}
+
+/// Map each leaf of a tree to the named internal nodes (clades) above
+/// it.
+pub fn groups_from_tree(tree: &Tree) -> HashMap<String, Vec<String>> {
+    let mut groups = HashMap::new();
+    for (leaf, node) in tree.leaves() {
+        let mut clades = vec![];
+        let mut parent = tree.nodes[node].parent;
+        while let Some(p) = parent {
+            if let Some(name) = &tree.nodes[p].name {
+                clades.push(name.clone());
+            }
+            parent = tree.nodes[p].parent;
+        }
+        groups.insert(leaf, clades);
+    }
+    groups
 }
 
-fn aligned_base(base: u8) -> bool {
-    match base {
-        b'A' | b'C' | b'G' | b'T' | b'N' | b'a' | b'c' | b'g' | b't' | b'n' => true,
-        _ => false,
+/// Regroup entries keyed by genome into entries keyed by group,
+/// dropping genomes that aren't in any group.
+fn regroup<'a, 'b, T>(
+    entries: &HashMap<&str, Vec<&'b T>>,
+    groups: &'a HashMap<String, Vec<String>>,
+) -> HashMap<&'a str, Vec<&'b T>> {
+    let mut grouped: HashMap<&str, Vec<&T>> = HashMap::new();
+    for (genome, genome_entries) in entries {
+        for group in groups.get(*genome).into_iter().flatten() {
+            grouped
+                .entry(group.as_str())
+                .or_default()
+                .extend(genome_entries.iter().copied());
+        }
     }
+    grouped
+}
+
+/// A dinucleotide context reference bases can be restricted to, like
+/// CG for CpG sites. IUPAC codes match any base they stand for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dinucleotide([u8; 2]);
+
+impl Dinucleotide {
+    pub fn parse(context: &str) -> Self {
+        match context.as_bytes() {
+            [first, second] if iupac_bases(*first).is_some() && iupac_bases(*second).is_some() => {
+                Dinucleotide([*first, *second])
+            }
+            _ => panic!("Context must be two bases or IUPAC codes, like CG"),
+        }
+    }
+
+    fn matches(&self, first: u8, second: u8) -> bool {
+        let matches = |code: u8, base: u8| match (iupac_bases(code), iupac_bases(base)) {
+            (Some(code), Some(base)) => base.count_ones() == 1 && code & base != 0,
+            _ => false,
+        };
+        matches(self.0[0], first) && matches(self.0[1], second)
+    }
+
+    /// Which columns of a row have a base that's part of this context,
+    /// read on the forward strand, given the bases of the sequence just
+    /// before and after the row (on the row's strand).
+    fn column_mask(
+        &self,
+        entry: &MAFBlockAlignedEntry,
+        before: Option<u8>,
+        after: Option<u8>,
+    ) -> Vec<bool> {
+        let mut bases: Vec<Option<u8>> = std::iter::once(before)
+            .chain(
+                entry
+                    .alignment
+                    .iter()
+                    .filter(|c| !is_gap(**c))
+                    .map(|c| Some(*c)),
+            )
+            .chain(std::iter::once(after))
+            .collect();
+        let negative = entry.strand == Strand::Negative;
+        if negative {
+            bases.reverse();
+            for base in bases.iter_mut().flatten() {
+                *base = complement(*base);
+            }
+        }
+        let matches = |first: Option<u8>, second: Option<u8>| match (first, second) {
+            (Some(first), Some(second)) => self.matches(first, second),
+            _ => false,
+        };
+        let mut in_context: Vec<bool> = bases
+            .windows(3)
+            .map(|w| matches(w[0], w[1]) || matches(w[1], w[2]))
+            .collect();
+        if negative {
+            in_context.reverse();
+        }
+        let mut in_context = in_context.into_iter();
+        entry
+            .alignment
+            .iter()
+            .map(|c| !is_gap(*c) && in_context.next().unwrap())
+            .collect()
+    }
+}
+
+/// The column and forward-strand position of each aligned base of a
+/// reference row.
+fn ref_positions<'a>(
+    ref_entry: &'a MAFBlockAlignedEntry,
+    alphabet: &'a dyn Alphabet,
+) -> impl Iterator<Item = (usize, u64)> + 'a {
+    ref_entry
+        .alignment
+        .iter()
+        .enumerate()
+        .filter(move |(_, c)| alphabet.is_aligned(**c))
+        .enumerate()
+        .map(move |(ref_offset, (i, _))| {
+            (
+                i,
+                ref_entry.forward_position(ref_entry.start + ref_offset as u64),
+            )
+        })
+}
+
+/// What to count coverage over, and how to report it.
+#[derive(Default, Clone)]
+pub struct CoverageOptions {
+    /// Only count reference bases within these regions.
+    pub regions: RegionFilter,
+    /// Only count columns where a query genome is aligned within
+    /// regions of its own.
+    pub query_filter: Option<QueryFilter>,
+    /// Genome -> groups it's in, to report coverage by groups of
+    /// genomes instead.
+    pub groups: Option<HashMap<String, Vec<String>>>,
+    /// Skip blocks with fewer reference bases than this.
+    pub min_block_length: u64,
+    /// Skip blocks where less than this fraction of the bases aligned
+    /// to the reference are identical to it.
+    pub min_identity: f64,
+    /// Only count reference bases in this context.
+    pub context: Option<Dinucleotide>,
+    /// Don't count reference bases in this context.
+    pub context_exclude: Option<Dinucleotide>,
+    pub format: CoverageFormat,
+    /// The alphabet whose residues count as aligned.
+    pub alphabet: &'static dyn Alphabet,
 }
 
 impl MAFCoverage {
-    fn new(ref_genome: &str, ranges: Option<BTreeSet<Range>>) -> Self {
+    pub fn new(ref_genome: &str, options: CoverageOptions) -> Self {
         MAFCoverage {
-            coverage: HashMap::new(),
+            coverage: BTreeMap::new(),
+            deleted: BTreeMap::new(),
+            gapped: BTreeMap::new(),
+            counted_bases: 0,
             ref_genome: ref_genome.to_string(),
-            ranges,
+            regions: options.regions,
+            query_filter: options.query_filter,
             ref_lengths: HashMap::new(),
+            groups: options.groups,
+            min_block_length: options.min_block_length,
+            min_identity: options.min_identity,
+            context: options.context,
+            context_exclude: options.context_exclude,
+            alphabet: options.alphabet,
+            pending: None,
+            last_ref_bases: vec![],
+            context_bases: 0,
+        }
+    }
+
+    /// Which columns of a reference row have a base in the context
+    /// (and not in the excluded one), or None if there's no context.
+    /// The bases either side of the row are taken from the previous
+    /// and next blocks, if they have a row continuing it.
+    fn context_mask(
+        &self,
+        ref_entry: &MAFBlockAlignedEntry,
+        next: Option<&MAFBlock>,
+    ) -> Option<Vec<bool>> {
+        if self.context.is_none() && self.context_exclude.is_none() {
+            return None;
+        }
+        let before = self
+            .last_ref_bases
+            .iter()
+            .find(|(seq, strand, end, _)| {
+                *seq == ref_entry.seq && *strand == ref_entry.strand && *end == ref_entry.start
+            })
+            .map(|(_, _, _, base)| *base);
+        let end = ref_entry.start + ref_entry.aligned_length;
+        let after = next.and_then(|next| {
+            next.aligned_entries()
+                .find(|e| e.seq == ref_entry.seq && e.strand == ref_entry.strand && e.start == end)
+                .and_then(|e| e.alignment.iter().find(|c| !is_gap(**c)).copied())
+        });
+        let mask = |context: Option<Dinucleotide>, keep: bool| {
+            context.map(|c| {
+                c.column_mask(ref_entry, before, after)
+                    .into_iter()
+                    .map(|in_context| in_context == keep)
+                    .collect::<Vec<bool>>()
+            })
+        };
+        let mut included =
+            mask(self.context, true).unwrap_or_else(|| vec![true; ref_entry.alignment.len()]);
+        if let Some(excluded) = mask(self.context_exclude, false) {
+            for (included, not_excluded) in included.iter_mut().zip(excluded) {
+                *included = *included && not_excluded;
+            }
+        }
+        Some(included)
+    }
+
+    /// Whether a block passes the length and identity thresholds, as
+    /// measured against a reference row.
+    fn passes_thresholds(&self, block: &MAFBlock, ref_entry: &MAFBlockAlignedEntry) -> bool {
+        ref_entry.aligned_length >= self.min_block_length
+            && (self.min_identity <= 0.0
+                || block_identity(block, ref_entry).is_some_and(|i| i >= self.min_identity))
+    }
+
+    /// Count a block. With a context, the block is held back until the
+    /// next one is added (or `finish` is called), so that the reference
+    /// base after it is known.
+    pub fn add_block(&mut self, block: MAFBlock) {
+        if self.context.is_none() && self.context_exclude.is_none() {
+            self.count_block(&block, None);
+        } else if let Some(pending) = self.pending.replace(block) {
+            let next = self.pending.take();
+            self.count_block(&pending, next.as_ref());
+            self.pending = next;
+        }
+    }
+
+    /// Count the block held back, if any. Must be called after the last
+    /// block is added.
+    pub fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.count_block(&pending, None);
         }
     }
 
-    fn add_block(&mut self, block: MAFBlock) {
-        dbg!(format!("{}", block));
+    fn count_block(&mut self, block: &MAFBlock, next: Option<&MAFBlock>) {
         let entries = block.entries_as_hash();
+        let mut unaligned_entries: HashMap<&str, Vec<&MAFBlockUnalignedEntry>> = HashMap::new();
+        for entry in block.unaligned_entries() {
+            unaligned_entries
+                .entry(entry.seq.split('.').next().unwrap())
+                .or_default()
+                .push(entry);
+        }
         let ref_entries_opt = entries.get::<str>(&self.ref_genome);
         if let Some(ref_entries) = ref_entries_opt {
+            let mask = self.query_filter.as_ref().map(|q| q.column_mask(block));
+            // With groups, a group covers a base if any of its genomes
+            // do.
+            let groups = self.groups.take();
+            let (entries, unaligned_entries) = match groups.as_ref() {
+                Some(groups) => (
+                    regroup(&entries, groups),
+                    regroup(&unaligned_entries, groups),
+                ),
+                None => (entries.clone(), unaligned_entries),
+            };
             for ref_entry in ref_entries {
-                self.add_block_with_ref_entry(ref_entry, &entries);
+                let context_mask = self.context_mask(ref_entry, next);
+                if !self.passes_thresholds(block, ref_entry) {
+                    // Still count the sequence in the total length.
+                    self.ref_lengths
+                        .entry(ref_entry.seq.clone())
+                        .or_insert(ref_entry.sequence_size);
+                    if let Some(context_mask) = context_mask {
+                        self.context_bases += ref_positions(ref_entry, self.alphabet)
+                            .filter(|(i, pos)| {
+                                context_mask[*i] && self.in_range(&chrom_part(&ref_entry.seq), *pos)
+                            })
+                            .count() as u64;
+                    }
+                    continue;
+                }
+                self.add_block_with_ref_entry(
+                    ref_entry,
+                    &entries,
+                    &unaligned_entries,
+                    mask.as_deref(),
+                    context_mask.as_deref(),
+                );
             }
+            self.groups = groups;
+            self.last_ref_bases = ref_entries
+                .iter()
+                .filter_map(|e| {
+                    let base = *e.alignment.iter().rev().find(|c| !is_gap(**c))?;
+                    Some((e.seq.clone(), e.strand, e.start + e.aligned_length, base))
+                })
+                .collect();
         }
     }
 
@@ -48,29 +423,34 @@ impl MAFCoverage {
         &mut self,
         ref_entry: &MAFBlockAlignedEntry,
         entries: &HashMap<&str, Vec<&MAFBlockAlignedEntry>>,
+        unaligned_entries: &HashMap<&str, Vec<&MAFBlockUnalignedEntry>>,
+        query_mask: Option<&[bool]>,
+        context_mask: Option<&[bool]>,
     ) {
-        // Offset within reference sequence (different than offset within block alignment)
-        let mut ref_offset = 0;
-        for i in 0..ref_entry.alignment.len() {
+        for (i, ref_pos) in ref_positions(ref_entry, self.alphabet) {
             // Within each column, add a base of coverage to a genome if:
             // - at least one entry in the genome is aligned (not a gap)
             // - the reference is aligned (not a gap)
             // - the reference base covered by the BED file (if provided)
-            if !aligned_base(ref_entry.alignment[i]) {
+            // - the reference base is in the context (if provided)
+            // - the query genome is aligned within the query BED (if provided)
+            if !self.in_range(&chrom_part(&ref_entry.seq), ref_pos) {
                 continue;
             }
-            let ref_pos = match ref_entry.strand {
-                Strand::Positive => ref_entry.start + ref_offset,
-                Strand::Negative => ref_entry.sequence_size - ref_entry.start - ref_offset,
-            };
-            ref_offset += 1;
-            if !self.in_range(&chrom_part(&ref_entry.seq), ref_pos) {
+            if let Some(context_mask) = context_mask {
+                if !context_mask[i] {
+                    continue;
+                }
+                self.context_bases += 1;
+            }
+            if query_mask.is_some_and(|mask| !mask[i]) {
                 continue;
             }
+            self.counted_bases += 1;
             for (genome, genome_entries) in entries {
                 let mut found_alignment = false;
                 for genome_entry in genome_entries {
-                    if aligned_base(genome_entry.alignment[i]) {
+                    if self.alphabet.is_aligned(genome_entry.alignment[i]) {
                         found_alignment = true;
                         break;
                     }
@@ -81,6 +461,28 @@ impl MAFCoverage {
                     }
                     let coverage = self.coverage.get_mut(*genome).unwrap();
                     *coverage += 1;
+                } else {
+                    *self.gapped.entry((*genome).to_string()).or_insert(0) += 1;
+                }
+            }
+            for (genome, genome_entries) in unaligned_entries {
+                if entries.contains_key(genome) {
+                    continue;
+                }
+                if genome_entries
+                    .iter()
+                    .any(|e| e.status == UnalignedContextStatus::Deletion)
+                {
+                    *self.deleted.entry((*genome).to_string()).or_insert(0) += 1;
+                } else if genome_entries
+                    .iter()
+                    .any(|e| e.status != UnalignedContextStatus::MissingData)
+                {
+                    *self.gapped.entry((*genome).to_string()).or_insert(0) += 1;
+                } else {
+                    // Missing data, but make sure the genome still gets
+                    // reported.
+                    self.gapped.entry((*genome).to_string()).or_insert(0);
                 }
             }
         }
@@ -90,56 +492,152 @@ impl MAFCoverage {
         }
     }
 
-    fn print(&self, output: &mut dyn Write) {
-        writeln!(output, "# referenceSpecies/Chr\tquerySpecies/Chr\tlengthOfReference\tpercentCoverage\tbasesCoverage").ok();
-        let total: u64 = match &self.ranges {
-            None => self.ref_lengths.values().sum(),
-            Some(set) => set.iter().map(|p| p.end - p.start).sum(),
-        };
-        for (genome, coverage) in self.coverage.iter() {
-            writeln!(
-                output,
-                "{}\t{}\t{}\t{}\t{}",
-                self.ref_genome,
-                genome,
-                total,
-                (*coverage as f64) / (total as f64),
-                coverage
+    /// One row per query genome, in name order.
+    fn rows(&self) -> Vec<CoverageRow> {
+        // Only aligned reference bases can be checked for a context, so
+        // with one, the reference length is that of the alignment.
+        let total = if self.context.is_some() || self.context_exclude.is_some() {
+            self.context_bases
+        } else {
+            self.regions.total_length(
+                self.ref_lengths
+                    .iter()
+                    .map(|(seq, length)| (chrom_part(seq), *length)),
             )
-            .ok();
+        };
+        let genomes: BTreeSet<&String> = self
+            .coverage
+            .keys()
+            .chain(self.deleted.keys())
+            .chain(self.gapped.keys())
+            .collect();
+        genomes
+            .into_iter()
+            .map(|genome| {
+                let covered = self.coverage.get(genome).cloned().unwrap_or(0);
+                let deleted = self.deleted.get(genome).cloned().unwrap_or(0);
+                let gapped = self.gapped.get(genome).cloned().unwrap_or(0);
+                CoverageRow {
+                    query: genome.clone(),
+                    reference_length: total,
+                    covered,
+                    deleted,
+                    missing: self.counted_bases - covered - deleted - gapped,
+                }
+            })
+            .collect()
+    }
+
+    /// Add in the totals from a calculation over other blocks, with the
+    /// same options.
+    pub fn merge(&mut self, other: MAFCoverage) {
+        for (genome, count) in other.coverage {
+            *self.coverage.entry(genome).or_insert(0) += count;
+        }
+        for (genome, count) in other.deleted {
+            *self.deleted.entry(genome).or_insert(0) += count;
+        }
+        for (genome, count) in other.gapped {
+            *self.gapped.entry(genome).or_insert(0) += count;
+        }
+        self.counted_bases += other.counted_bases;
+        self.context_bases += other.context_bases;
+        for (seq, length) in other.ref_lengths {
+            self.ref_lengths.entry(seq).or_insert(length);
         }
     }
 
-    fn in_range(&self, chrom: &str, position: u64) -> bool {
-        match &self.ranges {
-            None => true,
-            Some(set) => range_contains_pos(set, chrom, position),
+    pub fn print(&self, output: &mut dyn Write, format: CoverageFormat) {
+        let rows = self.rows();
+        match format {
+            CoverageFormat::Tsv => {
+                writeln!(output, "{}", COLUMNS.join("\t")).ok();
+                for row in rows {
+                    writeln!(
+                        output,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        self.ref_genome,
+                        row.query,
+                        row.reference_length,
+                        row.fraction_covered(),
+                        row.covered,
+                        row.deleted,
+                        row.missing
+                    )
+                    .ok();
+                }
+            }
+            CoverageFormat::Json => {
+                let records: Vec<_> = rows
+                    .iter()
+                    .map(|row| {
+                        json!({
+                            "reference": self.ref_genome,
+                            "query": row.query,
+                            "reference_length": row.reference_length,
+                            "fraction_covered": row.fraction_covered(),
+                            "bases_covered": row.covered,
+                            "bases_deleted": row.deleted,
+                            "bases_missing": row.missing,
+                        })
+                    })
+                    .collect();
+                serde_json::to_writer_pretty(&mut *output, &records).ok();
+                writeln!(output).ok();
+            }
+            CoverageFormat::Markdown => {
+                writeln!(output, "| {} |", COLUMNS.join(" | ")).ok();
+                writeln!(output, "|{}", "---|".repeat(COLUMNS.len())).ok();
+                for row in rows {
+                    writeln!(
+                        output,
+                        "| {} | {} | {} | {:.4} | {} | {} | {} |",
+                        self.ref_genome,
+                        row.query,
+                        row.reference_length,
+                        row.fraction_covered(),
+                        row.covered,
+                        row.deleted,
+                        row.missing
+                    )
+                    .ok();
+                }
+            }
         }
     }
+
+    fn in_range(&self, chrom: &str, position: u64) -> bool {
+        self.regions.contains(chrom, position)
+    }
 }
 
 pub fn coverage(
     input: &mut dyn BufRead,
     output: &mut dyn Write,
     ref_genome: &str,
-    bed: Option<impl BufRead>,
-) {
-    let ranges = bed.map(parse_bed);
-
-    let mut maf_coverage = MAFCoverage::new(ref_genome, ranges);
+    options: CoverageOptions,
+) -> Result<(), MAFParseError> {
+    let format = options.format;
+    let mut maf_coverage = MAFCoverage::new(ref_genome, options);
 
-    while let Ok(item) = next_maf_item(input) {
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
         if let MAFItem::Block(block) = item {
             maf_coverage.add_block(block);
         }
     }
+    maf_coverage.finish();
 
-    maf_coverage.print(output);
+    maf_coverage.print(output, format);
+    items.finish()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{parse_bed, Range};
+    use multiple_alignment_format::parser::next_maf_item;
+    use std::collections::BTreeSet;
 
     #[test]
     fn test_in_range() {
@@ -167,7 +665,13 @@ mod tests {
         ]
         .into_iter()
         .collect();
-        let maf_coverage = MAFCoverage::new("none", Some(ranges));
+        let maf_coverage = MAFCoverage::new(
+            "none",
+            CoverageOptions {
+                regions: RegionFilter::new(Some(ranges), None),
+                ..Default::default()
+            },
+        );
         assert!(!maf_coverage.in_range("chr0", 0));
         assert!(maf_coverage.in_range("chr1", 20));
         assert!(maf_coverage.in_range("chr1", 21));
@@ -181,6 +685,7 @@ mod tests {
 
     // Simple test (no region filtering)
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_add_block_no_bed() {
         let block = "a
 s       Erythrocercus_mccallii.scaffold_2093    58535   2       +       127396  T-G
@@ -191,7 +696,8 @@ s       Geospiza_fortis.scaffold54      15705654        3       -       19033121
 s       Glareola_pratincola.scaffold_8  396272  3       -       2357087 -C-
 s       Glaucidium_brasilianum.scaffold_161     1648450 3       -       1875072 TTT
 ";
-        let mut maf_coverage = MAFCoverage::new("Erythrocercus_mccallii", None);
+        let mut maf_coverage =
+            MAFCoverage::new("Erythrocercus_mccallii", CoverageOptions::default());
         let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
         if let MAFItem::Block(block) = item {
             maf_coverage.add_block(block);
@@ -206,6 +712,7 @@ s       Glaucidium_brasilianum.scaffold_161     1648450 3       -       1875072
 
     // Test w/ multiple reference entries
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_add_block_multi_ref() {
         let block = "a
 s       Erythrocercus_mccallii.scaffold_2093    58535   2       +       127396  T-G
@@ -217,7 +724,8 @@ s       Geospiza_fortis.scaffold54      15705654        3       -       19033121
 s       Glareola_pratincola.scaffold_8  396272  3       -       2357087 -C-
 s       Glaucidium_brasilianum.scaffold_161     1648450 3       -       1875072 TTT
 ";
-        let mut maf_coverage = MAFCoverage::new("Erythrocercus_mccallii", None);
+        let mut maf_coverage =
+            MAFCoverage::new("Erythrocercus_mccallii", CoverageOptions::default());
         let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
         if let MAFItem::Block(block) = item {
             maf_coverage.add_block(block);
@@ -232,6 +740,7 @@ s       Glaucidium_brasilianum.scaffold_161     1648450 3       -       1875072
 
     // Test with region filtering
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_add_block_with_bed() {
         let block = "a
 s       Erythrocercus_mccallii.scaffold_2093    58535   2       +       127396  T-G
@@ -249,7 +758,13 @@ s       Glaucidium_brasilianum.scaffold_161     1648450 3       -       1875072
         }]
         .into_iter()
         .collect();
-        let mut maf_coverage = MAFCoverage::new("Erythrocercus_mccallii", Some(regions));
+        let mut maf_coverage = MAFCoverage::new(
+            "Erythrocercus_mccallii",
+            CoverageOptions {
+                regions: RegionFilter::new(Some(regions), None),
+                ..Default::default()
+            },
+        );
         let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
         if let MAFItem::Block(block) = item {
             maf_coverage.add_block(block);
@@ -277,12 +792,238 @@ s       Glaucidium_brasilianum.scaffold_161     1648450 3       -       1875072
         } else {
             assert!(false, "Got unexpected maf item {:?}", item);
         }
-        assert_eq!(maf_coverage.coverage["Gavia_stellata"], 2);
-        assert!(!maf_coverage.coverage.contains_key("Geospiza_fortis"));
-        assert_eq!(maf_coverage.coverage["Erythrocercus_mccallii"], 2);
+        // Both reference bases (forward-strand positions 58537 and
+        // 58536) are in the region.
+        assert_eq!(maf_coverage.coverage["Gavia_stellata"], 3);
+        assert_eq!(maf_coverage.coverage["Geospiza_fortis"], 1);
+        assert_eq!(maf_coverage.coverage["Erythrocercus_mccallii"], 3);
         assert!(!maf_coverage.coverage.contains_key("Glareola_pratincola"));
     }
 
+    // Test with query-side region filtering
+    #[test]
+    fn test_add_block_with_query_bed() {
+        let block = "a
+s       Erythrocercus_mccallii.scaffold_2093    58535   2       +       127396  T-G
+s       Galbula_dea.scaffold1422        3938    3       -       1348798 CCC
+s       Gavia_stellata.scaffold9486     35556   3       +       49599   TTT
+s       Geospiza_fortis.scaffold54      15705654        2       -       19033121        TT-
+";
+        let query_regions: BTreeSet<_> = vec![Range {
+            seq: "scaffold9486".to_string(),
+            start: 35558,
+            end: 35559,
+        }]
+        .into_iter()
+        .collect();
+        let mut maf_coverage = MAFCoverage::new(
+            "Erythrocercus_mccallii",
+            CoverageOptions {
+                query_filter: Some(QueryFilter::new(
+                    "Gavia_stellata",
+                    RegionFilter::new(Some(query_regions), None),
+                )),
+                ..Default::default()
+            },
+        );
+        let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
+        if let MAFItem::Block(block) = item {
+            maf_coverage.add_block(block);
+        } else {
+            panic!("Got unexpected maf item {:?}", item);
+        }
+        assert_eq!(maf_coverage.coverage["Gavia_stellata"], 1);
+        assert_eq!(maf_coverage.coverage["Galbula_dea"], 1);
+        assert_eq!(maf_coverage.coverage["Erythrocercus_mccallii"], 1);
+        assert!(!maf_coverage.coverage.contains_key("Geospiza_fortis"));
+    }
+
+    // Test classification of uncovered bases using e lines
+    #[test]
+    fn test_add_block_e_lines() {
+        let block = "a
+s       Erythrocercus_mccallii.scaffold_2093    58535   2       +       127396  T-G
+s       Gavia_stellata.scaffold9486     35556   3       +       49599   T-T
+e       Galbula_dea.scaffold1422        3938    300     -       1348798 C
+e       Geospiza_fortis.scaffold54      15705654        3000    -       19033121        I
+e       Glareola_pratincola.scaffold_8  396272  3       -       2357087 M
+";
+        let mut maf_coverage =
+            MAFCoverage::new("Erythrocercus_mccallii", CoverageOptions::default());
+        let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
+        if let MAFItem::Block(block) = item {
+            maf_coverage.add_block(block);
+        } else {
+            panic!("Got unexpected maf item {:?}", item);
+        }
+        assert_eq!(maf_coverage.counted_bases, 2);
+        assert_eq!(maf_coverage.coverage["Gavia_stellata"], 2);
+        assert_eq!(maf_coverage.deleted["Galbula_dea"], 2);
+        assert_eq!(maf_coverage.gapped["Geospiza_fortis"], 2);
+        assert_eq!(maf_coverage.gapped["Glareola_pratincola"], 0);
+
+        let mut output = vec![];
+        maf_coverage.print(&mut output, CoverageFormat::Tsv);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("reference\tquery\treference_length\tfraction_covered\t"));
+        assert!(output.contains("Erythrocercus_mccallii\tGalbula_dea\t127396\t0\t0\t2\t0\n"));
+        assert!(output.contains("Erythrocercus_mccallii\tGeospiza_fortis\t127396\t0\t0\t0\t0\n"));
+        assert!(
+            output.contains("Erythrocercus_mccallii\tGlareola_pratincola\t127396\t0\t0\t0\t2\n")
+        );
+    }
+
+    #[test]
+    fn test_coverage_formats() {
+        let maf = "a
+s hg38.chr1 0 4 + 10 ACGT
+s rn7.chr3 0 2 + 10 A--T
+s mm39.chr2 0 4 + 10 ACGT
+";
+        let run = |format| {
+            let mut output = vec![];
+            coverage(
+                &mut maf.as_bytes(),
+                &mut output,
+                "hg38",
+                CoverageOptions {
+                    format,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            run(CoverageFormat::Markdown),
+            "| reference | query | reference_length | fraction_covered | bases_covered | bases_deleted | bases_missing |
+|---|---|---|---|---|---|---|
+| hg38 | hg38 | 10 | 0.4000 | 4 | 0 | 0 |
+| hg38 | mm39 | 10 | 0.4000 | 4 | 0 | 0 |
+| hg38 | rn7 | 10 | 0.2000 | 2 | 0 | 0 |
+"
+        );
+        let json: serde_json::Value = serde_json::from_str(&run(CoverageFormat::Json)).unwrap();
+        assert_eq!(json[2]["query"], "rn7");
+        assert_eq!(json[2]["bases_covered"], 2);
+        assert_eq!(json[2]["fraction_covered"], 0.2);
+    }
+
+    #[test]
+    fn test_coverage_by_group() {
+        let maf = "a
+s hg38.chr1 0 4 + 10 ACGT
+s rn7.chr3 0 2 + 10 A--T
+s mm39.chr2 0 2 + 10 -CG-
+e bosTau9.chr1 0 5 + 10 C
+";
+        let groups = parse_groups("rn7\trodents\nmm39\trodents\nbosTau9\tcow\n".as_bytes());
+        let tree_groups = groups_from_tree(&Tree::parse_newick(
+            "(hg38,((rn7,mm39)rodents,bosTau9)glires);",
+        ));
+        assert_eq!(tree_groups["rn7"], vec!["rodents", "glires"]);
+        for groups in [groups, tree_groups].iter() {
+            let mut output = vec![];
+            coverage(
+                &mut maf.as_bytes(),
+                &mut output,
+                "hg38",
+                CoverageOptions {
+                    groups: Some(groups.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            // Rat and mouse each cover half of the reference bases, but
+            // together they cover all of them.
+            assert!(output.contains("hg38\trodents\t10\t0.4\t4\t0\t0\n"));
+            assert!(!output.contains("\trn7\t"));
+        }
+    }
+
+    #[test]
+    fn test_coverage_thresholds() {
+        let maf = "a
+s hg38.chr1 0 4 + 10 ACGT
+s mm39.chr2 0 4 + 10 ACGA
+
+a
+s hg38.chr1 4 2 + 10 AC
+s mm39.chr2 4 2 + 10 AC
+
+a
+s hg38.chr1 6 4 + 10 ACGT
+s mm39.chr2 6 4 + 10 TTTT
+";
+        let mut output = vec![];
+        coverage(
+            &mut maf.as_bytes(),
+            &mut output,
+            "hg38",
+            CoverageOptions {
+                min_block_length: 3,
+                min_identity: 0.7,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // Only the first block is long enough and similar enough.
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("hg38\tmm39\t10\t0.4\t4\t0\t0\n"));
+    }
+
+    #[test]
+    fn test_coverage_context() {
+        // The reference is ACGCGTA, with a CpG spanning the blocks.
+        let maf = "a
+s hg38.chr1 0 4 + 20 AC-GC
+s mm39.chr2 0 5 + 20 ACTGC
+
+a
+s hg38.chr1 4 3 + 20 GTA
+s mm39.chr2 5 2 + 20 -TA
+";
+        let run = |options: CoverageOptions| {
+            let mut output = vec![];
+            coverage(&mut maf.as_bytes(), &mut output, "hg38", options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let cpg = run(CoverageOptions {
+            context: Some(Dinucleotide::parse("CG")),
+            ..Default::default()
+        });
+        assert!(cpg.contains("hg38\tmm39\t4\t0.75\t3\t0\t0\n"));
+        let non_cpg = run(CoverageOptions {
+            context_exclude: Some(Dinucleotide::parse("CG")),
+            ..Default::default()
+        });
+        assert!(non_cpg.contains("hg38\tmm39\t3\t1\t3\t0\t0\n"));
+    }
+
+    #[test]
+    fn test_context_negative_strand() {
+        let block = "a
+s hg38.chr1 0 3 - 20 TG-A
+";
+        let block = match next_maf_item(&mut block.as_bytes()).unwrap() {
+            MAFItem::Block(block) => block,
+            _ => panic!("Expected a block"),
+        };
+        let entry = block.aligned_entries().next().unwrap();
+        // The forward strand is TCA, so the CA is the last two bases.
+        // The G after the row comes before it on the forward strand.
+        assert_eq!(
+            Dinucleotide::parse("CA").column_mask(entry, None, None),
+            vec![true, true, false, false]
+        );
+        assert_eq!(
+            Dinucleotide::parse("CT").column_mask(entry, None, Some(b'G')),
+            vec![false, false, false, true]
+        );
+    }
+
     #[test]
     fn test_parse_bed() {
         let bed = "
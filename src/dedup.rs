@@ -0,0 +1,259 @@
+use maf_stream::{write_comment, MAFItems, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFItem, Strand};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use tempfile::tempfile;
+
+/// A row's sequence, start, length, and whether it's on the positive
+/// strand.
+type RowKey = (String, u64, u64, bool);
+
+/// The normalized block: the reference row followed by the (sorted)
+/// coordinates of every row. Blocks with equal keys are duplicates.
+type BlockKey = (RowKey, Vec<RowKey>);
+
+fn block_key(block: &MAFBlock) -> Option<BlockKey> {
+    let row_key = |e: &MAFBlockAlignedEntry| {
+        (
+            e.seq.clone(),
+            e.start,
+            e.aligned_length,
+            e.strand == Strand::Positive,
+        )
+    };
+    let ref_entry = block.aligned_entries().next()?;
+    let mut rows: Vec<_> = block.aligned_entries().map(row_key).collect();
+    rows.sort();
+    Some((row_key(ref_entry), rows))
+}
+
+fn block_score(block: &MAFBlock) -> f64 {
+    block
+        .metadata
+        .get("score")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+/// The best-scoring copy of each distinct block seen, by its index
+/// among the blocks added.
+#[derive(Default)]
+struct BestCopies {
+    best: HashMap<BlockKey, (f64, usize)>,
+    num_blocks: usize,
+}
+
+impl BestCopies {
+    fn add(&mut self, block: &MAFBlock) {
+        if let Some(key) = block_key(block) {
+            let score = block_score(block);
+            let entry = self.best.entry(key).or_insert((score, self.num_blocks));
+            if score > entry.0 {
+                *entry = (score, self.num_blocks);
+            }
+        }
+        self.num_blocks += 1;
+    }
+
+    /// Whether the block with the given index (which must have been
+    /// added) is the one to keep.
+    fn keeps(&self, block: &MAFBlock, index: usize) -> bool {
+        match block_key(block) {
+            Some(key) => self.best[&key].1 == index,
+            None => true,
+        }
+    }
+}
+
+/// Drop blocks that have the same reference interval and row
+/// coordinates as another block, keeping the copy with the highest
+/// score (or the first copy, on ties). The input is spooled to a
+/// temporary file; only the coordinates and score of each distinct
+/// block are kept in memory.
+pub fn dedup(input: &mut dyn BufRead, output: &mut dyn MAFOutput) -> Result<usize, MAFParseError> {
+    let mut spool = BufWriter::new(tempfile().expect("Couldn't open temporary file"));
+    let mut best = BestCopies::default();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                writeln!(spool, "#{}", comment).expect("failed to write");
+            }
+            MAFItem::Block(block) => {
+                best.add(&block);
+                write!(spool, "{}", block).expect("failed to write");
+            }
+        }
+    }
+    let result = items.finish();
+
+    let mut spool = spool.into_inner().expect("failed to write");
+    spool
+        .seek(SeekFrom::Start(0))
+        .expect("Couldn't rewind temporary file");
+    let mut spool = BufReader::new(spool);
+    let mut block_idx = 0;
+    let mut num_dropped = 0;
+    let mut spooled = MAFItems::new(&mut spool);
+    for item in spooled.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                write_comment(output, &comment);
+            }
+            MAFItem::Block(block) => {
+                if best.keeps(&block, block_idx) {
+                    output.write_block(&block);
+                } else {
+                    num_dropped += 1;
+                }
+                block_idx += 1;
+            }
+        }
+    }
+    spooled
+        .finish()
+        .expect("Couldn't read back the temporary file");
+    result?;
+    Ok(num_dropped)
+}
+
+/// Write the items held for one reference start, dropping the
+/// duplicate blocks among them. Returns the number dropped.
+fn flush_duplicates(held: &mut Vec<MAFItem>, output: &mut dyn MAFOutput) -> usize {
+    let mut best = BestCopies::default();
+    for item in held.iter() {
+        if let MAFItem::Block(block) = item {
+            best.add(block);
+        }
+    }
+    let mut block_idx = 0;
+    let mut num_dropped = 0;
+    for item in held.drain(..) {
+        match item {
+            MAFItem::Comment(comment) => write_comment(output, &comment),
+            MAFItem::Block(block) => {
+                if best.keeps(&block, block_idx) {
+                    output.write_block(&block);
+                } else {
+                    num_dropped += 1;
+                }
+                block_idx += 1;
+            }
+        }
+    }
+    num_dropped
+}
+
+/// `dedup` for input sorted by reference position, in a single pass.
+/// Duplicates share their reference start, so only the blocks starting
+/// at one position are held in memory at a time. Panics if the input
+/// turns out not to be sorted.
+pub fn dedup_sorted(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+) -> Result<usize, MAFParseError> {
+    let mut held = vec![];
+    let mut position: Option<(String, u64)> = None;
+    let mut done_seqs = HashSet::new();
+    let mut num_dropped = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = &item {
+            if let Some(ref_entry) = block.aligned_entries().next() {
+                let start = ref_entry.forward_interval().0;
+                match &position {
+                    Some((seq, last)) if *seq == ref_entry.seq && *last == start => (),
+                    Some((seq, last)) if *seq == ref_entry.seq && *last > start => {
+                        panic!(
+                            "Input isn't sorted: {} goes back from {} to {}",
+                            seq, last, start
+                        )
+                    }
+                    _ => {
+                        num_dropped += flush_duplicates(&mut held, output);
+                        if let Some((seq, _)) = position.take() {
+                            if seq != ref_entry.seq {
+                                done_seqs.insert(seq);
+                            }
+                        }
+                        if done_seqs.contains(&ref_entry.seq) {
+                            panic!("Input isn't sorted: {} comes back", ref_entry.seq);
+                        }
+                        position = Some((ref_entry.seq.clone(), start));
+                    }
+                }
+            }
+        }
+        held.push(item);
+    }
+    num_dropped += flush_duplicates(&mut held, output);
+    items.finish()?;
+    Ok(num_dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a score=1
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+
+a score=5
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACTT
+
+a score=3
+s hg38.chr1 0 4 + 100 ACGT
+s rn7.chr2 10 4 + 200 ACGT
+
+a score=2
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+
+a score=1
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 14 4 + 200 ACGT
+";
+
+    const DEDUPED: &str = "a score=5
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACTT
+
+a score=3
+s hg38.chr1 0 4 + 100 ACGT
+s rn7.chr2 10 4 + 200 ACGT
+
+a score=1
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 14 4 + 200 ACGT
+
+";
+
+    #[test]
+    fn test_dedup() {
+        let mut output = vec![];
+        assert_eq!(dedup(&mut MAF.as_bytes(), &mut output).unwrap(), 2);
+        assert_eq!(String::from_utf8(output).unwrap(), DEDUPED);
+    }
+
+    #[test]
+    fn test_dedup_sorted() {
+        let mut output = vec![];
+        assert_eq!(dedup_sorted(&mut MAF.as_bytes(), &mut output).unwrap(), 2);
+        assert_eq!(String::from_utf8(output).unwrap(), DEDUPED);
+    }
+
+    #[test]
+    #[should_panic(expected = "Input isn't sorted")]
+    fn test_dedup_sorted_unsorted() {
+        let maf = "a
+s hg38.chr1 4 4 + 100 ACGT
+
+a
+s hg38.chr1 0 4 + 100 ACGT
+";
+        dedup_sorted(&mut maf.as_bytes(), &mut vec![]).unwrap();
+    }
+}
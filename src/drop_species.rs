@@ -0,0 +1,216 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::transform::{transform_blocks, BlockTransform};
+use maf_stream::MAFOutput;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry};
+use std::collections::HashSet;
+use std::io::BufRead;
+
+fn species_of(seq: &str) -> &str {
+    seq.split('.').next().unwrap()
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Removes the rows (and "e" lines) of some species from every block.
+/// Columns left with only gaps are collapsed. The remaining rows may no
+/// longer all be aligned to the reference (the first remaining row), if
+/// they were only aligned to it through the dropped species: those are
+/// dropped too, so the block still starts with the reference, which is
+/// kept even if nothing is left aligned to it. Rows keep their
+/// coordinates, since only gap columns are removed from them. Blocks
+/// with no rows of the species are left alone.
+pub struct DropSpecies {
+    species: HashSet<String>,
+    pub num_rows_dropped: usize,
+    pub num_blocks_cut: usize,
+}
+
+impl DropSpecies {
+    pub fn new(species: HashSet<String>) -> Self {
+        DropSpecies {
+            species,
+            num_rows_dropped: 0,
+            num_blocks_cut: 0,
+        }
+    }
+}
+
+impl BlockTransform for DropSpecies {
+    fn transform(&mut self, mut block: MAFBlock) -> Vec<MAFBlock> {
+        let num_entries = block.entries.len();
+        block.entries.retain(|entry| match entry {
+            MAFBlockEntry::AlignedEntry(e) => !self.species.contains(species_of(&e.seq)),
+            MAFBlockEntry::UnalignedEntry(e) => !self.species.contains(species_of(&e.seq)),
+        });
+        if block.entries.len() == num_entries {
+            return vec![block];
+        }
+        self.num_rows_dropped += num_entries - block.entries.len();
+        let rows: Vec<&[u8]> = block
+            .aligned_entries()
+            .map(|entry| &entry.alignment[..])
+            .collect();
+        let width = rows.first().map_or(0, |row| row.len());
+        // Group the rows aligned to each other, directly or through
+        // other rows.
+        let mut parents: Vec<usize> = (0..rows.len()).collect();
+        for column in 0..width {
+            let mut first = None;
+            for (i, row) in rows.iter().enumerate() {
+                if is_gap(row[column]) {
+                    continue;
+                }
+                match first {
+                    None => first = Some(i),
+                    Some(first) => {
+                        let (a, b) = (find(&mut parents, first), find(&mut parents, i));
+                        parents[b] = a;
+                    }
+                }
+            }
+        }
+        let groups: Vec<usize> = (0..rows.len()).map(|i| find(&mut parents, i)).collect();
+        let root = match groups.first() {
+            Some(root) => *root,
+            None => return vec![],
+        };
+        let num_cut = groups.iter().filter(|g| **g != root).count();
+        if num_cut > 0 {
+            self.num_rows_dropped += num_cut;
+            self.num_blocks_cut += 1;
+        }
+        let columns: Vec<usize> = (0..width)
+            .filter(|column| {
+                rows.iter()
+                    .zip(groups.iter())
+                    .any(|(row, group)| *group == root && !is_gap(row[*column]))
+            })
+            .collect();
+        let mut row_groups = groups.iter();
+        let entries = block
+            .entries
+            .iter()
+            .filter(|entry| match entry {
+                MAFBlockEntry::AlignedEntry(_) => row_groups.next() == Some(&root),
+                MAFBlockEntry::UnalignedEntry(_) => true,
+            })
+            .map(|entry| match entry {
+                MAFBlockEntry::AlignedEntry(e) => {
+                    let mut e = e.clone();
+                    e.alignment = columns.iter().map(|c| e.alignment[*c]).collect();
+                    if let Some(qualities) = e.qualities.as_mut() {
+                        *qualities = columns.iter().map(|c| qualities[*c]).collect();
+                    }
+                    MAFBlockEntry::AlignedEntry(e)
+                }
+                MAFBlockEntry::UnalignedEntry(_) => entry.clone(),
+            })
+            .collect();
+        vec![MAFBlock {
+            entries,
+            metadata: block.metadata,
+        }]
+    }
+}
+
+/// Drop the species' rows from every block, with `DropSpecies`.
+/// Returns the numbers of rows dropped and of blocks that lost rows no
+/// longer aligned to the reference.
+pub fn drop_species(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    species: HashSet<String>,
+) -> Result<(usize, usize), MAFParseError> {
+    let mut dropper = DropSpecies::new(species);
+    transform_blocks(input, output, &mut dropper)?;
+    Ok((dropper.num_rows_dropped, dropper.num_blocks_cut))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_species() {
+        let maf = "##maf version=1
+a score=5
+s hg38.chr1 0 4 + 100 AC--GT
+s panTro6.chr1 0 6 + 100 ACTTGT
+s mm39.chr2 0 2 + 100 --TT--
+s rn7.chr1 0 4 + 100 AC--GA
+s rheMac10.chr3 0 2 + 100 --TA--
+e galGal6.chr1 0 10 + 100 I
+e panTro6.chr9 0 10 + 100 I
+
+a
+s hg38.chr1 10 2 + 100 A-C
+q hg38.chr1                9-8
+s panTro6.chr1 10 3 + 100 AGC
+s mm39.chr2 10 2 + 100 A-C
+
+a
+s hg38.chr1 20 2 + 100 AC
+s panTro6.chr1 20 2 + 100 AC
+
+a
+s hg38.chr1 30 2 + 100 AC
+";
+        let species = vec!["panTro6".to_string()].into_iter().collect();
+        let mut output = vec![];
+        let counts = drop_species(&mut maf.as_bytes(), &mut output, species).unwrap();
+        // Four panTro6 rows and lines, and mm39 and rheMac10 in the
+        // first block, which were only aligned to hg38 through panTro6.
+        assert_eq!(counts, (6, 1));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a score=5
+s hg38.chr1 0 4 + 100 ACGT
+s rn7.chr1 0 4 + 100 ACGA
+e galGal6.chr1 0 10 + 100 I
+
+a
+s hg38.chr1 10 2 + 100 AC
+q hg38.chr1 98
+s mm39.chr2 10 2 + 100 AC
+
+a
+s hg38.chr1 20 2 + 100 AC
+
+a
+s hg38.chr1 30 2 + 100 AC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_drop_species_keeps_reference() {
+        // The reference is only aligned through panTro6; the rest are
+        // aligned to each other, but can't be a block without it.
+        let maf = "a
+s hg38.chr1 0 2 + 100 AC----
+s panTro6.chr1 0 6 + 100 ACTTGT
+s mm39.chr2 0 4 + 100 --TTGT
+s rn7.chr1 0 4 + 100 --TTGA
+";
+        let species = vec!["panTro6".to_string()].into_iter().collect();
+        let mut output = vec![];
+        let counts = drop_species(&mut maf.as_bytes(), &mut output, species).unwrap();
+        assert_eq!(counts, (3, 1));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 2 + 100 AC
+
+"
+        );
+    }
+}
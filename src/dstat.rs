@@ -0,0 +1,299 @@
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// The species compared in the ABBA-BABA test: P3 is tested for gene
+/// flow with P1 or P2, and the outgroup gives the ancestral allele.
+pub struct DstatSpecies<'a> {
+    pub p1: &'a str,
+    pub p2: &'a str,
+    pub p3: &'a str,
+    pub outgroup: &'a str,
+    /// A second sample of P3's population, for the f4-ratio, which is
+    /// left out without it.
+    pub p3b: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SitePattern {
+    Abba,
+    Baba,
+}
+
+/// The pattern of a biallelic column, given the bases of P1, P2, P3 and
+/// the outgroup, if it's ABBA or BABA. Columns with gaps or ambiguous
+/// bases in any of them don't count.
+fn site_pattern(bases: [u8; 4]) -> Option<SitePattern> {
+    let bases = bases.map(|c| c.to_ascii_uppercase());
+    if bases.iter().any(|c| !b"ACGT".contains(c)) {
+        return None;
+    }
+    let [p1, p2, p3, outgroup] = bases;
+    if p3 == outgroup {
+        None
+    } else if p1 == outgroup && p2 == p3 {
+        Some(SitePattern::Abba)
+    } else if p2 == outgroup && p1 == p3 {
+        Some(SitePattern::Baba)
+    } else {
+        None
+    }
+}
+
+/// Site pattern counts within one jackknife block.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct PatternCounts {
+    abba: u64,
+    baba: u64,
+    /// Counts with P3b in place of P2, for the f4-ratio's denominator.
+    abba_p3b: u64,
+    baba_p3b: u64,
+}
+
+impl PatternCounts {
+    fn add(&mut self, other: &PatternCounts) {
+        self.abba += other.abba;
+        self.baba += other.baba;
+        self.abba_p3b += other.abba_p3b;
+        self.baba_p3b += other.baba_p3b;
+    }
+
+    fn sub(&self, other: &PatternCounts) -> PatternCounts {
+        PatternCounts {
+            abba: self.abba - other.abba,
+            baba: self.baba - other.baba,
+            abba_p3b: self.abba_p3b - other.abba_p3b,
+            baba_p3b: self.baba_p3b - other.baba_p3b,
+        }
+    }
+
+    fn d(&self) -> f64 {
+        (self.abba as f64 - self.baba as f64) / (self.abba + self.baba) as f64
+    }
+
+    /// The f4-ratio, or f_hom (Martin et al. 2015), estimating the
+    /// proportion of P2's genome from P3: the ABBA-BABA excess relative
+    /// to that of P3b, as a stand-in for a fully admixed P2.
+    fn f4_ratio(&self) -> f64 {
+        (self.abba as f64 - self.baba as f64) / (self.abba_p3b as f64 - self.baba_p3b as f64)
+    }
+}
+
+/// A statistic over all blocks, with its delete-one block-jackknife
+/// standard error (NaN with fewer than two blocks).
+fn jackknife(blocks: &[PatternCounts], stat: impl Fn(&PatternCounts) -> f64) -> (f64, f64) {
+    let mut total = PatternCounts::default();
+    for block in blocks.iter() {
+        total.add(block);
+    }
+    let n = blocks.len() as f64;
+    let leave_outs: Vec<f64> = blocks.iter().map(|block| stat(&total.sub(block))).collect();
+    let mean = leave_outs.iter().sum::<f64>() / n;
+    let variance = (n - 1.0) / n * leave_outs.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+    let stderr = if blocks.len() < 2 {
+        f64::NAN
+    } else {
+        variance.sqrt()
+    };
+    (stat(&total), stderr)
+}
+
+/// Compute Patterson's D (the ABBA-BABA statistic), and the f4-ratio if
+/// P3b is given, over the columns where all the species (including
+/// P3b, if given) have a base, using the first row of each species in a block. Standard errors come
+/// from a block jackknife over windows of `block_size` reference bases;
+/// each alignment block goes in the window holding its start. Writes a
+/// TSV of the statistics with their standard errors and Z-scores, and
+/// returns the total counts of ABBA and BABA sites and the number of
+/// jackknife blocks.
+pub fn dstat(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    species: &DstatSpecies,
+    block_size: u64,
+) -> Result<(u64, u64, usize), MAFParseError> {
+    if block_size == 0 {
+        panic!("Jackknife block size must be positive");
+    }
+    let mut blocks: Vec<PatternCounts> = vec![];
+    let mut block_index: HashMap<(String, u64), usize> = HashMap::new();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => continue,
+        };
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let row = |name: &str| {
+            block
+                .aligned_entries()
+                .find(|entry| entry.seq.split('.').next() == Some(name))
+                .map(|entry| &entry.alignment)
+        };
+        let (p1, p2, p3, outgroup) = match (
+            row(species.p1),
+            row(species.p2),
+            row(species.p3),
+            row(species.outgroup),
+        ) {
+            (Some(p1), Some(p2), Some(p3), Some(outgroup)) => (p1, p2, p3, outgroup),
+            _ => continue,
+        };
+        // The f4-ratio's numerator and denominator are counted over the
+        // same sites, so with P3b, only the sites where it has a base
+        // count at all.
+        let p3b = match species.p3b.map(row) {
+            Some(None) => continue,
+            Some(p3b) => p3b,
+            None => None,
+        };
+        let mut counts = PatternCounts::default();
+        for i in 0..p1.len() {
+            if p3b.is_some_and(|p3b| !b"ACGT".contains(&p3b[i].to_ascii_uppercase())) {
+                continue;
+            }
+            match site_pattern([p1[i], p2[i], p3[i], outgroup[i]]) {
+                Some(SitePattern::Abba) => counts.abba += 1,
+                Some(SitePattern::Baba) => counts.baba += 1,
+                None => (),
+            }
+            if let Some(p3b) = p3b {
+                match site_pattern([p1[i], p3b[i], p3[i], outgroup[i]]) {
+                    Some(SitePattern::Abba) => counts.abba_p3b += 1,
+                    Some(SitePattern::Baba) => counts.baba_p3b += 1,
+                    None => (),
+                }
+            }
+        }
+        let key = (
+            chrom_part(&ref_entry.seq),
+            ref_entry.forward_position(ref_entry.start) / block_size,
+        );
+        let index = *block_index.entry(key).or_insert_with(|| {
+            blocks.push(PatternCounts::default());
+            blocks.len() - 1
+        });
+        blocks[index].add(&counts);
+    }
+    let format = |x: f64| {
+        if x.is_finite() {
+            format!("{:.4}", x)
+        } else {
+            "NA".to_string()
+        }
+    };
+    writeln!(output, "#statistic\tvalue\tstderr\tz").ok();
+    let mut write_stat = |name: &str, (value, stderr): (f64, f64)| {
+        writeln!(
+            output,
+            "{}\t{}\t{}\t{}",
+            name,
+            format(value),
+            format(stderr),
+            format(value / stderr)
+        )
+        .ok();
+    };
+    write_stat("D", jackknife(&blocks, PatternCounts::d));
+    if species.p3b.is_some() {
+        write_stat("f4_ratio", jackknife(&blocks, PatternCounts::f4_ratio));
+    }
+    let mut total = PatternCounts::default();
+    for block in blocks.iter() {
+        total.add(block);
+    }
+    items.finish()?;
+    Ok((total.abba, total.baba, blocks.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_pattern() {
+        assert_eq!(site_pattern(*b"AccA"), Some(SitePattern::Abba));
+        assert_eq!(site_pattern(*b"CACA"), Some(SitePattern::Baba));
+        assert_eq!(site_pattern(*b"CCAA"), None);
+        assert_eq!(site_pattern(*b"AGTA"), None);
+        assert_eq!(site_pattern(*b"AC-A"), None);
+    }
+
+    #[test]
+    fn test_dstat() {
+        let maf = "a
+s P1.chr1 0 3 + 100 AAC
+s P2.chr1 0 3 + 100 CGC
+s P3.chr1 0 3 + 100 CGC
+s P3b.chr1 0 3 + 100 CGC
+s O.chr1 0 3 + 100 AAC
+
+a
+s P1.chr1 10 4 + 100 ACAN
+s P2.chr1 10 4 + 100 CAGA
+s P3.chr1 10 4 + 100 CCTA
+s P3b.chr1 10 4 + 100 CCAA
+s O.chr1 10 4 + 100 AAAA
+
+a
+s P1.chr2 0 1 + 100 A
+s P2.chr2 0 1 + 100 T
+s P3.chr2 0 1 + 100 T
+s P3b.chr2 0 1 + 100 T
+s O.chr2 0 1 + 100 A
+
+a
+s P1.chr2 50 1 + 100 A
+s P2.chr2 50 1 + 100 T
+s P3.chr2 50 1 + 100 T
+";
+        let species = DstatSpecies {
+            p1: "P1",
+            p2: "P2",
+            p3: "P3",
+            outgroup: "O",
+            p3b: Some("P3b"),
+        };
+        let mut output = vec![];
+        let counts = dstat(&mut maf.as_bytes(), &mut output, &species, 10).unwrap();
+        // The last block has no outgroup, so doesn't count.
+        assert_eq!(counts, (4, 1, 3));
+        // With the outgroup in the last block, it still doesn't count
+        // without P3b, so that the f4-ratio's numerator and denominator
+        // are over the same sites. Nor does a column where P3b has a
+        // gap.
+        let maf = format!(
+            "{}s O.chr2 50 1 + 100 A\n\na
+s P1.chr3 0 1 + 100 A
+s P2.chr3 0 1 + 100 T
+s P3.chr3 0 1 + 100 T
+s P3b.chr3 0 1 + 100 -
+s O.chr3 0 1 + 100 A
+",
+            maf
+        );
+        let counts = dstat(&mut maf.as_bytes(), &mut vec![], &species, 10).unwrap();
+        assert_eq!(counts, (4, 1, 4));
+        // Without P3b, they count.
+        let species = DstatSpecies {
+            p3b: None,
+            ..species
+        };
+        let counts = dstat(&mut maf.as_bytes(), &mut vec![], &species, 10).unwrap();
+        assert_eq!(counts, (6, 1, 5));
+        // D = 3/5, with leave-one-out values of 1/3, 1 and 1/2; the
+        // f4-ratio is 3/4, with 1/2, 1 and 2/3.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#statistic\tvalue\tstderr\tz
+D\t0.6000\t0.4006\t1.4977
+f4_ratio\t0.7500\t0.2940\t2.5513
+"
+        );
+    }
+}
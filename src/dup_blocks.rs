@@ -1,292 +1,401 @@
-use multiple_alignment_format::parser::next_maf_item;
-use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry, MAFItem};
-use std::collections::HashMap;
-use std::io::{BufRead, Write};
-
-fn dup_entries_from_block(block: &MAFBlock) -> HashMap<&str, Vec<&MAFBlockAlignedEntry>> {
-    let mut hash = block.entries_as_hash();
-    hash.retain(|_, v| v.len() > 1);
-    hash
+use crate::alphabet::{Alphabet, ResidueCounts};
+use crate::transform::{transform_blocks, BlockTransform};
+use crate::{chrom_part, write_unmodified, MAFItems, MAFOutput};
+use multiple_alignment_format::column_block::ColumnBlock;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry, MAFItem, Strand};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Entries of each species with more than one, in species name order.
+fn dup_entries_from_block(block: &MAFBlock) -> BTreeMap<&str, Vec<&MAFBlockAlignedEntry>> {
+    let mut entries = block.entries_by_species();
+    entries.retain(|_, v| v.len() > 1);
+    entries
 }
 
 fn block_contains_dups(block: &MAFBlock) -> bool {
     !dup_entries_from_block(block).is_empty()
 }
 
+/// Species with more than one row in a block, in name order.
+pub fn duplicated_species(block: &MAFBlock) -> Vec<&str> {
+    dup_entries_from_block(block).into_keys().collect()
+}
+
 /// When merging 2+ alignment entries from the same species within a
 /// single block, this describes what the base call will be for the
 /// merged entry.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ConsensusMode {
-    /// The base is kept as a nucleotide if all dups have the same
-    /// base in this position, and N otherwise.
+    /// The base is kept if all dups have the same base in this
+    /// position, and set to the alphabet's unknown residue (N, or X
+    /// for proteins) otherwise. For nucleotides, N and ambiguity codes
+    /// agree with the bases they stand for, so A and R give A, and R
+    /// and S give G.
     Unanimity,
     /// The base is set to the consensus of the dups within the
     /// species, using the most frequent base within the entire column
     /// to break ties within the species. If there is still a tie even
-    /// after considering the entire column, the base is set to N.
+    /// after considering the entire column, the base is set to the
+    /// unknown residue.
     Consensus,
-    /// All bases which are duplicated are set to N for that species's block entry.
+    /// All bases which are duplicated are set to the unknown residue
+    /// for that species's block entry.
     Mask,
+    /// The base is set to the IUPAC ambiguity code for every base
+    /// among the dups (so A and G give R), ignoring Ns. Only for
+    /// nucleotides.
+    Iupac,
 }
 
-fn unanimous_base(base_counts: &BaseCounts) -> u8 {
-    if base_counts.a > 0 && base_counts.c == 0 && base_counts.g == 0 && base_counts.t == 0 {
-        b'A'
-    } else if base_counts.a == 0 && base_counts.c > 0 && base_counts.g == 0 && base_counts.t == 0 {
-        b'C'
-    } else if base_counts.a == 0 && base_counts.c == 0 && base_counts.g > 0 && base_counts.t == 0 {
-        b'G'
-    } else if base_counts.a == 0 && base_counts.c == 0 && base_counts.g == 0 && base_counts.t > 0 {
-        b'T'
-    } else {
-        b'N'
+impl ConsensusMode {
+    /// The mode with a name as given on the command line, like
+    /// "consensus".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unanimity" => Some(ConsensusMode::Unanimity),
+            "consensus" => Some(ConsensusMode::Consensus),
+            "mask" => Some(ConsensusMode::Mask),
+            "iupac" => Some(ConsensusMode::Iupac),
+            _ => None,
+        }
     }
 }
 
-fn max_among_possibilities(base_counts: &BaseCounts, possibilities: &mut [bool; 4]) {
-    let mut max_so_far = if possibilities[0] { base_counts.a } else { 0 };
-    if possibilities[1] && max_so_far < base_counts.c {
-        possibilities[0] = false;
-        max_so_far = base_counts.c;
-    } else if base_counts.c < max_so_far {
-        possibilities[1] = false;
-    }
-    if possibilities[2] && max_so_far < base_counts.g {
-        possibilities[0] = false;
-        possibilities[1] = false;
-        max_so_far = base_counts.g;
-    } else if base_counts.g < max_so_far {
-        possibilities[2] = false;
-    }
-    if possibilities[3] && max_so_far < base_counts.t {
-        possibilities[0] = false;
-        possibilities[1] = false;
-        possibilities[2] = false;
-    } else if base_counts.t < max_so_far {
-        possibilities[3] = false;
-    }
+fn species(entry: &MAFBlockAlignedEntry) -> &str {
+    entry.seq.split('.').next().unwrap()
+}
+
+/// Where the row kept for a species in the previous block ended.
+struct RowEnd {
+    seq: String,
+    strand: Strand,
+    end: u64,
 }
 
-fn consensus_base(base_counts: &BaseCounts, tie_breaker: &BaseCounts) -> u8 {
-    let mut possible_bases = [true, true, true, true];
-    max_among_possibilities(base_counts, &mut possible_bases);
-    max_among_possibilities(tie_breaker, &mut possible_bases);
-    match possible_bases {
-        [true, false, false, false] => b'A',
-        [false, true, false, false] => b'C',
-        [false, false, true, false] => b'G',
-        [false, false, false, true] => b'T',
-        _ => b'N',
+/// Whether a row carries on from where the species' row in the
+/// previous block ended.
+fn continues_run(runs: &HashMap<String, RowEnd>, entry: &MAFBlockAlignedEntry) -> bool {
+    runs.get(species(entry)).is_some_and(|run| {
+        run.seq == entry.seq && run.strand == entry.strand && run.end == entry.start
+    })
+}
+
+/// Record where each species' row ends, for the next block.
+fn record_runs(runs: &mut HashMap<String, RowEnd>, block: &MAFBlock) {
+    for entry in block.aligned_entries() {
+        runs.insert(
+            species(entry).to_string(),
+            RowEnd {
+                seq: entry.seq.clone(),
+                strand: entry.strand,
+                end: entry.start + entry.aligned_length,
+            },
+        );
     }
 }
 
-fn merge_dup_entries(
-    dup_entries: &HashMap<&str, Vec<&MAFBlockAlignedEntry>>,
-    block_consensus: &[BaseCounts],
+/// Replace the entries of each species with more than one by a single
+/// merged entry (the first of them, with its bases replaced), placed
+/// after the other entries in species name order. If `runs` is given,
+/// the entry kept is instead the one that carries on from the
+/// species' row in the previous block, if there is one. The block is
+/// switched to column-major order to work out the merged bases a
+/// column at a time, which are written in place.
+fn merge_dups_in_block(
+    block: MAFBlock,
     mode: ConsensusMode,
-) -> Vec<MAFBlockEntry> {
-    let mut merged_entries = vec![];
-    for (_, alignments) in dup_entries.iter() {
-        let mut merged_alignment = alignments[0].clone();
-        let base_counts = get_consensus_info(&alignments);
-        for i in 0..merged_alignment.alignment.len() {
-            merged_alignment.alignment[i] = match mode {
-                ConsensusMode::Mask => b'N',
-                ConsensusMode::Unanimity => unanimous_base(&base_counts[i]),
-                ConsensusMode::Consensus => consensus_base(&base_counts[i], &block_consensus[i]),
+    runs: Option<&HashMap<String, RowEnd>>,
+    alphabet: &dyn Alphabet,
+) -> MAFBlock {
+    let dup_species: BTreeSet<String> = dup_entries_from_block(&block)
+        .keys()
+        .map(|s| s.to_string())
+        .collect();
+    if dup_species.is_empty() {
+        return block;
+    }
+    let mut columns = ColumnBlock::from(block);
+    // Rows of each duplicated species, in species name order.
+    let mut dup_rows: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (row, entry) in columns.row_entries().enumerate() {
+        if let Some(species) = dup_species.get(species(entry)) {
+            dup_rows.entry(species.as_str()).or_default().push(row);
+        }
+    }
+    // The row holding the merged bases for each species.
+    let kept_rows: Vec<usize> = dup_rows
+        .values()
+        .map(|rows| {
+            runs.and_then(|runs| {
+                rows.iter()
+                    .find(|row| continues_run(runs, columns.row_entry(**row)))
+            })
+            .copied()
+            .unwrap_or(rows[0])
+        })
+        .collect();
+    let mut merged_bases = vec![alphabet.unknown(); dup_rows.len()];
+    let mut species_bases = vec![];
+    for column in 0..columns.num_columns() {
+        let bases = columns.column(column);
+        let block_counts = match mode {
+            ConsensusMode::Consensus => Some(ResidueCounts::count(alphabet, bases.iter())),
+            _ => None,
+        };
+        for (base, rows) in merged_bases.iter_mut().zip(dup_rows.values()) {
+            species_bases.clear();
+            species_bases.extend(rows.iter().map(|row| bases[*row]));
+            *base = match mode {
+                ConsensusMode::Mask => alphabet.unknown(),
+                ConsensusMode::Unanimity => alphabet.unanimous(&species_bases),
+                ConsensusMode::Consensus => ResidueCounts::count(alphabet, species_bases.iter())
+                    .consensus(block_counts.as_ref().unwrap(), alphabet),
+                ConsensusMode::Iupac => alphabet
+                    .ambiguity_code(&species_bases)
+                    .expect("IUPAC consensus only works on nucleotide alignments"),
+            };
+        }
+        // Only write the column once every species' base has been
+        // decided, since the consensus depends on the whole column.
+        let bases = columns.column_mut(column);
+        for (base, row) in merged_bases.iter().zip(kept_rows.iter()) {
+            bases[*row] = *base;
+        }
+    }
+    let mut block = MAFBlock::from(columns);
+    let mut others = vec![];
+    let mut merged: BTreeMap<String, MAFBlockEntry> = BTreeMap::new();
+    let mut row = 0;
+    for entry in block.entries {
+        match entry {
+            MAFBlockEntry::AlignedEntry(a) if dup_species.contains(species(&a)) => {
+                if kept_rows.contains(&row) {
+                    merged.insert(species(&a).to_string(), MAFBlockEntry::AlignedEntry(a));
+                }
+                row += 1;
+            }
+            MAFBlockEntry::AlignedEntry(a) => {
+                others.push(MAFBlockEntry::AlignedEntry(a));
+                row += 1;
             }
+            e => others.push(e),
         }
-        merged_entries.push(MAFBlockEntry::AlignedEntry(merged_alignment));
     }
-    merged_entries
+    block.entries = others;
+    block.entries.extend(merged.into_values());
+    block
+}
+
+/// Merge the duplicated entries within each block (see
+/// `merge_dups_in_block`). If `max_block_bases` is given, any block
+/// with more than that many rows times columns is refused, to put a
+/// ceiling on the memory used. If `stitch` is set, the entry kept for a
+/// species follows the copy whose coordinates carry on from the
+/// previous block, so that a run of consecutive blocks keeps merging
+/// into the same copy rather than flipping between them.
+pub fn output_merged_consensus_blocks(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    mode: ConsensusMode,
+    max_block_bases: Option<u64>,
+    stitch: bool,
+    alphabet: &'static dyn Alphabet,
+) -> Result<(), MAFParseError> {
+    let mut merge_dups = MergeDups::new(mode, max_block_bases, stitch, alphabet);
+    transform_blocks(input, output, &mut merge_dups)
 }
 
-#[derive(Debug, PartialEq)]
-struct BaseCounts {
-    a: usize,
-    c: usize,
-    g: usize,
-    t: usize,
+/// Merges the duplicated entries of each block, as in
+/// `output_merged_consensus_blocks`.
+pub struct MergeDups {
+    mode: ConsensusMode,
+    alphabet: &'static dyn Alphabet,
+    max_block_bases: Option<u64>,
+    runs: Option<HashMap<String, RowEnd>>,
 }
 
-fn get_consensus_info(entries: &[&MAFBlockAlignedEntry]) -> Vec<BaseCounts> {
-    let bases = [b'a', b'c', b'g', b't'];
-    let mut counts = vec![];
-    let length = entries[0].alignment.len();
-    for i in 0..length {
-        let mut count_iter = bases.iter().map(|b| {
-            entries
-                .iter()
-                .filter(|e| e.alignment[i].eq_ignore_ascii_case(b))
-                .count()
-        });
-        counts.push(BaseCounts {
-            a: count_iter.next().unwrap(),
-            c: count_iter.next().unwrap(),
-            g: count_iter.next().unwrap(),
-            t: count_iter.next().unwrap(),
-        })
+impl MergeDups {
+    pub fn new(
+        mode: ConsensusMode,
+        max_block_bases: Option<u64>,
+        stitch: bool,
+        alphabet: &'static dyn Alphabet,
+    ) -> Self {
+        MergeDups {
+            mode,
+            alphabet,
+            max_block_bases,
+            runs: if stitch { Some(HashMap::new()) } else { None },
+        }
     }
-    counts
 }
 
-pub fn output_merged_consensus_blocks(
-    input: &mut dyn BufRead,
-    output: &mut dyn Write,
-    mode: ConsensusMode,
-) {
-    while let Ok(item) = next_maf_item(input) {
-        match item {
-            MAFItem::Comment(comment) => {
-                writeln!(output, "#{}", comment).ok();
-            }
-            MAFItem::Block(mut block) => {
-                let dup_entries = dup_entries_from_block(&block);
-                let aligned_entries: Vec<_> = block.aligned_entries().collect();
-                let block_counts = get_consensus_info(&aligned_entries);
-
-                // Clear out duplicated entries within the block.
-                let values: Vec<_> = dup_entries.values().flatten().collect();
-                let mut new_block_entries = block.entries.clone();
-                new_block_entries.retain(|e| match e {
-                    MAFBlockEntry::AlignedEntry(a) => !values.contains(&&a),
-                    _ => true,
-                });
-                let dup_entries = merge_dup_entries(&dup_entries, &block_counts, mode);
-                block.entries = new_block_entries;
-                block.entries.extend(dup_entries);
-                writeln!(output, "{}", block).ok();
+impl BlockTransform for MergeDups {
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+        if let Some(max_block_bases) = self.max_block_bases {
+            let rows = block.aligned_entries().count() as u64;
+            let columns = block
+                .aligned_entries()
+                .next()
+                .map_or(0, |e| e.alignment.len() as u64);
+            if rows * columns > max_block_bases {
+                panic!(
+                    "Block with {} rows of {} columns is over the limit of {} bases",
+                    rows, columns, max_block_bases
+                );
             }
         }
+        let block = merge_dups_in_block(block, self.mode, self.runs.as_ref(), self.alphabet);
+        if let Some(runs) = self.runs.as_mut() {
+            record_runs(runs, &block);
+        }
+        vec![block]
     }
 }
 
-pub fn output_dup_blocks(input: &mut dyn BufRead, output: &mut dyn Write) {
-    while let Ok(item) = next_maf_item(input) {
-        match item {
-            MAFItem::Comment(comment) => {
-                writeln!(output, "#{}", comment).ok();
-            }
-            MAFItem::Block(block) => {
-                if block_contains_dups(&block) {
-                    write!(output, "{}", block).ok();
-                }
+/// Writes the regions involved in duplications to a BED per species.
+struct DupBedWriter {
+    output_dir: PathBuf,
+    files: BTreeMap<String, BufWriter<File>>,
+}
+
+impl DupBedWriter {
+    fn write_row(&mut self, entry: &MAFBlockAlignedEntry, name: &str) {
+        let species = species(entry);
+        if !self.files.contains_key(species) {
+            let path = self.output_dir.join(format!("{}.bed", species));
+            let file = BufWriter::new(File::create(path).expect("Couldn't create BED file"));
+            self.files.insert(species.to_string(), file);
+        }
+        let (start, end) = entry.forward_interval();
+        let strand = match entry.strand {
+            Strand::Positive => '+',
+            Strand::Negative => '-',
+        };
+        writeln!(
+            self.files.get_mut(species).unwrap(),
+            "{}\t{}\t{}\t{}\t0\t{}",
+            chrom_part(&entry.seq),
+            start,
+            end,
+            name,
+            strand
+        )
+        .expect("failed to write");
+    }
+
+    /// Write the reference row and every duplicated row of a block,
+    /// named after the reference row's forward-strand interval.
+    fn write_block(&mut self, block: &MAFBlock) {
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let (ref_start, ref_end) = ref_entry.forward_interval();
+        let name = format!("{}:{}-{}", ref_entry.seq, ref_start, ref_end);
+        let dups = dup_entries_from_block(block);
+        if !dups.contains_key(species(ref_entry)) {
+            self.write_row(ref_entry, &name);
+        }
+        for entry in dups.values().flatten() {
+            self.write_row(entry, &name);
+        }
+    }
+}
+
+/// Write the blocks with duplicated entries. If `bed_dir` is given,
+/// also write `<bed_dir>/<species>.bed` for each species involved, a
+/// BED6 of the reference row of each of those blocks (in the
+/// reference's file) and of every duplicated row, on the forward
+/// strand with the row's strand, and named after the reference row's
+/// interval.
+pub fn output_dup_blocks(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    bed_dir: Option<&str>,
+) -> Result<(), MAFParseError> {
+    let mut bed_writer = bed_dir.map(|dir| DupBedWriter {
+        output_dir: PathBuf::from(dir),
+        files: BTreeMap::new(),
+    });
+    let mut items = MAFItems::new(input);
+    while let Some((item, raw)) = items.next_raw() {
+        let keep = match &item {
+            MAFItem::Comment(_) => true,
+            MAFItem::Block(block) => block_contains_dups(block),
+        };
+        if keep {
+            if let (MAFItem::Block(block), Some(bed_writer)) = (&item, bed_writer.as_mut()) {
+                bed_writer.write_block(block);
             }
+            write_unmodified(output, &item, &raw);
         }
     }
+    for file in bed_writer.iter_mut().flat_map(|w| w.files.values_mut()) {
+        file.flush().expect("failed to write");
+    }
+    items.finish()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::alphabet::Nucleotides;
+    use multiple_alignment_format::parser::next_maf_item;
 
     #[test]
     fn test_unanimous_base() {
-        let counts = BaseCounts {
-            a: 0,
-            c: 10,
-            g: 0,
-            t: 0,
-        };
-        assert_eq!(unanimous_base(&counts), b'C');
+        let counts = ResidueCounts(vec![0, 10, 0, 0]);
+        assert_eq!(counts.unanimous(&Nucleotides), b'C');
 
-        let counts = BaseCounts {
-            a: 0,
-            c: 0,
-            g: 0,
-            t: 10,
-        };
-        assert_eq!(unanimous_base(&counts), b'T');
+        let counts = ResidueCounts(vec![0, 0, 0, 10]);
+        assert_eq!(counts.unanimous(&Nucleotides), b'T');
 
         // Should give N if any disagreement
-        let counts = BaseCounts {
-            a: 9,
-            c: 0,
-            g: 0,
-            t: 1,
-        };
-        assert_eq!(unanimous_base(&counts), b'N');
+        let counts = ResidueCounts(vec![9, 0, 0, 1]);
+        assert_eq!(counts.unanimous(&Nucleotides), b'N');
     }
 
     #[test]
     fn test_consensus_base() {
         // If a majority points to a base, choose that
-        let counts = BaseCounts {
-            a: 6,
-            c: 5,
-            g: 5,
-            t: 5,
-        };
-        let tiebreakers = BaseCounts {
-            a: 0,
-            c: 0,
-            g: 0,
-            t: 0,
-        };
-        assert_eq!(consensus_base(&counts, &tiebreakers), b'A');
+        let counts = ResidueCounts(vec![6, 5, 5, 5]);
+        let tiebreakers = ResidueCounts(vec![0, 0, 0, 0]);
+        assert_eq!(counts.consensus(&tiebreakers, &Nucleotides), b'A');
 
         // If a tie exists, go to the tiebreaking counts to resolve it
-        let counts = BaseCounts {
-            a: 4,
-            c: 4,
-            g: 5,
-            t: 5,
-        };
-        let tiebreakers = BaseCounts {
-            a: 1,
-            c: 1,
-            g: 2,
-            t: 1,
-        };
-        assert_eq!(consensus_base(&counts, &tiebreakers), b'G');
+        let counts = ResidueCounts(vec![4, 4, 5, 5]);
+        let tiebreakers = ResidueCounts(vec![1, 1, 2, 1]);
+        assert_eq!(counts.consensus(&tiebreakers, &Nucleotides), b'G');
 
         // If a tie still exists even after tiebreaking, return an N
-        let counts = BaseCounts {
-            a: 4,
-            c: 5,
-            g: 4,
-            t: 5,
-        };
-        let tiebreakers = BaseCounts {
-            a: 1,
-            c: 2,
-            g: 1,
-            t: 2,
-        };
-        assert_eq!(consensus_base(&counts, &tiebreakers), b'N');
+        let counts = ResidueCounts(vec![4, 5, 4, 5]);
+        let tiebreakers = ResidueCounts(vec![1, 2, 1, 2]);
+        assert_eq!(counts.consensus(&tiebreakers, &Nucleotides), b'N');
     }
 
     #[test]
-    fn test_get_consensus_info() {
+    #[allow(clippy::assertions_on_constants)]
+    fn test_count_bases() {
         let block = "a
-s       Gallus_gallus.chr1 4432333   6       +       157682039  CAG
-s       Alca_torda.scaffold4709 42333   6       -       157682  TAG
-s       Alca_torda.scaffold4709 41641   6       -       157682  G-A
+s       Gallus_gallus.chr1 4432333   3       +       157682039  CAG
+s       Alca_torda.scaffold4709 42333   3       -       157682  TAG
+s       Alca_torda.scaffold4709 41641   2       -       157682  G-A
 ";
         let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
         if let MAFItem::Block(block) = item {
-            let alignments: Vec<_> = block.aligned_entries().collect();
-            let counts = get_consensus_info(&alignments);
+            let counts: Vec<_> = ColumnBlock::from(block)
+                .columns()
+                .map(|column| ResidueCounts::count(&Nucleotides, column.iter()))
+                .collect();
             let expected_counts = vec![
-                BaseCounts {
-                    a: 0,
-                    c: 1,
-                    t: 1,
-                    g: 1,
-                },
-                BaseCounts {
-                    a: 2,
-                    c: 0,
-                    t: 0,
-                    g: 0,
-                },
-                BaseCounts {
-                    a: 1,
-                    c: 0,
-                    t: 0,
-                    g: 2,
-                },
+                ResidueCounts(vec![0, 1, 1, 1]),
+                ResidueCounts(vec![2, 0, 0, 0]),
+                ResidueCounts(vec![1, 0, 2, 0]),
             ];
             assert_eq!(counts, expected_counts);
         } else {
@@ -295,6 +404,7 @@ s       Alca_torda.scaffold4709 41641   6       -       157682  G-A
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_block_contains_dups() {
         let dup_block = "a
 s       Gallus_gallus.chr1 4432333   6       +       157682039  CAACAG
@@ -334,4 +444,187 @@ s       Glaucidium_brasilianum.scaffold_161     1648450 1       -       1875072
             assert!(false, "Got unexpected maf item {:?}", item);
         }
     }
+
+    #[test]
+    fn test_merged_dups_in_species_order() {
+        let maf = "a
+s hg38.chr1 0 2 + 10 AC
+s rn7.chr1 0 2 + 10 AC
+s rn7.chr2 0 2 + 10 AC
+s mm39.chr1 0 2 + 10 AC
+s mm39.chr2 0 2 + 10 AG
+";
+        let mut output = vec![];
+        output_merged_consensus_blocks(
+            &mut maf.as_bytes(),
+            &mut output,
+            ConsensusMode::Unanimity,
+            None,
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 2 + 10 AC
+s mm39.chr1 0 2 + 10 AN
+s rn7.chr1 0 2 + 10 AC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_merged_dups_consensus() {
+        let maf = "a
+s hg38.chr1 0 3 + 10 ACG
+s mm39.chr1 0 3 + 10 AGT
+s mm39.chr2 0 3 + 10 ACA
+s rn7.chr1 0 3 + 10 ACA
+";
+        let mut output = vec![];
+        output_merged_consensus_blocks(
+            &mut maf.as_bytes(),
+            &mut output,
+            ConsensusMode::Consensus,
+            Some(12),
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+        // The ties within mouse are broken by the rest of the column.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 3 + 10 ACG
+s rn7.chr1 0 3 + 10 ACA
+s mm39.chr1 0 3 + 10 ACA
+
+"
+        );
+    }
+
+    #[test]
+    fn test_merged_dups_iupac() {
+        let maf = "a
+s hg38.chr1 0 4 + 10 ACGT
+s mm39.chr1 0 4 + 10 AcNR
+s mm39.chr2 0 4 + 10 GTGS
+";
+        let mut output = vec![];
+        output_merged_consensus_blocks(
+            &mut maf.as_bytes(),
+            &mut output,
+            ConsensusMode::Iupac,
+            None,
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 4 + 10 ACGT
+s mm39.chr1 0 4 + 10 RYGV
+
+"
+        );
+        let mut output = vec![];
+        output_merged_consensus_blocks(
+            &mut maf.as_bytes(),
+            &mut output,
+            ConsensusMode::Unanimity,
+            None,
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("mm39.chr1 0 4 + 10 NNGG"));
+    }
+
+    #[test]
+    fn test_merged_dups_stitched() {
+        let maf = "a
+s hg38.chr1 0 2 + 10 AC
+s mm39.chr1 0 2 + 10 AC
+s mm39.chr2 5 2 - 10 AC
+
+a
+s hg38.chr1 2 2 + 10 GT
+s mm39.chr2 7 2 - 10 GA
+s mm39.chr1 2 2 + 10 GT
+";
+        let merge = |stitch| {
+            let mut output = vec![];
+            output_merged_consensus_blocks(
+                &mut maf.as_bytes(),
+                &mut output,
+                ConsensusMode::Unanimity,
+                None,
+                stitch,
+                &Nucleotides,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        // Without stitching, the second block switches to mm39.chr2.
+        assert!(merge(false).contains("s mm39.chr2 7 2 - 10 GN"));
+        assert_eq!(
+            merge(true),
+            "a
+s hg38.chr1 0 2 + 10 AC
+s mm39.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 2 2 + 10 GT
+s mm39.chr1 2 2 + 10 GN
+
+"
+        );
+    }
+
+    #[test]
+    fn test_dup_blocks_bed_out() {
+        let maf = "a
+s hg38.chr1 10 2 + 100 AC
+s mm39.chr1 0 2 + 10 AC
+s mm39.chr2 5 2 - 10 AC
+s rn7.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 12 2 + 100 GT
+s mm39.chr1 2 2 + 10 GT
+";
+        let tempdir = tempfile::TempDir::new().unwrap();
+        let mut output = vec![];
+        output_dup_blocks(&mut maf.as_bytes(), &mut output, tempdir.path().to_str()).unwrap();
+        let read = |name: &str| std::fs::read_to_string(tempdir.path().join(name)).unwrap();
+        assert_eq!(read("hg38.bed"), "chr1\t10\t12\thg38.chr1:10-12\t0\t+\n");
+        assert_eq!(
+            read("mm39.bed"),
+            "chr1\t0\t2\thg38.chr1:10-12\t0\t+\nchr2\t3\t5\thg38.chr1:10-12\t0\t-\n"
+        );
+        assert!(!tempdir.path().join("rn7.bed").exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "over the limit")]
+    fn test_merge_dups_block_limit() {
+        let maf = "a
+s hg38.chr1 0 3 + 10 ACG
+s mm39.chr1 0 3 + 10 AGT
+";
+        output_merged_consensus_blocks(
+            &mut maf.as_bytes(),
+            &mut vec![],
+            ConsensusMode::Mask,
+            Some(5),
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+    }
 }
@@ -0,0 +1,218 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{is_header, MAFItems, MAFOutput, OutputOptions};
+use multiple_alignment_format::output::MAFValidationError;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry, MAFItem};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::PathBuf;
+
+fn entry_species(entry: &MAFBlockEntry) -> &str {
+    let seq = match entry {
+        MAFBlockEntry::AlignedEntry(e) => &e.seq,
+        MAFBlockEntry::UnalignedEntry(e) => &e.seq,
+    };
+    seq.split('.').next().unwrap()
+}
+
+/// The pairwise block between the reference row and the rows of one
+/// other species, without the columns that are gaps in all of them.
+fn pairwise_block(block: &MAFBlock, ref_idx: usize, species: &str) -> MAFBlock {
+    let mut entries: Vec<MAFBlockEntry> = block
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(i, e)| *i == ref_idx || entry_species(e) == species)
+        .map(|(_, e)| e.clone())
+        .collect();
+    let rows: Vec<&[u8]> = entries
+        .iter()
+        .filter_map(|e| match e {
+            MAFBlockEntry::AlignedEntry(a) => Some(a.alignment.as_slice()),
+            MAFBlockEntry::UnalignedEntry(_) => None,
+        })
+        .collect();
+    let keep: Vec<bool> = (0..rows[0].len())
+        .map(|column| rows.iter().any(|r| !is_gap(r[column])))
+        .collect();
+    if keep.iter().all(|k| *k) {
+        return MAFBlock {
+            entries,
+            metadata: block.metadata.clone(),
+        };
+    }
+    let filter = |text: &[u8]| -> Vec<u8> {
+        text.iter()
+            .zip(keep.iter())
+            .filter(|(_, k)| **k)
+            .map(|(c, _)| *c)
+            .collect()
+    };
+    for entry in entries.iter_mut() {
+        if let MAFBlockEntry::AlignedEntry(a) = entry {
+            a.alignment = filter(&a.alignment);
+            a.qualities = a.qualities.as_ref().map(|q| filter(q));
+        }
+    }
+    MAFBlock {
+        entries,
+        metadata: block.metadata.clone(),
+    }
+}
+
+/// Writes each species' pairwise blocks to its own file.
+struct Exploder {
+    output_dir: PathBuf,
+    files: BTreeMap<String, BufWriter<File>>,
+    /// Comments before the first block, which are copied into every
+    /// file.
+    header_comments: Vec<String>,
+    seen_block: bool,
+    options: OutputOptions,
+}
+
+impl Exploder {
+    fn file(&mut self, species: &str) -> &mut BufWriter<File> {
+        if !self.files.contains_key(species) {
+            let path = self.output_dir.join(format!("{}.maf", species));
+            let mut file = BufWriter::new(File::create(&path).expect("Couldn't create file"));
+            writeln!(file, "##maf version=1").expect("failed to write");
+            for comment in self.header_comments.iter() {
+                writeln!(file, "#{}", comment).expect("failed to write");
+            }
+            self.files.insert(species.to_string(), file);
+        }
+        self.files.get_mut(species).unwrap()
+    }
+
+    fn output_block(&mut self, block: &MAFBlock) -> Result<(), MAFValidationError> {
+        self.seen_block = true;
+        let ref_idx = match block
+            .entries
+            .iter()
+            .position(|e| matches!(e, MAFBlockEntry::AlignedEntry(_)))
+        {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        let ref_species = entry_species(&block.entries[ref_idx]);
+        let mut others: Vec<&str> = block
+            .aligned_entries()
+            .map(|e| e.seq.split('.').next().unwrap())
+            .filter(|s| *s != ref_species)
+            .collect();
+        others.sort_unstable();
+        others.dedup();
+        for species in others {
+            let pairwise = pairwise_block(block, ref_idx, species);
+            let writer = self.options.writer;
+            writer.write_block(self.file(species), &pairwise)?;
+        }
+        Ok(())
+    }
+
+    fn output_comment(&mut self, comment: &str) {
+        if !self.seen_block && !is_header(comment) && !self.options.strip_comments {
+            self.header_comments.push(comment.to_string());
+        }
+    }
+}
+
+/// Write a pairwise MAF for each species other than the reference to
+/// `<output_dir>/<species>.maf`, in one pass. Each block is cut down
+/// to its reference row and that species' rows, without the columns
+/// where they're all gaps. Comments before the first block are copied
+/// into every file; later comments are dropped. Returns the number of
+/// files written. The files are written with `output`'s options, and a
+/// block strict output refuses is passed on to it, stopping the
+/// explosion.
+pub fn explode(
+    input: &mut dyn BufRead,
+    output_dir: &str,
+    output: &mut dyn MAFOutput,
+) -> Result<usize, MAFParseError> {
+    let mut exploder = Exploder {
+        output_dir: PathBuf::from(output_dir),
+        files: BTreeMap::new(),
+        header_comments: vec![],
+        seen_block: false,
+        options: output.options(),
+    };
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => exploder.output_comment(&comment),
+            MAFItem::Block(block) => {
+                if let Err(e) = exploder.output_block(&block) {
+                    output.refuse(e);
+                    break;
+                }
+            }
+        }
+    }
+    for file in exploder.files.values_mut() {
+        file.flush().expect("failed to write");
+    }
+    items.finish()?;
+    Ok(exploder.files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_explode() {
+        let maf = "##maf version=1
+# multiz
+a score=1
+s hg38.chr1 10 4 + 100 AC-GT
+s mm39.chr2 5 3 - 50 A--GT
+e rn7.chr3 0 5 + 10 I
+s hg38.chr5 0 4 + 100 ACTGT
+
+# ignored
+a
+s hg38.chr1 20 2 + 100 A--C
+s rn7.chr3 0 3 + 10 AG-C
+q rn7.chr3 99-F
+s mm39.chr2 0 2 + 50 -G-C
+";
+        let tempdir = TempDir::new().unwrap();
+        let num_files = explode(
+            &mut maf.as_bytes(),
+            tempdir.path().to_str().unwrap(),
+            &mut vec![],
+        )
+        .unwrap();
+        assert_eq!(num_files, 2);
+        assert_eq!(
+            read_to_string(tempdir.path().join("mm39.maf")).unwrap(),
+            "##maf version=1
+# multiz
+a score=1
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 5 3 - 50 A-GT
+
+a
+s hg38.chr1 20 2 + 100 A-C
+s mm39.chr2 0 2 + 50 -GC
+
+"
+        );
+        assert_eq!(
+            read_to_string(tempdir.path().join("rn7.maf")).unwrap(),
+            "##maf version=1
+# multiz
+a
+s hg38.chr1 20 2 + 100 A-C
+s rn7.chr3 0 3 + 10 AGC
+q rn7.chr3 99F
+
+"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, reverse_complement, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFItem, Strand};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+/// The stretches of each contig of a genome seen in the alignment, as
+/// (forward-strand start, forward-strand bases).
+type Contigs = BTreeMap<String, Vec<(u64, Vec<u8>)>>;
+
+/// Merge overlapping and adjacent pieces of a contig into contiguous
+/// segments. Where pieces overlap, the bases of the earlier-starting
+/// one are kept.
+fn merge_pieces(mut pieces: Vec<(u64, Vec<u8>)>) -> Vec<(u64, Vec<u8>)> {
+    pieces.sort_by_key(|(start, seq)| (*start, std::cmp::Reverse(seq.len())));
+    let mut segments: Vec<(u64, Vec<u8>)> = vec![];
+    for (start, seq) in pieces {
+        if let Some((last_start, last_seq)) = segments.last_mut() {
+            let last_end = *last_start + last_seq.len() as u64;
+            if start <= last_end {
+                let overlap = (last_end - start) as usize;
+                if overlap < seq.len() {
+                    last_seq.extend_from_slice(&seq[overlap..]);
+                }
+                continue;
+            }
+        }
+        segments.push((start, seq));
+    }
+    segments
+}
+
+/// Write, for every species, the ungapped sequence present in the
+/// alignment to `<output_dir>/<species>.fa`, with one record per
+/// contiguous segment of each contig named "contig:start-end", and the
+/// same segments to `<output_dir>/<species>.bed`. Coordinates are
+/// 0-based, half-open, and on the forward strand, and sequence from
+/// negative-strand rows is reverse-complemented. The whole content is
+/// held in memory. Returns the number of species written.
+pub fn extract_genomes(input: &mut dyn BufRead, output_dir: &str) -> Result<usize, MAFParseError> {
+    let mut genomes: BTreeMap<String, Contigs> = BTreeMap::new();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            for entry in block.aligned_entries() {
+                if entry.aligned_length == 0 {
+                    continue;
+                }
+                let mut bases: Vec<u8> = entry
+                    .alignment
+                    .iter()
+                    .filter(|c| !is_gap(**c))
+                    .copied()
+                    .collect();
+                if entry.strand == Strand::Negative {
+                    reverse_complement(&mut bases);
+                }
+                let (start, _) = entry.forward_interval();
+                let species = entry.seq.split('.').next().unwrap();
+                genomes
+                    .entry(species.to_string())
+                    .or_default()
+                    .entry(chrom_part(&entry.seq))
+                    .or_default()
+                    .push((start, bases));
+            }
+        }
+    }
+    let output_dir = Path::new(output_dir);
+    for (species, contigs) in genomes.iter_mut() {
+        let create = |extension: &str| {
+            let path = output_dir.join(format!("{}.{}", species, extension));
+            BufWriter::new(File::create(path).expect("Couldn't create file"))
+        };
+        let mut fasta = create("fa");
+        let mut bed = create("bed");
+        for (contig, pieces) in contigs.iter_mut() {
+            for (start, seq) in merge_pieces(std::mem::take(pieces)) {
+                let end = start + seq.len() as u64;
+                writeln!(fasta, ">{}:{}-{}", contig, start, end).expect("failed to write");
+                fasta.write_all(&seq).expect("failed to write");
+                writeln!(fasta).expect("failed to write");
+                writeln!(bed, "{}\t{}\t{}", contig, start, end).expect("failed to write");
+            }
+        }
+    }
+    items.finish()?;
+    Ok(genomes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_extract_genomes() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 AC-GT
+s mm39.chr2 5 3 - 20 A--gC
+s mm39.chr2 0 2 + 20 --AT-
+
+a
+s hg38.chr1 12 4 + 100 GTTA
+s mm39.chr2 3 2 + 20 CC--
+s hg38.chr1 20 2 + 100 -CG-
+";
+        let tempdir = TempDir::new().unwrap();
+        let num_species =
+            extract_genomes(&mut maf.as_bytes(), tempdir.path().to_str().unwrap()).unwrap();
+        assert_eq!(num_species, 2);
+        let read = |name: &str| read_to_string(tempdir.path().join(name)).unwrap();
+        assert_eq!(read("hg38.fa"), ">chr1:10-16\nACGTTA\n>chr1:20-22\nCG\n");
+        assert_eq!(read("hg38.bed"), "chr1\t10\t16\nchr1\t20\t22\n");
+        // The negative-strand row covers forward positions 12-14.
+        assert_eq!(
+            read("mm39.fa"),
+            ">chr2:0-2\nAT\n>chr2:3-5\nCC\n>chr2:12-15\nGcT\n"
+        );
+        assert_eq!(read("mm39.bed"), "chr2\t0\t2\nchr2\t3\t5\nchr2\t12\t15\n");
+    }
+}
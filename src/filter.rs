@@ -1,8 +1,9 @@
-use crate::lib::{chrom_part, overlapping_ranges, parse_bed, Range};
-use multiple_alignment_format::parser::next_maf_item;
-use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry, MAFItem, Strand};
-use std::collections::BTreeSet;
-use std::io::{BufRead, Write};
+use maf_stream::alphabet::is_gap;
+use maf_stream::transform::{transform_blocks, BlockTransform};
+use maf_stream::{chrom_part, MAFOutput, QueryFilter, Range, RegionFilter};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry, Strand};
+use std::io::BufRead;
 
 /// Run of columns.
 #[derive(Debug, PartialEq)]
@@ -11,33 +12,41 @@ struct Run {
     length: usize,
 }
 
-/// Get the columns within the block which should be kept.
-fn get_filtered_columns(ref_entry: &MAFBlockAlignedEntry, ranges: &BTreeSet<Range>) -> Vec<Run> {
-    assert!(ref_entry.strand == Strand::Positive);
+/// Get the columns within the block which should be kept. If a column
+/// mask is given, only columns with a true value in the mask are kept.
+fn get_filtered_columns(
+    ref_entry: &MAFBlockAlignedEntry,
+    regions: &RegionFilter,
+    mask: Option<&[bool]>,
+) -> Vec<Run> {
     let chrom = chrom_part(&ref_entry.seq);
+    let (start, end) = ref_entry.forward_interval();
+    let mut passing = regions.passing_ranges(&Range {
+        seq: chrom.clone(),
+        start,
+        end,
+    });
+    // Sweep through the ranges in the order the row passes through them.
+    if ref_entry.strand == Strand::Negative {
+        passing.reverse();
+    }
+    let mut passing = passing.into_iter().peekable();
     let mut runs: Vec<Run> = vec![];
-    let mut relevant_ranges = overlapping_ranges(
-        ranges,
-        &Range {
-            seq: chrom_part(&ref_entry.seq),
-            start: ref_entry.start,
-            end: ref_entry.start + ref_entry.aligned_length,
-        },
-    );
-    let mut current_range = relevant_ranges.next();
-    let mut current_pos = ref_entry.start;
-    let end = ref_entry.start + ref_entry.aligned_length;
     let mut was_within_run = false;
-    for (i, c) in ref_entry.alignment.iter().enumerate() {
-        while current_range.map_or(false, |r| r.precedes(&chrom, current_pos)) {
-            current_range = relevant_ranges.next();
-        }
-        if current_range.map_or(true, |r| r.succeeds(&chrom, end)) {
-            break;
-        }
+    for (i, pos) in ref_entry.column_positions().enumerate() {
         let mut within_run = false;
-        if *c != b'-' {
-            if current_range.unwrap().overlaps(&chrom, current_pos) {
+        if let Some(pos) = pos {
+            let pos = ref_entry.forward_position(pos);
+            while passing.peek().is_some_and(|r| match ref_entry.strand {
+                Strand::Positive => r.end <= pos,
+                Strand::Negative => r.start > pos,
+            }) {
+                passing.next();
+            }
+            if passing.peek().is_none() {
+                break;
+            }
+            if passing.peek().unwrap().overlaps(&chrom, pos) && mask.is_none_or(|m| m[i]) {
                 if was_within_run {
                     runs.last_mut().unwrap().length += 1;
                 } else {
@@ -48,7 +57,6 @@ fn get_filtered_columns(ref_entry: &MAFBlockAlignedEntry, ranges: &BTreeSet<Rang
                 }
                 within_run = true;
             }
-            current_pos += 1;
         }
         was_within_run = within_run;
     }
@@ -58,11 +66,11 @@ fn get_filtered_columns(ref_entry: &MAFBlockAlignedEntry, ranges: &BTreeSet<Rang
 fn filter_entry_columns(entry: &MAFBlockAlignedEntry, run: &Run) -> MAFBlockAlignedEntry {
     let before_range_offset = entry.alignment[..run.start]
         .iter()
-        .filter(|c| **c != b'-')
+        .filter(|c| !is_gap(**c))
         .count() as u64;
     let inside_range_offset = entry.alignment[run.start..run.start + run.length]
         .iter()
-        .take_while(|c| **c == b'-')
+        .take_while(|c| is_gap(**c))
         .count() as u64;
 
     MAFBlockAlignedEntry {
@@ -73,11 +81,14 @@ fn filter_entry_columns(entry: &MAFBlockAlignedEntry, run: &Run) -> MAFBlockAlig
         alignment: entry.alignment[run.start..run.start + run.length].to_vec(),
         aligned_length: entry.alignment[run.start..run.start + run.length]
             .iter()
-            .filter(|c| **c != b'-')
+            .filter(|c| !is_gap(**c))
             .count() as u64,
         // TODO. But no one uses/cares about these anyway
         context: None,
-        qualities: None,
+        qualities: entry
+            .qualities
+            .as_ref()
+            .map(|q| q[run.start..run.start + run.length].to_vec()),
     }
 }
 
@@ -91,39 +102,53 @@ fn filter_block_columns(block: &MAFBlock, run: &Run) -> MAFBlock {
     }
 }
 
-fn filter_block(block: &MAFBlock, ranges: &BTreeSet<Range>) -> Vec<MAFBlock> {
+pub fn filter_block(
+    block: &MAFBlock,
+    regions: &RegionFilter,
+    query: Option<&QueryFilter>,
+) -> Vec<MAFBlock> {
     if block.aligned_entries().next().is_none() {
         return vec![];
     }
     let ref_entry = block.aligned_entries().next().unwrap();
-    get_filtered_columns(ref_entry, ranges)
+    let mask = query.map(|q| q.column_mask(block));
+    get_filtered_columns(ref_entry, regions, mask.as_deref())
         .iter()
         .map(|run| filter_block_columns(block, run))
         .collect()
 }
 
-pub fn filter(input: &mut dyn BufRead, output: &mut dyn Write, bed: impl BufRead) {
-    let ranges = parse_bed(bed);
+/// Cuts blocks down to the runs of columns within the regions.
+pub struct Filter<'a> {
+    pub regions: &'a RegionFilter,
+    pub query: Option<&'a QueryFilter>,
+}
 
-    while let Ok(item) = next_maf_item(input) {
-        match item {
-            MAFItem::Comment(comment) => {
-                writeln!(output, "#{}", comment).ok();
-            }
-            MAFItem::Block(block) => {
-                for filtered_block in filter_block(&block, &ranges) {
-                    write!(output, "{}", filtered_block).ok();
-                }
-            }
-        }
+impl BlockTransform for Filter<'_> {
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+        filter_block(&block, self.regions, self.query)
     }
 }
 
+pub fn filter(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    regions: &RegionFilter,
+    query: Option<&QueryFilter>,
+) -> Result<(), MAFParseError> {
+    transform_blocks(input, output, &mut Filter { regions, query })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use maf_stream::Range;
+    use multiple_alignment_format::parser::next_maf_item;
+    use multiple_alignment_format::MAFItem;
+    use std::collections::BTreeSet;
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_filter_block() {
         let block = "a
 s       Gallus_gallus.chr1 4432333   5       +       157682039  CAGT-A
@@ -154,6 +179,7 @@ s Alca_torda.scaffold4709 41642 2 - 157682 AA-
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_get_filtered_columns() {
         let block = "a
 s       Gallus_gallus.chr1 4432333   5       +       157682039  CAGT-A
@@ -179,7 +205,7 @@ s       Alca_torda.scaffold4709 41641   3       -       157682  G-AA--
         if let MAFItem::Block(block) = item {
             let ref_entry = block.aligned_entries().next().unwrap();
             assert_eq!(
-                get_filtered_columns(ref_entry, &regions),
+                get_filtered_columns(ref_entry, &RegionFilter::new(Some(regions), None), None),
                 vec![
                     Run {
                         start: 0,
@@ -199,4 +225,92 @@ s       Alca_torda.scaffold4709 41641   3       -       157682  G-AA--
             assert!(false, "Got unexpected maf item {:?}", item);
         }
     }
+
+    #[test]
+    fn test_get_filtered_columns_negative_strand() {
+        // Forward-strand positions of the reference are 89, 88, 87,
+        // 86, and 85, with a gap after the second.
+        let block = "a
+s hg38.chr1 10 5 - 100 AC-GTA
+s mm39.chr2 10 6 + 100 ACTGTA
+
+";
+        let range = |start, end| Range {
+            seq: "chr1".to_string(),
+            start,
+            end,
+        };
+        let regions = RegionFilter::new(
+            Some(vec![range(86, 90)].into_iter().collect()),
+            Some(vec![range(88, 89)].into_iter().collect()),
+        );
+        let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
+        if let MAFItem::Block(block) = item {
+            let ref_entry = block.aligned_entries().next().unwrap();
+            assert_eq!(
+                get_filtered_columns(ref_entry, &regions, None),
+                vec![
+                    Run {
+                        start: 0,
+                        length: 1
+                    },
+                    Run {
+                        start: 3,
+                        length: 2
+                    }
+                ]
+            );
+        } else {
+            panic!("Got unexpected maf item {:?}", item);
+        }
+    }
+
+    #[test]
+    fn test_get_filtered_columns_query() {
+        let block = "a
+s       Gallus_gallus.chr1 4432333   5       +       157682039  CAGT-A
+s       Alca_torda.scaffold4709 42333   6       -       157682  TAGTAA
+s       Alca_torda.scaffold4709 41641   3       -       157682  G-AA--
+";
+        // Forward-strand positions of the first Alca_torda entry are
+        // 115348 down to 115343, and of the second 116040 down to
+        // 116038.
+        let regions: BTreeSet<_> = vec![
+            Range {
+                seq: "scaffold4709".to_string(),
+                start: 115347,
+                end: 115349,
+            },
+            Range {
+                seq: "scaffold4709".to_string(),
+                start: 116038,
+                end: 116039,
+            },
+        ]
+        .into_iter()
+        .collect();
+        let query = QueryFilter::new("Alca_torda", RegionFilter::new(Some(regions), None));
+
+        let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
+        if let MAFItem::Block(block) = item {
+            let mask = query.column_mask(&block);
+            assert_eq!(mask, vec![true, true, false, true, false, false]);
+            let ref_entry = block.aligned_entries().next().unwrap();
+            assert_eq!(
+                get_filtered_columns(ref_entry, &RegionFilter::default(), Some(&mask)),
+                vec![
+                    Run {
+                        start: 0,
+                        length: 2
+                    },
+                    Run {
+                        start: 3,
+                        length: 1
+                    }
+                ]
+            );
+        } else {
+            panic!("Got unexpected maf item {:?}", item);
+        }
+    }
 }
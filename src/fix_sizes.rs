@@ -0,0 +1,194 @@
+use maf_stream::{chrom_part, write_comment, MAFItems, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlockEntry, MAFItem};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+/// Parse a chrom.sizes file (chromosome name, tab, length).
+pub fn parse_chrom_sizes(sizes: impl BufRead) -> HashMap<String, u64> {
+    let mut chrom_sizes = HashMap::new();
+    for line_res in sizes.lines() {
+        let line = line_res.expect("Can't read line");
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            panic!("chrom.sizes line has too few fields: {}", line);
+        }
+        let size = fields[1].parse().expect("Can't parse chromosome size");
+        chrom_sizes.insert(fields[0].to_string(), size);
+    }
+    chrom_sizes
+}
+
+/// A sequence whose size in the MAF doesn't agree with the chrom.sizes.
+#[derive(Debug, Default, PartialEq)]
+pub struct SizeMismatch {
+    /// Size(s) seen in the MAF.
+    pub maf_sizes: Vec<u64>,
+    /// The size from the chrom.sizes file, if the sequence is in it.
+    pub correct_size: Option<u64>,
+    /// Number of rows with a wrong size.
+    pub rows: u64,
+}
+
+/// Rewrites the sequence_size field of rows from genomes with known
+/// chromosome sizes, keeping track of the mismatches found.
+pub struct SizeFixer {
+    /// Genome -> chromosome -> size.
+    sizes: HashMap<String, HashMap<String, u64>>,
+    pub mismatches: BTreeMap<String, SizeMismatch>,
+}
+
+impl SizeFixer {
+    pub fn new(sizes: HashMap<String, HashMap<String, u64>>) -> Self {
+        SizeFixer {
+            sizes,
+            mismatches: BTreeMap::new(),
+        }
+    }
+
+    /// Get the correct size for a row, recording it as a mismatch if
+    /// the row's size is wrong. Rows from genomes without a chrom.sizes
+    /// file, or from sequences missing from it, keep their size.
+    fn fixed_size(&mut self, seq: &str, size: u64) -> u64 {
+        let genome = seq.split('.').next().unwrap();
+        let genome_sizes = match self.sizes.get(genome) {
+            Some(genome_sizes) => genome_sizes,
+            None => return size,
+        };
+        let correct_size = genome_sizes.get(&chrom_part(seq)).cloned();
+        if correct_size == Some(size) {
+            return size;
+        }
+        let mismatch = self.mismatches.entry(seq.to_string()).or_default();
+        mismatch.correct_size = correct_size;
+        mismatch.rows += 1;
+        if !mismatch.maf_sizes.contains(&size) {
+            mismatch.maf_sizes.push(size);
+        }
+        correct_size.unwrap_or(size)
+    }
+
+    pub fn write_report(&self, output: &mut dyn Write) {
+        writeln!(output, "#seq\tmafSizes\tchromSizesSize\trows").ok();
+        for (seq, mismatch) in self.mismatches.iter() {
+            let maf_sizes: Vec<_> = mismatch.maf_sizes.iter().map(|s| s.to_string()).collect();
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}",
+                seq,
+                maf_sizes.join(","),
+                mismatch
+                    .correct_size
+                    .map_or("NA".to_string(), |s| s.to_string()),
+                mismatch.rows
+            )
+            .ok();
+        }
+    }
+}
+
+/// Rewrite incorrect sequence sizes. Starts are left as-is, so rows on
+/// the negative strand keep their reverse-strand coordinates. If
+/// `check_only` is set, no MAF is written, only a report of the
+/// sequences with wrong (or unknown) sizes.
+pub fn fix_sizes(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    fixer: &mut SizeFixer,
+    check_only: bool,
+) -> Result<(), MAFParseError> {
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                if !check_only {
+                    write_comment(output, &comment);
+                }
+            }
+            MAFItem::Block(mut block) => {
+                for entry in block.entries.iter_mut() {
+                    match entry {
+                        MAFBlockEntry::AlignedEntry(e) => {
+                            e.sequence_size = fixer.fixed_size(&e.seq, e.sequence_size)
+                        }
+                        MAFBlockEntry::UnalignedEntry(e) => {
+                            e.sequence_size = fixer.fixed_size(&e.seq, e.sequence_size)
+                        }
+                    }
+                }
+                if !check_only {
+                    output.write_block(&block);
+                }
+            }
+        }
+    }
+    if check_only {
+        fixer.write_report(output);
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 - 150 ACGT
+e mm39.chr5 10 4 + 150 I
+s rn7.chr3 10 4 + 200 ACGT
+
+a
+s hg38.chr1 4 4 + 90 ACGT
+s mm39.chr2 14 4 - 150 ACGT
+";
+
+    fn fixer() -> SizeFixer {
+        let mut sizes = HashMap::new();
+        sizes.insert(
+            "hg38".to_string(),
+            parse_chrom_sizes("chr1\t100\n".as_bytes()),
+        );
+        sizes.insert(
+            "mm39".to_string(),
+            parse_chrom_sizes("chr2\t200\nchr5\t150\n".as_bytes()),
+        );
+        SizeFixer::new(sizes)
+    }
+
+    #[test]
+    fn test_fix_sizes() {
+        let mut output = vec![];
+        fix_sizes(&mut MAF.as_bytes(), &mut output, &mut fixer(), false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 - 200 ACGT
+e mm39.chr5 10 4 + 150 I
+s rn7.chr3 10 4 + 200 ACGT
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 14 4 - 200 ACGT
+
+"
+        );
+    }
+
+    #[test]
+    fn test_check_sizes() {
+        let mut output = vec![];
+        fix_sizes(&mut MAF.as_bytes(), &mut output, &mut fixer(), true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#seq\tmafSizes\tchromSizesSize\trows
+hg38.chr1\t90\t100\t1
+mm39.chr2\t150\t200\t2
+"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use maf_stream::{chrom_part, complement, MAFItems, RegionFilter};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::io::{BufRead, Write};
+
+/// Write a row for every row of the block at every reference base
+/// within the regions.
+fn flatten_block(block: &MAFBlock, output: &mut dyn Write, regions: &RegionFilter) {
+    let ref_entry = match block.aligned_entries().next() {
+        Some(entry) => entry,
+        None => return,
+    };
+    let chrom = chrom_part(&ref_entry.seq);
+    let rows: Vec<_> = block
+        .aligned_entries()
+        .map(|e| {
+            (
+                e,
+                e.seq.split('.').next().unwrap(),
+                chrom_part(&e.seq),
+                e.column_positions().collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    for (column, pos) in ref_entry.column_positions().enumerate() {
+        let pos = match pos {
+            Some(pos) => ref_entry.forward_position(pos),
+            None => continue,
+        };
+        if !regions.contains(&chrom, pos) {
+            continue;
+        }
+        for (entry, species, seq, positions) in rows.iter() {
+            let mut base = entry.alignment[column];
+            // Report bases as they appear on the reference's forward
+            // strand.
+            if ref_entry.strand == Strand::Negative {
+                base = complement(base);
+            }
+            let seq_pos = match positions[column] {
+                Some(p) => entry.forward_position(p).to_string(),
+                None => ".".to_string(),
+            };
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                chrom, pos, species, seq, seq_pos, base as char
+            )
+            .ok();
+        }
+    }
+}
+
+/// Write a long-format "pileup" TSV with a line for every (reference
+/// position, row) pair: the reference chromosome and position, the
+/// row's species, sequence, and position (or "." at a gap), and the
+/// aligned base, on the reference's forward strand. Positions are
+/// 0-based and on the forward strand.
+pub fn flatten(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    regions: &RegionFilter,
+) -> Result<(), MAFParseError> {
+    writeln!(output, "#chrom\tpos\tspecies\tseq\tseq_pos\tbase").ok();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            flatten_block(&block, output, regions);
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::Range;
+
+    #[test]
+    fn test_flatten() {
+        let maf = "a
+s hg38.chr1 10 2 + 100 A-C
+s mm39.chr2 5 3 - 50 AGT
+
+a
+s hg38.chr1 87 2 - 100 GA
+s rn7.chr3 0 1 + 10 -T
+";
+        let regions = RegionFilter::new(
+            None,
+            Some(
+                vec![Range {
+                    seq: "chr1".to_string(),
+                    start: 10,
+                    end: 11,
+                }]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let mut output = vec![];
+        flatten(&mut maf.as_bytes(), &mut output, &regions).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tpos\tspecies\tseq\tseq_pos\tbase
+chr1\t11\thg38\tchr1\t11\tC
+chr1\t11\tmm39\tchr2\t42\tT
+chr1\t12\thg38\tchr1\t12\tC
+chr1\t12\trn7\tchr3\t.\t-
+chr1\t11\thg38\tchr1\t11\tT
+chr1\t11\trn7\tchr3\t0\tA
+"
+        );
+    }
+}
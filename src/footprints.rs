@@ -0,0 +1,93 @@
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFItem, Strand};
+use std::io::{BufRead, Write};
+
+/// Write a BED6 line for every row of the genome in the alignment,
+/// giving the row's forward-strand interval and its strand, and named
+/// after the forward-strand interval of its block's reference (first)
+/// row, as "seq:start-end". Rows with no bases are skipped. Returns the
+/// number of intervals written.
+pub fn footprints(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    genome: &str,
+) -> Result<usize, MAFParseError> {
+    let mut num_intervals = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            let ref_entry = match block.aligned_entries().next() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let (ref_start, ref_end) = ref_entry.forward_interval();
+            for entry in block.aligned_entries() {
+                if entry.aligned_length == 0 || entry.seq.split('.').next().unwrap() != genome {
+                    continue;
+                }
+                let (start, end) = entry.forward_interval();
+                let strand = match entry.strand {
+                    Strand::Positive => '+',
+                    Strand::Negative => '-',
+                };
+                writeln!(
+                    output,
+                    "{}\t{}\t{}\t{}:{}-{}\t0\t{}",
+                    chrom_part(&entry.seq),
+                    start,
+                    end,
+                    ref_entry.seq,
+                    ref_start,
+                    ref_end,
+                    strand
+                )
+                .ok();
+                num_intervals += 1;
+            }
+        }
+    }
+    items.finish()?;
+    Ok(num_intervals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_footprints() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 AC-GT
+s mm39.chr2 5 3 - 20 A--GC
+e mm39.chr3 0 5 + 10 I
+s mm39.chr4 0 2 + 20 --AT-
+
+a
+s hg38.chr1 80 2 - 100 AC
+s rn7.chr1 0 2 + 10 AC
+";
+        let mut output = vec![];
+        assert_eq!(
+            footprints(&mut maf.as_bytes(), &mut output, "mm39").unwrap(),
+            2
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "chr2\t12\t15\thg38.chr1:10-14\t0\t-
+chr4\t0\t2\thg38.chr1:10-14\t0\t+
+"
+        );
+        let mut output = vec![];
+        assert_eq!(
+            footprints(&mut maf.as_bytes(), &mut output, "hg38").unwrap(),
+            2
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "chr1\t10\t14\thg38.chr1:10-14\t0\t+
+chr1\t18\t20\thg38.chr1:18-20\t0\t-
+"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{parse_region, MAFOutput};
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry, Strand};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::BufRead;
+
+/// Where an aligned FASTA record's bases come from, as in a MAF "s"
+/// line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    seq: String,
+    start: u64,
+    size: u64,
+    strand: Strand,
+    sequence_size: u64,
+}
+
+fn parse_strand(strand: &str) -> Strand {
+    match strand {
+        "+" => Strand::Positive,
+        "-" => Strand::Negative,
+        _ => panic!("Invalid strand {}", strand),
+    }
+}
+
+/// How to find the source coordinates of each FASTA record.
+pub enum Coordinates {
+    /// Looked up by record name in a table. A name used in more than one
+    /// alignment has a line for each, used in turn.
+    Table(HashMap<String, VecDeque<Source>>),
+    /// Parsed from record names like `genome.contig:start-end:strand`,
+    /// with 1-based, inclusive coordinates on the forward strand (the
+    /// strand defaults to +), given the sizes of the sequences.
+    Headers(HashMap<String, u64>),
+}
+
+impl Coordinates {
+    /// Read a whitespace-separated table of each record's name, then
+    /// the fields of its MAF "s" line: sequence name, start, size,
+    /// strand, and source size.
+    pub fn parse_table(table: impl BufRead) -> Self {
+        let mut sources: HashMap<String, VecDeque<Source>> = HashMap::new();
+        for line_res in table.lines() {
+            let line = line_res.expect("Can't read line");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+            if fields.len() != 6 {
+                panic!(
+                    "Coordinate table line has {} fields, not 6: {}",
+                    fields.len(),
+                    line
+                );
+            }
+            let parse = |field: &str| -> u64 {
+                field
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Can't parse {} in coordinate table", field))
+            };
+            sources
+                .entry(fields[0].to_string())
+                .or_default()
+                .push_back(Source {
+                    seq: fields[1].to_string(),
+                    start: parse(fields[2]),
+                    size: parse(fields[3]),
+                    strand: parse_strand(fields[4]),
+                    sequence_size: parse(fields[5]),
+                });
+        }
+        Coordinates::Table(sources)
+    }
+
+    fn source(&mut self, name: &str) -> Source {
+        match self {
+            Coordinates::Table(sources) => sources
+                .get_mut(name)
+                .and_then(|sources| sources.pop_front())
+                .unwrap_or_else(|| panic!("No coordinates left for {}", name)),
+            Coordinates::Headers(sizes) => {
+                let (region, strand) = match name.rsplit_once(':') {
+                    Some((region, strand @ ("+" | "-"))) => (region, parse_strand(strand)),
+                    _ => (name, Strand::Positive),
+                };
+                let range = parse_region(region);
+                let sequence_size = *sizes
+                    .get(&range.seq)
+                    .unwrap_or_else(|| panic!("No size given for {}", range.seq));
+                if range.end > sequence_size {
+                    panic!("{} is past the end of {}", name, range.seq);
+                }
+                let start = match strand {
+                    Strand::Positive => range.start,
+                    Strand::Negative => sequence_size - range.end,
+                };
+                Source {
+                    seq: range.seq,
+                    start,
+                    size: range.end - range.start,
+                    strand,
+                    sequence_size,
+                }
+            }
+        }
+    }
+}
+
+/// The name `Coordinates::Headers` reads a row's source coordinates
+/// from: `genome.contig:start-end:strand`, with 1-based, inclusive
+/// coordinates on the forward strand.
+pub fn coordinate_name(entry: &MAFBlockAlignedEntry) -> String {
+    let (start, end) = entry.forward_interval();
+    let strand = match entry.strand {
+        Strand::Positive => '+',
+        Strand::Negative => '-',
+    };
+    format!("{}:{}-{}:{}", entry.seq, start + 1, end, strand)
+}
+
+/// Build a block from the records of one alignment, with the lengths of
+/// its rows counted from their bases, failing if the records aren't all
+/// the same width or their bases don't fit their coordinates.
+pub fn build_block(
+    records: Vec<(String, Vec<u8>)>,
+    coordinates: &mut Coordinates,
+) -> Result<MAFBlock, String> {
+    let length = records[0].1.len();
+    let mut entries = vec![];
+    for (name, alignment) in records {
+        if alignment.len() != length {
+            return Err(format!(
+                "{} has {} columns, but the alignment has {}",
+                name,
+                alignment.len(),
+                length
+            ));
+        }
+        let source = coordinates.source(&name);
+        let aligned_length = alignment.iter().filter(|c| !is_gap(**c)).count() as u64;
+        if aligned_length != source.size {
+            return Err(format!(
+                "{} has {} bases, but its coordinates cover {}",
+                name, aligned_length, source.size
+            ));
+        }
+        if source.start + source.size > source.sequence_size {
+            return Err(format!("{} is past the end of {}", name, source.seq));
+        }
+        entries.push(MAFBlockEntry::AlignedEntry(MAFBlockAlignedEntry {
+            alignment,
+            seq: source.seq,
+            start: source.start,
+            aligned_length,
+            sequence_size: source.sequence_size,
+            strand: source.strand,
+            context: None,
+            qualities: None,
+        }));
+    }
+    Ok(MAFBlock {
+        entries,
+        metadata: BTreeMap::new(),
+    })
+}
+
+/// Turn aligned FASTA back into MAF, with a block for each alignment;
+/// alignments are separated by blank lines, and the first record of
+/// each is the block's reference row. Each record's source coordinates
+/// come from `coordinates`, and must cover exactly its bases. Returns
+/// the number of blocks written.
+pub fn from_fasta(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    coordinates: &mut Coordinates,
+) -> usize {
+    writeln!(output, "##maf version=1").ok();
+    let mut num_blocks = 0;
+    let mut records: Vec<(String, Vec<u8>)> = vec![];
+    let mut lines = input.lines();
+    loop {
+        let line = lines.next().map(|line| line.expect("Can't read line"));
+        let line = line.as_deref().map(|line| line.trim_end());
+        if let Some(name) = line.and_then(|line| line.strip_prefix('>')) {
+            let name = name.split_whitespace().next().unwrap_or("");
+            records.push((name.to_string(), vec![]));
+        } else if let Some(line) = line.filter(|line| !line.is_empty()) {
+            match records.last_mut() {
+                Some((_, seq)) => seq.extend(line.bytes().filter(|c| !c.is_ascii_whitespace())),
+                None => panic!("Sequence before the first FASTA header"),
+            }
+        } else if !records.is_empty() {
+            let block = build_block(std::mem::take(&mut records), coordinates)
+                .unwrap_or_else(|e| panic!("{}", e));
+            output.write_block(&block);
+            num_blocks += 1;
+        }
+        if line.is_none() {
+            break;
+        }
+    }
+    num_blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multiple_alignment_format::parser::next_maf_item;
+    use multiple_alignment_format::MAFItem;
+
+    #[test]
+    fn test_from_fasta_table() {
+        let fasta = ">hg38
+ACG-T
+>mm39
+AC
+GAT
+
+>hg38
+AA
+";
+        let table = "#name\tseq\tstart\tsize\tstrand\tsrc_size
+hg38\thg38.chr1\t10\t4\t+\t100
+mm39\tmm39.chr2\t5\t5\t-\t50
+hg38\thg38.chr1\t20\t2\t+\t100
+";
+        let mut coordinates = Coordinates::parse_table(table.as_bytes());
+        let mut output = vec![];
+        let num_blocks = from_fasta(&mut fasta.as_bytes(), &mut output, &mut coordinates);
+        assert_eq!(num_blocks, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 10 4 + 100 ACG-T
+s mm39.chr2 5 5 - 50 ACGAT
+
+a
+s hg38.chr1 20 2 + 100 AA
+
+"
+        );
+    }
+
+    #[test]
+    fn test_from_fasta_headers() {
+        let fasta = ">hg38.chr1:11-14 extra words
+ACG-T
+>mm39.chr2:41-45:-
+ACGAT
+";
+        let sizes = vec![
+            ("hg38.chr1".to_string(), 100),
+            ("mm39.chr2".to_string(), 50),
+        ];
+        let mut coordinates = Coordinates::Headers(sizes.into_iter().collect());
+        let mut output = vec![];
+        from_fasta(&mut fasta.as_bytes(), &mut output, &mut coordinates);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 10 4 + 100 ACG-T
+s mm39.chr2 5 5 - 50 ACGAT
+
+"
+        );
+    }
+
+    #[test]
+    fn test_coordinate_name() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 AC--GT
+s mm39.chr2 90 3 - 100 a-cG--
+";
+        let block = match next_maf_item(&mut maf.as_bytes()) {
+            Ok(MAFItem::Block(block)) => block,
+            _ => panic!("Couldn't parse block"),
+        };
+        let names: Vec<String> = block.aligned_entries().map(coordinate_name).collect();
+        assert_eq!(names, vec!["hg38.chr1:11-14:+", "mm39.chr2:8-10:-"]);
+        let sizes = vec![
+            ("hg38.chr1".to_string(), 100),
+            ("mm39.chr2".to_string(), 100),
+        ];
+        let mut coordinates = Coordinates::Headers(sizes.into_iter().collect());
+        assert_eq!(coordinates.source(&names[1]).start, 90);
+    }
+
+    #[test]
+    #[should_panic(expected = "has 3 bases, but its coordinates cover 4")]
+    fn test_from_fasta_wrong_size() {
+        let fasta = ">hg38.chr1:11-14\nAC-G\n";
+        let sizes = vec![("hg38.chr1".to_string(), 100)];
+        let mut coordinates = Coordinates::Headers(sizes.into_iter().collect());
+        from_fasta(&mut fasta.as_bytes(), &mut vec![], &mut coordinates);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::filter::filter_block;
+use maf_stream::{
+    block_id, write_unmodified, MAFItems, MAFOutput, Range, RegionFilter, BLOCK_ID_KEY,
+};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem};
+use std::collections::BTreeSet;
+use std::io::BufRead;
+
+/// What blocks should be selected.
+pub enum GrepQuery {
+    /// Blocks whose reference (first) entry overlaps this region. Blocks
+    /// that only partially overlap are trimmed to the region.
+    Region(Range),
+    /// Blocks containing an entry on this sequence (e.g. "hg38.chr1").
+    Seq(String),
+}
+
+fn block_columns(block: &MAFBlock) -> usize {
+    block
+        .aligned_entries()
+        .next()
+        .map_or(0, |e| e.alignment.len())
+}
+
+fn block_contains_seq(block: &MAFBlock, seq: &str) -> bool {
+    block.aligned_entries().any(|e| e.seq == seq)
+}
+
+/// Write the blocks selected by `query`. With `number`, selected blocks
+/// without an ID get their 1-based number among the input's blocks as
+/// one (`id=N`), which pieces trimmed from them keep.
+pub fn grep(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    query: &GrepQuery,
+    number: bool,
+) -> Result<(), MAFParseError> {
+    // Reuse the BED filtering machinery with a single range.
+    let regions = match query {
+        GrepQuery::Region(range) => RegionFilter::new(
+            Some(vec![range.clone()].into_iter().collect::<BTreeSet<_>>()),
+            None,
+        ),
+        GrepQuery::Seq(_) => RegionFilter::default(),
+    };
+
+    let mut num_blocks = 0;
+    let mut items = MAFItems::new(input);
+    while let Some((item, raw)) = items.next_raw() {
+        let mut block = match item {
+            MAFItem::Comment(_) => {
+                write_unmodified(output, &item, &raw);
+                continue;
+            }
+            MAFItem::Block(block) => block,
+        };
+        num_blocks += 1;
+        // Numbered blocks can't be copied from the input as they were.
+        let numbered = number && !block.metadata.contains_key(BLOCK_ID_KEY);
+        if numbered {
+            block
+                .metadata
+                .insert(BLOCK_ID_KEY.to_string(), block_id(&block, num_blocks));
+        }
+        let item = MAFItem::Block(block);
+        let block = match &item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => unreachable!(),
+        };
+        let write_whole = |output: &mut dyn MAFOutput| {
+            if numbered {
+                output.write_block(block);
+            } else {
+                write_unmodified(output, &item, &raw);
+            }
+        };
+        match query {
+            GrepQuery::Region(_) => {
+                let filtered_blocks = filter_block(block, &regions, None);
+                if filtered_blocks.len() == 1
+                    && block_columns(&filtered_blocks[0]) == block_columns(block)
+                {
+                    // Entirely within the region.
+                    write_whole(output);
+                    continue;
+                }
+                for filtered_block in filtered_blocks {
+                    output.write_block(&filtered_block);
+                }
+            }
+            GrepQuery::Seq(seq) => {
+                if block_contains_seq(block, seq) {
+                    write_whole(output);
+                }
+            }
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::parse_region;
+
+    const MAF: &str = "##maf version=1
+a
+s       Gallus_gallus.chr1 4432333   5       +       157682039  CAGT-A
+s       Alca_torda.scaffold4709 42333   6       -       157682  TAGTAA
+
+a
+s       Gallus_gallus.chr2 100   3       +       1000  CAG
+s       Alca_torda.scaffold12 200   3       +       157682  TAG
+";
+
+    #[test]
+    fn test_grep_region() {
+        let mut output = vec![];
+        grep(
+            &mut MAF.as_bytes(),
+            &mut output,
+            &GrepQuery::Region(parse_region("chr1:4432335-4432336")),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s Gallus_gallus.chr1 4432334 2 + 157682039 AG
+s Alca_torda.scaffold4709 42334 2 - 157682 AG
+
+"
+        );
+    }
+
+    #[test]
+    fn test_grep_seq() {
+        let mut output = vec![];
+        grep(
+            &mut MAF.as_bytes(),
+            &mut output,
+            &GrepQuery::Seq("Alca_torda.scaffold12".to_string()),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s       Gallus_gallus.chr2 100   3       +       1000  CAG
+s       Alca_torda.scaffold12 200   3       +       157682  TAG
+
+"
+        );
+    }
+
+    #[test]
+    fn test_grep_number() {
+        let mut output = vec![];
+        grep(
+            &mut MAF.as_bytes(),
+            &mut output,
+            &GrepQuery::Region(parse_region("chr2:101-102")),
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a id=2
+s Gallus_gallus.chr2 100 2 + 1000 CA
+s Alca_torda.scaffold12 200 2 + 157682 TA
+
+"
+        );
+    }
+}
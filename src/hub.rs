@@ -0,0 +1,226 @@
+use crate::wig::{ColumnMetric, WigStep, WigWriter};
+use maf_stream::bbi::{BigBedWriter, BigWigWriter};
+use maf_stream::dup_blocks::duplicated_species;
+use maf_stream::{chrom_part, reverse_complement, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry, MAFItem, Strand};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+const BIG_MAF_AUTO_SQL: &str = "table bedMaf
+\"Bed3 with MAF block\"
+    (
+    string chrom;      \"Reference sequence chromosome or scaffold\"
+    uint   chromStart; \"Start position in chromosome\"
+    uint   chromEnd;   \"End position in chromosome\"
+    lstring mafBlock;  \"MAF block\"
+    )
+";
+
+/// The block with its reference row on the positive strand, as bigMaf
+/// needs: every row is reverse complemented, dropping their "i" lines
+/// and the "e" lines, which would no longer be right.
+fn forward_block(mut block: MAFBlock) -> MAFBlock {
+    block
+        .entries
+        .retain(|entry| matches!(entry, MAFBlockEntry::AlignedEntry(_)));
+    for entry in block.entries.iter_mut() {
+        if let MAFBlockEntry::AlignedEntry(entry) = entry {
+            entry.start = entry.sequence_size - entry.start - entry.aligned_length;
+            entry.strand = match entry.strand {
+                Strand::Positive => Strand::Negative,
+                Strand::Negative => Strand::Positive,
+            };
+            reverse_complement(&mut entry.alignment);
+            if let Some(qualities) = entry.qualities.as_mut() {
+                qualities.reverse();
+            }
+            entry.context = None;
+        }
+    }
+    block
+}
+
+/// Write a UCSC track hub to `output_dir` for the reference genome of
+/// the alignment, which should be sorted by reference position: a bigMaf
+/// of the alignment, a bigWig of the number of species aligned at each
+/// reference base, and a bigBed of the blocks with duplicated species
+/// (named after those species), with the hub.txt, genomes.txt, and
+/// <genome>/trackDb.txt describing them. Returns the number of blocks
+/// and the number of reference positions left out of the coverage
+/// track for overlapping.
+pub fn hub(
+    input: &mut dyn BufRead,
+    output_dir: &str,
+    chrom_sizes: HashMap<String, u64>,
+    name: &str,
+    email: &str,
+) -> Result<(usize, usize), MAFParseError> {
+    let mut alignment = BigBedWriter::new(chrom_sizes.clone())
+        .presorted()
+        .with_format(BIG_MAF_AUTO_SQL, 3);
+    let mut duplications = BigBedWriter::new(chrom_sizes.clone()).presorted();
+    let mut coverage = BigWigWriter::new(chrom_sizes);
+    let mut coverage_wig =
+        WigWriter::new(&mut coverage, ColumnMetric::SpeciesCount, WigStep::Fixed);
+    let mut genome = None;
+    let mut species_order: Vec<String> = vec![];
+    let mut num_blocks = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => continue,
+        };
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let ref_species = ref_entry.seq.split('.').next().unwrap();
+        let genome = genome.get_or_insert_with(|| ref_species.to_string());
+        for entry in block.aligned_entries() {
+            let species = entry.seq.split('.').next().unwrap();
+            if species != genome && !species_order.iter().any(|s| s == species) {
+                species_order.push(species.to_string());
+            }
+        }
+        num_blocks += 1;
+        coverage_wig.add_block(&block);
+        let dups = duplicated_species(&block).join(",");
+        let forward = match ref_entry.strand {
+            Strand::Positive => block,
+            Strand::Negative => forward_block(block),
+        };
+        let ref_entry = forward.aligned_entries().next().unwrap();
+        let chrom = chrom_part(&ref_entry.seq);
+        let (start, end) = (ref_entry.start, ref_entry.start + ref_entry.aligned_length);
+        let maf_block: String = forward
+            .to_string()
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("{};", line))
+            .collect();
+        writeln!(alignment, "{}\t{}\t{}\t{}", chrom, start, end, maf_block).ok();
+        if !dups.is_empty() {
+            writeln!(duplications, "{}\t{}\t{}\t{}", chrom, start, end, dups).ok();
+        }
+    }
+    let num_overlapping = coverage_wig.finish();
+    let genome = genome.unwrap_or_else(|| panic!("No blocks to make a hub of"));
+
+    let genome_dir = Path::new(output_dir).join(&genome);
+    create_dir_all(&genome_dir).expect("Couldn't create hub directory");
+    let create =
+        |path: &Path| BufWriter::new(File::create(path).expect("Couldn't create hub file"));
+    alignment.finish(&mut create(&genome_dir.join("alignment.bb")));
+    coverage.finish(&mut create(&genome_dir.join("coverage.bw")));
+    duplications.finish(&mut create(&genome_dir.join("duplications.bb")));
+
+    let mut hub_txt = create(&Path::new(output_dir).join("hub.txt"));
+    writeln!(hub_txt, "hub {}", name).ok();
+    writeln!(hub_txt, "shortLabel {}", name).ok();
+    writeln!(hub_txt, "longLabel {} alignment", name).ok();
+    writeln!(hub_txt, "genomesFile genomes.txt").ok();
+    writeln!(hub_txt, "email {}", email).ok();
+    let mut genomes_txt = create(&Path::new(output_dir).join("genomes.txt"));
+    writeln!(genomes_txt, "genome {}", genome).ok();
+    writeln!(genomes_txt, "trackDb {}/trackDb.txt", genome).ok();
+    let mut track_db = create(&genome_dir.join("trackDb.txt"));
+    writeln!(track_db, "track alignment").ok();
+    writeln!(track_db, "shortLabel Alignment").ok();
+    writeln!(track_db, "longLabel {} multiple alignment", name).ok();
+    writeln!(track_db, "type bigMaf").ok();
+    writeln!(track_db, "bigDataUrl alignment.bb").ok();
+    if !species_order.is_empty() {
+        writeln!(track_db, "speciesOrder {}", species_order.join(" ")).ok();
+    }
+    writeln!(track_db, "visibility pack").ok();
+    writeln!(track_db).ok();
+    writeln!(track_db, "track coverage").ok();
+    writeln!(track_db, "shortLabel Species aligned").ok();
+    writeln!(track_db, "longLabel Number of species aligned at each base").ok();
+    writeln!(track_db, "type bigWig").ok();
+    writeln!(track_db, "bigDataUrl coverage.bw").ok();
+    writeln!(track_db, "autoScale on").ok();
+    writeln!(track_db, "visibility full").ok();
+    writeln!(track_db).ok();
+    writeln!(track_db, "track duplications").ok();
+    writeln!(track_db, "shortLabel Duplications").ok();
+    writeln!(track_db, "longLabel Blocks with duplicated species").ok();
+    writeln!(track_db, "type bigBed 4").ok();
+    writeln!(track_db, "bigDataUrl duplications.bb").ok();
+    writeln!(track_db, "visibility dense").ok();
+    items.finish()?;
+    Ok((num_blocks, num_overlapping))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multiple_alignment_format::parser::next_maf_item;
+    use std::fs::read;
+    use std::fs::read_to_string;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_hub() {
+        let maf = "##maf version=1
+a
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 20 4 + 200 ACGT
+s mm39.chr3 30 4 + 200 ACGA
+
+a
+s hg38.chr1 80 2 - 100 AC
+s rn7.chr3 0 2 + 200 AC
+";
+        let sizes = vec![("chr1".to_string(), 100)].into_iter().collect();
+        let dir = tempdir().unwrap();
+        let output_dir = dir.path().to_str().unwrap();
+        let (num_blocks, _) = hub(
+            &mut maf.as_bytes(),
+            output_dir,
+            sizes,
+            "test",
+            "me@example.com",
+        )
+        .unwrap();
+        assert_eq!(num_blocks, 2);
+        let track_db = read_to_string(dir.path().join("hg38/trackDb.txt")).unwrap();
+        assert!(track_db.contains("type bigMaf\nbigDataUrl alignment.bb\nspeciesOrder mm39 rn7\n"));
+        assert_eq!(
+            read_to_string(dir.path().join("genomes.txt")).unwrap(),
+            "genome hg38\ntrackDb hg38/trackDb.txt\n"
+        );
+        for (file, magic) in [
+            ("alignment.bb", 0x8789_F2EBu32),
+            ("coverage.bw", 0x888F_FC26),
+            ("duplications.bb", 0x8789_F2EB),
+        ] {
+            let bytes = read(dir.path().join("hg38").join(file)).unwrap();
+            assert_eq!(bytes[..4], magic.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_forward_block() {
+        let maf = "a
+s hg38.chr1 80 2 - 100 AC-
+s rn7.chr3 0 3 + 200 ACT
+";
+        let block = match next_maf_item(&mut maf.as_bytes()).unwrap() {
+            MAFItem::Block(block) => block,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            forward_block(block).to_string(),
+            "a
+s hg38.chr1 18 2 + 100 -GT
+s rn7.chr3 197 3 - 200 AGT
+
+"
+        );
+    }
+}
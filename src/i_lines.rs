@@ -0,0 +1,350 @@
+use maf_stream::{write_comment, MAFItems, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{
+    AlignedContext, AlignedContextStatus, MAFBlockAlignedEntry, MAFBlockEntry,
+    MAFBlockUnalignedEntry, MAFItem, Strand, UnalignedContextStatus,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::ops::Range;
+use tempfile::tempfile;
+
+/// The last place a sequence was seen in the stream.
+struct LastSeen {
+    block: usize,
+    entry: usize,
+    end: u64,
+    strand: Strand,
+}
+
+/// The last entry seen from a genome, for bridging it across blocks
+/// it's missing from.
+struct LastGenomeEntry {
+    block: usize,
+    entry: usize,
+    seq: String,
+    end: u64,
+    strand: Strand,
+    sequence_size: u64,
+}
+
+/// Get the "e" line bridging a genome from its last entry to `next`,
+/// if they're colinear.
+fn bridge(prev: &LastGenomeEntry, next: &MAFBlockAlignedEntry) -> Option<MAFBlockUnalignedEntry> {
+    if prev.seq != next.seq || prev.strand != next.strand || prev.end > next.start {
+        return None;
+    }
+    let size = next.start - prev.end;
+    Some(MAFBlockUnalignedEntry {
+        seq: prev.seq.clone(),
+        start: prev.end,
+        size,
+        strand: prev.strand,
+        sequence_size: prev.sequence_size,
+        status: if size == 0 {
+            UnalignedContextStatus::Deletion
+        } else {
+            UnalignedContextStatus::Insertion
+        },
+    })
+}
+
+/// Context status and count between two consecutive entries from the
+/// same sequence.
+fn context_between(prev: &LastSeen, next: &MAFBlockAlignedEntry) -> (AlignedContextStatus, u64) {
+    if prev.strand != next.strand {
+        (AlignedContextStatus::Insertion, 0)
+    } else if next.start == prev.end {
+        (AlignedContextStatus::Contiguous, 0)
+    } else if next.start > prev.end {
+        (AlignedContextStatus::Insertion, next.start - prev.end)
+    } else {
+        (AlignedContextStatus::AlreadyUsed, 0)
+    }
+}
+
+/// Recompute the "i" lines of every non-reference entry from the
+/// entries before and after it in the same sequence. The input is
+/// spooled to a temporary file, so only the contexts (not the blocks)
+/// are held in memory.
+///
+/// If `add_e_lines` is set, blocks that are missing a genome which is
+/// present and colinear in the blocks on either side (on the same
+/// reference chromosome) get an "e" line bridging the gap, so the
+/// output stays a properly threaded MAF. The "i" lines on either side
+/// of the gap are given the same status as the "e" lines. Each bridge
+/// is held once, however many blocks it spans, until they're written.
+pub fn fix_i_lines(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    add_e_lines: bool,
+) -> Result<(), MAFParseError> {
+    let mut spool = BufWriter::new(tempfile().expect("Couldn't open temporary file"));
+    // Context for each aligned entry of each block. The reference
+    // (first entry) never gets one.
+    let mut contexts: Vec<Vec<Option<AlignedContext>>> = vec![];
+    let mut last_seen: HashMap<String, LastSeen> = HashMap::new();
+    // "e" lines to add, with the range of blocks to add each to.
+    let mut bridges: Vec<(Range<usize>, MAFBlockUnalignedEntry)> = vec![];
+    let mut last_genome_entry: HashMap<String, LastGenomeEntry> = HashMap::new();
+    let mut ref_seq: Option<String> = None;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                writeln!(spool, "#{}", comment).expect("failed to write");
+            }
+            MAFItem::Block(block) => {
+                let block_idx = contexts.len();
+                let mut block_contexts = vec![];
+                for (i, entry) in block.aligned_entries().enumerate() {
+                    if i == 0 {
+                        block_contexts.push(None);
+                        continue;
+                    }
+                    let (left_status, left_count) = match last_seen.get(&entry.seq) {
+                        None => (AlignedContextStatus::FirstInSequence, 0),
+                        Some(prev) => {
+                            let (status, count) = context_between(prev, entry);
+                            let prev_context = if prev.block == block_idx {
+                                block_contexts[prev.entry].as_mut()
+                            } else {
+                                contexts[prev.block][prev.entry].as_mut()
+                            };
+                            let prev_context = prev_context.unwrap();
+                            prev_context.right_status = status.clone();
+                            prev_context.right_count = count;
+                            (status, count)
+                        }
+                    };
+                    block_contexts.push(Some(AlignedContext {
+                        left_status,
+                        left_count,
+                        right_status: AlignedContextStatus::FirstInSequence,
+                        right_count: 0,
+                    }));
+                    last_seen.insert(
+                        entry.seq.clone(),
+                        LastSeen {
+                            block: block_idx,
+                            entry: i,
+                            end: entry.start + entry.aligned_length,
+                            strand: entry.strand,
+                        },
+                    );
+                }
+                contexts.push(block_contexts);
+                if add_e_lines {
+                    // Genomes can't be bridged across a change of
+                    // reference chromosome.
+                    let block_ref_seq = block.aligned_entries().next().map(|e| &e.seq);
+                    if ref_seq.as_ref() != block_ref_seq {
+                        last_genome_entry.clear();
+                        ref_seq = block_ref_seq.cloned();
+                    }
+                    let index_of = |entry: &MAFBlockAlignedEntry| {
+                        block
+                            .aligned_entries()
+                            .position(|e| std::ptr::eq(e, entry))
+                            .unwrap()
+                    };
+                    for (genome, entries) in block.entries_by_species() {
+                        if let Some(prev) = last_genome_entry.get(genome) {
+                            if prev.block + 1 < block_idx {
+                                if let Some(e_line) = bridge(prev, entries[0]) {
+                                    let status = if e_line.size == 0 {
+                                        AlignedContextStatus::Contiguous
+                                    } else {
+                                        AlignedContextStatus::Insertion
+                                    };
+                                    if let Some(context) = contexts[prev.block][prev.entry].as_mut()
+                                    {
+                                        context.right_status = status.clone();
+                                        context.right_count = e_line.size;
+                                    }
+                                    let next = index_of(entries[0]);
+                                    if let Some(context) = contexts[block_idx][next].as_mut() {
+                                        context.left_status = status;
+                                        context.left_count = e_line.size;
+                                    }
+                                    bridges.push((prev.block + 1..block_idx, e_line));
+                                }
+                            }
+                        }
+                        let last = entries.last().unwrap();
+                        last_genome_entry.insert(
+                            genome.to_string(),
+                            LastGenomeEntry {
+                                block: block_idx,
+                                entry: index_of(last),
+                                seq: last.seq.clone(),
+                                end: last.start + last.aligned_length,
+                                strand: last.strand,
+                                sequence_size: last.sequence_size,
+                            },
+                        );
+                    }
+                }
+                write!(spool, "{}", block).expect("failed to write");
+            }
+        }
+    }
+    let result = items.finish();
+
+    let mut spool = spool.into_inner().expect("failed to write");
+    spool
+        .seek(SeekFrom::Start(0))
+        .expect("Couldn't rewind temporary file");
+    let mut spool = BufReader::new(spool);
+    let mut contexts = contexts.into_iter();
+    bridges.sort_by_key(|(blocks, _)| blocks.start);
+    let mut bridges = bridges.into_iter().peekable();
+    // The bridges spanning the current block.
+    let mut open_bridges: Vec<(Range<usize>, MAFBlockUnalignedEntry)> = vec![];
+    let mut block_idx = 0;
+    let mut spooled = MAFItems::new(&mut spool);
+    for item in spooled.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                write_comment(output, &comment);
+            }
+            MAFItem::Block(mut block) => {
+                let mut block_contexts = contexts.next().unwrap().into_iter();
+                for entry in block.entries.iter_mut() {
+                    if let MAFBlockEntry::AlignedEntry(e) = entry {
+                        e.context = block_contexts.next().unwrap();
+                    }
+                }
+                open_bridges.retain(|(blocks, _)| blocks.contains(&block_idx));
+                while let Some(bridge) = bridges.next_if(|(blocks, _)| blocks.start == block_idx) {
+                    open_bridges.push(bridge);
+                }
+                block_idx += 1;
+                let mut e_lines: Vec<_> = open_bridges.iter().map(|(_, e)| e.clone()).collect();
+                e_lines.sort_by(|a, b| a.seq.cmp(&b.seq));
+                for e_line in e_lines {
+                    // Don't duplicate an existing "e" line.
+                    if !block.unaligned_entries().any(|e| e.seq == e_line.seq) {
+                        block.entries.push(MAFBlockEntry::UnalignedEntry(e_line));
+                    }
+                }
+                output.write_block(&block);
+            }
+        }
+    }
+    spooled
+        .finish()
+        .expect("Couldn't read back the temporary file");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fix_i_lines() {
+        let maf = "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+s rn7.chr3 50 2 - 200 AC--
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 14 4 + 200 ACGT
+i mm39.chr2 N 0 C 0
+
+a
+s hg38.chr1 8 4 + 100 ACGT
+s mm39.chr2 20 2 + 200 AC--
+s rn7.chr3 40 4 - 200 ACGT
+";
+        let mut output = vec![];
+        fix_i_lines(&mut maf.as_bytes(), &mut output, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+i mm39.chr2 N 0 C 0
+s rn7.chr3 50 2 - 200 AC--
+i rn7.chr3 N 0 T 0
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 14 4 + 200 ACGT
+i mm39.chr2 C 0 I 2
+
+a
+s hg38.chr1 8 4 + 100 ACGT
+s mm39.chr2 20 2 + 200 AC--
+i mm39.chr2 I 2 N 0
+s rn7.chr3 40 4 - 200 ACGT
+i rn7.chr3 T 0 N 0
+
+"
+        );
+    }
+
+    #[test]
+    fn test_add_e_lines() {
+        // mm39 and rn7 are bridged over the second block, but mm39
+        // isn't bridged over the fourth, since the reference changes
+        // chromosome.
+        let maf = "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+s rn7.chr3 10 4 + 200 ACGT
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+
+a
+s hg38.chr1 8 4 + 100 ACGT
+s mm39.chr2 14 4 + 200 ACGT
+s rn7.chr3 20 4 + 200 ACGT
+
+a
+s hg38.chr2 0 4 + 100 ACGT
+
+a
+s hg38.chr2 4 4 + 100 ACGT
+s mm39.chr2 30 4 + 200 ACGT
+";
+        let mut output = vec![];
+        fix_i_lines(&mut maf.as_bytes(), &mut output, true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+i mm39.chr2 N 0 C 0
+s rn7.chr3 10 4 + 200 ACGT
+i rn7.chr3 N 0 I 6
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+e mm39.chr2 14 0 + 200 C
+e rn7.chr3 14 6 + 200 I
+
+a
+s hg38.chr1 8 4 + 100 ACGT
+s mm39.chr2 14 4 + 200 ACGT
+i mm39.chr2 C 0 I 12
+s rn7.chr3 20 4 + 200 ACGT
+i rn7.chr3 I 6 N 0
+
+a
+s hg38.chr2 0 4 + 100 ACGT
+
+a
+s hg38.chr2 4 4 + 100 ACGT
+s mm39.chr2 30 4 + 200 ACGT
+i mm39.chr2 I 12 N 0
+
+"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem};
+use std::io::{BufRead, Write};
+
+/// Forward-strand intervals of each row of the query species that are
+/// aligned to gaps in the reference, merged within the row.
+fn block_insertions(block: &MAFBlock, query: &str) -> Vec<(String, u64, u64)> {
+    let ref_entry = match block.aligned_entries().next() {
+        Some(entry) => entry,
+        None => return vec![],
+    };
+    let mut insertions = vec![];
+    for entry in block
+        .aligned_entries()
+        .skip(1)
+        .filter(|e| e.seq.split('.').next() == Some(query))
+    {
+        let mut positions: Vec<u64> = entry
+            .column_positions()
+            .zip(ref_entry.alignment.iter())
+            .filter(|(_, ref_base)| is_gap(**ref_base))
+            .filter_map(|(pos, _)| pos.map(|p| entry.forward_position(p)))
+            .collect();
+        positions.sort_unstable();
+        let chrom = chrom_part(&entry.seq);
+        let mut current: Option<(u64, u64)> = None;
+        for pos in positions {
+            current = match current {
+                Some((start, end)) if end == pos => Some((start, pos + 1)),
+                Some((start, end)) => {
+                    insertions.push((chrom.clone(), start, end));
+                    Some((pos, pos + 1))
+                }
+                None => Some((pos, pos + 1)),
+            };
+        }
+        if let Some((start, end)) = current {
+            insertions.push((chrom, start, end));
+        }
+    }
+    insertions
+}
+
+/// Write a BED file, on the query genome, of the query sequence that
+/// is aligned to gaps in the reference, i.e. sequence the query has
+/// that the reference lacks. Returns the number of bases reported.
+pub fn insertions(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    query: &str,
+) -> Result<u64, MAFParseError> {
+    let mut num_bases = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            for (chrom, start, end) in block_insertions(&block, query) {
+                writeln!(output, "{}\t{}\t{}", chrom, start, end).ok();
+                num_bases += end - start;
+            }
+        }
+    }
+    items.finish()?;
+    Ok(num_bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insertions() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 AC---G-T
+s mm39.chr2 20 7 + 200 ACGTAGC-
+s mm39.chr3 20 5 - 100 A-G-AG-T
+s rn7.chr3 30 8 + 200 ACGTAGCT
+";
+        let mut output = vec![];
+        assert_eq!(
+            insertions(&mut maf.as_bytes(), &mut output, "mm39").unwrap(),
+            6
+        );
+        // mm39.chr3 is on the negative strand, so its forward-strand
+        // positions run backwards through the block.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "chr2\t22\t25
+chr2\t26\t27
+chr3\t77\t79
+"
+        );
+    }
+}
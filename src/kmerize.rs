@@ -0,0 +1,149 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::MAFItems;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, Write};
+
+fn base_code(base: u8) -> Option<u64> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// The distinct canonical k-mers (the lesser of a k-mer and its
+/// reverse complement, 2 bits per base) of each species.
+struct KmerSets {
+    k: usize,
+    species: BTreeMap<String, HashSet<u64>>,
+}
+
+impl KmerSets {
+    fn new(k: usize) -> Self {
+        assert!((1..=32).contains(&k), "k must be between 1 and 32");
+        KmerSets {
+            k,
+            species: BTreeMap::new(),
+        }
+    }
+
+    /// Add the k-mers of a row's bases (without gaps). K-mers
+    /// containing anything but A, C, G, or T are skipped.
+    fn add_bases(&mut self, species: &str, bases: impl Iterator<Item = u8>) {
+        let k = self.k;
+        let mask = if k == 32 {
+            u64::MAX
+        } else {
+            (1 << (2 * k)) - 1
+        };
+        let set = self.species.entry(species.to_string()).or_default();
+        let (mut forward, mut reverse, mut len) = (0u64, 0u64, 0);
+        for base in bases {
+            match base_code(base) {
+                Some(code) => {
+                    forward = ((forward << 2) | code) & mask;
+                    reverse = (reverse >> 2) | ((3 - code) << (2 * (k - 1)));
+                    len += 1;
+                    if len >= k {
+                        set.insert(forward.min(reverse));
+                    }
+                }
+                None => len = 0,
+            }
+        }
+    }
+
+    /// The number of k-mers two species share.
+    fn shared(&self, a: &str, b: &str) -> usize {
+        let (a, b) = (&self.species[a], &self.species[b]);
+        let (smaller, larger) = if a.len() < b.len() { (a, b) } else { (b, a) };
+        smaller.iter().filter(|kmer| larger.contains(kmer)).count()
+    }
+}
+
+/// Count the distinct k-mers in the aligned (non-gap) bases of every
+/// species, treating each row as a separate stretch of sequence, and
+/// write a TSV comparing every pair of species: their numbers of
+/// k-mers and shared k-mers, the Jaccard index, and the fraction of
+/// each species' k-mers contained in the other's.
+pub fn kmerize(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    k: usize,
+) -> Result<(), MAFParseError> {
+    let mut sets = KmerSets::new(k);
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            for entry in block.aligned_entries() {
+                let species = entry.seq.split('.').next().unwrap();
+                let bases = entry.alignment.iter().copied().filter(|c| !is_gap(*c));
+                sets.add_bases(species, bases);
+            }
+        }
+    }
+    writeln!(
+        output,
+        "#species1\tspecies2\tkmers1\tkmers2\tshared\tjaccard\tcontainment1\tcontainment2"
+    )
+    .ok();
+    let names: Vec<&String> = sets.species.keys().collect();
+    let fraction = |n: usize, d: usize| {
+        if d == 0 {
+            "NA".to_string()
+        } else {
+            format!("{:.4}", n as f64 / d as f64)
+        }
+    };
+    for (i, a) in names.iter().enumerate() {
+        for b in names[i + 1..].iter() {
+            let (num_a, num_b) = (sets.species[*a].len(), sets.species[*b].len());
+            let shared = sets.shared(a, b);
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                a,
+                b,
+                num_a,
+                num_b,
+                shared,
+                fraction(shared, num_a + num_b - shared),
+                fraction(shared, num_a),
+                fraction(shared, num_b)
+            )
+            .ok();
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmerize() {
+        let maf = "a
+s hg38.chr1 0 6 + 100 ACG-TAC
+s mm39.chr2 0 6 - 100 GTA-CGT
+s rn7.chr3 0 5 + 100 ACGNT--
+";
+        let mut output = vec![];
+        kmerize(&mut maf.as_bytes(), &mut output, 3).unwrap();
+        // hg38 has ACG, CGT, GTA, TAC, which are ACG, ACG, GTA, GTA
+        // canonically. mm39 is its reverse complement, and rn7 only
+        // has ACG.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#species1\tspecies2\tkmers1\tkmers2\tshared\tjaccard\tcontainment1\tcontainment2
+hg38\tmm39\t2\t2\t2\t1.0000\t1.0000\t1.0000
+hg38\trn7\t2\t1\t1\t0.5000\t0.5000\t1.0000
+mm39\trn7\t2\t1\t1\t0.5000\t0.5000\t1.0000
+"
+        );
+    }
+}
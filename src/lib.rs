@@ -1,9 +1,31 @@
+use crate::alphabet::is_gap;
 use itertools::Itertools;
+use multiple_alignment_format::output::{MAFValidationError, MAFWriter, OutputStyle};
+use multiple_alignment_format::parser::{next_maf_item, next_maf_item_raw, MAFParseError};
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFItem};
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
-use std::io::BufRead;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
 
-#[derive(PartialEq, Eq, Debug)]
+pub mod alphabet;
+pub mod bbi;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod coverage;
+pub mod dup_blocks;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod seq_filter;
+pub mod synthetic;
+pub mod transform;
+pub mod tree;
+pub mod window;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Range {
     pub seq: String,
     pub start: u64,
@@ -56,6 +78,89 @@ pub fn parse_bed(bed: impl BufRead) -> BTreeSet<Range> {
         .collect()
 }
 
+/// Get the positions of the variants in a VCF as single-base ranges.
+pub fn parse_vcf_positions(vcf: impl BufRead) -> BTreeSet<Range> {
+    vcf.lines()
+        .enumerate()
+        .filter_map(|(i, line_res)| {
+            let line = line_res.expect("Can't read line");
+            if line.starts_with('#') || line.trim().is_empty() {
+                // Header or blank line
+                return None;
+            }
+            let mut fields = line.split('\t');
+            let seq = fields.next().unwrap().to_string();
+            let pos: u64 = fields
+                .next()
+                .expect("VCF line incomplete")
+                .parse()
+                .expect("Can't parse VCF position");
+            if pos == 0 {
+                panic!(
+                    "VCF positions start at 1, but line {} has 0: {}",
+                    i + 1,
+                    line
+                );
+            }
+            Some(Range {
+                seq,
+                start: pos - 1,
+                end: pos,
+            })
+        })
+        .collect()
+}
+
+/// Get the positions in a VCF, recognized by its `##fileformat=VCF`
+/// header line, or else the ranges in a BED.
+pub fn parse_positions(mut input: impl BufRead) -> BTreeSet<Range> {
+    let is_vcf = input
+        .fill_buf()
+        .expect("Can't read positions")
+        .starts_with(b"##fileformat=VCF");
+    if is_vcf {
+        parse_vcf_positions(input)
+    } else {
+        parse_bed(input)
+    }
+}
+
+/// Parse a region string like "chr1:1001-2000". The positions are
+/// 1-based and inclusive (as in samtools or the UCSC browser), but the
+/// resulting Range is 0-based and half-open like a BED entry.
+pub fn parse_region(region: &str) -> Range {
+    try_parse_region(region).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Parse a region string as `parse_region` does, returning a
+/// description of the problem if it can't be parsed.
+pub fn try_parse_region(region: &str) -> Result<Range, String> {
+    let shape = || format!("Region must look like chr:start-end, not {}", region);
+    let colon = region.rfind(':').ok_or_else(shape)?;
+    let seq = region[..colon].to_string();
+    let mut positions = region[colon + 1..].splitn(2, '-');
+    let start: u64 = positions
+        .next()
+        .unwrap()
+        .replace(',', "")
+        .parse()
+        .map_err(|_| format!("Can't parse region start in {}", region))?;
+    let end: u64 = positions
+        .next()
+        .ok_or_else(shape)?
+        .replace(',', "")
+        .parse()
+        .map_err(|_| format!("Can't parse region end in {}", region))?;
+    if start == 0 || end < start {
+        return Err(format!("Invalid region {}", region));
+    }
+    Ok(Range {
+        seq,
+        start: start - 1,
+        end,
+    })
+}
+
 pub fn range_contains_pos(set: &BTreeSet<Range>, chrom: &str, position: u64) -> bool {
     let pos = Range {
         seq: chrom.to_string(),
@@ -68,6 +173,172 @@ pub fn range_contains_pos(set: &BTreeSet<Range>, chrom: &str, position: u64) ->
     }
 }
 
+/// Remove the bases in `exclude` from the ranges in `include`. The
+/// exclude set should not contain overlaps.
+pub fn subtract_ranges(include: &BTreeSet<Range>, exclude: &BTreeSet<Range>) -> BTreeSet<Range> {
+    let mut result = BTreeSet::new();
+    let mut excludes = exclude.iter().peekable();
+    for range in include {
+        // Skip past excluded ranges entirely before this range.
+        while let Some(ex) = excludes.peek() {
+            if ex.seq < range.seq || (ex.seq == range.seq && ex.end <= range.start) {
+                excludes.next();
+            } else {
+                break;
+            }
+        }
+        let mut start = range.start;
+        for ex in excludes.clone() {
+            if ex.seq != range.seq || ex.start >= range.end {
+                break;
+            }
+            if ex.start > start {
+                result.insert(Range {
+                    seq: range.seq.clone(),
+                    start,
+                    end: ex.start,
+                });
+            }
+            start = start.max(ex.end);
+        }
+        if start < range.end {
+            result.insert(Range {
+                seq: range.seq.clone(),
+                start,
+                end: range.end,
+            });
+        }
+    }
+    result
+}
+
+/// Merge overlapping or adjacent ranges, so the result can be used
+/// with `range_contains_pos` and `subtract_ranges`.
+pub fn merge_ranges(ranges: impl IntoIterator<Item = Range>) -> BTreeSet<Range> {
+    let sorted: BTreeSet<Range> = ranges.into_iter().collect();
+    let mut merged: Vec<Range> = vec![];
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if last.seq == range.seq && range.start <= last.end => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged.into_iter().collect()
+}
+
+/// Regions of the reference to include and/or exclude. A position
+/// passes if it's in the include set (or there is none) and not in the
+/// exclude set.
+#[derive(Debug, Default, Clone)]
+pub struct RegionFilter {
+    pub include: Option<BTreeSet<Range>>,
+    pub exclude: Option<BTreeSet<Range>>,
+}
+
+impl RegionFilter {
+    pub fn new(include: Option<BTreeSet<Range>>, exclude: Option<BTreeSet<Range>>) -> Self {
+        RegionFilter { include, exclude }
+    }
+
+    pub fn contains(&self, chrom: &str, position: u64) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|set| range_contains_pos(set, chrom, position))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|set| range_contains_pos(set, chrom, position))
+    }
+
+    /// The parts of `range` passing the filter, in order.
+    pub fn passing_ranges(&self, range: &Range) -> Vec<Range> {
+        let within = |set: &BTreeSet<Range>| {
+            merge_ranges(
+                overlapping_ranges(set, range)
+                    .filter(|r| r.seq == range.seq && r.start < range.end && r.end > range.start)
+                    .map(|r| Range {
+                        seq: r.seq.clone(),
+                        start: r.start.max(range.start),
+                        end: r.end.min(range.end),
+                    }),
+            )
+        };
+        let included = match &self.include {
+            Some(set) => within(set),
+            None => std::iter::once(range.clone()).collect(),
+        };
+        match &self.exclude {
+            Some(set) => subtract_ranges(&included, &within(set)),
+            None => included,
+        }
+        .into_iter()
+        .collect()
+    }
+
+    /// Total number of bases passing the filter. If there is no
+    /// include set, the whole of each sequence in `sequence_lengths`
+    /// (chrom -> length) is considered included.
+    pub fn total_length(&self, sequence_lengths: impl Iterator<Item = (String, u64)>) -> u64 {
+        let included: BTreeSet<Range> = match &self.include {
+            Some(set) => set.clone(),
+            None => sequence_lengths
+                .map(|(seq, length)| Range {
+                    seq,
+                    start: 0,
+                    end: length,
+                })
+                .collect(),
+        };
+        let included = match &self.exclude {
+            Some(exclude) => subtract_ranges(&included, exclude),
+            None => included,
+        };
+        included.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+/// Regions on a query (non-reference) genome.
+#[derive(Debug, Clone)]
+pub struct QueryFilter {
+    pub genome: String,
+    pub regions: RegionFilter,
+}
+
+impl QueryFilter {
+    pub fn new(genome: &str, regions: RegionFilter) -> Self {
+        QueryFilter {
+            genome: genome.to_string(),
+            regions,
+        }
+    }
+
+    /// For each column of the block, whether an entry from the query
+    /// genome has a base there that falls within the query regions.
+    pub fn column_mask(&self, block: &MAFBlock) -> Vec<bool> {
+        let length = block
+            .aligned_entries()
+            .next()
+            .map_or(0, |e| e.alignment.len());
+        let mut mask = vec![false; length];
+        for entry in block
+            .aligned_entries()
+            .filter(|e| e.seq.split('.').next().unwrap() == self.genome)
+        {
+            let chrom = chrom_part(&entry.seq);
+            for (i, pos) in entry.column_positions().enumerate() {
+                if let Some(pos) = pos {
+                    if self.regions.contains(&chrom, entry.forward_position(pos)) {
+                        mask[i] = true;
+                    }
+                }
+            }
+        }
+        mask
+    }
+}
+
 /// Gives (potentially) overlapping ranges
 pub fn overlapping_ranges<'a>(
     set: &'a BTreeSet<Range>,
@@ -84,15 +355,626 @@ pub fn overlapping_ranges<'a>(
         .chain(set.range(range..=&end))
 }
 
+/// How MAF output is written.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputOptions {
+    pub writer: MAFWriter,
+    /// Drop comments, rather than passing them through. The "##maf"
+    /// header line is kept either way.
+    pub strip_comments: bool,
+}
+
+/// Somewhere MAF is written to, with the options to write it with.
+pub trait MAFOutput: Write {
+    fn options(&self) -> OutputOptions;
+
+    /// The error for the block strict output refused to write, here or
+    /// in a file written alongside this output, if any. No more MAF is
+    /// written after it.
+    fn refused(&self) -> Option<&MAFValidationError>;
+
+    fn refuse(&mut self, error: MAFValidationError);
+
+    /// Print a warning to stderr.
+    fn warn(&mut self, message: &str) {
+        eprintln!("Warning: {}", message);
+    }
+
+    /// Note blocks that couldn't be processed, and were dropped or left
+    /// alone.
+    fn record_skipped(&mut self, _num_blocks: usize) {}
+
+    /// Write a block in the output's style, unless strict output
+    /// refuses it.
+    fn write_block(&mut self, block: &MAFBlock) {
+        if self.refused().is_some() {
+            return;
+        }
+        let writer = self.options().writer;
+        if let Err(e) = writer.write_block(self, block) {
+            self.refuse(e);
+        }
+    }
+}
+
+/// Written with the default options, under which no block is refused.
+impl MAFOutput for Vec<u8> {
+    fn options(&self) -> OutputOptions {
+        OutputOptions::default()
+    }
+
+    fn refused(&self) -> Option<&MAFValidationError> {
+        None
+    }
+
+    fn refuse(&mut self, _error: MAFValidationError) {}
+}
+
+/// A stream MAF is written to with the given options.
+pub struct MAFOutputStream<W: Write> {
+    inner: W,
+    options: OutputOptions,
+    refused: Option<MAFValidationError>,
+    num_warnings: usize,
+    num_skipped: usize,
+}
+
+impl<W: Write> MAFOutputStream<W> {
+    pub fn new(inner: W, options: OutputOptions) -> Self {
+        MAFOutputStream {
+            inner,
+            options,
+            refused: None,
+            num_warnings: 0,
+            num_skipped: 0,
+        }
+    }
+
+    pub fn num_warnings(&self) -> usize {
+        self.num_warnings
+    }
+
+    pub fn num_skipped(&self) -> usize {
+        self.num_skipped
+    }
+}
+
+impl<W: Write> Write for MAFOutputStream<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> MAFOutput for MAFOutputStream<W> {
+    fn options(&self) -> OutputOptions {
+        self.options
+    }
+
+    fn refused(&self) -> Option<&MAFValidationError> {
+        self.refused.as_ref()
+    }
+
+    fn refuse(&mut self, error: MAFValidationError) {
+        self.refused.get_or_insert(error);
+    }
+
+    /// Print a warning, counting it for the run summary.
+    fn warn(&mut self, message: &str) {
+        eprintln!("Warning: {}", message);
+        self.num_warnings += 1;
+    }
+
+    fn record_skipped(&mut self, num_blocks: usize) {
+        self.num_skipped += num_blocks;
+    }
+}
+
+/// Whether a comment (without its leading '#') is the "##maf" header
+/// line.
+pub fn is_header(comment: &str) -> bool {
+    comment.starts_with("#maf")
+}
+
+/// Pass a comment through to MAF output, unless comments are being
+/// stripped or a block has been refused.
+pub fn write_comment(output: &mut dyn MAFOutput, comment: &str) {
+    if output.refused().is_none() && (!output.options().strip_comments || is_header(comment)) {
+        writeln!(output, "#{}", comment).ok();
+    }
+}
+
+/// Write rows of an alignment (by name) to a file, as FASTA, or as the
+/// sequential PHYLIP that PAML and IQ-TREE read.
+pub fn write_alignment_file(path: &Path, rows: &BTreeMap<String, Vec<u8>>, phylip: bool) {
+    let mut file = BufWriter::new(File::create(path).expect("Couldn't create file"));
+    if phylip {
+        let length = rows.values().next().map_or(0, |seq| seq.len());
+        writeln!(file, " {} {}", rows.len(), length).expect("failed to write");
+    }
+    for (name, seq) in rows.iter() {
+        if phylip {
+            write!(file, "{}  ", name).expect("failed to write");
+        } else {
+            writeln!(file, ">{}", name).expect("failed to write");
+        }
+        file.write_all(seq).expect("failed to write");
+        writeln!(file).expect("failed to write");
+    }
+}
+
+/// The metadata key holding a block's stable ID.
+pub const BLOCK_ID_KEY: &str = "id";
+
+/// A block's ID, for cross-referencing it between reports and the MAF:
+/// the `id` in its metadata if it has one (as `annotate --compute id`
+/// adds), which survives filtering and trimming, or else `number`, its
+/// 1-based position among the blocks of the input.
+pub fn block_id(block: &MAFBlock, number: usize) -> String {
+    block
+        .metadata
+        .get(BLOCK_ID_KEY)
+        .cloned()
+        .unwrap_or_else(|| number.to_string())
+}
+
+/// The items of a MAF, read until the end of the input or the first
+/// one that can't be parsed. That one's error is kept for `finish`, so
+/// that the items before it can be used first.
+pub struct MAFItems<'a> {
+    input: &'a mut dyn BufRead,
+    error: Option<MAFParseError>,
+}
+
+impl<'a> MAFItems<'a> {
+    pub fn new(input: &'a mut dyn BufRead) -> Self {
+        MAFItems { input, error: None }
+    }
+
+    /// Keep the error that stopped the input. A parse error handed on
+    /// by a reader wrapping the input (as `SeqFilterReader` does) comes
+    /// through as an I/O error, and is unwrapped.
+    fn stop(&mut self, e: MAFParseError) {
+        let e = match e {
+            MAFParseError::IOError(e) if e.get_ref().is_some_and(|e| e.is::<MAFParseError>()) => {
+                *e.into_inner().unwrap().downcast::<MAFParseError>().unwrap()
+            }
+            e => e,
+        };
+        self.error = Some(e);
+    }
+
+    /// The next item, along with its original text, as
+    /// `next_maf_item_raw` gives it.
+    pub fn next_raw(&mut self) -> Option<(MAFItem, String)> {
+        if self.error.is_some() {
+            return None;
+        }
+        match next_maf_item_raw(self.input) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                self.stop(e);
+                None
+            }
+        }
+    }
+
+    /// Ok if the input was read to its end (or not all of it was
+    /// asked for), or else the error that stopped it.
+    pub fn finish(self) -> Result<(), MAFParseError> {
+        match self.error {
+            None | Some(MAFParseError::EOF) => Ok(()),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+impl Iterator for MAFItems<'_> {
+    type Item = MAFItem;
+
+    fn next(&mut self) -> Option<MAFItem> {
+        if self.error.is_some() {
+            return None;
+        }
+        match next_maf_item(self.input) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                self.stop(e);
+                None
+            }
+        }
+    }
+}
+
+/// Run `f` on each block of a MAF that's being transformed into
+/// another MAF, passing the header and comments through to the output
+/// as they come. Only the first "##maf" header is kept, so
+/// concatenated MAFs come out as one. Returns the error for the block
+/// that stopped the input from being read to its end, if any.
+pub fn for_each_block(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    mut f: impl FnMut(MAFBlock, &mut dyn MAFOutput),
+) -> Result<(), MAFParseError> {
+    let mut seen_header = false;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) if is_header(&comment) => {
+                if !seen_header {
+                    write_comment(output, &comment);
+                }
+                seen_header = true;
+            }
+            MAFItem::Comment(comment) => write_comment(output, &comment),
+            MAFItem::Block(block) => f(block, output),
+        }
+    }
+    items.finish()
+}
+
+/// Write out an item read with `next_maf_item_raw` that hasn't been
+/// modified. Its original text is used, so it comes out byte-for-byte
+/// identical to the input, unless the padded output style was asked
+/// for. Blocks are still checked under strict output.
+pub fn write_unmodified(output: &mut dyn MAFOutput, item: &MAFItem, raw: &str) {
+    if output.refused().is_some() {
+        return;
+    }
+    match item {
+        MAFItem::Comment(comment) => {
+            if output.options().strip_comments && !is_header(comment) {
+                return;
+            }
+        }
+        MAFItem::Block(block) => {
+            if output.options().writer.strict {
+                if let Err(e) = block.validate() {
+                    output.refuse(e);
+                    return;
+                }
+            }
+        }
+    }
+    if output.options().writer.style == OutputStyle::Compact {
+        output.write_all(raw.as_bytes()).ok();
+        return;
+    }
+    match item {
+        MAFItem::Comment(comment) => {
+            writeln!(output, "#{}", comment).ok();
+        }
+        MAFItem::Block(block) => output.write_block(block),
+    }
+}
+
+/// Fraction of the bases aligned between a row and the other rows of
+/// its block that are identical (ignoring case), or None if no bases
+/// are aligned to the row.
+pub fn block_identity(block: &MAFBlock, row: &MAFBlockAlignedEntry) -> Option<f64> {
+    let mut num_aligned = 0;
+    let mut num_identical = 0;
+    for entry in block.aligned_entries() {
+        if std::ptr::eq(entry, row) {
+            continue;
+        }
+        for (a, b) in row.alignment.iter().zip(entry.alignment.iter()) {
+            if !is_gap(*a) && !is_gap(*b) {
+                num_aligned += 1;
+                if a.eq_ignore_ascii_case(b) {
+                    num_identical += 1;
+                }
+            }
+        }
+    }
+    if num_aligned == 0 {
+        None
+    } else {
+        Some(num_identical as f64 / num_aligned as f64)
+    }
+}
+
 /// Get "chr.name" from "genome.chr.name".
 pub fn chrom_part(seq: &str) -> String {
     seq.split('.').skip(1).join(".")
 }
 
+/// Complement a base, keeping its case. Anything that isn't a
+/// nucleotide (gaps, Ns) is left alone.
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => base,
+    }
+}
+
+pub fn reverse_complement(seq: &mut [u8]) {
+    seq.reverse();
+    for base in seq.iter_mut() {
+        *base = complement(*base);
+    }
+}
+
+/// Small deterministic PRNG (SplitMix64). We don't need anything
+/// fancy, just output that is reproducible for a given seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform float in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform integer in [0, n), from the high word of a widening
+    /// multiply. Draws whose low word falls below `2^64 mod n` are
+    /// redrawn, since they'd make some results more likely than others.
+    pub fn below(&mut self, n: u64) -> u64 {
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let product = self.next_u64() as u128 * n as u128;
+            if product as u64 >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_rng_below() {
+        let mut rng = Rng::new(7);
+        let mut counts = [0; 3];
+        for _ in 0..30000 {
+            counts[rng.below(3) as usize] += 1;
+        }
+        assert!(counts.iter().all(|count| (9500..10500).contains(count)));
+        // The largest bound, where almost every draw is kept.
+        assert!(rng.below(u64::MAX) < u64::MAX);
+        assert_eq!(rng.below(1), 0);
+    }
+
+    #[test]
+    fn test_block_id() {
+        let maf = "a id=chr1.7
+s hg38.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 2 2 + 10 GT
+";
+        let mut ids = vec![];
+        for_each_block(&mut maf.as_bytes(), &mut vec![], |block, _| {
+            ids.push(block_id(&block, ids.len() + 1));
+        })
+        .unwrap();
+        assert_eq!(ids, vec!["chr1.7", "2"]);
+    }
+
+    #[test]
+    fn test_for_each_block() {
+        let maf = "##maf version=1
+# comment
+a
+s hg38.chr1 0 2 + 10 AC
+";
+        let mut output = vec![];
+        let mut num_blocks = 0;
+        for_each_block(&mut maf.as_bytes(), &mut output, |block, output| {
+            num_blocks += 1;
+            output.write_block(&block);
+        })
+        .unwrap();
+        assert_eq!(num_blocks, 1);
+        assert_eq!(String::from_utf8(output).unwrap(), format!("{}\n", maf));
+    }
+
+    #[test]
+    fn test_write_unmodified_padded() {
+        let maf = "a
+s hg38.chr1 0 2 + 10 AC
+s mm39.chr1 100 2 + 1000 AC
+
+";
+        let mut options = OutputOptions::default();
+        options.writer.style = OutputStyle::Padded;
+        let mut output = MAFOutputStream::new(vec![], options);
+        let mut input = maf.as_bytes();
+        let mut items = MAFItems::new(&mut input);
+        while let Some((item, raw)) = items.next_raw() {
+            write_unmodified(&mut output, &item, &raw);
+        }
+        assert_eq!(
+            String::from_utf8(output.inner).unwrap(),
+            "a
+s hg38.chr1   0 2 +   10 AC
+s mm39.chr1 100 2 + 1000 AC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_strict_output() {
+        let maf = "a
+s hg38.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 2 3 + 10 GT
+
+a
+s hg38.chr1 4 2 + 10 AC
+";
+        let mut options = OutputOptions::default();
+        options.writer.strict = true;
+        let mut output = MAFOutputStream::new(vec![], options);
+        let mut input = maf.as_bytes();
+        let mut items = MAFItems::new(&mut input);
+        while let Some((item, raw)) = items.next_raw() {
+            write_unmodified(&mut output, &item, &raw);
+        }
+        assert_eq!(
+            output.refused().unwrap().to_string(),
+            "invalid block at hg38.chr1:2: hg38.chr1 has 2 bases but a size of 3"
+        );
+        assert_eq!(
+            String::from_utf8(output.inner).unwrap(),
+            "a\ns hg38.chr1 0 2 + 10 AC\n\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let maf = "##maf version=1
+# comment
+a
+s hg38.chr1 0 2 + 10 AC
+";
+        let options = OutputOptions {
+            strip_comments: true,
+            ..OutputOptions::default()
+        };
+        let mut output = MAFOutputStream::new(vec![], options);
+        for_each_block(&mut maf.as_bytes(), &mut output, |block, output| {
+            output.write_block(&block);
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output.inner).unwrap(),
+            "##maf version=1\na\ns hg38.chr1 0 2 + 10 AC\n\n"
+        );
+    }
+
+    #[test]
+    fn test_output_counts() {
+        let mut output = MAFOutputStream::new(vec![], OutputOptions::default());
+        output.warn("first");
+        output.warn("second");
+        output.record_skipped(3);
+        assert_eq!((output.num_warnings(), output.num_skipped()), (2, 3));
+    }
+
+    #[test]
+    fn test_for_each_block_error() {
+        let maf = "a
+s hg38.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 2 2 + 10 GT
+x unknown
+
+a
+s hg38.chr1 4 2 + 10 AC
+";
+        let mut num_blocks = 0;
+        let result = for_each_block(&mut maf.as_bytes(), &mut vec![], |_, _| num_blocks += 1);
+        assert_eq!(num_blocks, 1);
+        assert!(matches!(result, Err(MAFParseError::BadLineType(_))));
+
+        let result = for_each_block(&mut "a\ns hg38.chr1".as_bytes(), &mut vec![], |_, _| {});
+        assert!(matches!(result, Err(MAFParseError::Truncated(_))));
+    }
+
+    #[test]
+    fn test_for_each_block_concatenated() {
+        let maf = "track name=alignment
+##maf version=1
+a
+s hg38.chr1 0 2 + 10 AC
+
+track name=alignment
+##maf version=1
+a
+s hg38.chr1 2 2 + 10 GT
+";
+        let mut output = vec![];
+        for_each_block(&mut maf.as_bytes(), &mut output, |block, output| {
+            output.write_block(&block);
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 2 2 + 10 GT
+
+"
+        );
+    }
+
+    #[test]
+    fn test_for_each_block_bom_and_cr() {
+        // Concatenated files saved with a byte order mark and old Mac
+        // line endings still have their headers recognized.
+        let maf = "\u{feff}##maf version=1\ra\rs hg38.chr1 0 2 + 10 AC\r\r\
+                   \u{feff}##maf version=1\r\na\r\ns hg38.chr1 2 2 + 10 GT\r\n";
+        let mut output = vec![];
+        for_each_block(&mut maf.as_bytes(), &mut output, |block, output| {
+            output.write_block(&block);
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 0 2 + 10 AC
+
+a
+s hg38.chr1 2 2 + 10 GT
+
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_positions() {
+        let vcf = "##fileformat=VCFv4.2\n#CHROM\tPOS\tID\nchr1\t10\t.\n";
+        let bed = "chr1\t9\t10\n";
+        let expected: BTreeSet<Range> = vec![Range {
+            seq: "chr1".to_string(),
+            start: 9,
+            end: 10,
+        }]
+        .into_iter()
+        .collect();
+        assert_eq!(parse_positions(vcf.as_bytes()), expected);
+        assert_eq!(parse_positions(bed.as_bytes()), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "line 3 has 0")]
+    fn test_parse_vcf_position_zero() {
+        parse_vcf_positions("##fileformat=VCFv4.2\n#CHROM\tPOS\nchr1\t0\n".as_bytes());
+    }
+
     #[test]
     fn test_range_contains_pos() {
         let regions: BTreeSet<_> = vec![
@@ -126,6 +1008,116 @@ mod tests {
         assert!(range_contains_pos(&regions, "chr2", 4));
     }
 
+    #[test]
+    fn test_merge_ranges() {
+        let range = |seq: &str, start, end| Range {
+            seq: seq.to_string(),
+            start,
+            end,
+        };
+        let merged = merge_ranges(vec![
+            range("chr1", 10, 20),
+            range("chr2", 0, 5),
+            range("chr1", 15, 30),
+            range("chr1", 30, 35),
+            range("chr1", 40, 50),
+        ]);
+        assert_eq!(
+            merged.into_iter().collect::<Vec<_>>(),
+            vec![
+                range("chr1", 10, 35),
+                range("chr1", 40, 50),
+                range("chr2", 0, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subtract_ranges() {
+        let include: BTreeSet<_> = vec![
+            Range {
+                seq: "chr1".to_string(),
+                start: 0,
+                end: 100,
+            },
+            Range {
+                seq: "chr2".to_string(),
+                start: 10,
+                end: 20,
+            },
+        ]
+        .into_iter()
+        .collect();
+        let exclude: BTreeSet<_> = vec![
+            Range {
+                seq: "chr1".to_string(),
+                start: 10,
+                end: 20,
+            },
+            Range {
+                seq: "chr1".to_string(),
+                start: 90,
+                end: 200,
+            },
+            Range {
+                seq: "chr2".to_string(),
+                start: 0,
+                end: 20,
+            },
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            subtract_ranges(&include, &exclude)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![
+                Range {
+                    seq: "chr1".to_string(),
+                    start: 0,
+                    end: 10,
+                },
+                Range {
+                    seq: "chr1".to_string(),
+                    start: 20,
+                    end: 90,
+                },
+            ]
+        );
+
+        let regions = RegionFilter::new(None, Some(exclude));
+        assert!(regions.contains("chr1", 9));
+        assert!(!regions.contains("chr1", 10));
+        assert_eq!(
+            regions.total_length(vec![("chr1".to_string(), 100)].into_iter()),
+            80
+        );
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            parse_region("chr1:1,001-2,000"),
+            Range {
+                seq: "chr1".to_string(),
+                start: 1000,
+                end: 2000,
+            }
+        );
+        assert_eq!(
+            parse_region("HLA:A*01:01:1-5"),
+            Range {
+                seq: "HLA:A*01:01".to_string(),
+                start: 0,
+                end: 5,
+            }
+        );
+        assert!(try_parse_region("chr1").is_err());
+        assert!(try_parse_region("chr1:5").is_err());
+        assert!(try_parse_region("chr1:x-5").is_err());
+        assert!(try_parse_region("chr1:0-5").is_err());
+    }
+
     #[test]
     fn test_overlapping_ranges() {
         let regions: BTreeSet<_> = vec![
@@ -1,30 +1,1063 @@
-use clap::{value_t, App, Arg, SubCommand};
+use clap::{value_t, App, Arg, ArgMatches, SubCommand};
+use flate2::read::MultiGzDecoder;
+use multiple_alignment_format::output::OutputStyle;
+use multiple_alignment_format::parser::MAFParseError;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io;
-use std::io::{stdout, BufRead, BufReader, Write};
+use std::io::{stdout, BufRead, BufReader, BufWriter, Write};
 
-mod dup_blocks;
-use dup_blocks::{output_dup_blocks, output_merged_consensus_blocks, ConsensusMode};
+use maf_stream::dup_blocks::{output_dup_blocks, output_merged_consensus_blocks, ConsensusMode};
 mod split;
+use maf_stream::coverage::{
+    coverage, groups_from_tree, parse_groups, CoverageFormat, CoverageOptions, Dinucleotide,
+};
 use split::split_maf;
-mod coverage;
-use coverage::coverage;
 mod filter;
 use filter::filter;
-mod lib;
+mod grep;
+use grep::{grep, GrepQuery};
+use maf_stream::alphabet::alphabet;
+use maf_stream::bbi::{BigBedWriter, BigWigWriter};
+use maf_stream::seq_filter::{SeqFilter, SeqFilterReader};
+use maf_stream::synthetic::{LengthDistribution, SyntheticMaf};
+use maf_stream::transform::Normalize;
+use maf_stream::{
+    merge_ranges, parse_bed, parse_positions, parse_region, MAFOutput, MAFOutputStream,
+    OutputOptions, QueryFilter, RegionFilter,
+};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+mod subsample;
+use subsample::{head, sample, tail};
+mod snps;
+use snps::snps;
+mod cds;
+use cds::{extract_cds, gene_loss, CdsOptions};
+mod i_lines;
+use i_lines::fix_i_lines;
+mod view;
+use view::{view, ViewFormat};
+mod rename;
+use rename::{parse_mapping, rename, Renamer};
+mod fix_sizes;
+use fix_sizes::{fix_sizes, parse_chrom_sizes, SizeFixer};
+mod relabel;
+use relabel::{parse_agp, parse_offsets, relabel_coords};
+mod dedup;
+use dedup::{dedup, dedup_sorted};
+mod overlaps;
+use overlaps::check_overlaps;
+mod single_cov;
+use single_cov::single_cov;
+mod mask_quality;
+use mask_quality::{mask_quality, MaskMode};
+mod constraint;
+use constraint::constraint;
+use maf_stream::tree::Tree;
+use maf_stream::window::{export_windows, write_windows};
+mod presence;
+use presence::presence;
+mod insertions;
+use insertions::insertions;
+mod reorder_rows;
+use reorder_rows::{reorder_rows, RowOrder};
+mod annotate;
+use annotate::{annotate, annotate_genes, parse_key_value, Annotator, Computed, IntervalLookup};
+mod flatten;
+use flatten::flatten;
+mod pairwise_identity;
+use pairwise_identity::{bootstrap_identity, pairwise_identity};
+mod quick_tree;
+use quick_tree::quick_tree;
+mod trim;
+use trim::trim;
+mod trim_columns;
+use trim_columns::{trim_columns, ColumnTrimmer};
+mod explode;
+use explode::explode;
+mod mapreduce;
+use mapreduce::{mapreduce, Operation};
+mod recase;
+use recase::recase;
+mod break_at_ref_gaps;
+use break_at_ref_gaps::break_at_ref_gaps;
+mod contiguity;
+use contiguity::contiguity;
+mod extract_genomes;
+use extract_genomes::extract_genomes;
+mod footprints;
+use footprints::footprints;
+mod kmerize;
+use kmerize::kmerize;
+mod assembly_gaps;
+use assembly_gaps::{assembly_gaps, GapChecker};
+mod shuffle;
+use shuffle::shuffle;
+mod to_tensor;
+use to_tensor::{to_tensor, TensorFormat};
+mod profile;
+use profile::{profile, tree_weights};
+mod realign;
+use realign::realign;
+mod pipe;
+use pipe::pipe;
+mod wig;
+use wig::{wig, ColumnMetric, WigStep};
+mod hub;
+use hub::hub;
+mod thin;
+use thin::thin;
+mod stats;
+use stats::stats;
+mod pair_classes;
+use pair_classes::{pair_classes, PairIntervals};
+mod dstat;
+use dstat::{dstat, DstatSpecies};
+mod patterns;
+use patterns::{patterns, PatternMode};
+mod to_ss;
+use to_ss::to_ss;
+mod from_fasta;
+use from_fasta::{from_fasta, Coordinates};
+mod roundtrip;
+use roundtrip::roundtrip;
+mod drop_species;
+use drop_species::drop_species;
+mod run_summary;
+use run_summary::{CountingReader, CountingWriter, RunStatus, RunSummary, SharedCounts};
+#[cfg(feature = "browse")]
+mod browse;
+#[cfg(feature = "remote")]
+use maf_stream::remote;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+/// Open the input MAF, which may be a URL if built with the "remote"
+/// feature, or memory-mapped if built with the "mmap" feature.
+fn open_input(path: &str) -> Box<dyn BufRead> {
+    #[cfg(feature = "remote")]
+    {
+        if remote::is_url(path) {
+            return Box::new(BufReader::new(remote::UrlReader::new(path)));
+        }
+    }
+    let file = File::open(path).expect("Couldn't open input file");
+    #[cfg(feature = "mmap")]
+    {
+        // Pipes (like the /dev/fd/N of process substitution) can't be
+        // mapped, so they're read like any other stream.
+        if file.metadata().is_ok_and(|m| m.is_file()) {
+            // The input must not be modified while we're reading it.
+            let map = unsafe { Mmap::map(&file) }.expect("Couldn't map input file");
+            // The whole map is the cursor's buffer, so `next_maf_item`
+            // parses every block in place.
+            return Box::new(io::Cursor::new(map));
+        }
+    }
+    Box::new(BufReader::new(file))
+}
+
+/// The panic payload `StopOnBrokenPipe` unwinds to `main` with.
+struct BrokenPipe;
+
+/// Stops the subcommand once whatever is reading the output has gone
+/// away (as with `| head`), rather than carrying on through the rest of
+/// the input for nothing, by unwinding to `main` with `BrokenPipe`.
+/// Later writes, as of the summary's flush, are dropped.
+struct StopOnBrokenPipe<W: Write> {
+    inner: W,
+    broken: bool,
+}
+
+impl<W: Write> StopOnBrokenPipe<W> {
+    fn new(inner: W) -> Self {
+        StopOnBrokenPipe {
+            inner,
+            broken: false,
+        }
+    }
+
+    fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.broken = true;
+                panic::panic_any(BrokenPipe)
+            }
+            result => result,
+        }
+    }
+}
+
+impl<W: Write> Write for StopOnBrokenPipe<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.broken {
+            return Ok(buf.len());
+        }
+        let result = self.inner.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.broken {
+            return Ok(());
+        }
+        let result = self.inner.flush();
+        self.check(result)
+    }
+}
+
+/// Build the region filter from the "bed" and "bed_exclude" options.
+fn region_filter(matches: &ArgMatches) -> RegionFilter {
+    let open_bed = |path| {
+        parse_bed(BufReader::new(
+            File::open(path).expect("Couldn't open bed file"),
+        ))
+    };
+    RegionFilter::new(
+        matches.value_of("bed").map(open_bed),
+        matches.value_of("bed_exclude").map(open_bed),
+    )
+}
+
+fn query_filter_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+    [
+        Arg::with_name("query_genome")
+            .long("query_genome")
+            .alias("query-genome")
+            .takes_value(true)
+            .requires("query_bed"),
+        Arg::with_name("query_bed")
+            .long("query_bed")
+            .alias("query-bed")
+            .takes_value(true)
+            .requires("query_genome"),
+    ]
+}
+
+/// Build the query filter from the "query_genome" and "query_bed" options.
+fn query_filter(matches: &ArgMatches) -> Option<QueryFilter> {
+    matches.value_of("query_bed").map(|path| {
+        let bed = parse_bed(BufReader::new(
+            File::open(path).expect("Couldn't open bed file"),
+        ));
+        QueryFilter::new(
+            matches.value_of("query_genome").unwrap(),
+            RegionFilter::new(Some(bed), None),
+        )
+    })
+}
+
+/// Build the genome -> groups map for coverage from the "groups" or
+/// "group_tree" options.
+fn coverage_groups(matches: &ArgMatches) -> Option<HashMap<String, Vec<String>>> {
+    if let Some(path) = matches.value_of("groups") {
+        Some(parse_groups(BufReader::new(
+            File::open(path).expect("Couldn't open groups file"),
+        )))
+    } else {
+        matches.value_of("group_tree").map(|path| {
+            let newick = std::fs::read_to_string(path).expect("Couldn't read tree file");
+            groups_from_tree(&Tree::parse_newick(&newick))
+        })
+    }
+}
+
+fn chrom_sizes_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("chrom_sizes")
+        .long("chrom_sizes")
+        .alias("chrom-sizes")
+        .help("Chrom sizes of the reference, to write a bigWig or bigBed instead of text")
+        .takes_value(true)
+}
+
+/// Read the "chrom_sizes" option's file, if it was given.
+fn chrom_sizes(matches: &ArgMatches) -> Option<HashMap<String, u64>> {
+    matches.value_of("chrom_sizes").map(|path| {
+        parse_chrom_sizes(BufReader::new(
+            File::open(path).expect("Couldn't open chrom sizes file"),
+        ))
+    })
+}
+
+/// Run the subcommand that was asked for. Returns the error for the
+/// block that stopped the input from being read to its end, if any.
+/// Warn about reference positions skipped for being covered by more
+/// than one block.
+fn warn_overlapping(output: &mut dyn MAFOutput, num_overlapping: usize) {
+    if num_overlapping > 0 {
+        output.warn(&format!(
+            "Skipped {} reference positions covered by more than one block",
+            num_overlapping
+        ));
+    }
+}
+
+fn run_subcommand(
+    matches: &ArgMatches,
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+) -> Result<(), MAFParseError> {
+    let alphabet = alphabet(matches.is_present("protein"));
+    if let Some(matches) = matches.subcommand_matches("dup_blocks") {
+        output_dup_blocks(input, output, matches.value_of("bed_out"))?;
+    } else if let Some(matches) = matches.subcommand_matches("merge_dups") {
+        let mode = ConsensusMode::from_name(matches.value_of("mode").unwrap())
+            .expect("Unknown consensus mode");
+        let max_block_bases = matches
+            .value_of("max_block_bases")
+            .map(|_| value_t!(matches, "max_block_bases", u64).unwrap_or_else(|e| e.exit()));
+        let stitch = matches.is_present("stitch");
+        output_merged_consensus_blocks(input, output, mode, max_block_bases, stitch, alphabet)?;
+    } else if let Some(_matches) = matches.subcommand_matches("to_fasta") {
+        unimplemented!();
+    //maf_to_fasta(input, output);
+    } else if let Some(matches) = matches.subcommand_matches("split") {
+        let max_length = value_t!(matches, "max_length", u64).unwrap_or(100_000);
+        let tree = matches.value_of("tree").map(|path| {
+            let newick = std::fs::read_to_string(path).expect("Couldn't read tree file");
+            Tree::parse_newick(&newick)
+        });
+        let max_compressed_bytes = matches
+            .value_of("max_compressed_bytes")
+            .map(|_| value_t!(matches, "max_compressed_bytes", u64).unwrap_or_else(|e| e.exit()));
+        split_maf(
+            input,
+            max_length,
+            max_compressed_bytes,
+            matches.value_of("output_dir").unwrap(),
+            output,
+            matches.is_present("emit_metadata"),
+            tree.as_ref(),
+            matches.value_of("shard_by_species"),
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("coverage") {
+        let ref_genome = matches.value_of("ref_genome").unwrap();
+        let options = CoverageOptions {
+            regions: region_filter(matches),
+            query_filter: query_filter(matches),
+            groups: coverage_groups(matches),
+            min_block_length: value_t!(matches, "min_block_length", u64)
+                .unwrap_or_else(|e| e.exit()),
+            min_identity: value_t!(matches, "min_identity", f64).unwrap_or_else(|e| e.exit()),
+            context: matches.value_of("context").map(Dinucleotide::parse),
+            context_exclude: matches.value_of("context_exclude").map(Dinucleotide::parse),
+            format: match matches.value_of("format") {
+                Some("json") => CoverageFormat::Json,
+                Some("markdown") => CoverageFormat::Markdown,
+                _ => CoverageFormat::Tsv,
+            },
+            alphabet,
+        };
+        let num_threads = value_t!(matches, "threads", usize).unwrap_or_else(|e| e.exit());
+        if num_threads > 1 {
+            let operation = Operation::Coverage {
+                ref_genome,
+                options: &options,
+            };
+            mapreduce(input, output, &operation, num_threads)?;
+        } else {
+            coverage(input, output, ref_genome, options)?;
+        }
+    } else if let Some(matches) = matches.subcommand_matches("filter") {
+        let regions = region_filter(matches);
+        let query = query_filter(matches);
+        let num_threads = value_t!(matches, "threads", usize).unwrap_or_else(|e| e.exit());
+        if num_threads > 1 {
+            let operation = Operation::Filter {
+                regions: &regions,
+                query: query.as_ref(),
+            };
+            mapreduce(input, output, &operation, num_threads)?;
+        } else {
+            filter(input, output, &regions, query.as_ref())?;
+        }
+    } else if let Some(matches) = matches.subcommand_matches("grep") {
+        let query = match matches.value_of("region") {
+            Some(region) => GrepQuery::Region(parse_region(region)),
+            None => GrepQuery::Seq(matches.value_of("seq").unwrap().to_string()),
+        };
+        grep(input, output, &query, matches.is_present("number"))?;
+    } else if let Some(matches) = matches.subcommand_matches("head") {
+        let num_blocks = value_t!(matches, "num_blocks", usize).unwrap_or_else(|e| e.exit());
+        head(input, output, num_blocks)?;
+    } else if let Some(matches) = matches.subcommand_matches("tail") {
+        let num_blocks = value_t!(matches, "num_blocks", usize).unwrap_or_else(|e| e.exit());
+        tail(input, output, num_blocks)?;
+    } else if let Some(matches) = matches.subcommand_matches("sample") {
+        let fraction = value_t!(matches, "fraction", f64).unwrap_or_else(|e| e.exit());
+        let seed = value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit());
+        sample(input, output, fraction, seed)?;
+    } else if let Some(matches) = matches.subcommand_matches("snps") {
+        let path = matches.value_of("positions").unwrap();
+        let positions_file = File::open(path).expect("Couldn't open positions file");
+        let positions = if path.ends_with(".gz") {
+            parse_positions(BufReader::new(MultiGzDecoder::new(positions_file)))
+        } else {
+            parse_positions(BufReader::new(positions_file))
+        };
+        snps(input, output, positions)?;
+    } else if let Some(matches) = matches.subcommand_matches("extract_cds") {
+        let gff_file = matches
+            .value_of("gff")
+            .map(|path| BufReader::new(File::open(path).expect("Couldn't open GFF file")))
+            .unwrap();
+        extract_cds(
+            input,
+            output,
+            matches.value_of("output_dir").unwrap(),
+            gff_file,
+            &CdsOptions {
+                translate: matches.is_present("translate"),
+                drop_gappy_codons: matches.is_present("drop_gappy_codons"),
+                mask_cpg: matches.is_present("mask_cpg"),
+                phylip: matches.is_present("phylip"),
+                tree: matches.value_of("tree").map(|path| {
+                    let newick = std::fs::read_to_string(path).expect("Couldn't read tree file");
+                    Tree::parse_newick(&newick)
+                }),
+            },
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("fix_i_lines") {
+        fix_i_lines(input, output, matches.is_present("add_e_lines"))?;
+    } else if let Some(matches) = matches.subcommand_matches("view") {
+        let format = if matches.is_present("html") {
+            ViewFormat::Html
+        } else {
+            ViewFormat::Ansi
+        };
+        let width = value_t!(matches, "width", usize).unwrap_or_else(|e| e.exit());
+        view(input, output, format, width)?;
+    } else if let Some(matches) = matches.subcommand_matches("rename") {
+        let mapping = BufReader::new(
+            File::open(matches.value_of("mapping").unwrap()).expect("Couldn't open mapping file"),
+        );
+        let mut renamer = Renamer::new(parse_mapping(mapping));
+        rename(input, output, &mut renamer)?;
+    } else if let Some(matches) = matches.subcommand_matches("fix_sizes") {
+        let mut sizes = HashMap::new();
+        for arg in matches.values_of("sizes").unwrap() {
+            let mut split = arg.splitn(2, '=');
+            let genome = split.next().unwrap();
+            let path = split
+                .next()
+                .expect("--sizes should be of the form genome=chrom.sizes");
+            let file = BufReader::new(File::open(path).expect("Couldn't open chrom.sizes file"));
+            sizes.insert(genome.to_string(), parse_chrom_sizes(file));
+        }
+        let mut fixer = SizeFixer::new(sizes);
+        fix_sizes(input, output, &mut fixer, matches.is_present("check"))?;
+    } else if let Some(matches) = matches.subcommand_matches("relabel_coords") {
+        let placements = match matches.value_of("agp") {
+            Some(path) => parse_agp(BufReader::new(
+                File::open(path).expect("Couldn't open AGP file"),
+            )),
+            None => parse_offsets(BufReader::new(
+                File::open(matches.value_of("offsets").unwrap())
+                    .expect("Couldn't open offset table"),
+            )),
+        };
+        let genomes: HashSet<String> = matches
+            .values_of("genome")
+            .unwrap()
+            .map(|g| g.to_string())
+            .collect();
+        relabel_coords(input, output, &placements, &genomes)?;
+    } else if let Some(matches) = matches.subcommand_matches("dedup") {
+        let num_dropped = if matches.is_present("sorted") {
+            dedup_sorted(input, output)?
+        } else {
+            dedup(input, output)?
+        };
+        eprintln!("Dropped {} duplicate blocks", num_dropped);
+    } else if let Some(matches) = matches.subcommand_matches("check_overlaps") {
+        let trim = matches.is_present("trim");
+        let num_overlaps = check_overlaps(input, output, trim)?;
+        if trim {
+            eprintln!("Trimmed {} overlapping block pairs", num_overlaps);
+        }
+    } else if matches.subcommand_matches("single_cov").is_some() {
+        single_cov(input, output)?;
+    } else if let Some(matches) = matches.subcommand_matches("simulate") {
+        let block_length = if matches.is_present("mean_length") {
+            LengthDistribution::Geometric {
+                mean: value_t!(matches, "mean_length", f64).unwrap_or_else(|e| e.exit()),
+            }
+        } else {
+            let min = value_t!(matches, "min_length", usize).unwrap_or_else(|e| e.exit());
+            let max = value_t!(matches, "max_length", usize).unwrap_or_else(|e| e.exit());
+            if min > max {
+                clap::Error::with_description(
+                    &format!(
+                        "--min_length ({}) can't be more than --max_length ({})",
+                        min, max
+                    ),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit();
+            }
+            LengthDistribution::Uniform { min, max }
+        };
+        let synthetic = SyntheticMaf {
+            num_species: value_t!(matches, "num_species", usize).unwrap_or_else(|e| e.exit()),
+            num_blocks: value_t!(matches, "num_blocks", usize).unwrap_or_else(|e| e.exit()),
+            block_length,
+            substitution_rate: value_t!(matches, "substitution_rate", f64)
+                .unwrap_or_else(|e| e.exit()),
+            indel_rate: value_t!(matches, "indel_rate", f64).unwrap_or_else(|e| e.exit()),
+            duplication_rate: value_t!(matches, "duplication_rate", f64)
+                .unwrap_or_else(|e| e.exit()),
+            seed: value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit()),
+        };
+        synthetic.write_maf(output);
+    } else if let Some(matches) = matches.subcommand_matches("mask_quality") {
+        let min_q = value_t!(matches, "min_q", u8).unwrap_or_else(|e| e.exit());
+        let species: Option<HashSet<String>> = matches
+            .values_of("species")
+            .map(|values| values.map(|s| s.to_string()).collect());
+        let mode = if matches.is_present("lowercase") {
+            MaskMode::Lowercase
+        } else {
+            MaskMode::N
+        };
+        let num_masked = mask_quality(input, output, min_q, species.as_ref(), mode)?;
+        eprintln!("Masked {} bases", num_masked);
+    } else if let Some(matches) = matches.subcommand_matches("constraint") {
+        let newick = std::fs::read_to_string(matches.value_of("tree").unwrap())
+            .expect("Couldn't read tree file");
+        let tree = Tree::parse_newick(&newick);
+        let missing = match chrom_sizes(matches) {
+            Some(sizes) => {
+                let mut bigwig = BigWigWriter::new(sizes);
+                let missing = constraint(input, &mut bigwig, tree)?;
+                bigwig.finish(output);
+                missing
+            }
+            None => constraint(input, output, tree)?,
+        };
+        for species in missing {
+            output.warn(&format!(
+                "Species {} isn't in the tree, ignored it",
+                species
+            ));
+        }
+    } else if let Some(matches) = matches.subcommand_matches("presence") {
+        let window = value_t!(matches, "window", u64).unwrap_or_else(|e| e.exit());
+        let min_bases = value_t!(matches, "min_bases", u64).unwrap_or_else(|e| e.exit());
+        presence(input, output, window, min_bases)?;
+    } else if let Some(matches) = matches.subcommand_matches("insertions") {
+        let num_bases = insertions(input, output, matches.value_of("query").unwrap())?;
+        eprintln!("Found {} inserted bases", num_bases);
+    } else if let Some(matches) = matches.subcommand_matches("reorder_rows") {
+        let order = if let Some(species) = matches.values_of("species") {
+            RowOrder::from_species_list(species)
+        } else if let Some(path) = matches.value_of("tree") {
+            let newick = std::fs::read_to_string(path).expect("Couldn't read tree file");
+            RowOrder::from_tree(&Tree::parse_newick(&newick))
+        } else {
+            RowOrder::Alphabetical
+        };
+        reorder_rows(input, output, &order)?;
+    } else if let Some(matches) = matches.subcommand_matches("annotate") {
+        let annotator = Annotator {
+            remove: matches
+                .values_of("remove")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            compute: matches
+                .values_of("compute")
+                .map(|values| values.map(Computed::from_name).collect())
+                .unwrap_or_default(),
+            lookup: matches.value_of("lookup_bed").map(|path| {
+                IntervalLookup::from_bed(
+                    matches.value_of("lookup_key").unwrap(),
+                    BufReader::new(File::open(path).expect("Couldn't open bed file")),
+                )
+            }),
+            set: matches
+                .values_of("set")
+                .map(|values| values.map(parse_key_value).collect())
+                .unwrap_or_default(),
+        };
+        annotate(input, output, &annotator)?;
+    } else if let Some(matches) = matches.subcommand_matches("flatten") {
+        flatten(input, output, &region_filter(matches))?;
+    } else if let Some(matches) = matches.subcommand_matches("pairwise_identity") {
+        let regions = if matches.is_present("bed") || matches.is_present("bed_exclude") {
+            Some(region_filter(matches))
+        } else {
+            None
+        };
+        if matches.is_present("bootstrap") {
+            let replicates = value_t!(matches, "bootstrap", usize).unwrap_or_else(|e| e.exit());
+            let seed = value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit());
+            bootstrap_identity(input, output, regions.as_ref(), replicates, seed)?;
+        } else {
+            pairwise_identity(input, output, regions.as_ref())?;
+        }
+    } else if matches.subcommand_matches("quick_tree").is_some() {
+        quick_tree(input, output)?;
+    } else if let Some(matches) = matches.subcommand_matches("trim") {
+        let max_gap_fraction =
+            value_t!(matches, "max_gap_fraction", f64).unwrap_or_else(|e| e.exit());
+        let window = value_t!(matches, "window", usize).unwrap_or_else(|e| e.exit());
+        let (num_trimmed, num_dropped) = trim(input, output, max_gap_fraction, window)?;
+        eprintln!(
+            "Trimmed {} columns, dropping {} blocks",
+            num_trimmed, num_dropped
+        );
+    } else if let Some(matches) = matches.subcommand_matches("trim_columns") {
+        let trimmer = ColumnTrimmer {
+            min_present: value_t!(matches, "min_present", f64).unwrap_or_else(|e| e.exit()),
+            min_flank_conservation: value_t!(matches, "min_flank_conservation", f64)
+                .unwrap_or_else(|e| e.exit()),
+            min_run_length: value_t!(matches, "min_run_length", usize).unwrap_or_else(|e| e.exit()),
+            alphabet,
+        };
+        let mut report = matches
+            .value_of("report")
+            .map(|p| File::create(p).expect("Couldn't create report file"));
+        let num_removed = trim_columns(
+            input,
+            output,
+            &trimmer,
+            report.as_mut().map(|f| f as &mut dyn Write),
+        )?;
+        eprintln!("Removed {} columns", num_removed);
+    } else if let Some(matches) = matches.subcommand_matches("explode") {
+        let num_files = explode(input, matches.value_of("output_dir").unwrap(), output)?;
+        eprintln!("Wrote {} pairwise files", num_files);
+    } else if let Some(matches) = matches.subcommand_matches("recase") {
+        let mut normalize = Normalize {
+            upper: matches.is_present("upper"),
+            ..Normalize::default()
+        };
+        for arg in matches.values_of("repeats").into_iter().flatten() {
+            let mut split = arg.splitn(2, '=');
+            let genome = split.next().unwrap();
+            let path = split
+                .next()
+                .expect("--repeats should be of the form genome=repeats.bed");
+            let file = BufReader::new(File::open(path).expect("Couldn't open BED file"));
+            normalize
+                .repeats
+                .insert(genome.to_string(), merge_ranges(parse_bed(file)));
+        }
+        let num_masked = recase(input, output, &mut normalize)?;
+        eprintln!("Soft-masked {} bases", num_masked);
+    } else if matches.subcommand_matches("break_at_ref_gaps").is_some() {
+        let num_dropped = break_at_ref_gaps(input, output)?;
+        eprintln!("Dropped {} reference gap columns", num_dropped);
+    } else if matches.subcommand_matches("contiguity").is_some() {
+        let counts = contiguity(input, output)?;
+        eprintln!(
+            "{} gaps ({} bases), {} overlaps, {} out-of-order blocks, {} revisited chromosomes",
+            counts.gaps, counts.gap_bases, counts.overlaps, counts.out_of_order, counts.revisits
+        );
+    } else if let Some(matches) = matches.subcommand_matches("extract_genomes") {
+        let num_species = extract_genomes(input, matches.value_of("output_dir").unwrap())?;
+        eprintln!("Wrote sequence for {} species", num_species);
+    } else if let Some(matches) = matches.subcommand_matches("footprints") {
+        let genome = matches.value_of("genome").unwrap();
+        let num_intervals = match chrom_sizes(matches) {
+            Some(sizes) => {
+                let mut bigbed = BigBedWriter::new(sizes);
+                let num_intervals = footprints(input, &mut bigbed, genome)?;
+                bigbed.finish(output);
+                num_intervals
+            }
+            None => footprints(input, output, genome)?,
+        };
+        eprintln!("Wrote {} intervals", num_intervals);
+    } else if let Some(matches) = matches.subcommand_matches("kmerize") {
+        let k = value_t!(matches, "k", usize).unwrap_or_else(|e| e.exit());
+        kmerize(input, output, k)?;
+    } else if let Some(matches) = matches.subcommand_matches("assembly_gaps") {
+        let mut gaps = HashMap::new();
+        for arg in matches.values_of("gaps").unwrap() {
+            let mut split = arg.splitn(2, '=');
+            let genome = split.next().unwrap();
+            let path = split
+                .next()
+                .expect("--gaps should be of the form genome=gaps.bed");
+            let file = BufReader::new(File::open(path).expect("Couldn't open BED file"));
+            gaps.insert(genome.to_string(), parse_bed(file));
+        }
+        let flank = value_t!(matches, "flank", u64).unwrap_or_else(|e| e.exit());
+        let mut checker = GapChecker::new(gaps, flank);
+        let mut report: Box<dyn Write> = match matches.value_of("report") {
+            Some(path) => Box::new(File::create(path).expect("Couldn't create report file")),
+            None => Box::new(io::stderr()),
+        };
+        assembly_gaps(input, output, &mut checker, &mut report)?;
+    } else if let Some(matches) = matches.subcommand_matches("shuffle") {
+        let seed = value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit());
+        let num_buckets = value_t!(matches, "buckets", usize).unwrap_or_else(|e| e.exit());
+        let num_blocks = shuffle(input, output, seed, num_buckets)?;
+        eprintln!("Shuffled {} blocks", num_blocks);
+    } else if let Some(matches) = matches.subcommand_matches("to_tensor") {
+        let species: Vec<String> = matches
+            .value_of("species")
+            .unwrap()
+            .split(',')
+            .map(|s| s.to_string())
+            .collect();
+        let width = value_t!(matches, "width", usize).unwrap_or_else(|e| e.exit());
+        let step = if matches.is_present("step") {
+            value_t!(matches, "step", usize).unwrap_or_else(|e| e.exit())
+        } else {
+            width
+        };
+        let format = match matches.value_of("format") {
+            Some("raw") => TensorFormat::Raw,
+            _ => TensorFormat::Npy,
+        };
+        let mut manifest = BufWriter::new(
+            File::create(matches.value_of("manifest").unwrap())
+                .expect("Couldn't create manifest file"),
+        );
+        let num_windows = to_tensor(
+            input,
+            output,
+            &mut manifest,
+            &species,
+            width,
+            step,
+            format,
+            alphabet,
+        )?;
+        eprintln!("Wrote {} windows", num_windows);
+    } else if let Some(matches) = matches.subcommand_matches("profile") {
+        let weights = matches.value_of("tree").map(|path| {
+            let newick = std::fs::read_to_string(path).expect("Couldn't read tree file");
+            tree_weights(&Tree::parse_newick(&newick))
+        });
+        profile(
+            input,
+            output,
+            &region_filter(matches),
+            weights.as_ref(),
+            alphabet,
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("realign") {
+        let below_identity = if matches.is_present("below_identity") {
+            Some(value_t!(matches, "below_identity", f64).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+        let (num_realigned, num_failed) = realign(
+            input,
+            output,
+            matches.value_of("command").unwrap(),
+            below_identity,
+        )?;
+        eprintln!(
+            "Realigned {} blocks ({} failed and were left alone)",
+            num_realigned, num_failed
+        );
+    } else if let Some(matches) = matches.subcommand_matches("pipe") {
+        pipe(input, output, matches.value_of("ops").unwrap(), alphabet)?;
+    } else if let Some(matches) = matches.subcommand_matches("wig") {
+        let metric = ColumnMetric::from_name(matches.value_of("metric").unwrap()).unwrap();
+        let step = match matches.value_of("step") {
+            Some("variable") => WigStep::Variable,
+            _ => WigStep::Fixed,
+        };
+        let num_overlapping = match chrom_sizes(matches) {
+            Some(sizes) => {
+                let mut bigwig = BigWigWriter::new(sizes);
+                let num_overlapping = wig(input, &mut bigwig, metric, step)?;
+                bigwig.finish(output);
+                num_overlapping
+            }
+            None => wig(input, output, metric, step)?,
+        };
+        warn_overlapping(output, num_overlapping);
+    } else if let Some(matches) = matches.subcommand_matches("hub") {
+        let output_dir = matches.value_of("output_dir").unwrap();
+        let (num_blocks, num_overlapping) = hub(
+            input,
+            output_dir,
+            chrom_sizes(matches).unwrap(),
+            matches.value_of("name").unwrap(),
+            matches.value_of("email").unwrap(),
+        )?;
+        warn_overlapping(output, num_overlapping);
+        eprintln!("Wrote a hub of {} blocks to {}", num_blocks, output_dir);
+    } else if let Some(matches) = matches.subcommand_matches("thin") {
+        let every = value_t!(matches, "every", u64).unwrap_or_else(|e| e.exit());
+        if every == 0 {
+            panic!("--every must be at least 1");
+        }
+        let num_kept = thin(input, output, every)?;
+        eprintln!("Kept {} columns", num_kept);
+    } else if let Some(matches) = matches.subcommand_matches("stats") {
+        let max_n_frac = matches
+            .value_of("max_n_frac")
+            .map(|_| value_t!(matches, "max_n_frac", f64).unwrap_or_else(|e| e.exit()));
+        let mut kept = matches
+            .value_of("kept_maf")
+            .map(|path| BufWriter::new(File::create(path).expect("Couldn't create kept MAF")))
+            .map(|kept| MAFOutputStream::new(kept, output.options()));
+        let (num_kept, num_dropped) = stats(
+            input,
+            output,
+            max_n_frac,
+            kept.as_mut().map(|kept| kept as &mut dyn MAFOutput),
+            matches.is_present("per_block"),
+            alphabet,
+        )?;
+        eprintln!("Kept {} blocks, dropped {}", num_kept, num_dropped);
+    } else if let Some(matches) = matches.subcommand_matches("annotate_genes") {
+        let path = matches.value_of("genes").unwrap();
+        let genes_file = BufReader::new(File::open(path).expect("Couldn't open genes file"));
+        let key = matches.value_of("key").unwrap();
+        let genes = if path.ends_with(".bed") {
+            IntervalLookup::from_bed(key, genes_file)
+        } else {
+            IntervalLookup::from_gff(key, genes_file)
+        };
+        let num_overlapping = annotate_genes(input, output, &genes, matches.is_present("tsv"))?;
+        eprintln!("{} blocks overlap a gene", num_overlapping);
+    } else if let Some(matches) = matches.subcommand_matches("gene_loss") {
+        let gff_file = BufReader::new(
+            File::open(matches.value_of("gff").unwrap()).expect("Couldn't open GFF file"),
+        );
+        let num_transcripts = gene_loss(input, output, gff_file)?;
+        eprintln!("Screened {} transcripts", num_transcripts);
+    } else if let Some(matches) = matches.subcommand_matches("window") {
+        let size = value_t!(matches, "size", u64).unwrap_or_else(|e| e.exit());
+        let min_species = value_t!(matches, "min_species", usize).unwrap_or_else(|e| e.exit());
+        let (num_windows, num_overlapping) = match matches.value_of("output_dir") {
+            Some(output_dir) => export_windows(
+                input,
+                output_dir,
+                size,
+                min_species,
+                matches.is_present("phylip"),
+            )?,
+            None => write_windows(input, output, size, min_species)?,
+        };
+        warn_overlapping(output, num_overlapping);
+        eprintln!("Wrote {} windows", num_windows);
+    } else if let Some(matches) = matches.subcommand_matches("pair_classes") {
+        let species: Vec<&str> = matches.values_of("species").unwrap().collect();
+        let intervals = match matches.value_of("bed") {
+            Some(path) => PairIntervals::Regions(
+                parse_bed(BufReader::new(
+                    File::open(path).expect("Couldn't open bed file"),
+                ))
+                .into_iter()
+                .collect(),
+            ),
+            None => PairIntervals::Windows(
+                value_t!(matches, "window", u64).unwrap_or_else(|e| e.exit()),
+            ),
+        };
+        let num_intervals = pair_classes(input, output, (species[0], species[1]), &intervals)?;
+        eprintln!("Counted {} intervals", num_intervals);
+    } else if let Some(matches) = matches.subcommand_matches("dstat") {
+        let names: Vec<&str> = matches.values_of("species").unwrap().collect();
+        let species = DstatSpecies {
+            p1: names[0],
+            p2: names[1],
+            p3: names[2],
+            outgroup: names[3],
+            p3b: matches.value_of("p3b"),
+        };
+        let block_size = value_t!(matches, "block_size", u64).unwrap_or_else(|e| e.exit());
+        let (abba, baba, num_blocks) = dstat(input, output, &species, block_size)?;
+        eprintln!(
+            "Counted {} ABBA and {} BABA sites in {} jackknife blocks",
+            abba, baba, num_blocks
+        );
+    } else if let Some(matches) = matches.subcommand_matches("patterns") {
+        let species: Vec<&str> = matches.values_of("species").unwrap().collect();
+        let mode = if matches.is_present("states") {
+            PatternMode::States
+        } else {
+            PatternMode::Binary
+        };
+        let region = matches.value_of("region").map(parse_region);
+        let num_patterns = patterns(input, output, &species, mode, region.as_ref())?;
+        eprintln!("Found {} distinct column patterns", num_patterns);
+    } else if let Some(matches) = matches.subcommand_matches("to_ss") {
+        let species: Vec<&str> = matches.values_of("species").unwrap().collect();
+        let (num_tuples, num_columns) =
+            to_ss(input, output, &species, !matches.is_present("unordered"))?;
+        eprintln!("Wrote {} tuples for {} columns", num_tuples, num_columns);
+    } else if let Some(matches) = matches.subcommand_matches("from_fasta") {
+        let mut coordinates = match matches.value_of("coords") {
+            Some(path) => Coordinates::parse_table(BufReader::new(
+                File::open(path).expect("Couldn't open coordinate table"),
+            )),
+            None => Coordinates::Headers(parse_chrom_sizes(BufReader::new(
+                File::open(matches.value_of("sizes").unwrap()).expect("Couldn't open sizes file"),
+            ))),
+        };
+        let num_blocks = from_fasta(input, output, &mut coordinates);
+        eprintln!("Wrote {} blocks", num_blocks);
+    } else if let Some(matches) = matches.subcommand_matches("roundtrip") {
+        let (num_roundtripped, num_failed) =
+            roundtrip(input, output, matches.value_of("command").unwrap())?;
+        eprintln!(
+            "Round-tripped {} blocks ({} failed and were left alone)",
+            num_roundtripped, num_failed
+        );
+    } else if let Some(matches) = matches.subcommand_matches("drop_species") {
+        let species = matches
+            .values_of("species")
+            .unwrap()
+            .map(|s| s.to_string())
+            .collect();
+        let (num_rows, num_cut) = drop_species(input, output, species)?;
+        eprintln!(
+            "Dropped {} rows; {} blocks lost rows no longer aligned to the reference",
+            num_rows, num_cut
+        );
+    }
+    #[cfg(feature = "browse")]
+    {
+        if let Some(browse_matches) = matches.subcommand_matches("browse") {
+            let path = matches
+                .value_of("input_maf")
+                .expect("browse needs an input file, since commands are read from stdin");
+            let width = value_t!(browse_matches, "width", usize).unwrap_or_else(|e| e.exit());
+            let index_path = browse_matches.value_of("index").map(std::path::Path::new);
+            #[cfg(feature = "remote")]
+            {
+                if remote::is_url(path) {
+                    let file = remote::RemoteFile::open(path).expect("Couldn't open input URL");
+                    let version = file.version().map(|version| version.to_string());
+                    browse::browse(
+                        file,
+                        index_path,
+                        version,
+                        &mut io::stdin().lock(),
+                        output,
+                        width,
+                    );
+                    return Ok(());
+                }
+            }
+            let file = File::open(path).expect("Couldn't open input file");
+            if !file.metadata().is_ok_and(|m| m.is_file()) {
+                panic!("browse needs to seek in its input, so it can't read from a pipe");
+            }
+            // The modification time stands in for an ETag.
+            let version = file
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|time| format!("{}.{:09}", time.as_secs(), time.subsec_nanos()));
+            browse::browse(
+                file,
+                index_path,
+                version,
+                &mut io::stdin().lock(),
+                output,
+                width,
+            );
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    let matches = App::new("maf_junk")
+    let app = App::new("maf_junk")
         .arg(Arg::with_name("input_maf").global(true))
         .arg(Arg::with_name("output").global(true))
-        .subcommand(SubCommand::with_name("dup_blocks"))
-        .subcommand(SubCommand::with_name("merge_dups").arg(
-            Arg::with_name("mode").required(true).possible_values(&[
-                "unanimity",
-                "consensus",
-                "mask",
-            ]),
-        ))
+        .arg(
+            Arg::with_name("output_style")
+                .long("output_style")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["compact", "padded"])
+                .default_value("compact"),
+        )
+        .arg(
+            Arg::with_name("strict_output")
+                .long("strict_output")
+                .alias("strict-output")
+                .global(true)
+                .help("Check every block written against the MAF spec, stopping at the first invalid one"),
+        )
+        .arg(
+            Arg::with_name("allow_truncated")
+                .long("allow_truncated")
+                .alias("allow-truncated")
+                .global(true)
+                .help("Keep the blocks before a block the input ends partway through, rather than failing"),
+        )
+        .arg(
+            Arg::with_name("strip_comments")
+                .long("strip_comments")
+                .alias("strip-comments")
+                .global(true)
+                .help("Drop comments from MAF output (the ##maf header line is kept)"),
+        )
+        .arg(
+            Arg::with_name("protein")
+                .long("protein")
+                .global(true)
+                .help("The alignment is of amino acids rather than nucleotides"),
+        )
+        .arg(
+            Arg::with_name("include_seqs")
+                .long("include_seqs")
+                .alias("include-seqs")
+                .global(true)
+                .takes_value(true)
+                .help("Only read rows whose sequence name matches this regex (dropping whole blocks if the reference row doesn't)"),
+        )
+        .arg(
+            Arg::with_name("exclude_seqs")
+                .long("exclude_seqs")
+                .alias("exclude-seqs")
+                .global(true)
+                .takes_value(true)
+                .help("Drop rows whose sequence name matches this regex (and whole blocks if the reference row does)"),
+        )
+        .arg(
+            Arg::with_name("summary_json")
+                .long("summary_json")
+                .alias("summary-json")
+                .global(true)
+                .takes_value(true)
+                .help("Write counts of blocks read, written, and skipped, warnings, and timing to this JSON file"),
+        )
+        .subcommand(
+            SubCommand::with_name("dup_blocks").arg(
+                Arg::with_name("bed_out")
+                    .long("bed_out")
+                    .alias("bed-out")
+                    .takes_value(true)
+                    .help("Also write <species>.bed of duplicated regions to this directory"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge_dups")
+                .arg(Arg::with_name("mode").required(true).possible_values(&[
+                    "unanimity",
+                    "consensus",
+                    "mask",
+                    "iupac",
+                ]))
+                .arg(
+                    Arg::with_name("max_block_bases")
+                        .long("max_block_bases")
+                        .alias("max-block-bases")
+                        .help("Refuse blocks with more than this many rows times columns")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("stitch")
+                        .long("stitch")
+                        .help("Keep merging into the copy that continues from the previous block"),
+                ),
+        )
         .subcommand(SubCommand::with_name("to_fasta"))
         .subcommand(
             SubCommand::with_name("split")
@@ -33,68 +1066,1127 @@ fn main() {
                     Arg::with_name("max_length")
                         .long("max_length")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("emit_metadata")
+                        .long("emit_metadata")
+                        .alias("emit-metadata")
+                        .help("Write a JSON summary next to each output file"),
+                )
+                .arg(
+                    Arg::with_name("max_compressed_bytes")
+                        .long("max_compressed_bytes")
+                        .alias("max-compressed-bytes")
+                        .help("Gzip the output files, starting a new one after about this many compressed bytes")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("Newick species tree to prune to each file's species, and write next to it")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("shard_by_species")
+                        .long("shard_by_species")
+                        .alias("shard-by-species")
+                        .help("Split blocks with and without this species into separate subdirectories")
+                        .takes_value(true),
                 ),
         )
         .subcommand(
             SubCommand::with_name("coverage")
                 .arg(Arg::with_name("ref_genome").required(true))
-                .arg(Arg::with_name("bed").long("bed").takes_value(true)),
+                .arg(Arg::with_name("bed").long("bed").takes_value(true))
+                .arg(
+                    Arg::with_name("bed_exclude")
+                        .long("bed_exclude")
+                        .alias("bed-exclude")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["tsv", "json", "markdown"])
+                        .default_value("tsv"),
+                )
+                .arg(
+                    Arg::with_name("groups")
+                        .long("groups")
+                        .help("TSV of genome and group, to report coverage by any genome in each group")
+                        .takes_value(true)
+                        .conflicts_with("group_tree"),
+                )
+                .arg(
+                    Arg::with_name("group_tree")
+                        .long("group_tree")
+                        .alias("group-tree")
+                        .help("Newick tree whose named internal nodes are the groups to report")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("min_block_length")
+                        .long("min_block_length")
+                        .alias("min-block-length")
+                        .help("Skip blocks with fewer reference bases than this")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("min_identity")
+                        .long("min_identity")
+                        .alias("min-identity")
+                        .help("Skip blocks less identical to the reference than this (0-1)")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("context")
+                        .long("context")
+                        .help("Only count reference bases in this dinucleotide context (e.g. CG)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("context_exclude")
+                        .long("context_exclude")
+                        .alias("context-exclude")
+                        .help("Don't count reference bases in this dinucleotide context")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .help("Process reference sequences in parallel with this many threads")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .args(&query_filter_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("filter")
+                .arg(
+                    Arg::with_name("bed")
+                        .long("bed")
+                        .required_unless_one(&["bed_exclude", "query_bed"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bed_exclude")
+                        .long("bed_exclude")
+                        .alias("bed-exclude")
+                        .takes_value(true),
+                )
+                .args(&query_filter_args())
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .help("Process reference sequences in parallel with this many threads")
+                        .takes_value(true)
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("grep")
+                .arg(
+                    Arg::with_name("region")
+                        .long("region")
+                        .takes_value(true)
+                        .required_unless("seq")
+                        .conflicts_with("seq"),
+                )
+                .arg(Arg::with_name("seq").long("seq").takes_value(true))
+                .arg(
+                    Arg::with_name("number")
+                        .long("number")
+                        .help("Give blocks without an id their number in the input as id=N"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("head").arg(
+                Arg::with_name("num_blocks")
+                    .short("n")
+                    .takes_value(true)
+                    .default_value("10"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("tail").arg(
+                Arg::with_name("num_blocks")
+                    .short("n")
+                    .takes_value(true)
+                    .default_value("10"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("sample")
+                .arg(
+                    Arg::with_name("fraction")
+                        .long("fraction")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("snps").arg(
+                Arg::with_name("positions")
+                    .long("positions")
+                    .required(true)
+                    .takes_value(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract_cds")
+                .alias("extract-cds")
+                .arg(Arg::with_name("output_dir").required(true))
+                .arg(
+                    Arg::with_name("gff")
+                        .long("gff")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("translate")
+                        .long("translate")
+                        .help("Also write protein alignments, and a TSV of frameshifts and premature stops"),
+                )
+                .arg(
+                    Arg::with_name("drop_gappy_codons")
+                        .long("drop_gappy_codons")
+                        .alias("drop-gappy-codons")
+                        .help("Drop codons with a gap or an N in any species"),
+                )
+                .arg(
+                    Arg::with_name("mask_cpg")
+                        .long("mask_cpg")
+                        .alias("mask-cpg")
+                        .help("Mask codons in a CpG in any species with NNN"),
+                )
+                .arg(
+                    Arg::with_name("phylip")
+                        .long("phylip")
+                        .help("Write sequential PHYLIP, for PAML, instead of FASTA"),
+                )
+                .arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("Newick species tree to prune to each alignment's species")
+                        .takes_value(true),
+                ),
         )
         .subcommand(
-            SubCommand::with_name("filter").arg(
-                Arg::with_name("bed")
-                    .long("bed")
+            SubCommand::with_name("fix_i_lines")
+                .arg(Arg::with_name("add_e_lines").long("add_e_lines")),
+        )
+        .subcommand(
+            SubCommand::with_name("view")
+                .arg(Arg::with_name("html").long("html"))
+                .arg(
+                    Arg::with_name("width")
+                        .long("width")
+                        .takes_value(true)
+                        .default_value("100"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rename").arg(
+                Arg::with_name("mapping")
+                    .long("mapping")
+                    .help("Two-column file of old name (or re:PATTERN) to new name")
                     .required(true)
                     .takes_value(true),
             ),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("fix_sizes")
+                .alias("fix-sizes")
+                .arg(
+                    Arg::with_name("sizes")
+                        .long("sizes")
+                        .help("genome=chrom.sizes file (can be repeated)")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(Arg::with_name("check").long("check")),
+        )
+        .subcommand(
+            SubCommand::with_name("relabel_coords")
+                .alias("relabel-coords")
+                .arg(
+                    Arg::with_name("agp")
+                        .long("agp")
+                        .takes_value(true)
+                        .required_unless("offsets")
+                        .conflicts_with("offsets"),
+                )
+                .arg(Arg::with_name("offsets").long("offsets").takes_value(true))
+                .arg(
+                    Arg::with_name("genome")
+                        .long("genome")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dedup").arg(
+                Arg::with_name("sorted")
+                    .long("sorted")
+                    .help("The input is sorted by reference position: dedup in one pass, holding only the blocks at one position"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("check_overlaps")
+            .alias("check-overlaps").arg(Arg::with_name("trim").long("trim")),
+        )
+        .subcommand(SubCommand::with_name("single_cov").alias("single-cov"))
+        .subcommand(
+            SubCommand::with_name("simulate")
+                .arg(
+                    Arg::with_name("num_species")
+                        .long("num_species")
+                        .takes_value(true)
+                        .default_value("3"),
+                )
+                .arg(
+                    Arg::with_name("num_blocks")
+                        .long("num_blocks")
+                        .takes_value(true)
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::with_name("min_length")
+                        .long("min_length")
+                        .takes_value(true)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("max_length")
+                        .long("max_length")
+                        .takes_value(true)
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::with_name("mean_length")
+                        .long("mean_length")
+                        .takes_value(true)
+                        .conflicts_with_all(&["min_length", "max_length"]),
+                )
+                .arg(
+                    Arg::with_name("substitution_rate")
+                        .long("substitution_rate")
+                        .takes_value(true)
+                        .default_value("0.1"),
+                )
+                .arg(
+                    Arg::with_name("indel_rate")
+                        .long("indel_rate")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("duplication_rate")
+                        .long("duplication_rate")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mask_quality")
+                .alias("mask-quality")
+                .arg(
+                    Arg::with_name("min_q")
+                        .long("min_q")
+                        .alias("min-q")
+                        .help("Mask bases with quality (0-9, F counting as 10) below this")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("Only mask rows from this species (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("lowercase")
+                        .long("lowercase")
+                        .help("Lowercase masked bases instead of replacing them with N"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("constraint")
+                .arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("Newick file of the neutral tree, with branch lengths")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(chrom_sizes_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("presence")
+                .arg(
+                    Arg::with_name("window")
+                        .long("window")
+                        .help("Window size along the reference")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("min_bases")
+                        .long("min_bases")
+                        .alias("min-bases")
+                        .help("Aligned bases a species needs in a window to count as present")
+                        .default_value("1")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("insertions").arg(
+                Arg::with_name("query")
+                    .long("query")
+                    .help("Species to report insertions in")
+                    .required(true)
+                    .takes_value(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("reorder_rows")
+                .alias("reorder-rows")
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("Put rows in this species order (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required_unless_one(&["tree", "alphabetical"])
+                        .conflicts_with_all(&["tree", "alphabetical"]),
+                )
+                .arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("Put rows in the order of the leaves of this Newick tree")
+                        .takes_value(true)
+                        .conflicts_with("alphabetical"),
+                )
+                .arg(
+                    Arg::with_name("alphabetical")
+                        .long("alphabetical")
+                        .help("Sort rows by sequence name"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("annotate")
+                .arg(
+                    Arg::with_name("set")
+                        .long("set")
+                        .help("Set key=value in every block header (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .long("remove")
+                        .help("Remove this key from every block header (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("compute")
+                        .long("compute")
+                        .help("Add a value computed from each block (can be repeated)")
+                        .takes_value(true)
+                        .possible_values(&["identity", "species_count", "id"])
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("lookup_bed")
+                        .long("lookup_bed")
+                        .alias("lookup-bed")
+                        .help("BED whose name column holds values for blocks overlapping each interval")
+                        .takes_value(true)
+                        .requires("lookup_key"),
+                )
+                .arg(
+                    Arg::with_name("lookup_key")
+                        .long("lookup_key")
+                        .alias("lookup-key")
+                        .help("Key to store values from --lookup_bed under")
+                        .takes_value(true)
+                        .requires("lookup_bed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("flatten")
+                .arg(
+                    Arg::with_name("bed")
+                        .long("bed")
+                        .help("Only output reference positions within these regions")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bed_exclude")
+                        .long("bed_exclude")
+                        .alias("bed-exclude")
+                        .help("Skip reference positions within these regions")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pairwise_identity")
+                .alias("pairwise-identity")
+                .arg(
+                    Arg::with_name("bed")
+                        .long("bed")
+                        .help("Only count columns with reference bases within these regions")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bed_exclude")
+                        .long("bed_exclude")
+                        .alias("bed-exclude")
+                        .help("Skip columns with reference bases within these regions")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bootstrap")
+                        .long("bootstrap")
+                        .help("Write each pair's identity with a 95% CI from this many block resamplings")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("quick_tree").alias("quick-tree"))
+        .subcommand(
+            SubCommand::with_name("trim")
+                .arg(
+                    Arg::with_name("max_gap_fraction")
+                        .long("max_gap_fraction")
+                        .alias("max-gap-fraction")
+                        .help("Trim edge columns where more than this fraction of rows are gaps")
+                        .takes_value(true)
+                        .default_value("0.5"),
+                )
+                .arg(
+                    Arg::with_name("window")
+                        .long("window")
+                        .help("Number of columns that must pass on average where trimming stops")
+                        .takes_value(true)
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trim_columns")
+                .alias("trim-columns")
+                .arg(
+                    Arg::with_name("min_present")
+                        .long("min_present")
+                        .alias("min-present")
+                        .help("Remove columns where less than this fraction of rows have a base")
+                        .takes_value(true)
+                        .default_value("0.5"),
+                )
+                .arg(
+                    Arg::with_name("min_flank_conservation")
+                        .long("min_flank_conservation")
+                        .alias("min-flank-conservation")
+                        .help("Fraction of rows that must share a base at the ends of each run of columns")
+                        .takes_value(true)
+                        .default_value("0.5"),
+                )
+                .arg(
+                    Arg::with_name("min_run_length")
+                        .long("min_run_length")
+                        .alias("min-run-length")
+                        .help("Remove runs of kept columns shorter than this")
+                        .takes_value(true)
+                        .default_value("5"),
+                )
+                .arg(
+                    Arg::with_name("report")
+                        .long("report")
+                        .help("Write a TSV of the columns removed from each block here")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("explode").arg(Arg::with_name("output_dir").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("recase")
+                .arg(
+                    Arg::with_name("upper")
+                        .long("upper")
+                        .help("Uppercase every base")
+                        .required_unless("repeats"),
+                )
+                .arg(
+                    Arg::with_name("repeats")
+                        .long("repeats")
+                        .help("genome=repeats.bed, whose bases are lowercased (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(SubCommand::with_name("break_at_ref_gaps").alias("break-at-ref-gaps"))
+        .subcommand(SubCommand::with_name("contiguity"))
+        .subcommand(
+            SubCommand::with_name("extract_genomes")
+                .alias("extract-genomes")
+                .arg(Arg::with_name("output_dir").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("footprints")
+                .arg(
+                    Arg::with_name("genome")
+                        .long("genome")
+                        .help("Genome whose intervals are written")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(chrom_sizes_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("kmerize").arg(
+                Arg::with_name("k")
+                    .short("k")
+                    .help("K-mer length (at most 32)")
+                    .takes_value(true)
+                    .default_value("21"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("assembly_gaps")
+                .alias("assembly-gaps")
+                .arg(
+                    Arg::with_name("gaps")
+                        .long("gaps")
+                        .help("genome=gaps.bed, of the genome's assembly gaps (can be repeated)")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("flank")
+                        .long("flank")
+                        .help("Bases within this distance of a gap count as near it")
+                        .takes_value(true)
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::with_name("report")
+                        .long("report")
+                        .help("Write the per-genome report here instead of stderr")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("shuffle")
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("buckets")
+                        .long("buckets")
+                        .help("Spool blocks into this many temporary files, holding one at a time in memory")
+                        .takes_value(true)
+                        .default_value("64"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("to_tensor")
+                .alias("to-tensor")
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("Comma-separated species, in the order of the species axis")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .help("Write the reference interval of each window to this TSV")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("width")
+                        .long("width")
+                        .help("Reference bases in each window")
+                        .takes_value(true)
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::with_name("step")
+                        .long("step")
+                        .help("Reference bases between the starts of windows (defaults to the width)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["npy", "raw"])
+                        .default_value("npy"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("profile")
+                .arg(
+                    Arg::with_name("bed")
+                        .long("bed")
+                        .help("Only write reference positions within these regions")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bed_exclude")
+                        .long("bed_exclude")
+                        .alias("bed-exclude")
+                        .help("Skip reference positions within these regions")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("tree")
+                        .long("tree")
+                        .help("Newick file whose branch lengths weight the species")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("realign")
+                .arg(
+                    Arg::with_name("command")
+                        .long("command")
+                        .help("Aligner reading FASTA on stdin and writing aligned FASTA to stdout, e.g. 'mafft --quiet -'")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("below_identity")
+                        .long("below_identity")
+                        .alias("below-identity")
+                        .help("Only realign blocks less identical to the reference than this (0-1)")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pipe").arg(
+                Arg::with_name("ops")
+                    .long("ops")
+                    .help("Operations to run in order, e.g. \"subset:species=hg38,mm39 filter:bed=x.bed merge_dups:mode=consensus\"")
+                    .required(true)
+                    .takes_value(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("wig")
+                .arg(
+                    Arg::with_name("metric")
+                        .long("metric")
+                        .takes_value(true)
+                        .possible_values(&["species_count", "gap_fraction", "identity", "gc"])
+                        .default_value("species_count"),
+                )
+                .arg(
+                    Arg::with_name("step")
+                        .long("step")
+                        .help("Write fixedStep sections for runs of positions, or variableStep lines")
+                        .takes_value(true)
+                        .possible_values(&["fixed", "variable"])
+                        .default_value("fixed"),
+                )
+                .arg(chrom_sizes_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("hub")
+                .arg(Arg::with_name("output_dir").required(true))
+                .arg(chrom_sizes_arg().required(true))
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .help("Name of the hub")
+                        .takes_value(true)
+                        .default_value("maf_stream"),
+                )
+                .arg(
+                    Arg::with_name("email")
+                        .long("email")
+                        .help("Contact email for the hub")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("thin").arg(
+                Arg::with_name("every")
+                    .long("every")
+                    .help("Keep the columns at reference positions that are multiples of this")
+                    .takes_value(true)
+                    .default_value("100"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .arg(
+                    Arg::with_name("max_n_frac")
+                        .long("max_n_frac")
+                        .alias("max-n-frac")
+                        .help("Drop blocks whose reference row has a greater fraction of Ns than this (0-1)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("kept_maf")
+                        .long("kept_maf")
+                        .alias("kept-maf")
+                        .help("Write the blocks that aren't dropped to this MAF")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("per_block")
+                        .long("per_block")
+                        .alias("per-block")
+                        .help("Write a row for each block as it's read, instead of per-species totals"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("annotate_genes")
+                .alias("annotate-genes")
+                .arg(
+                    Arg::with_name("genes")
+                        .long("genes")
+                        .help("GFF3/GTF (gene lines) or BED (name column) of reference genes")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .help("Key to store the overlapping genes under in block headers")
+                        .takes_value(true)
+                        .default_value("genes"),
+                )
+                .arg(
+                    Arg::with_name("tsv")
+                        .long("tsv")
+                        .help("Write a TSV of block-gene overlaps instead of a MAF"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gene_loss")
+            .alias("gene-loss").arg(
+                Arg::with_name("gff")
+                    .long("gff")
+                    .help("GFF3 or GTF of the reference CDS")
+                    .required(true)
+                    .takes_value(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("window")
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .help("Reference bases per window")
+                        .takes_value(true)
+                        .default_value("10000"),
+                )
+                .arg(
+                    Arg::with_name("min_species")
+                        .long("min_species")
+                        .alias("min-species")
+                        .help("Skip windows with fewer species than this")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("output_dir")
+                        .long("output_dir")
+                        .alias("output-dir")
+                        .help("Write each window to its own file here, with a manifest, instead")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("phylip")
+                        .long("phylip")
+                        .help("Write PHYLIP rather than FASTA files with --output_dir")
+                        .requires("output_dir"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pair_classes")
+                .alias("pair-classes")
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("The two species to compare")
+                        .takes_value(true)
+                        .number_of_values(2)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("window")
+                        .long("window")
+                        .help("Count in windows of this many reference bases")
+                        .takes_value(true)
+                        .required_unless("bed")
+                        .conflicts_with("bed"),
+                )
+                .arg(
+                    Arg::with_name("bed")
+                        .long("bed")
+                        .help("Count in each of these regions instead")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dstat")
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("P1, P2, P3 and the outgroup")
+                        .takes_value(true)
+                        .number_of_values(4)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("p3b")
+                        .long("p3b")
+                        .help("A second P3 sample, to compute the f4-ratio")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("block_size")
+                        .long("block_size")
+                        .alias("block-size")
+                        .help("Reference bases per jackknife block")
+                        .takes_value(true)
+                        .default_value("5000000"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("patterns")
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("Species to include, in pattern order (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("states")
+                        .long("states")
+                        .help("Record each species' base, not whether it matches the reference"),
+                )
+                .arg(
+                    Arg::with_name("region")
+                        .long("region")
+                        .help("Only count columns in this reference region (chr:start-end)")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("to_ss")
+                .alias("to-ss")
+                .arg(
+                    Arg::with_name("species")
+                        .long("species")
+                        .help("Species to include, in tuple order (can be repeated)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("unordered")
+                        .long("unordered")
+                        .help("Leave out the tuple at each position, allowing several chromosomes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("from_fasta")
+                .alias("from-fasta")
+                .arg(
+                    Arg::with_name("coords")
+                        .long("coords")
+                        .help("Table of each record's name, seq, start, size, strand, and source size")
+                        .takes_value(true)
+                        .required_unless("sizes")
+                        .conflicts_with("sizes"),
+                )
+                .arg(
+                    Arg::with_name("sizes")
+                        .long("sizes")
+                        .help("Sequence sizes, to read coordinates from genome.contig:start-end:strand headers")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("roundtrip").arg(
+                Arg::with_name("command")
+                    .long("cmd")
+                    .help("Command reading aligned FASTA on stdin and writing it to stdout, e.g. 'mafft -'")
+                    .required(true)
+                    .takes_value(true),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("drop_species")
+            .alias("drop-species").arg(
+                Arg::with_name("species")
+                    .long("species")
+                    .help("Comma-separated species to remove (can be repeated)")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .use_delimiter(true)
+                    .required(true),
+            ),
+        );
+    #[cfg(feature = "browse")]
+    let app = app.subcommand(
+        SubCommand::with_name("browse")
+            .arg(
+                Arg::with_name("width")
+                    .long("width")
+                    .takes_value(true)
+                    .default_value("100"),
+            )
+            .arg(Arg::with_name("index").long("index").takes_value(true)),
+    );
+    let matches = app.get_matches();
+
+    let mut options = OutputOptions::default();
+    if matches.value_of("output_style") == Some("padded") {
+        options.writer.style = OutputStyle::Padded;
+    }
+    options.writer.strict = matches.is_present("strict_output");
+    options.strip_comments = matches.is_present("strip_comments");
 
+    let start_time = Instant::now();
     let stdin = io::stdin();
-    let mut input = matches
-        .value_of("input_maf")
-        .map(|p| {
-            Box::new(BufReader::new(
-                File::open(p).expect("Couldn't open input file"),
-            )) as Box<dyn BufRead>
-        })
-        .unwrap_or_else(|| Box::new(stdin.lock()));
-    let mut output = matches
-        .value_of("output")
-        .map(|p| Box::new(File::create(p).expect("Couldn't create output file")) as Box<dyn Write>)
-        .unwrap_or_else(|| Box::new(stdout()));
-
-    if matches.subcommand_matches("dup_blocks").is_some() {
-        output_dup_blocks(&mut input, &mut output);
-    } else if let Some(matches) = matches.subcommand_matches("merge_dups") {
-        let mode = match matches.value_of("mode").unwrap() {
-            "unanimity" => ConsensusMode::Unanimity,
-            "consensus" => ConsensusMode::Consensus,
-            "mask" => ConsensusMode::Mask,
-            _ => panic!("Unknown consensus mode"),
+    let input_counts = SharedCounts::default();
+    let mut input: Box<dyn BufRead> = Box::new(CountingReader::new(
+        matches
+            .value_of("input_maf")
+            .map(open_input)
+            .unwrap_or_else(|| Box::new(stdin.lock())),
+        input_counts.clone(),
+    ));
+    if matches.is_present("include_seqs") || matches.is_present("exclude_seqs") {
+        let regex = |name| {
+            matches
+                .value_of(name)
+                .map(|r| Regex::new(r).expect("Invalid sequence name regex"))
         };
-        output_merged_consensus_blocks(&mut input, &mut output, mode);
-    } else if let Some(_matches) = matches.subcommand_matches("to_fasta") {
-        unimplemented!();
-    //maf_to_fasta(&mut input, &mut output);
-    } else if let Some(matches) = matches.subcommand_matches("split") {
-        let max_length = value_t!(matches, "max_length", u64).unwrap_or(100_000);
-        split_maf(
-            &mut input,
-            max_length,
-            matches.value_of("output_dir").unwrap(),
+        let seq_filter = SeqFilter::new(regex("include_seqs"), regex("exclude_seqs"));
+        input = Box::new(SeqFilterReader::new(input, seq_filter));
+    }
+    let output_counts = SharedCounts::default();
+    let mut output = MAFOutputStream::new(
+        CountingWriter::new(
+            matches
+                .value_of("output")
+                .map(|p| {
+                    Box::new(StopOnBrokenPipe::new(
+                        File::create(p).expect("Couldn't create output file"),
+                    )) as Box<dyn Write>
+                })
+                .unwrap_or_else(|| Box::new(StopOnBrokenPipe::new(stdout()))),
+            output_counts.clone(),
+        ),
+        options,
+    );
+
+    // Don't report the panic a closed output unwinds with.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !info.payload().is::<BrokenPipe>() {
+            default_hook(info);
+        }
+    }));
+    let (result, output_closed) = match panic::catch_unwind(AssertUnwindSafe(|| {
+        run_subcommand(&matches, &mut input, &mut output)
+    })) {
+        Ok(result) => (result, false),
+        Err(payload) if payload.is::<BrokenPipe>() => (Ok(()), true),
+        Err(payload) => panic::resume_unwind(payload),
+    };
+
+    output.flush().ok();
+    let subcommand = matches.subcommand_name().unwrap_or("");
+    let sub_matches = matches.subcommand_matches(subcommand);
+    let writes_output = !sub_matches.is_some_and(|m| m.is_present("output_dir"));
+    let summary = RunSummary::new(
+        subcommand,
+        input_counts.take(),
+        output_counts.take(),
+        start_time.elapsed(),
+        (result.err(), output.refused().cloned(), output_closed),
+        (writes_output, matches.is_present("allow_truncated")),
+        (output.num_skipped(), output.num_warnings()),
+    );
+    if summary.truncated() && summary.status != RunStatus::Truncated {
+        eprintln!(
+            "Warning: {}; kept the blocks before it",
+            summary.error.as_ref().unwrap()
         );
-    } else if let Some(matches) = matches.subcommand_matches("coverage") {
-        let bed_file = matches
-            .value_of("bed")
-            .map(|path| BufReader::new(File::open(path).expect("Couldn't open bed file")));
-        let ref_genome = matches.value_of("ref_genome").unwrap();
-        coverage(&mut input, &mut output, ref_genome, bed_file);
-    } else if let Some(matches) = matches.subcommand_matches("filter") {
-        let bed_file = matches
-            .value_of("bed")
-            .map(|path| BufReader::new(File::open(path).expect("Couldn't open bed file")))
-            .unwrap();
-        filter(&mut input, &mut output, bed_file);
+    }
+    if let Some(path) = matches.value_of("summary_json") {
+        summary.write_json_file(path);
+    }
+    match summary.status {
+        RunStatus::Success => {}
+        RunStatus::ParseError => eprintln!(
+            "Couldn't parse the input, so stopped before its end: {}",
+            summary.error.as_ref().unwrap()
+        ),
+        RunStatus::EmptyOutput => eprintln!("No blocks were written"),
+        RunStatus::Partial => eprintln!("Some blocks couldn't be processed"),
+        RunStatus::Truncated => eprintln!(
+            "{} (rerun with --allow_truncated to keep the blocks before it)",
+            summary.error.as_ref().unwrap()
+        ),
+        RunStatus::InvalidBlock => {
+            eprintln!("Refusing to write an {}", summary.refused.as_ref().unwrap())
+        }
+    }
+    if summary.status != RunStatus::Success {
+        std::process::exit(summary.status as i32);
     }
 }
@@ -0,0 +1,405 @@
+use crate::filter::filter_block;
+use maf_stream::coverage::{CoverageFormat, CoverageOptions, MAFCoverage};
+use maf_stream::{write_comment, MAFItems, MAFOutput, QueryFilter, RegionFilter};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+/// Blocks queued for each worker before the reader waits for it.
+const QUEUE_SIZE: usize = 64;
+
+/// How blocks are held while queued for a worker: packed, with the
+/// "packed" feature.
+#[cfg(feature = "packed")]
+type QueuedBlock = multiple_alignment_format::packed::PackedBlock;
+#[cfg(not(feature = "packed"))]
+type QueuedBlock = MAFBlock;
+
+#[cfg(feature = "packed")]
+fn unqueue(block: QueuedBlock) -> MAFBlock {
+    block.into()
+}
+
+#[cfg(not(feature = "packed"))]
+fn unqueue(block: QueuedBlock) -> MAFBlock {
+    block
+}
+
+#[cfg(feature = "packed")]
+fn queue(block: MAFBlock) -> QueuedBlock {
+    block.into()
+}
+
+#[cfg(not(feature = "packed"))]
+fn queue(block: MAFBlock) -> QueuedBlock {
+    block
+}
+
+/// What's sent to a worker: a block, or a comment that came after a
+/// block of the same partition.
+enum Queued {
+    Block(QueuedBlock),
+    Comment(String),
+}
+
+/// A block-wise operation that can be run over partitions of the input
+/// in parallel.
+pub enum Operation<'a> {
+    Coverage {
+        ref_genome: &'a str,
+        options: &'a CoverageOptions,
+    },
+    Filter {
+        regions: &'a RegionFilter,
+        query: Option<&'a QueryFilter>,
+    },
+}
+
+/// What a worker has built up from the partitions given to it.
+enum WorkerState<'a> {
+    Coverage(Box<MAFCoverage>),
+    /// The filtered blocks and comments of every partition, spilled to
+    /// one temporary file, with the runs of it belonging to each
+    /// partition as (partition, start, end) byte offsets. Only the one
+    /// file is kept open, however many partitions there are.
+    Filter {
+        regions: &'a RegionFilter,
+        query: Option<&'a QueryFilter>,
+        spool: BufWriter<File>,
+        runs: Vec<(usize, u64, u64)>,
+    },
+}
+
+/// Add the text of some of a partition's items to a worker's spool.
+fn spool_text(
+    spool: &mut BufWriter<File>,
+    runs: &mut Vec<(usize, u64, u64)>,
+    partition: usize,
+    text: &str,
+) {
+    let len = text.len() as u64;
+    match runs.last_mut() {
+        Some(run) if run.0 == partition => run.2 += len,
+        last => {
+            let start = last.map_or(0, |run| run.2);
+            runs.push((partition, start, start + len));
+        }
+    }
+    spool.write_all(text.as_bytes()).expect("failed to write");
+}
+
+impl<'a> WorkerState<'a> {
+    fn new(operation: &Operation<'a>) -> Self {
+        match operation {
+            Operation::Coverage {
+                ref_genome,
+                options,
+            } => WorkerState::Coverage(Box::new(MAFCoverage::new(ref_genome, (*options).clone()))),
+            Operation::Filter { regions, query } => WorkerState::Filter {
+                regions,
+                query: *query,
+                spool: BufWriter::new(
+                    tempfile::tempfile().expect("Couldn't create temporary file"),
+                ),
+                runs: vec![],
+            },
+        }
+    }
+
+    fn add_block(&mut self, partition: usize, block: MAFBlock) {
+        match self {
+            WorkerState::Coverage(coverage) => coverage.add_block(block),
+            WorkerState::Filter {
+                regions,
+                query,
+                spool,
+                runs,
+            } => {
+                let mut text = String::new();
+                for filtered_block in filter_block(&block, regions, *query) {
+                    text.push_str(&filtered_block.to_string());
+                }
+                spool_text(spool, runs, partition, &text);
+            }
+        }
+    }
+
+    fn add_comment(&mut self, partition: usize, comment: &str) {
+        if let WorkerState::Filter { spool, runs, .. } = self {
+            spool_text(spool, runs, partition, &format!("#{}\n", comment));
+        }
+    }
+
+    /// Deal with anything held back once there are no more blocks.
+    fn finish(&mut self) {
+        if let WorkerState::Coverage(coverage) = self {
+            coverage.finish();
+        }
+    }
+}
+
+/// Run an operation over the input in parallel: blocks are partitioned
+/// by the sequence of their reference row, each partition is processed
+/// by one of `num_threads` worker threads, and the workers' results
+/// are merged. Filtered blocks come out grouped by partition, in the
+/// order the partitions first appear, so the output is only in the
+/// same order as the input if each reference sequence is contiguous in
+/// it. Comments after the first block go with the partition of the
+/// block before them.
+pub fn mapreduce(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    operation: &Operation,
+    num_threads: usize,
+) -> Result<(), MAFParseError> {
+    let num_threads = num_threads.max(1);
+    let (states, result): (Vec<WorkerState>, _) = thread::scope(|scope| {
+        let mut senders = vec![];
+        let mut handles = vec![];
+        for _ in 0..num_threads {
+            let (sender, receiver) = sync_channel::<(usize, Queued)>(QUEUE_SIZE);
+            let mut state = WorkerState::new(operation);
+            senders.push(sender);
+            handles.push(scope.spawn(move || {
+                for (partition, queued) in receiver {
+                    match queued {
+                        Queued::Block(block) => state.add_block(partition, unqueue(block)),
+                        Queued::Comment(comment) => state.add_comment(partition, &comment),
+                    }
+                }
+                state.finish();
+                state
+            }));
+        }
+        let mut partitions: HashMap<String, usize> = HashMap::new();
+        let mut last_partition: Option<usize> = None;
+        let mut items = MAFItems::new(input);
+        for item in items.by_ref() {
+            match item {
+                MAFItem::Comment(comment) => {
+                    if !matches!(operation, Operation::Filter { .. }) {
+                        continue;
+                    }
+                    match last_partition {
+                        None => write_comment(output, &comment),
+                        Some(partition) => senders[partition % num_threads]
+                            .send((partition, Queued::Comment(comment)))
+                            .expect("Worker thread died"),
+                    }
+                }
+                MAFItem::Block(block) => {
+                    let partition = match block.aligned_entries().next() {
+                        Some(entry) => {
+                            let next = partitions.len();
+                            *partitions.entry(entry.seq.clone()).or_insert(next)
+                        }
+                        None => continue,
+                    };
+                    last_partition = Some(partition);
+                    senders[partition % num_threads]
+                        .send((partition, Queued::Block(queue(block))))
+                        .expect("Worker thread died");
+                }
+            }
+        }
+        drop(senders);
+        let states = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Worker thread panicked"))
+            .collect();
+        (states, items.finish())
+    });
+    reduce(states, output, operation);
+    result
+}
+
+/// Merge the workers' results into the output.
+fn reduce(states: Vec<WorkerState>, output: &mut dyn MAFOutput, operation: &Operation) {
+    match operation {
+        Operation::Coverage { options, .. } => {
+            let mut total: Option<Box<MAFCoverage>> = None;
+            for state in states {
+                if let WorkerState::Coverage(coverage) = state {
+                    match total.as_mut() {
+                        Some(total) => total.merge(*coverage),
+                        None => total = Some(coverage),
+                    }
+                }
+            }
+            let format: CoverageFormat = options.format;
+            total.unwrap().print(output, format);
+        }
+        Operation::Filter { .. } => {
+            let mut spools = vec![];
+            let mut runs = vec![];
+            for state in states {
+                if let WorkerState::Filter {
+                    spool,
+                    runs: worker_runs,
+                    ..
+                } = state
+                {
+                    let worker = spools.len();
+                    spools.push(BufReader::new(spool.into_inner().expect("failed to write")));
+                    runs.extend(
+                        worker_runs
+                            .into_iter()
+                            .map(|(partition, start, end)| (partition, worker, start, end)),
+                    );
+                }
+            }
+            // A partition's runs are all in one worker's spool, in order,
+            // so a stable sort brings them together in order.
+            runs.sort_by_key(|run| run.0);
+            for (_, worker, start, end) in runs {
+                let spool = &mut spools[worker];
+                spool.seek(SeekFrom::Start(start)).expect("failed to seek");
+                let mut run = Read::take(spool, end - start);
+                let mut spooled = MAFItems::new(&mut run);
+                for item in spooled.by_ref() {
+                    match item {
+                        MAFItem::Block(block) => output.write_block(&block),
+                        MAFItem::Comment(comment) => write_comment(output, &comment),
+                    }
+                }
+                spooled
+                    .finish()
+                    .expect("Couldn't read back the temporary file");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filter;
+    use maf_stream::coverage::coverage;
+    use maf_stream::parse_bed;
+
+    const MAF: &str = "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 0 4 + 50 ACGA
+
+a
+s hg38.chr2 10 4 + 100 ACGT
+s mm39.chr2 20 2 + 50 AT--
+s rn7.chr1 0 4 + 50 ATCG
+
+a
+s hg38.chr1 4 2 + 100 AC
+s rn7.chr1 10 2 + 50 AC
+
+a
+s hg38.chr3 0 2 + 100 AC
+s mm39.chr5 0 2 + 50 AC
+";
+
+    #[test]
+    fn test_mapreduce_coverage() {
+        let options = CoverageOptions::default();
+        let mut expected = vec![];
+        coverage(&mut MAF.as_bytes(), &mut expected, "hg38", options.clone()).unwrap();
+        for num_threads in 1..4 {
+            let mut output = vec![];
+            let operation = Operation::Coverage {
+                ref_genome: "hg38",
+                options: &options,
+            };
+            mapreduce(&mut MAF.as_bytes(), &mut output, &operation, num_threads).unwrap();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_mapreduce_filter() {
+        let bed = "chr1\t1\t5\nchr2\t0\t100\n";
+        let regions = RegionFilter::new(Some(parse_bed(bed.as_bytes())), None);
+        let mut serial = vec![];
+        filter(&mut MAF.as_bytes(), &mut serial, &regions, None).unwrap();
+        let operation = Operation::Filter {
+            regions: &regions,
+            query: None,
+        };
+        let mut output = vec![];
+        mapreduce(&mut MAF.as_bytes(), &mut output, &operation, 2).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let serial = String::from_utf8(serial).unwrap();
+        let mut blocks: Vec<&str> = output.split("\n\n").collect();
+        let mut serial_blocks: Vec<&str> = serial.split("\n\n").collect();
+        blocks.sort_unstable();
+        serial_blocks.sort_unstable();
+        assert_eq!(blocks, serial_blocks);
+        // Blocks on chr1 come out together.
+        assert_eq!(
+            output,
+            "##maf version=1
+a
+s hg38.chr1 1 3 + 100 CGT
+s mm39.chr2 1 3 + 50 CGA
+
+a
+s hg38.chr1 4 1 + 100 A
+s rn7.chr1 10 1 + 50 A
+
+a
+s hg38.chr2 10 4 + 100 ACGT
+s mm39.chr2 20 2 + 50 AT--
+s rn7.chr1 0 4 + 50 ATCG
+
+"
+        );
+    }
+
+    #[test]
+    fn test_mapreduce_filter_comments_and_many_partitions() {
+        let regions = RegionFilter::new(None, None);
+        let operation = Operation::Filter {
+            regions: &regions,
+            query: None,
+        };
+        // A comment goes with the block before it.
+        let maf = "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACGT
+
+# after chr1
+a
+s hg38.chr2 0 4 + 100 ACGT
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+";
+        let mut output = vec![];
+        mapreduce(&mut maf.as_bytes(), &mut output, &operation, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACGT
+
+# after chr1
+a
+s hg38.chr1 4 4 + 100 ACGT
+
+a
+s hg38.chr2 0 4 + 100 ACGT
+
+"
+        );
+
+        // Far more partitions than a worker could keep files open for.
+        let mut maf = String::new();
+        for i in 0..3000 {
+            maf.push_str(&format!("a\ns hg38.scaffold{} 0 2 + 10 AC\n\n", i));
+        }
+        let mut output = vec![];
+        mapreduce(&mut maf.as_bytes(), &mut output, &operation, 2).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), maf);
+    }
+}
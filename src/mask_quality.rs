@@ -0,0 +1,115 @@
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlockAlignedEntry, MAFBlockEntry};
+use std::collections::HashSet;
+use std::io::BufRead;
+
+/// How low-quality bases are masked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaskMode {
+    /// Replace the base with N.
+    N,
+    /// Lowercase the base.
+    Lowercase,
+}
+
+/// Mask the bases of a row whose "q" line quality is below `min_q`.
+/// Returns the number of bases masked.
+fn mask_entry(entry: &mut MAFBlockAlignedEntry, min_q: u8, mode: MaskMode) -> usize {
+    let mut num_masked = 0;
+    for column in 0..entry.alignment.len() {
+        if entry.quality(column).is_some_and(|q| q < min_q) {
+            let base = &mut entry.alignment[column];
+            *base = match mode {
+                MaskMode::N => b'N',
+                MaskMode::Lowercase => base.to_ascii_lowercase(),
+            };
+            num_masked += 1;
+        }
+    }
+    num_masked
+}
+
+/// Mask bases with a quality (on the 0-9 scale of "q" lines, with
+/// finished sequence counting as 10) below `min_q`, in the rows of the
+/// given species, or of every species if `species` is None. Rows
+/// without a "q" line are left alone. Returns the number of bases
+/// masked.
+pub fn mask_quality(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    min_q: u8,
+    species: Option<&HashSet<String>>,
+    mode: MaskMode,
+) -> Result<usize, MAFParseError> {
+    let mut num_masked = 0;
+    for_each_block(input, output, |mut block, output| {
+        for entry in block.entries.iter_mut() {
+            if let MAFBlockEntry::AlignedEntry(e) = entry {
+                let entry_species = e.seq.split('.').next().unwrap();
+                if species.is_none_or(|s| s.contains(entry_species)) {
+                    num_masked += mask_entry(e, min_q, mode);
+                }
+            }
+        }
+        output.write_block(&block);
+    })?;
+    Ok(num_masked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s hg38.chr1 0 6 + 100 ACGTAC
+s mm39.chr2 10 5 + 200 AC-TAC
+q mm39.chr2 09-F45
+s rn7.chr3 10 6 + 200 ACGTAC
+q rn7.chr3 000000
+";
+
+    #[test]
+    fn test_mask_quality() {
+        let mut output = vec![];
+        let species: HashSet<String> = vec!["mm39".to_string()].into_iter().collect();
+        assert_eq!(
+            mask_quality(
+                &mut MAF.as_bytes(),
+                &mut output,
+                5,
+                Some(&species),
+                MaskMode::N
+            )
+            .unwrap(),
+            2
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 6 + 100 ACGTAC
+s mm39.chr2 10 5 + 200 NC-TNC
+q mm39.chr2 09-F45
+s rn7.chr3 10 6 + 200 ACGTAC
+q rn7.chr3 000000
+
+"
+        );
+
+        let mut output = vec![];
+        assert_eq!(
+            mask_quality(
+                &mut MAF.as_bytes(),
+                &mut output,
+                1,
+                None,
+                MaskMode::Lowercase
+            )
+            .unwrap(),
+            7
+        );
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("s rn7.chr3 10 6 + 200 acgtac\n"));
+    }
+}
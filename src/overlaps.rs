@@ -0,0 +1,223 @@
+use crate::filter::filter_block;
+use maf_stream::{
+    chrom_part, merge_ranges, write_comment, MAFItems, MAFOutput, Range, RegionFilter,
+};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use tempfile::tempfile;
+
+/// The forward-strand reference interval of a block.
+struct BlockInterval {
+    range: Range,
+    score: f64,
+    block: usize,
+}
+
+/// Two blocks whose reference intervals overlap, by index in the file.
+#[derive(Debug, PartialEq)]
+pub struct Overlap {
+    pub first: usize,
+    pub second: usize,
+    pub range: Range,
+}
+
+fn block_interval(block: &MAFBlock, idx: usize) -> Option<BlockInterval> {
+    let ref_entry = block.aligned_entries().next()?;
+    let (start, end) = ref_entry.forward_interval();
+    Some(BlockInterval {
+        range: Range {
+            seq: chrom_part(&ref_entry.seq),
+            start,
+            end,
+        },
+        score: block
+            .metadata
+            .get("score")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(f64::NEG_INFINITY),
+        block: idx,
+    })
+}
+
+/// Find every pair of overlapping intervals, sorted by position.
+fn find_overlaps(intervals: &mut [BlockInterval]) -> Vec<Overlap> {
+    intervals.sort_by(|a, b| a.range.cmp(&b.range).then(a.block.cmp(&b.block)));
+    let mut overlaps = vec![];
+    // Intervals that might still overlap the next one.
+    let mut active: Vec<&BlockInterval> = vec![];
+    for interval in intervals.iter() {
+        active.retain(|a| a.range.seq == interval.range.seq && a.range.end > interval.range.start);
+        for a in active.iter() {
+            overlaps.push(Overlap {
+                first: a.block.min(interval.block),
+                second: a.block.max(interval.block),
+                range: Range {
+                    seq: interval.range.seq.clone(),
+                    start: interval.range.start,
+                    end: a.range.end.min(interval.range.end),
+                },
+            });
+        }
+        active.push(interval);
+    }
+    overlaps
+}
+
+/// Report the pairs of blocks whose reference intervals overlap. If
+/// `trim` is set, the MAF is written instead, with the overlapping
+/// region removed from the lower-scoring block of each pair (the later
+/// block, on ties) using the same column filtering as `filter`.
+/// Returns the number of overlapping pairs.
+pub fn check_overlaps(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    trim: bool,
+) -> Result<usize, MAFParseError> {
+    let mut spool = BufWriter::new(tempfile().expect("Couldn't open temporary file"));
+    let mut intervals = vec![];
+    let mut num_blocks = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                if trim {
+                    writeln!(spool, "#{}", comment).expect("failed to write");
+                }
+            }
+            MAFItem::Block(block) => {
+                intervals.extend(block_interval(&block, num_blocks));
+                num_blocks += 1;
+                if trim {
+                    write!(spool, "{}", block).expect("failed to write");
+                }
+            }
+        }
+    }
+    let result = items.finish();
+    let overlaps = find_overlaps(&mut intervals);
+
+    if !trim {
+        writeln!(output, "#chrom\tstart\tend\tblock1\tblock2").ok();
+        for overlap in overlaps.iter() {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}",
+                overlap.range.seq,
+                overlap.range.start,
+                overlap.range.end,
+                overlap.first,
+                overlap.second
+            )
+            .ok();
+        }
+        result?;
+        return Ok(overlaps.len());
+    }
+
+    let scores: HashMap<usize, f64> = intervals.iter().map(|i| (i.block, i.score)).collect();
+    let mut trimmed: HashMap<usize, Vec<Range>> = HashMap::new();
+    for overlap in overlaps.iter() {
+        let loser = match scores[&overlap.first].partial_cmp(&scores[&overlap.second]) {
+            Some(Ordering::Less) => overlap.first,
+            _ => overlap.second,
+        };
+        trimmed
+            .entry(loser)
+            .or_default()
+            .push(overlap.range.clone());
+    }
+
+    let mut spool = spool.into_inner().expect("failed to write");
+    spool
+        .seek(SeekFrom::Start(0))
+        .expect("Couldn't rewind temporary file");
+    let mut spool = BufReader::new(spool);
+    let mut block_idx = 0;
+    let mut spooled = MAFItems::new(&mut spool);
+    for item in spooled.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => {
+                write_comment(output, &comment);
+            }
+            MAFItem::Block(block) => {
+                match trimmed.remove(&block_idx) {
+                    Some(ranges) => {
+                        let regions = RegionFilter::new(None, Some(merge_ranges(ranges)));
+                        for filtered_block in filter_block(&block, &regions, None) {
+                            output.write_block(&filtered_block);
+                        }
+                    }
+                    None => {
+                        output.write_block(&block);
+                    }
+                }
+                block_idx += 1;
+            }
+        }
+    }
+    spooled
+        .finish()
+        .expect("Couldn't read back the temporary file");
+    result?;
+    Ok(overlaps.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a score=10
+s hg38.chr1 0 6 + 100 ACGTAC
+s mm39.chr2 10 6 + 200 ACGTAC
+
+a score=20
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 30 4 + 200 ACGT
+
+a score=5
+s hg38.chr1 90 5 - 100 ACGTA
+s mm39.chr2 50 5 + 200 ACGTA
+";
+
+    #[test]
+    fn test_check_overlaps() {
+        let mut output = vec![];
+        assert_eq!(
+            check_overlaps(&mut MAF.as_bytes(), &mut output, false).unwrap(),
+            3
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tstart\tend\tblock1\tblock2
+chr1\t4\t6\t0\t1
+chr1\t5\t6\t0\t2
+chr1\t5\t8\t1\t2
+"
+        );
+    }
+
+    #[test]
+    fn test_trim_overlaps() {
+        let mut output = vec![];
+        check_overlaps(&mut MAF.as_bytes(), &mut output, true).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a score=10
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+
+a score=20
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 30 4 + 200 ACGT
+
+a score=5
+s hg38.chr1 90 2 - 100 AC
+s mm39.chr2 50 2 + 200 AC
+
+"
+        );
+    }
+}
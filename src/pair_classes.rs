@@ -0,0 +1,280 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, MAFItems, Range};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// How two species' characters in a column compare.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnClass {
+    Identical,
+    Transition,
+    Transversion,
+    /// Either species has a gap.
+    Gap,
+    /// Either species isn't in the block, or has an ambiguous base.
+    Missing,
+}
+
+fn is_purine(base: u8) -> bool {
+    base == b'A' || base == b'G'
+}
+
+fn classify(a: Option<u8>, b: Option<u8>) -> ColumnClass {
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a.to_ascii_uppercase(), b.to_ascii_uppercase()),
+        _ => return ColumnClass::Missing,
+    };
+    if is_gap(a) || is_gap(b) {
+        ColumnClass::Gap
+    } else if !b"ACGT".contains(&a) || !b"ACGT".contains(&b) {
+        ColumnClass::Missing
+    } else if a == b {
+        ColumnClass::Identical
+    } else if is_purine(a) == is_purine(b) {
+        ColumnClass::Transition
+    } else {
+        ColumnClass::Transversion
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ClassCounts {
+    identical: u64,
+    transitions: u64,
+    transversions: u64,
+    gaps: u64,
+    missing: u64,
+    /// Reference bases seen in blocks, to count the rest as missing.
+    ref_bases: u64,
+}
+
+impl ClassCounts {
+    fn add(&mut self, class: ColumnClass) {
+        match class {
+            ColumnClass::Identical => self.identical += 1,
+            ColumnClass::Transition => self.transitions += 1,
+            ColumnClass::Transversion => self.transversions += 1,
+            ColumnClass::Gap => self.gaps += 1,
+            ColumnClass::Missing => self.missing += 1,
+        }
+    }
+}
+
+/// Where the counts are gathered.
+pub enum PairIntervals {
+    /// Windows of this many reference bases, starting at multiples of
+    /// it. Only windows with blocks in them are reported.
+    Windows(u64),
+    /// These regions, in this order, whether or not any blocks
+    /// overlap them.
+    Regions(Vec<Range>),
+}
+
+/// Count, for each interval, the alignment columns where two species
+/// have identical bases, a transition, a transversion, a gap, or
+/// missing data (either species absent or ambiguous), writing them as
+/// a TSV. Columns are placed by the forward-strand reference position
+/// of the last reference base at or before them, and reference bases no
+/// block covers count as missing. Only the first row of each species in
+/// a block is used. Returns the number of intervals.
+pub fn pair_classes(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    species: (&str, &str),
+    intervals: &PairIntervals,
+) -> Result<usize, MAFParseError> {
+    // The counts of each interval, in the order they're reported.
+    let mut counts: Vec<(Range, ClassCounts)> = vec![];
+    // Windows' indices in `counts`, by chromosome and window number.
+    let mut window_index: HashMap<(String, u64), usize> = HashMap::new();
+    // Regions' indices in `counts` by chromosome, sorted by start, with
+    // the longest's length to find those starting before a position but
+    // overlapping it.
+    let mut regions_by_chrom: HashMap<&str, Vec<(u64, u64, usize)>> = HashMap::new();
+    let mut max_region_length = 0;
+    if let PairIntervals::Regions(regions) = intervals {
+        for (i, region) in regions.iter().enumerate() {
+            counts.push((region.clone(), ClassCounts::default()));
+            regions_by_chrom
+                .entry(&region.seq)
+                .or_default()
+                .push((region.start, region.end, i));
+            max_region_length = max_region_length.max(region.end - region.start);
+        }
+        for regions in regions_by_chrom.values_mut() {
+            regions.sort();
+        }
+    }
+
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => continue,
+        };
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let chrom = chrom_part(&ref_entry.seq);
+        let row = |name: &str| {
+            block
+                .aligned_entries()
+                .find(|entry| entry.seq.split('.').next() == Some(name))
+        };
+        let (row_a, row_b) = (row(species.0), row(species.1));
+        let mut last_pos = None;
+        for (i, ref_pos) in ref_entry.column_positions().enumerate() {
+            let is_ref_base = ref_pos.is_some();
+            last_pos = ref_pos.or(last_pos);
+            let pos = ref_entry.forward_position(last_pos.unwrap_or(ref_entry.start));
+            let class = classify(
+                row_a.map(|entry| entry.alignment[i]),
+                row_b.map(|entry| entry.alignment[i]),
+            );
+            let add = |counts: &mut ClassCounts| {
+                counts.add(class);
+                counts.ref_bases += is_ref_base as u64;
+            };
+            match intervals {
+                PairIntervals::Windows(size) => {
+                    let index = *window_index
+                        .entry((chrom.clone(), pos / size))
+                        .or_insert_with(|| {
+                            let start = pos / size * size;
+                            let window = Range {
+                                seq: chrom.clone(),
+                                start,
+                                end: (start + size).min(ref_entry.sequence_size),
+                            };
+                            counts.push((window, ClassCounts::default()));
+                            counts.len() - 1
+                        });
+                    add(&mut counts[index].1);
+                }
+                PairIntervals::Regions(_) => {
+                    let regions = match regions_by_chrom.get(chrom.as_str()) {
+                        Some(regions) => regions,
+                        None => continue,
+                    };
+                    let first =
+                        regions.partition_point(|(start, _, _)| *start + max_region_length <= pos);
+                    for (start, end, i) in regions[first..].iter() {
+                        if *start > pos {
+                            break;
+                        }
+                        if *end > pos {
+                            add(&mut counts[*i].1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    writeln!(
+        output,
+        "#chrom\tstart\tend\tidentical\ttransitions\ttransversions\tgaps\tmissing"
+    )
+    .ok();
+    for (interval, counts) in counts.iter() {
+        let uncovered = (interval.end - interval.start).saturating_sub(counts.ref_bases);
+        writeln!(
+            output,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            interval.seq,
+            interval.start,
+            interval.end,
+            counts.identical,
+            counts.transitions,
+            counts.transversions,
+            counts.gaps,
+            counts.missing + uncovered
+        )
+        .ok();
+    }
+    items.finish()?;
+    Ok(counts.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s hg38.chr1 0 6 + 100 ACGTA-C
+s panTro6.chr1 0 6 + 100 ACATC-C
+s mm39.chr2 0 7 + 100 AC-TGAN
+
+a
+s hg38.chr1 10 2 - 100 AC
+s panTro6.chr1 0 2 + 100 AC
+";
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(Some(b'a'), Some(b'A')), ColumnClass::Identical);
+        assert_eq!(classify(Some(b'C'), Some(b'T')), ColumnClass::Transition);
+        assert_eq!(classify(Some(b'C'), Some(b'G')), ColumnClass::Transversion);
+        assert_eq!(classify(Some(b'-'), Some(b'G')), ColumnClass::Gap);
+        assert_eq!(classify(Some(b'N'), Some(b'G')), ColumnClass::Missing);
+        assert_eq!(classify(None, Some(b'G')), ColumnClass::Missing);
+    }
+
+    #[test]
+    fn test_pair_classes() {
+        let mut output = vec![];
+        let num_intervals = pair_classes(
+            &mut MAF.as_bytes(),
+            &mut output,
+            ("panTro6", "mm39"),
+            &PairIntervals::Windows(50),
+        )
+        .unwrap();
+        assert_eq!(num_intervals, 2);
+        // The second block is at 88-90 on the forward strand, where
+        // mm39 is missing.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tstart\tend\tidentical\ttransitions\ttransversions\tgaps\tmissing
+chr1\t0\t50\t3\t0\t1\t2\t45
+chr1\t50\t100\t0\t0\t0\t0\t50
+"
+        );
+
+        let regions = vec![
+            Range {
+                seq: "chr1".to_string(),
+                start: 2,
+                end: 4,
+            },
+            Range {
+                seq: "chr1".to_string(),
+                start: 0,
+                end: 10,
+            },
+            Range {
+                seq: "chr2".to_string(),
+                start: 0,
+                end: 10,
+            },
+        ];
+        let mut output = vec![];
+        pair_classes(
+            &mut MAF.as_bytes(),
+            &mut output,
+            ("hg38", "panTro6"),
+            &PairIntervals::Regions(regions),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tstart\tend\tidentical\ttransitions\ttransversions\tgaps\tmissing
+chr1\t2\t4\t1\t1\t0\t0\t0
+chr1\t0\t10\t4\t1\t1\t1\t4
+chr2\t0\t10\t0\t0\t0\t0\t10
+"
+        );
+    }
+}
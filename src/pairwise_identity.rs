@@ -0,0 +1,302 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, MAFItems, RegionFilter, Rng};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+/// Counts of aligned and identical bases between every pair of
+/// species.
+#[derive(Default)]
+pub struct PairwiseIdentity {
+    pub species: Vec<String>,
+    species_idx: HashMap<String, usize>,
+    /// (aligned, identical) base counts for each pair i < j, at index
+    /// j * (j - 1) / 2 + i, so that the triangle can grow as new
+    /// species turn up.
+    counts: Vec<(u64, u64)>,
+    /// The (pair index, aligned, identical) counts of each block, kept
+    /// for resampling if bootstrapping.
+    block_counts: Option<Vec<Vec<(usize, u64, u64)>>>,
+}
+
+impl PairwiseIdentity {
+    /// Keep each block's counts, so they can be resampled with
+    /// `bootstrap`.
+    pub fn with_block_counts() -> Self {
+        PairwiseIdentity {
+            block_counts: Some(vec![]),
+            ..Default::default()
+        }
+    }
+
+    fn species_idx(&mut self, species: &str) -> usize {
+        if let Some(idx) = self.species_idx.get(species) {
+            return *idx;
+        }
+        let idx = self.species.len();
+        self.species.push(species.to_string());
+        self.species_idx.insert(species.to_string(), idx);
+        self.counts.resize(self.counts.len() + idx, (0, 0));
+        idx
+    }
+
+    fn pair_idx(i: usize, j: usize) -> usize {
+        let (i, j) = if i < j { (i, j) } else { (j, i) };
+        j * (j - 1) / 2 + i
+    }
+
+    /// Count the columns of a block, using the first row of each
+    /// species. If regions are given, only columns with a reference
+    /// base within them are counted.
+    pub fn add_block(&mut self, block: &MAFBlock, regions: Option<&RegionFilter>) {
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let chrom = chrom_part(&ref_entry.seq);
+        let mut rows = vec![];
+        let mut seen = vec![];
+        for entry in block.aligned_entries() {
+            let idx = self.species_idx(entry.seq.split('.').next().unwrap());
+            if !seen.contains(&idx) {
+                seen.push(idx);
+                rows.push((idx, entry));
+            }
+        }
+        let mut block_counts: Option<BTreeMap<usize, (u64, u64)>> =
+            self.block_counts.as_ref().map(|_| BTreeMap::new());
+        let mut bases = vec![];
+        for (column, pos) in ref_entry.column_positions().enumerate() {
+            if let Some(regions) = regions {
+                match pos {
+                    Some(pos) if regions.contains(&chrom, ref_entry.forward_position(pos)) => {}
+                    _ => continue,
+                }
+            }
+            bases.clear();
+            bases.extend(
+                rows.iter()
+                    .map(|(idx, entry)| (*idx, entry.alignment[column]))
+                    .filter(|(_, base)| !is_gap(*base)),
+            );
+            for (a, (i, base_i)) in bases.iter().enumerate() {
+                for (j, base_j) in bases[a + 1..].iter() {
+                    let pair = Self::pair_idx(*i, *j);
+                    let identical = base_i.eq_ignore_ascii_case(base_j) as u64;
+                    let counts = &mut self.counts[pair];
+                    counts.0 += 1;
+                    counts.1 += identical;
+                    if let Some(block_counts) = block_counts.as_mut() {
+                        let counts = block_counts.entry(pair).or_insert((0, 0));
+                        counts.0 += 1;
+                        counts.1 += identical;
+                    }
+                }
+            }
+        }
+        if let (Some(all), Some(block)) = (self.block_counts.as_mut(), block_counts) {
+            all.push(
+                block
+                    .into_iter()
+                    .map(|(pair, (aligned, identical))| (pair, aligned, identical))
+                    .collect(),
+            );
+        }
+    }
+
+    /// The 95% percentile confidence interval of each pair's identity
+    /// (by pair index), from resampling the blocks with replacement
+    /// `replicates` times. Pairs never aligned in any replicate get
+    /// None. Panics unless made with `with_block_counts`.
+    fn bootstrap(&self, replicates: usize, seed: u64) -> Vec<Option<(f64, f64)>> {
+        let blocks = self
+            .block_counts
+            .as_ref()
+            .expect("Block counts weren't kept");
+        let mut rng = Rng::new(seed);
+        let mut samples: Vec<Vec<f64>> = vec![vec![]; self.counts.len()];
+        let mut counts = vec![(0u64, 0u64); self.counts.len()];
+        for _ in 0..replicates {
+            counts.iter_mut().for_each(|c| *c = (0, 0));
+            for _ in 0..blocks.len() {
+                let block = &blocks[rng.below(blocks.len() as u64) as usize];
+                for (pair, aligned, identical) in block.iter() {
+                    counts[*pair].0 += aligned;
+                    counts[*pair].1 += identical;
+                }
+            }
+            for (pair, (aligned, identical)) in counts.iter().enumerate() {
+                if *aligned > 0 {
+                    samples[pair].push(*identical as f64 / *aligned as f64);
+                }
+            }
+        }
+        samples
+            .into_iter()
+            .map(|mut samples| {
+                if samples.is_empty() {
+                    return None;
+                }
+                samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let quantile = |q: f64| samples[((samples.len() - 1) as f64 * q).round() as usize];
+                Some((quantile(0.025), quantile(0.975)))
+            })
+            .collect()
+    }
+
+    /// Fraction of identical bases over the columns where both species
+    /// have a base, or None if there are no such columns.
+    pub fn identity(&self, i: usize, j: usize) -> Option<f64> {
+        if i == j {
+            return Some(1.0);
+        }
+        let (aligned, identical) = self.counts[Self::pair_idx(i, j)];
+        if aligned == 0 {
+            None
+        } else {
+            Some(identical as f64 / aligned as f64)
+        }
+    }
+
+    /// Species indices in name order.
+    pub fn sorted_species(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.species.len()).collect();
+        order.sort_by_key(|i| &self.species[*i]);
+        order
+    }
+}
+
+/// Write the matrix of pairwise identity between every pair of
+/// species, over every column, or only columns whose reference base
+/// is within the regions if they're given. Pairs never aligned to
+/// each other are "NA".
+pub fn pairwise_identity(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    regions: Option<&RegionFilter>,
+) -> Result<(), MAFParseError> {
+    let mut identity = PairwiseIdentity::default();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            identity.add_block(&block, regions);
+        }
+    }
+    let order = identity.sorted_species();
+    write!(output, "species").ok();
+    for i in order.iter() {
+        write!(output, "\t{}", identity.species[*i]).ok();
+    }
+    writeln!(output).ok();
+    for i in order.iter() {
+        write!(output, "{}", identity.species[*i]).ok();
+        for j in order.iter() {
+            match identity.identity(*i, *j) {
+                Some(value) => write!(output, "\t{:.4}", value).ok(),
+                None => write!(output, "\tNA").ok(),
+            };
+        }
+        writeln!(output).ok();
+    }
+    items.finish()
+}
+
+/// Write a TSV of the identity between every pair of species (as in
+/// `pairwise_identity`) with a 95% confidence interval from resampling
+/// the blocks with replacement `replicates` times. Only each block's
+/// counts are kept in memory, not the blocks. Pairs never aligned to
+/// each other are left out.
+pub fn bootstrap_identity(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    regions: Option<&RegionFilter>,
+    replicates: usize,
+    seed: u64,
+) -> Result<(), MAFParseError> {
+    let mut identity = PairwiseIdentity::with_block_counts();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            identity.add_block(&block, regions);
+        }
+    }
+    let intervals = identity.bootstrap(replicates, seed);
+    let order = identity.sorted_species();
+    writeln!(output, "#species1\tspecies2\tidentity\tci_low\tci_high").ok();
+    for (a, i) in order.iter().enumerate() {
+        for j in order[a + 1..].iter() {
+            let value = match identity.identity(*i, *j) {
+                Some(value) => value,
+                None => continue,
+            };
+            let (low, high) = intervals[PairwiseIdentity::pair_idx(*i, *j)].unwrap();
+            writeln!(
+                output,
+                "{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+                identity.species[*i], identity.species[*j], value, low, high
+            )
+            .ok();
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairwise_identity() {
+        let maf = "a
+s mm39.chr2 10 4 + 200 ACGT
+s hg38.chr1 10 4 + 100 ACGA
+s hg38.chr5 10 4 + 100 TTTT
+s rn7.chr3 10 2 + 200 -cg-
+
+a
+s hg38.chr1 0 2 + 100 AC
+s bosTau9.chr2 0 2 + 100 AG
+";
+        let mut output = vec![];
+        pairwise_identity(&mut maf.as_bytes(), &mut output, None).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "species\tbosTau9\thg38\tmm39\trn7
+bosTau9\t1.0000\t0.5000\tNA\tNA
+hg38\t0.5000\t1.0000\t0.7500\t1.0000
+mm39\tNA\t0.7500\t1.0000\t1.0000
+rn7\tNA\t1.0000\t1.0000\t1.0000
+"
+        );
+    }
+
+    #[test]
+    fn test_bootstrap_identity() {
+        let maf = "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 0 4 + 100 ACGT
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+s mm39.chr2 4 4 + 100 TTTT
+s rn7.chr3 0 4 + 100 ACGA
+";
+        let run = |seed| {
+            let mut output = vec![];
+            bootstrap_identity(&mut maf.as_bytes(), &mut output, None, 100, seed).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        // With two blocks, hg38-mm39 replicates are 1, 0.625, or 0.25
+        // identical, while the pairs with rn7 are only in one block.
+        assert_eq!(
+            run(1),
+            "#species1\tspecies2\tidentity\tci_low\tci_high
+hg38\tmm39\t0.6250\t0.2500\t1.0000
+hg38\trn7\t0.7500\t0.7500\t0.7500
+mm39\trn7\t0.0000\t0.0000\t0.0000
+"
+        );
+        assert_eq!(run(1), run(1));
+    }
+}
@@ -0,0 +1,253 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, MAFItems, Range};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// The symbols of a pattern, indexed by their code: missing (absent or
+/// ambiguous), gap, matching and mismatching the reference, and the
+/// bases themselves.
+const SYMBOLS: &[u8] = b"?-01ACGT";
+const MISSING: u8 = 0;
+const GAP: u8 = 1;
+const MATCH: u8 = 2;
+const MISMATCH: u8 = 3;
+
+/// Bits per species' code, so that a pattern packs into words.
+const BITS: usize = 4;
+const CODES_PER_WORD: usize = 64 / BITS;
+
+/// How each species' character in a column is recorded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternMode {
+    /// Whether it matches the reference's base. Columns where the
+    /// reference has a gap, or an ambiguous base, are skipped.
+    Binary,
+    /// The base itself.
+    States,
+}
+
+fn base_code(c: u8) -> u8 {
+    match c.to_ascii_uppercase() {
+        c if is_gap(c) => GAP,
+        b'A' => 4,
+        b'C' => 5,
+        b'G' => 6,
+        b'T' => 7,
+        _ => MISSING,
+    }
+}
+
+/// Counts of column patterns, each packed into a few 64-bit words (four
+/// bits per species) so that patterns over many species stay cheap to
+/// hash and store.
+struct PatternCounts {
+    num_species: usize,
+    counts: HashMap<Box<[u64]>, u64>,
+    /// The pattern being built, reused between columns so that only new
+    /// patterns allocate.
+    scratch: Vec<u64>,
+}
+
+impl PatternCounts {
+    fn new(num_species: usize) -> Self {
+        PatternCounts {
+            num_species,
+            counts: HashMap::new(),
+            scratch: vec![0; num_species.div_ceil(CODES_PER_WORD)],
+        }
+    }
+
+    fn add(&mut self, codes: impl Iterator<Item = u8>) {
+        self.scratch.iter_mut().for_each(|word| *word = 0);
+        for (i, code) in codes.enumerate() {
+            self.scratch[i / CODES_PER_WORD] |= (code as u64) << (i % CODES_PER_WORD * BITS);
+        }
+        match self.counts.get_mut(&self.scratch[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts
+                    .insert(self.scratch.clone().into_boxed_slice(), 1);
+            }
+        }
+    }
+
+    fn unpack(&self, pattern: &[u64]) -> String {
+        (0..self.num_species)
+            .map(|i| {
+                let code = pattern[i / CODES_PER_WORD] >> (i % CODES_PER_WORD * BITS) & 0xf;
+                SYMBOLS[code as usize] as char
+            })
+            .collect()
+    }
+}
+
+/// Tabulate the patterns of the columns whose reference position is in
+/// `region` (or all columns), with one character per species in the
+/// order given, and write them with their counts as a TSV, most common
+/// first. Species absent from a block are missing (`?`), and only the
+/// first row of each species in a block is used. Returns the number of
+/// distinct patterns.
+pub fn patterns(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    species: &[&str],
+    mode: PatternMode,
+    region: Option<&Range>,
+) -> Result<usize, MAFParseError> {
+    let mut counts = PatternCounts::new(species.len());
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => continue,
+        };
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if let Some(region) = region {
+            if chrom_part(&ref_entry.seq) != region.seq {
+                continue;
+            }
+        }
+        let rows: Vec<Option<&Vec<u8>>> = species
+            .iter()
+            .map(|name| {
+                block
+                    .aligned_entries()
+                    .find(|entry| entry.seq.split('.').next() == Some(*name))
+                    .map(|entry| &entry.alignment)
+            })
+            .collect();
+        for (i, pos) in ref_entry.column_positions().enumerate() {
+            if let Some(region) = region {
+                match pos.map(|pos| ref_entry.forward_position(pos)) {
+                    Some(pos) if pos >= region.start && pos < region.end => (),
+                    _ => continue,
+                }
+            }
+            let ref_code = base_code(ref_entry.alignment[i]);
+            let codes = rows
+                .iter()
+                .map(|row| row.map_or(MISSING, |row| base_code(row[i])));
+            match mode {
+                PatternMode::States => counts.add(codes),
+                PatternMode::Binary => {
+                    if ref_code == GAP || ref_code == MISSING {
+                        continue;
+                    }
+                    counts.add(codes.map(|code| match code {
+                        MISSING | GAP => code,
+                        _ if code == ref_code => MATCH,
+                        _ => MISMATCH,
+                    }));
+                }
+            }
+        }
+    }
+    let mut sorted: Vec<(String, u64)> = counts
+        .counts
+        .iter()
+        .map(|(pattern, count)| (counts.unpack(pattern), *count))
+        .collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    writeln!(output, "##species={}", species.join(",")).ok();
+    writeln!(output, "#pattern\tcount").ok();
+    for (pattern, count) in sorted.iter() {
+        writeln!(output, "{}\t{}", pattern, count).ok();
+    }
+    items.finish()?;
+    Ok(sorted.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s hg38.chr1 0 5 + 100 ACG-TN
+s panTro6.chr1 0 6 + 100 ACGATA
+s mm39.chr2 0 4 + 100 AT--TA
+
+a
+s hg38.chr1 10 2 + 100 AC
+s panTro6.chr1 6 2 + 100 AC
+";
+
+    #[test]
+    fn test_patterns() {
+        let species = ["hg38", "panTro6", "mm39"];
+        let mut output = vec![];
+        let num_patterns = patterns(
+            &mut MAF.as_bytes(),
+            &mut output,
+            &species,
+            PatternMode::Binary,
+            None,
+        )
+        .unwrap();
+        assert_eq!(num_patterns, 4);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##species=hg38,panTro6,mm39
+#pattern\tcount
+000\t2
+00?\t2
+00-\t1
+001\t1
+"
+        );
+
+        let mut output = vec![];
+        let region = Range {
+            seq: "chr1".to_string(),
+            start: 1,
+            end: 11,
+        };
+        patterns(
+            &mut MAF.as_bytes(),
+            &mut output,
+            &species,
+            PatternMode::States,
+            Some(&region),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##species=hg38,panTro6,mm39
+#pattern\tcount
+?AA\t1
+AA?\t1
+CCT\t1
+GG-\t1
+TTT\t1
+"
+        );
+    }
+
+    #[test]
+    fn test_many_species() {
+        // Patterns spanning more than one word.
+        let species: Vec<String> = (0..20).map(|i| format!("s{}", i)).collect();
+        let names: Vec<&str> = species.iter().map(|s| s.as_str()).collect();
+        let mut maf = "a\n".to_string();
+        for (i, name) in names.iter().enumerate() {
+            let c = if i == 17 { 'G' } else { 'A' };
+            maf.push_str(&format!("s {}.chr1 0 2 + 100 A{}\n", name, c));
+        }
+        let mut output = vec![];
+        patterns(
+            &mut maf.as_bytes(),
+            &mut output,
+            &names,
+            PatternMode::Binary,
+            None,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("\n{}\t1\n", "0".repeat(20))));
+        assert!(output.contains(&format!("\n{}1{}\t1\n", "0".repeat(17), "00")));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::filter::filter_block;
+use maf_stream::alphabet::Alphabet;
+use maf_stream::dup_blocks::{ConsensusMode, MergeDups};
+use maf_stream::seq_filter::SeqFilter;
+use maf_stream::transform::{transform_blocks, BlockTransform, Chain, Normalize, Subset};
+use maf_stream::{merge_ranges, parse_bed, MAFOutput, RegionFilter};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFBlock;
+use regex::Regex;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// One operation of a pipeline.
+enum Step {
+    Subset(Subset),
+    Filter(RegionFilter),
+    MergeDups(MergeDups),
+    Normalize(Normalize),
+}
+
+impl BlockTransform for Step {
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+        match self {
+            Step::Subset(subset) => subset.transform(block),
+            Step::Filter(regions) => filter_block(&block, regions, None),
+            Step::MergeDups(merge_dups) => merge_dups.transform(block),
+            Step::Normalize(normalize) => normalize.transform(block),
+        }
+    }
+}
+
+impl Step {
+    /// Parse an operation, given as its name followed by its options,
+    /// each as ":key=value" (or ":key" for flags).
+    fn parse(op: &str, alphabet: &'static dyn Alphabet) -> Step {
+        let mut parts = op.split(':');
+        let name = parts.next().unwrap();
+        let options: Vec<(&str, Option<&str>)> = parts
+            .map(|option| {
+                let mut split = option.splitn(2, '=');
+                (split.next().unwrap(), split.next())
+            })
+            .collect();
+        let value = |key: &str| {
+            options.iter().find(|(k, _)| *k == key).map(|(_, value)| {
+                value.unwrap_or_else(|| panic!("{} needs a value in {}", key, op))
+            })
+        };
+        let allowed: &[&str] = match name {
+            "subset" => &["species", "include", "exclude"],
+            "filter" => &["bed", "bed_exclude"],
+            "merge_dups" => &["mode", "max_block_bases", "stitch"],
+            "normalize" => &["upper", "repeats"],
+            _ => panic!(
+                "Unknown operation {} (expected subset, filter, merge_dups, or normalize)",
+                name
+            ),
+        };
+        for (key, _) in options.iter() {
+            if !allowed.contains(key) {
+                panic!("Unknown option {} in {}", key, op);
+            }
+        }
+        let regex = |r: &str| Regex::new(r).expect("Invalid sequence name regex");
+        match name {
+            "subset" => match (value("species"), value("include")) {
+                (Some(_), Some(_)) => panic!("subset can't take both species and include"),
+                (Some(species), None) => Step::Subset(Subset::species(
+                    species.split(','),
+                    value("exclude").map(regex),
+                )),
+                (None, include) => Step::Subset(Subset(SeqFilter::new(
+                    include.map(regex),
+                    value("exclude").map(regex),
+                ))),
+            },
+            "filter" => {
+                let open_bed = |path: &str| {
+                    parse_bed(BufReader::new(
+                        File::open(path).expect("Couldn't open bed file"),
+                    ))
+                };
+                Step::Filter(RegionFilter::new(
+                    value("bed").map(open_bed),
+                    value("bed_exclude").map(open_bed),
+                ))
+            }
+            "normalize" => {
+                let mut normalize = Normalize {
+                    upper: options.iter().any(|(key, _)| *key == "upper"),
+                    ..Normalize::default()
+                };
+                for (key, arg) in options.iter() {
+                    if *key != "repeats" {
+                        continue;
+                    }
+                    let mut split = arg
+                        .unwrap_or_else(|| panic!("repeats needs a value in {}", op))
+                        .splitn(2, '=');
+                    let genome = split.next().unwrap();
+                    let path = split
+                        .next()
+                        .expect("repeats should be of the form genome=repeats.bed");
+                    let file = BufReader::new(File::open(path).expect("Couldn't open BED file"));
+                    normalize
+                        .repeats
+                        .insert(genome.to_string(), merge_ranges(parse_bed(file)));
+                }
+                Step::Normalize(normalize)
+            }
+            _ => {
+                let mode = value("mode").unwrap_or("consensus");
+                let mode = ConsensusMode::from_name(mode)
+                    .unwrap_or_else(|| panic!("Unknown consensus mode {}", mode));
+                let max_block_bases = value("max_block_bases")
+                    .map(|n| n.parse().expect("max_block_bases should be a number"));
+                let stitch = options.iter().any(|(key, _)| *key == "stitch");
+                Step::MergeDups(MergeDups::new(mode, max_block_bases, stitch, alphabet))
+            }
+        }
+    }
+}
+
+/// Run a whitespace-separated list of operations over each block in
+/// turn, in a single pass through the alignment. Each operation is
+/// "name:key=value:...", one of:
+/// - "subset", keeping only rows of the comma-separated "species", or
+///   matching the "include" regex, and dropping those matching the
+///   "exclude" regex, as with `--include_seqs` and `--exclude_seqs`
+/// - "filter", keeping columns with reference bases within the "bed"
+///   regions and outside the "bed_exclude" regions, as in `filter`
+/// - "merge_dups", merging duplicated rows with the consensus "mode"
+///   (consensus by default), with "max_block_bases" and "stitch" as in
+///   `merge_dups`
+/// - "normalize", uppercasing every base with the "upper" flag, and
+///   soft-masking each "repeats=genome=repeats.bed" (which can be
+///   repeated), as in `recase`
+pub fn pipe(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    ops: &str,
+    alphabet: &'static dyn Alphabet,
+) -> Result<(), MAFParseError> {
+    let mut chain = Chain::default();
+    for op in ops.split_whitespace() {
+        chain = chain.then(Step::parse(op, alphabet));
+    }
+    transform_blocks(input, output, &mut chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::alphabet::Nucleotides;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_pipe() {
+        let maf = "##maf version=1
+a
+s hg38.chr1 0 6 + 100 ACgTAC
+s mm39.chr2 0 6 + 100 ACGTAC
+s mm39.chr3 0 6 + 100 ACTTAA
+s rn7.chr1 0 6 + 100 ACGTAC
+";
+        let mut bed = NamedTempFile::new().unwrap();
+        writeln!(bed, "chr1\t1\t4").unwrap();
+        let ops = format!(
+            "subset:species=hg38,mm39 normalize:upper filter:bed={} merge_dups:mode=unanimity",
+            bed.path().to_str().unwrap()
+        );
+        let mut output = vec![];
+        pipe(&mut maf.as_bytes(), &mut output, &ops, &Nucleotides).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 1 3 + 100 CGT
+s mm39.chr2 1 3 + 100 CNT
+
+"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown operation")]
+    fn test_pipe_unknown_op() {
+        pipe(&mut "".as_bytes(), &mut vec![], "reverse", &Nucleotides).unwrap();
+    }
+}
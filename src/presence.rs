@@ -0,0 +1,136 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+/// Aligned bases per species in each window along the reference.
+struct WindowCounts {
+    window_size: u64,
+    species: Vec<String>,
+    species_idx: HashMap<String, usize>,
+    /// Reference chromosome -> (size, window index -> bases aligned
+    /// per species).
+    chroms: BTreeMap<String, (u64, BTreeMap<u64, Vec<u64>>)>,
+}
+
+impl WindowCounts {
+    fn species_idx(&mut self, species: &str) -> usize {
+        if let Some(idx) = self.species_idx.get(species) {
+            return *idx;
+        }
+        self.species.push(species.to_string());
+        self.species_idx
+            .insert(species.to_string(), self.species.len() - 1);
+        self.species.len() - 1
+    }
+}
+
+/// Write a presence/absence matrix of which species have at least
+/// `min_bases` bases aligned to each `window_size` window of the
+/// reference, covering every reference chromosome seen from start to
+/// end. The whole matrix is built in memory before it's written, but
+/// it only takes one count per species per window.
+pub fn presence(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    window_size: u64,
+    min_bases: u64,
+) -> Result<(), MAFParseError> {
+    let mut counts = WindowCounts {
+        window_size,
+        species: vec![],
+        species_idx: HashMap::new(),
+        chroms: BTreeMap::new(),
+    };
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => continue,
+        };
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let rows: Vec<_> = block
+            .aligned_entries()
+            .map(|e| (e, counts.species_idx(e.seq.split('.').next().unwrap())))
+            .collect();
+        let num_species = counts.species.len();
+        let windows = &mut counts
+            .chroms
+            .entry(chrom_part(&ref_entry.seq))
+            .or_insert((ref_entry.sequence_size, BTreeMap::new()))
+            .1;
+        for (column, pos) in ref_entry.column_positions().enumerate() {
+            let pos = match pos {
+                Some(pos) => ref_entry.forward_position(pos),
+                None => continue,
+            };
+            let window = windows.entry(pos / counts.window_size).or_default();
+            window.resize(num_species, 0);
+            for (entry, species) in rows.iter() {
+                if !is_gap(entry.alignment[column]) {
+                    window[*species] += 1;
+                }
+            }
+        }
+    }
+
+    write!(output, "#chrom\tstart\tend").ok();
+    for species in counts.species.iter() {
+        write!(output, "\t{}", species).ok();
+    }
+    writeln!(output).ok();
+    for (chrom, (size, windows)) in counts.chroms.iter() {
+        for window in 0..size.div_ceil(window_size) {
+            let start = window * window_size;
+            write!(
+                output,
+                "{}\t{}\t{}",
+                chrom,
+                start,
+                (start + window_size).min(*size)
+            )
+            .ok();
+            let bases = windows.get(&window);
+            for species in 0..counts.species.len() {
+                let num_bases = bases.and_then(|b| b.get(species)).copied().unwrap_or(0);
+                write!(output, "\t{}", (num_bases >= min_bases) as u8).ok();
+            }
+            writeln!(output).ok();
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presence() {
+        let maf = "a
+s hg38.chr1 8 4 + 25 ACGT
+s mm39.chr2 10 2 + 200 AC--
+s rn7.chr3 10 1 + 200 ---T
+
+a
+s hg38.chr1 5 3 - 25 ACG
+s rn7.chr3 11 3 + 200 ACG
+";
+        let mut output = vec![];
+        presence(&mut maf.as_bytes(), &mut output, 10, 1).unwrap();
+        // The second block covers hg38 positions 17-19.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tstart\tend\thg38\tmm39\trn7
+chr1\t0\t10\t1\t1\t0
+chr1\t10\t20\t1\t0\t1
+chr1\t20\t25\t0\t0\t0
+"
+        );
+    }
+}
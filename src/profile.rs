@@ -0,0 +1,171 @@
+use maf_stream::alphabet::Alphabet;
+use maf_stream::tree::Tree;
+use maf_stream::{chrom_part, complement, MAFItems, RegionFilter};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFItem, Strand};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// Weights for each leaf of a tree, so that closely related species
+/// don't swamp the rest: each branch's length is split evenly between
+/// the leaves below it, and a leaf's weight is the total it gets from
+/// the branches between it and the root.
+pub fn tree_weights(tree: &Tree) -> HashMap<String, f64> {
+    let mut num_leaves = vec![0usize; tree.nodes.len()];
+    for node in tree.postorder() {
+        if tree.is_leaf(node) {
+            num_leaves[node] = 1;
+        }
+        if let Some(parent) = tree.nodes[node].parent {
+            num_leaves[parent] += num_leaves[node];
+        }
+    }
+    tree.leaves()
+        .into_iter()
+        .map(|(name, leaf)| {
+            let mut weight = 0.0;
+            let mut node = leaf;
+            while let Some(parent) = tree.nodes[node].parent {
+                weight += tree.nodes[node].branch_length / num_leaves[node] as f64;
+                node = parent;
+            }
+            (name, weight)
+        })
+        .collect()
+}
+
+/// Write a TSV with a line for every reference position within the
+/// regions, giving its chromosome, forward-strand position, and base,
+/// followed by the fraction of species with each residue of the
+/// alphabet there (out of those with a residue, including the
+/// reference), and the number (or total weight) of those species. Bases
+/// are complemented where the reference is on the negative strand, and
+/// only the first row of each species is used. With weights, each
+/// species counts by its weight, and species without one are ignored.
+/// Lines are in the order of the alignment.
+pub fn profile(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    regions: &RegionFilter,
+    weights: Option<&HashMap<String, f64>>,
+    alphabet: &dyn Alphabet,
+) -> Result<(), MAFParseError> {
+    let residues = alphabet.residues();
+    write!(output, "#chrom\tpos\tref").ok();
+    for residue in residues.iter() {
+        write!(output, "\t{}", *residue as char).ok();
+    }
+    writeln!(output, "\tspecies").ok();
+    let mut totals = vec![0.0; residues.len()];
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            let ref_entry = match block.aligned_entries().next() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let chrom = chrom_part(&ref_entry.seq);
+            let mut rows = vec![];
+            for entry in block.aligned_entries() {
+                let species = entry.seq.split('.').next().unwrap();
+                if rows.iter().any(|(s, _)| *s == species) {
+                    continue;
+                }
+                let weight = match weights {
+                    Some(weights) => match weights.get(species) {
+                        Some(weight) => *weight,
+                        None => continue,
+                    },
+                    None => 1.0,
+                };
+                rows.push((species, (entry, weight)));
+            }
+            let forward = |base: u8| match ref_entry.strand {
+                Strand::Positive => base,
+                Strand::Negative => complement(base),
+            };
+            for (column, pos) in ref_entry.column_positions().enumerate() {
+                let pos = match pos {
+                    Some(pos) => ref_entry.forward_position(pos),
+                    None => continue,
+                };
+                if !regions.contains(&chrom, pos) {
+                    continue;
+                }
+                totals.iter_mut().for_each(|t| *t = 0.0);
+                for (_, (entry, weight)) in rows.iter() {
+                    if let Some(i) = alphabet.index(forward(entry.alignment[column])) {
+                        totals[i] += weight;
+                    }
+                }
+                let total: f64 = totals.iter().sum();
+                write!(
+                    output,
+                    "{}\t{}\t{}",
+                    chrom,
+                    pos,
+                    forward(ref_entry.alignment[column]).to_ascii_uppercase() as char
+                )
+                .ok();
+                for count in totals.iter() {
+                    if total > 0.0 {
+                        write!(output, "\t{:.4}", count / total).ok();
+                    } else {
+                        write!(output, "\tNA").ok();
+                    }
+                }
+                writeln!(output, "\t{}", total).ok();
+            }
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::alphabet::Nucleotides;
+
+    #[test]
+    fn test_profile() {
+        let maf = "a
+s hg38.chr1 10 3 + 100 AC-G
+s mm39.chr2 0 4 + 100 ATTN
+s mm39.chr3 0 4 + 100 CCCC
+s rn7.chr3 0 3 + 100 G-GG
+
+a
+s hg38.chr1 20 2 - 100 AC
+s mm39.chr2 4 2 + 100 AA
+";
+        let mut output = vec![];
+        let regions = RegionFilter::new(None, None);
+        profile(
+            &mut maf.as_bytes(),
+            &mut output,
+            &regions,
+            None,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tpos\tref\tA\tC\tG\tT\tspecies
+chr1\t10\tA\t0.6667\t0.0000\t0.3333\t0.0000\t3
+chr1\t11\tC\t0.0000\t0.5000\t0.0000\t0.5000\t2
+chr1\t12\tG\t0.0000\t0.0000\t1.0000\t0.0000\t2
+chr1\t79\tT\t0.0000\t0.0000\t0.0000\t1.0000\t2
+chr1\t78\tG\t0.0000\t0.0000\t0.5000\t0.5000\t2
+"
+        );
+    }
+
+    #[test]
+    fn test_tree_weights() {
+        let tree = Tree::parse_newick("((hg38:0.1,panTro6:0.1):0.2,mm39:0.4);");
+        let weights = tree_weights(&tree);
+        assert!((weights["hg38"] - 0.2).abs() < 1e-9);
+        assert!((weights["panTro6"] - 0.2).abs() < 1e-9);
+        assert!((weights["mm39"] - 0.4).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,248 @@
+//! Python bindings, built as the `maf_stream` extension module. See
+//! `pyproject.toml` for building with maturin.
+use multiple_alignment_format::parser::{next_maf_item, MAFParseError};
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFItem, Strand};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+fn to_py_error(err: MAFParseError) -> PyErr {
+    match err {
+        MAFParseError::IOError(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(format!("{:?}", e)),
+    }
+}
+
+/// An aligned (`s` line) row of a block.
+#[pyclass(name = "Row", module = "maf_stream")]
+#[derive(Clone)]
+pub struct PyRow {
+    entry: MAFBlockAlignedEntry,
+}
+
+#[pymethods]
+impl PyRow {
+    #[getter]
+    fn seq(&self) -> &str {
+        &self.entry.seq
+    }
+
+    #[getter]
+    fn start(&self) -> u64 {
+        self.entry.start
+    }
+
+    #[getter]
+    fn size(&self) -> u64 {
+        self.entry.aligned_length
+    }
+
+    #[getter]
+    fn strand(&self) -> &str {
+        match self.entry.strand {
+            Strand::Positive => "+",
+            Strand::Negative => "-",
+        }
+    }
+
+    #[getter]
+    fn src_size(&self) -> u64 {
+        self.entry.sequence_size
+    }
+
+    #[getter]
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.entry.alignment).into_owned()
+    }
+
+    /// The positive-strand position of the base in each column, or
+    /// None for gaps.
+    fn column_positions(&self) -> Vec<Option<u64>> {
+        self.entry
+            .column_positions()
+            .map(|pos| pos.map(|p| self.entry.forward_position(p)))
+            .collect()
+    }
+
+    /// The column holding a positive-strand position, or None if this
+    /// row doesn't cover it.
+    fn column_of(&self, pos: u64) -> Option<usize> {
+        self.entry
+            .column_positions()
+            .position(|p| p.map(|p| self.entry.forward_position(p)) == Some(pos))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Row({} {} {} {} {})",
+            self.entry.seq,
+            self.entry.start,
+            self.entry.aligned_length,
+            self.strand(),
+            self.entry.sequence_size
+        )
+    }
+}
+
+/// An alignment block.
+#[pyclass(name = "Block", module = "maf_stream")]
+pub struct PyBlock {
+    block: MAFBlock,
+}
+
+#[pymethods]
+impl PyBlock {
+    /// Parse a single block out of MAF text.
+    #[new]
+    fn new(text: &str) -> PyResult<Self> {
+        match next_maf_item(&mut text.as_bytes()) {
+            Ok(MAFItem::Block(block)) => Ok(PyBlock { block }),
+            Ok(MAFItem::Comment(_)) => Err(PyValueError::new_err("Expected a block")),
+            Err(e) => Err(to_py_error(e)),
+        }
+    }
+
+    #[getter]
+    fn metadata(&self) -> BTreeMap<String, String> {
+        self.block.metadata.clone()
+    }
+
+    /// The aligned rows, in order; the first is the reference.
+    #[getter]
+    fn rows(&self) -> Vec<PyRow> {
+        self.block
+            .aligned_entries()
+            .map(|entry| PyRow {
+                entry: entry.clone(),
+            })
+            .collect()
+    }
+
+    /// Map a positive-strand position on `seq` to the aligned
+    /// positive-strand position on every other row, as a list of
+    /// (seq, position) pairs, with None for rows with a gap there.
+    /// Returns an empty list if `seq` doesn't cover the position.
+    fn map_position(&self, seq: &str, pos: u64) -> Vec<(String, Option<u64>)> {
+        let column = self
+            .block
+            .aligned_entries()
+            .filter(|e| e.seq == seq)
+            .find_map(|e| {
+                e.column_positions()
+                    .position(|p| p.map(|p| e.forward_position(p)) == Some(pos))
+            });
+        let column = match column {
+            Some(column) => column,
+            None => return vec![],
+        };
+        self.block
+            .aligned_entries()
+            .filter(|e| e.seq != seq)
+            .map(|e| {
+                let mapped = e
+                    .column_positions()
+                    .nth(column)
+                    .flatten()
+                    .map(|p| e.forward_position(p));
+                (e.seq.clone(), mapped)
+            })
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.block
+            .aligned_entries()
+            .next()
+            .map_or(0, |e| e.alignment.len())
+    }
+
+    fn __str__(&self) -> String {
+        self.block.to_string()
+    }
+}
+
+/// Iterates over the blocks of a MAF file, skipping comments.
+#[pyclass(name = "Reader", module = "maf_stream")]
+pub struct PyReader {
+    input: Box<dyn BufRead + Send>,
+}
+
+#[pymethods]
+impl PyReader {
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        let file = File::open(path)?;
+        Ok(PyReader {
+            input: Box::new(BufReader::new(file)),
+        })
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<PyBlock>> {
+        loop {
+            match next_maf_item(&mut self.input) {
+                Ok(MAFItem::Block(block)) => return Ok(Some(PyBlock { block })),
+                Ok(MAFItem::Comment(_)) => continue,
+                Err(MAFParseError::EOF) => return Ok(None),
+                Err(e) => return Err(to_py_error(e)),
+            }
+        }
+    }
+}
+
+#[pymodule]
+fn maf_stream(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyReader>()?;
+    m.add_class::<PyBlock>()?;
+    m.add_class::<PyRow>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python_block() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let block = Bound::new(
+                py,
+                PyBlock::new(
+                    "a score=2
+s hg38.chr1 10 4 + 100 AC-GT
+s mm39.chr2 20 5 - 200 ACTGT
+",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            let locals = PyDict::new_bound(py);
+            locals.set_item("block", block).unwrap();
+            py.run_bound(
+                r#"
+assert len(block) == 5
+assert block.metadata == {"score": "2"}
+ref, mouse = block.rows
+assert (ref.seq, ref.start, ref.size, ref.strand, ref.src_size) == ("hg38.chr1", 10, 4, "+", 100)
+assert mouse.text == "ACTGT"
+assert ref.column_positions() == [10, 11, None, 12, 13]
+assert mouse.column_positions() == [179, 178, 177, 176, 175]
+assert mouse.column_of(176) == 3
+assert block.map_position("hg38.chr1", 12) == [("mm39.chr2", 176)]
+assert block.map_position("mm39.chr2", 177) == [("hg38.chr1", None)]
+assert block.map_position("hg38.chr1", 50) == []
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
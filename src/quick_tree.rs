@@ -0,0 +1,235 @@
+use crate::pairwise_identity::PairwiseIdentity;
+use maf_stream::tree::{Node, Tree};
+use maf_stream::{MAFItems, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::io::BufRead;
+
+/// A subtree built up while joining neighbors.
+struct Subtree {
+    name: Option<String>,
+    /// Children with the lengths of the branches to them.
+    children: Vec<(Subtree, f64)>,
+}
+
+impl Subtree {
+    fn add_to(self, tree: &mut Tree, parent: Option<usize>, branch_length: f64) {
+        let idx = tree.nodes.len();
+        tree.nodes.push(Node {
+            name: self.name,
+            // Round off floating-point noise so the Newick is readable.
+            branch_length: (branch_length.max(0.0) * 1e6).round() / 1e6,
+            parent,
+            children: vec![],
+        });
+        if let Some(parent) = parent {
+            tree.nodes[parent].children.push(idx);
+        }
+        for (child, length) in self.children {
+            child.add_to(tree, Some(idx), length);
+        }
+    }
+}
+
+/// Build a tree from a distance matrix by neighbor joining. The tree
+/// is unrooted, so it's written with a trifurcation at the root.
+/// Negative branch lengths are set to 0.
+pub fn neighbor_joining(names: &[String], mut dist: Vec<Vec<f64>>) -> Tree {
+    let mut subtrees: Vec<Subtree> = names
+        .iter()
+        .map(|name| Subtree {
+            name: Some(name.clone()),
+            children: vec![],
+        })
+        .collect();
+    while subtrees.len() > 3 {
+        let n = subtrees.len();
+        let row_sums: Vec<f64> = dist.iter().map(|row| row.iter().sum()).collect();
+        let mut best = (f64::INFINITY, 0, 0);
+        for i in 0..n {
+            for j in i + 1..n {
+                let q = (n - 2) as f64 * dist[i][j] - row_sums[i] - row_sums[j];
+                if q < best.0 {
+                    best = (q, i, j);
+                }
+            }
+        }
+        let (_, i, j) = best;
+        let length_i = dist[i][j] / 2.0 + (row_sums[i] - row_sums[j]) / (2.0 * (n - 2) as f64);
+        let length_j = dist[i][j] - length_i;
+        let new_dists: Vec<f64> = (0..n)
+            .map(|k| (dist[i][k] + dist[j][k] - dist[i][j]) / 2.0)
+            .collect();
+        // Remove j first, since it's after i.
+        let subtree_j = subtrees.remove(j);
+        let subtree_i = subtrees.remove(i);
+        for row in dist.iter_mut() {
+            row.remove(j);
+            row.remove(i);
+        }
+        dist.remove(j);
+        dist.remove(i);
+        let mut new_row: Vec<f64> = new_dists
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| *k != i && *k != j)
+            .map(|(_, d)| *d)
+            .collect();
+        for (row, d) in dist.iter_mut().zip(new_row.iter()) {
+            row.push(*d);
+        }
+        new_row.push(0.0);
+        dist.push(new_row);
+        subtrees.push(Subtree {
+            name: None,
+            children: vec![(subtree_i, length_i), (subtree_j, length_j)],
+        });
+    }
+    let lengths: Vec<f64> = match subtrees.len() {
+        3 => (0..3)
+            .map(|a| {
+                let (b, c) = ((a + 1) % 3, (a + 2) % 3);
+                (dist[a][b] + dist[a][c] - dist[b][c]) / 2.0
+            })
+            .collect(),
+        2 => vec![dist[0][1] / 2.0; 2],
+        _ => vec![0.0; subtrees.len()],
+    };
+    let mut tree = Tree { nodes: vec![] };
+    let root = if subtrees.len() == 1 {
+        subtrees.pop().unwrap()
+    } else {
+        Subtree {
+            name: None,
+            children: subtrees.into_iter().zip(lengths).collect(),
+        }
+    };
+    root.add_to(&mut tree, None, 0.0);
+    tree
+}
+
+/// The distance given to pairs too divergent for a Jukes-Cantor
+/// distance, or never aligned to each other: far enough to keep them
+/// apart, but finite so the tree can still be built.
+const MAX_DISTANCE: f64 = 10.0;
+
+/// Jukes-Cantor corrected distance for a fraction of identical bases,
+/// saturating at `MAX_DISTANCE`.
+fn jukes_cantor(identity: f64) -> f64 {
+    let p = 1.0 - identity;
+    if p >= 0.75 {
+        return MAX_DISTANCE;
+    }
+    (-0.75 * (1.0 - 4.0 / 3.0 * p).ln()).min(MAX_DISTANCE)
+}
+
+/// Write a neighbor-joining tree, in Newick format, of the
+/// Jukes-Cantor distances between species calculated from their
+/// pairwise identity across the whole alignment.
+pub fn quick_tree(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+) -> Result<(), MAFParseError> {
+    let mut identity = PairwiseIdentity::default();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            identity.add_block(&block, None);
+        }
+    }
+    let order = identity.sorted_species();
+    let names: Vec<String> = order.iter().map(|i| identity.species[*i].clone()).collect();
+    let dist = order
+        .iter()
+        .map(|i| {
+            order
+                .iter()
+                .map(|j| match identity.identity(*i, *j) {
+                    Some(value) => jukes_cantor(value),
+                    None => {
+                        if i < j {
+                            output.warn(&format!(
+                                "{} and {} are never aligned to each other",
+                                identity.species[*i], identity.species[*j]
+                            ));
+                        }
+                        MAX_DISTANCE
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    writeln!(output, "{}", neighbor_joining(&names, dist).to_newick()).ok();
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbor_joining() {
+        // The example from Saitou and Nei (1987), as used on Wikipedia:
+        // the distances are additive, so the tree is recovered exactly.
+        let names: Vec<String> = "abcde".chars().map(|c| c.to_string()).collect();
+        let dist = vec![
+            vec![0.0, 5.0, 9.0, 9.0, 8.0],
+            vec![5.0, 0.0, 10.0, 10.0, 9.0],
+            vec![9.0, 10.0, 0.0, 8.0, 7.0],
+            vec![9.0, 10.0, 8.0, 0.0, 3.0],
+            vec![8.0, 9.0, 7.0, 3.0, 0.0],
+        ];
+        assert_eq!(
+            neighbor_joining(&names, dist).to_newick(),
+            "(d:2,e:1,(c:4,(a:2,b:3):3):2);"
+        );
+    }
+
+    #[test]
+    fn test_quick_tree() {
+        let maf = "a
+s hg38.chr1 0 8 + 100 ACGTACGT
+s panTro6.chr1 0 8 + 100 ACGTACGA
+s mm39.chr2 0 8 + 100 ACGAACTA
+s rn7.chr3 0 8 + 100 TCGAACTA
+";
+        let mut output = vec![];
+        quick_tree(&mut maf.as_bytes(), &mut output).unwrap();
+        let tree = Tree::parse_newick(&String::from_utf8(output).unwrap());
+        // Human and chimp should be split from mouse and rat.
+        let leaves = tree.leaves();
+        assert_eq!(
+            tree.nodes[leaves["mm39"]].parent,
+            tree.nodes[leaves["rn7"]].parent
+        );
+        assert_ne!(
+            tree.nodes[leaves["mm39"]].parent,
+            tree.nodes[leaves["hg38"]].parent
+        );
+    }
+
+    #[test]
+    fn test_jukes_cantor_saturates() {
+        assert_eq!(jukes_cantor(1.0), 0.0);
+        assert!(jukes_cantor(0.5) < MAX_DISTANCE);
+        assert_eq!(jukes_cantor(0.25), MAX_DISTANCE);
+        assert_eq!(jukes_cantor(0.0), MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_quick_tree_unaligned_pair() {
+        // galGal6 is never aligned to mm39, and shares nothing with hg38.
+        let maf = "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 0 4 + 100 ACGA
+
+a
+s hg38.chr1 4 4 + 100 ACGT
+s galGal6.chr3 0 4 + 100 TGCA
+";
+        let mut output = vec![];
+        quick_tree(&mut maf.as_bytes(), &mut output).unwrap();
+        let tree = Tree::parse_newick(&String::from_utf8(output).unwrap());
+        assert_eq!(tree.leaves().len(), 3);
+    }
+}
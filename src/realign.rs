@@ -0,0 +1,223 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{block_identity, for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
+use tempfile::tempfile;
+
+/// Parse FASTA into (name, sequence) pairs.
+pub fn parse_fasta(fasta: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut records: Vec<(String, Vec<u8>)> = vec![];
+    for line in fasta.split(|c| *c == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if let Some(name) = line.strip_prefix(b">") {
+            let name = String::from_utf8_lossy(name);
+            let name = name.split_whitespace().next().unwrap_or("");
+            records.push((name.to_string(), vec![]));
+        } else if let Some((_, seq)) = records.last_mut() {
+            seq.extend(line.iter().filter(|c| !c.is_ascii_whitespace()));
+        }
+    }
+    records
+}
+
+/// Run `command` through `sh -c` with `stdin` as its input, returning
+/// its output.
+pub fn run_command(command: &str, mut stdin: File) -> Result<Vec<u8>, String> {
+    stdin.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::from(stdin))
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Couldn't run {}: {}", command, e))?;
+    if !result.status.success() {
+        return Err(format!("{} failed with {}", command, result.status));
+    }
+    Ok(result.stdout)
+}
+
+/// Put a row's original bases (and their qualities) in between the gaps
+/// of `gapped`, which must have the same bases, ignoring case. Returns
+/// the new alignment and qualities, or None if the bases differ.
+pub fn regap(entry: &MAFBlockAlignedEntry, gapped: &[u8]) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    let bases: Vec<(usize, u8)> = entry
+        .alignment
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !is_gap(**c))
+        .map(|(column, c)| (column, *c))
+        .collect();
+    let mut bases_iter = bases.iter();
+    let mut alignment = Vec::with_capacity(gapped.len());
+    let mut qualities = entry
+        .qualities
+        .as_ref()
+        .map(|_| Vec::with_capacity(gapped.len()));
+    for c in gapped.iter() {
+        if is_gap(*c) {
+            alignment.push(b'-');
+            if let Some(qualities) = qualities.as_mut() {
+                qualities.push(b'-');
+            }
+            continue;
+        }
+        match bases_iter.next() {
+            Some((column, base)) if base.eq_ignore_ascii_case(c) => {
+                alignment.push(*base);
+                if let Some(qualities) = qualities.as_mut() {
+                    qualities.push(entry.qualities.as_ref().unwrap()[*column]);
+                }
+            }
+            _ => return None,
+        }
+    }
+    if bases_iter.next().is_some() {
+        return None;
+    }
+    Some((alignment, qualities))
+}
+
+/// Realign the rows of a block with an external aligner, run through
+/// `sh -c`, which is given the ungapped rows as FASTA on stdin (named
+/// by their index in the block) and should write the aligned FASTA to
+/// stdout. Only the aligner's gap placement is used: the original bases
+/// (and their case and qualities) are put back in between the gaps, so
+/// aligners that change case don't matter. Rows with no bases become
+/// all gaps. Fails, leaving the block alone, if the aligner fails or
+/// gives back different sequences.
+pub fn realign_block(block: &mut MAFBlock, command: &str) -> Result<(), String> {
+    let mut fasta = tempfile().map_err(|e| e.to_string())?;
+    let mut num_seqs = 0;
+    for (i, entry) in block.aligned_entries().enumerate() {
+        if entry.aligned_length == 0 {
+            continue;
+        }
+        writeln!(fasta, ">{}", i).map_err(|e| e.to_string())?;
+        fasta
+            .write_all(
+                &entry
+                    .alignment
+                    .iter()
+                    .copied()
+                    .filter(|c| !is_gap(*c))
+                    .collect::<Vec<u8>>(),
+            )
+            .map_err(|e| e.to_string())?;
+        writeln!(fasta).map_err(|e| e.to_string())?;
+        num_seqs += 1;
+    }
+    if num_seqs < 2 {
+        return Ok(());
+    }
+    let mut aligned: HashMap<String, Vec<u8>> = parse_fasta(&run_command(command, fasta)?)
+        .into_iter()
+        .collect();
+    let width = match aligned.values().next() {
+        Some(seq) => seq.len(),
+        None => return Err("Aligner gave no sequences".to_string()),
+    };
+    let mut rows = vec![];
+    for (i, entry) in block.aligned_entries().enumerate() {
+        if entry.aligned_length == 0 {
+            rows.push((
+                vec![b'-'; width],
+                entry.qualities.as_ref().map(|_| vec![b'-'; width]),
+            ));
+            continue;
+        }
+        let gapped = aligned
+            .remove(&i.to_string())
+            .ok_or_else(|| format!("Aligner lost sequence {}", i))?;
+        if gapped.len() != width {
+            return Err("Aligner gave sequences of different lengths".to_string());
+        }
+        rows.push(regap(entry, &gapped).ok_or_else(|| format!("Aligner changed sequence {}", i))?);
+    }
+    let mut rows = rows.into_iter();
+    for entry in block.entries.iter_mut() {
+        if let MAFBlockEntry::AlignedEntry(entry) = entry {
+            let (alignment, qualities) = rows.next().unwrap();
+            entry.alignment = alignment;
+            entry.qualities = qualities;
+        }
+    }
+    Ok(())
+}
+
+/// Realign the blocks, or only those where the reference row's identity
+/// to the rest of the block is below `below_identity`, with
+/// `realign_block`. Blocks the aligner fails on are written unchanged,
+/// with a warning. Returns the numbers of blocks realigned and failed.
+pub fn realign(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    command: &str,
+    below_identity: Option<f64>,
+) -> Result<(usize, usize), MAFParseError> {
+    let mut num_realigned = 0;
+    let mut num_failed = 0;
+    for_each_block(input, output, |mut block, output| {
+        let needs_realignment = match (below_identity, block.aligned_entries().next()) {
+            (None, _) => true,
+            (Some(threshold), Some(ref_entry)) => {
+                block_identity(&block, ref_entry).is_some_and(|identity| identity < threshold)
+            }
+            (Some(_), None) => false,
+        };
+        if needs_realignment {
+            match realign_block(&mut block, command) {
+                Ok(()) => num_realigned += 1,
+                Err(e) => {
+                    output.warn(&format!("Couldn't realign block: {}", e));
+                    output.record_skipped(1);
+                    num_failed += 1;
+                }
+            }
+        }
+        output.write_block(&block);
+    })?;
+    Ok((num_realigned, num_failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_realign() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 AC--GT
+s mm39.chr2 0 3 + 100 a-cG--
+s rn7.chr3 0 0 + 100 ------
+";
+        // A stand-in aligner that right-aligns every sequence in 5
+        // columns, in upper case.
+        let command = "awk '/^>/ { print; next } { printf \"%5s\\n\", toupper($0) }' | tr ' ' -";
+        let mut output = vec![];
+        let counts = realign(&mut maf.as_bytes(), &mut output, command, None).unwrap();
+        assert_eq!(counts, (1, 0));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 10 4 + 100 -ACGT
+s mm39.chr2 0 3 + 100 --acG
+s rn7.chr3 0 0 + 100 -----
+
+"
+        );
+        // An aligner that drops a base is refused.
+        let mut output = vec![];
+        let counts = realign(&mut maf.as_bytes(), &mut output, "sed 's/C//'", None).unwrap();
+        assert_eq!(counts, (0, 1));
+        assert!(String::from_utf8(output).unwrap().contains("AC--GT"));
+        // Identical enough blocks are left alone.
+        let mut output = vec![];
+        let counts = realign(&mut maf.as_bytes(), &mut output, "false", Some(0.5)).unwrap();
+        assert_eq!(counts, (0, 0));
+    }
+}
@@ -0,0 +1,52 @@
+use maf_stream::transform::{transform_blocks, Normalize};
+use maf_stream::MAFOutput;
+use multiple_alignment_format::parser::MAFParseError;
+use std::io::BufRead;
+
+/// Normalize the case of the alignment with `Normalize`. Returns the
+/// number of bases soft-masked.
+pub fn recase(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    normalize: &mut Normalize,
+) -> Result<usize, MAFParseError> {
+    transform_blocks(input, output, normalize)?;
+    Ok(normalize.num_masked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::{merge_ranges, parse_bed};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_recase() {
+        let maf = "a
+s hg38.chr1 10 5 + 100 acGTa
+s mm39.chr2 5 4 - 20 AC-GT
+";
+        let mut normalize = Normalize {
+            upper: true,
+            repeats: HashMap::new(),
+            num_masked: 0,
+        };
+        // Forward positions 12-14 of mm39's chr2 are the first three
+        // bases of its negative-strand row.
+        let bed = "chr2\t12\t14\nchr2\t13\t15\nchr1\t0\t100\n";
+        normalize
+            .repeats
+            .insert("mm39".to_string(), merge_ranges(parse_bed(bed.as_bytes())));
+        let mut output = vec![];
+        let num_masked = recase(&mut maf.as_bytes(), &mut output, &mut normalize).unwrap();
+        assert_eq!(num_masked, 3);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 10 5 + 100 ACGTA
+s mm39.chr2 5 4 - 20 ac-gT
+
+"
+        );
+    }
+}
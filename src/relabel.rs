@@ -0,0 +1,263 @@
+use maf_stream::{chrom_part, for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlockEntry, Strand};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+/// Where (part of) a component sequence is placed in an object
+/// sequence, e.g. a scaffold in a chromosome.
+#[derive(Debug, PartialEq)]
+pub struct Placement {
+    pub object: String,
+    /// Start of the placement in the object (0-based).
+    pub object_start: u64,
+    pub object_size: u64,
+    /// The placed part of the component (0-based, half-open).
+    pub component_start: u64,
+    pub component_end: u64,
+    /// Whether the component is reverse-complemented in the object.
+    pub reversed: bool,
+}
+
+impl Placement {
+    /// Map a forward-strand interval of the component to a
+    /// forward-strand start in the object.
+    fn object_position(&self, start: u64, end: u64) -> u64 {
+        if self.reversed {
+            self.object_start + (self.component_end - end)
+        } else {
+            self.object_start + (start - self.component_start)
+        }
+    }
+}
+
+fn split_fields(line: &str) -> Option<Vec<&str>> {
+    if line.starts_with('#') || line.trim().is_empty() {
+        None
+    } else {
+        Some(line.split_whitespace().collect())
+    }
+}
+
+/// Parse the component lines of an AGP file into placements, keyed by
+/// component name. Gap lines are skipped.
+pub fn parse_agp(agp: impl BufRead) -> HashMap<String, Vec<Placement>> {
+    let mut components = vec![];
+    let mut object_sizes: HashMap<String, u64> = HashMap::new();
+    for line_res in agp.lines() {
+        let line = line_res.expect("Can't read line");
+        let fields = match split_fields(&line) {
+            Some(fields) => fields,
+            None => continue,
+        };
+        if fields.len() < 9 {
+            panic!("AGP line has too few fields: {}", line);
+        }
+        let object_end: u64 = fields[2].parse().expect("Can't parse object end");
+        let size = object_sizes.entry(fields[0].to_string()).or_insert(0);
+        *size = (*size).max(object_end);
+        if fields[4] == "N" || fields[4] == "U" {
+            continue;
+        }
+        let object_start: u64 = fields[1].parse().expect("Can't parse object start");
+        let component_start: u64 = fields[6].parse().expect("Can't parse component start");
+        let component_end: u64 = fields[7].parse().expect("Can't parse component end");
+        components.push((
+            fields[5].to_string(),
+            Placement {
+                object: fields[0].to_string(),
+                object_start: object_start - 1,
+                object_size: 0,
+                component_start: component_start - 1,
+                component_end,
+                reversed: fields[8] == "-",
+            },
+        ));
+    }
+    let mut placements: HashMap<String, Vec<Placement>> = HashMap::new();
+    for (component, mut placement) in components {
+        placement.object_size = object_sizes[&placement.object];
+        placements.entry(component).or_default().push(placement);
+    }
+    placements
+}
+
+/// Parse an offset table with the columns: old sequence, new sequence,
+/// offset of the old sequence within the new one, and size of the new
+/// sequence.
+pub fn parse_offsets(offsets: impl BufRead) -> HashMap<String, Vec<Placement>> {
+    let mut placements: HashMap<String, Vec<Placement>> = HashMap::new();
+    for line_res in offsets.lines() {
+        let line = line_res.expect("Can't read line");
+        let fields = match split_fields(&line) {
+            Some(fields) => fields,
+            None => continue,
+        };
+        if fields.len() < 4 {
+            panic!("Offset table line has too few fields: {}", line);
+        }
+        placements
+            .entry(fields[0].to_string())
+            .or_default()
+            .push(Placement {
+                object: fields[1].to_string(),
+                object_start: fields[2].parse().expect("Can't parse offset"),
+                object_size: fields[3].parse().expect("Can't parse sequence size"),
+                component_start: 0,
+                component_end: u64::MAX,
+                reversed: false,
+            });
+    }
+    placements
+}
+
+/// Relabel a row in place. Rows that aren't fully inside a single
+/// placement are left alone.
+fn relabel(
+    seq: &mut String,
+    start: &mut u64,
+    size: u64,
+    strand: &mut Strand,
+    sequence_size: &mut u64,
+    placements: &HashMap<String, Vec<Placement>>,
+) {
+    let genome = seq.split('.').next().unwrap().to_string();
+    let candidates = match placements.get(&chrom_part(seq)) {
+        Some(candidates) => candidates,
+        None => return,
+    };
+    let forward_start = match strand {
+        Strand::Positive => *start,
+        Strand::Negative => *sequence_size - *start - size,
+    };
+    let forward_end = forward_start + size;
+    let placement = match candidates
+        .iter()
+        .find(|p| p.component_start <= forward_start && forward_end <= p.component_end)
+    {
+        Some(placement) => placement,
+        None => return,
+    };
+    let object_start = placement.object_position(forward_start, forward_end);
+    let new_strand = match (*strand, placement.reversed) {
+        (s, false) => s,
+        (Strand::Positive, true) => Strand::Negative,
+        (Strand::Negative, true) => Strand::Positive,
+    };
+    *start = match new_strand {
+        Strand::Positive => object_start,
+        Strand::Negative => placement.object_size - object_start - size,
+    };
+    *strand = new_strand;
+    *sequence_size = placement.object_size;
+    *seq = format!("{}.{}", genome, placement.object);
+}
+
+/// Rename and shift the rows of the given genomes into the coordinates
+/// of the sequences they're placed in.
+pub fn relabel_coords(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    placements: &HashMap<String, Vec<Placement>>,
+    genomes: &HashSet<String>,
+) -> Result<(), MAFParseError> {
+    for_each_block(input, output, |mut block, output| {
+        for entry in block.entries.iter_mut() {
+            match entry {
+                MAFBlockEntry::AlignedEntry(e) => {
+                    if genomes.contains(e.seq.split('.').next().unwrap()) {
+                        relabel(
+                            &mut e.seq,
+                            &mut e.start,
+                            e.aligned_length,
+                            &mut e.strand,
+                            &mut e.sequence_size,
+                            placements,
+                        );
+                    }
+                }
+                MAFBlockEntry::UnalignedEntry(e) => {
+                    if genomes.contains(e.seq.split('.').next().unwrap()) {
+                        relabel(
+                            &mut e.seq,
+                            &mut e.start,
+                            e.size,
+                            &mut e.strand,
+                            &mut e.sequence_size,
+                            placements,
+                        );
+                    }
+                }
+            }
+        }
+        output.write_block(&block);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relabel_coords_agp() {
+        let maf = "a
+s hg38.chr1 0 4 + 100 ACGT
+s galGal6.scaf1 10 4 + 50 ACGT
+s galGal6.scaf2 2 4 - 20 ACGT
+e galGal6.scaf3 2 4 + 20 I
+";
+        let agp = "##agp-version 2.0
+chr5\t1\t50\t1\tW\tscaf1\t1\t50\t+
+chr5\t51\t150\t2\tN\t100\tscaffold\tyes\tna
+chr5\t151\t170\t3\tW\tscaf2\t1\t20\t-
+";
+        let mut genomes = HashSet::new();
+        genomes.insert("galGal6".to_string());
+        let mut output = vec![];
+        relabel_coords(
+            &mut maf.as_bytes(),
+            &mut output,
+            &parse_agp(agp.as_bytes()),
+            &genomes,
+        )
+        .unwrap();
+        // scaf2 - strand 2..6 is forward 14..18, which is forward
+        // 150 + (20 - 18) = 152 in chr5 on the other strand.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 4 + 100 ACGT
+s galGal6.chr5 10 4 + 170 ACGT
+s galGal6.chr5 152 4 + 170 ACGT
+e galGal6.scaf3 2 4 + 20 I
+
+"
+        );
+    }
+
+    #[test]
+    fn test_relabel_coords_offsets() {
+        let maf = "a
+s galGal6.scaf1 10 4 - 50 ACGT
+";
+        let offsets = "scaf1\tchr1\t1000\t2000\n";
+        let mut genomes = HashSet::new();
+        genomes.insert("galGal6".to_string());
+        let mut output = vec![];
+        relabel_coords(
+            &mut maf.as_bytes(),
+            &mut output,
+            &parse_offsets(offsets.as_bytes()),
+            &genomes,
+        )
+        .unwrap();
+        // Forward 36..40 -> 1036..1040 -> reverse start 2000 - 1040.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s galGal6.chr1 960 4 - 2000 ACGT
+
+"
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Reading input MAFs from http(s):// and s3:// URLs.
+use std::cmp::min;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Size of each range request made by `RemoteFile`.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+pub fn is_url(path: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+/// Translate an s3://bucket/key URL into the bucket's public https
+/// endpoint. Requests aren't signed, so the object has to be publicly
+/// readable. Other URLs are returned unchanged.
+pub fn http_url(url: &str) -> String {
+    match url.strip_prefix("s3://") {
+        Some(path) => {
+            let (bucket, key) = path.split_at(path.find('/').unwrap_or(path.len()));
+            format!("https://{}.s3.amazonaws.com{}", bucket, key)
+        }
+        None => url.to_string(),
+    }
+}
+
+fn to_io_error(err: ureq::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+/// Streams a whole remote object. The request isn't made until the
+/// first read, so subcommands that don't read their input stream never
+/// start a download.
+pub struct UrlReader {
+    url: String,
+    response: Option<Box<dyn Read + Send + Sync>>,
+}
+
+impl UrlReader {
+    pub fn new(url: &str) -> UrlReader {
+        UrlReader {
+            url: http_url(url),
+            response: None,
+        }
+    }
+}
+
+impl Read for UrlReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            let response = ureq::get(&self.url).call().map_err(to_io_error)?;
+            self.response = Some(response.into_reader());
+        }
+        self.response.as_mut().unwrap().read(buf)
+    }
+}
+
+/// Random access to a remote object using HTTP range requests, so that
+/// only the parts that are actually read are downloaded. The most
+/// recently fetched chunk is cached.
+pub struct RemoteFile {
+    url: String,
+    len: u64,
+    version: Option<String>,
+    pos: u64,
+    chunk_start: u64,
+    chunk: Vec<u8>,
+}
+
+impl RemoteFile {
+    pub fn open(url: &str) -> io::Result<RemoteFile> {
+        let url = http_url(url);
+        let response = ureq::head(&url).call().map_err(to_io_error)?;
+        let len = response
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| io::Error::other("Server didn't report a length"))?;
+        let version = response
+            .header("ETag")
+            .or_else(|| response.header("Last-Modified"))
+            .map(|version| version.to_string());
+        Ok(RemoteFile {
+            url,
+            len,
+            version,
+            pos: 0,
+            chunk_start: 0,
+            chunk: vec![],
+        })
+    }
+
+    /// The object's ETag, or else its modification time, if the server
+    /// gave either.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    fn fetch_chunk(&mut self) -> io::Result<()> {
+        let end = min(self.pos + CHUNK_SIZE, self.len) - 1;
+        let response = ureq::get(&self.url)
+            .set("Range", &format!("bytes={}-{}", self.pos, end))
+            .call()
+            .map_err(to_io_error)?;
+        if response.status() != 206 {
+            return Err(io::Error::other("Server doesn't support range requests"));
+        }
+        self.chunk.clear();
+        response.into_reader().read_to_end(&mut self.chunk)?;
+        self.chunk_start = self.pos;
+        Ok(())
+    }
+}
+
+impl Read for RemoteFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk_end = self.chunk_start + self.chunk.len() as u64;
+        if self.pos < self.chunk_start || self.pos >= chunk_end {
+            self.fetch_chunk()?;
+        }
+        let offset = (self.pos - self.chunk_start) as usize;
+        let n = min(buf.len(), self.chunk.len() - offset);
+        buf[..n].copy_from_slice(&self.chunk[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RemoteFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve `body` over HTTP on a local port, honoring single range
+    /// requests, for `num_requests` requests.
+    fn serve(body: &'static [u8], num_requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/test.maf", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(num_requests) {
+                let mut stream = stream.unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut lines = vec![];
+                loop {
+                    let mut line = String::new();
+                    request.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    lines.push(line.trim().to_string());
+                }
+                let range = lines
+                    .iter()
+                    .find_map(|l| l.strip_prefix("range: ").or(l.strip_prefix("Range: ")))
+                    .map(|r| {
+                        let (start, end) = r["bytes=".len()..].split_once('-').unwrap();
+                        (
+                            start.parse::<usize>().unwrap(),
+                            end.parse::<usize>().unwrap(),
+                        )
+                    });
+                let (status, content) = match range {
+                    Some((start, end)) => ("206 Partial Content", &body[start..=end]),
+                    None => ("200 OK", body),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    content.len()
+                )
+                .unwrap();
+                if !lines[0].starts_with("HEAD") {
+                    stream.write_all(content).unwrap();
+                }
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_http_url() {
+        assert!(is_url("s3://bucket/a.maf"));
+        assert!(!is_url("a.maf"));
+        assert_eq!(
+            http_url("s3://bucket/dir/a.maf"),
+            "https://bucket.s3.amazonaws.com/dir/a.maf"
+        );
+        assert_eq!(http_url("https://host/a.maf"), "https://host/a.maf");
+    }
+
+    #[test]
+    fn test_remote_file() {
+        let body = b"##maf version=1\na\ns hg38.chr1 0 4 + 100 ACGT\n\n";
+        let mut streamed = String::new();
+        UrlReader::new(&serve(body, 1))
+            .read_to_string(&mut streamed)
+            .unwrap();
+        assert_eq!(streamed.as_bytes(), &body[..]);
+
+        // HEAD, then one range request per seek outside the cached chunk.
+        let mut file = RemoteFile::open(&serve(body, 3)).unwrap();
+        file.seek(SeekFrom::Start(16)).unwrap();
+        let mut line = String::new();
+        BufReader::new(&mut file).read_line(&mut line).unwrap();
+        assert_eq!(line, "a\n");
+        file.seek(SeekFrom::End(-4)).unwrap();
+        let mut end = String::new();
+        file.read_to_string(&mut end).unwrap();
+        assert_eq!(end, "GT\n\n");
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut header = [0; 6];
+        file.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"##maf ");
+    }
+}
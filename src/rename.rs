@@ -0,0 +1,136 @@
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFBlockEntry;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Prefix marking a rule's old name as a regex rather than a literal
+/// name.
+pub const REGEX_PREFIX: &str = "re:";
+
+/// Sequence-name mapping. Old names are matched literally, unless
+/// marked with `REGEX_PREFIX`: a name exactly equal to a literal rule's
+/// old name is always renamed by it; otherwise the regex rules are
+/// tried in order, and must match the whole name, with `$1`-style
+/// capture groups allowed in the replacement. Names matching no rule
+/// are left alone.
+pub struct Renamer {
+    literal: HashMap<String, String>,
+    patterns: Vec<(Regex, String)>,
+    /// Names already looked up, since there are normally few distinct
+    /// sequences compared to the number of rows.
+    cache: HashMap<String, String>,
+}
+
+impl Renamer {
+    pub fn new(rules: Vec<(String, String)>) -> Self {
+        let mut literal = HashMap::new();
+        let mut patterns = vec![];
+        for (old, new) in rules {
+            match old.strip_prefix(REGEX_PREFIX) {
+                Some(pattern) => {
+                    let regex = Regex::new(&format!("^(?:{})$", pattern))
+                        .unwrap_or_else(|e| panic!("Invalid pattern {}: {}", pattern, e));
+                    patterns.push((regex, new));
+                }
+                None => {
+                    literal.entry(old).or_insert(new);
+                }
+            }
+        }
+        Renamer {
+            literal,
+            patterns,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn rename(&mut self, name: &str) -> String {
+        if let Some(new) = self.cache.get(name) {
+            return new.clone();
+        }
+        let new = match self.literal.get(name) {
+            Some(new) => new.clone(),
+            None => self
+                .patterns
+                .iter()
+                .find(|(regex, _)| regex.is_match(name))
+                .map(|(regex, new)| regex.replace(name, new.as_str()).into_owned())
+                .unwrap_or_else(|| name.to_string()),
+        };
+        self.cache.insert(name.to_string(), new.clone());
+        new
+    }
+}
+
+/// Parse a two-column (whitespace-separated) mapping file of old name
+/// (or `REGEX_PREFIX` and a pattern) to new name. Blank lines and lines starting with '#' are
+/// ignored.
+pub fn parse_mapping(mapping: impl BufRead) -> Vec<(String, String)> {
+    let mut rules = vec![];
+    for line_res in mapping.lines() {
+        let line = line_res.expect("Can't read line");
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            panic!("Mapping line doesn't have exactly two fields: {}", line);
+        }
+        rules.push((fields[0].to_string(), fields[1].to_string()));
+    }
+    rules
+}
+
+pub fn rename(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    renamer: &mut Renamer,
+) -> Result<(), MAFParseError> {
+    for_each_block(input, output, |mut block, output| {
+        for entry in block.entries.iter_mut() {
+            let seq = match entry {
+                MAFBlockEntry::AlignedEntry(e) => &mut e.seq,
+                MAFBlockEntry::UnalignedEntry(e) => &mut e.seq,
+            };
+            *seq = renamer.rename(seq);
+        }
+        output.write_block(&block);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename() {
+        let maf = "a
+s GCA_000002315.5.chr1 0 4 + 100 ACGT
+i GCA_000002315.5.chr1 N 0 C 0
+s mm39.chr2 10 4 + 200 ACGT
+e rn7.chr3 10 4 + 200 I
+s rn7xchr3 0 4 + 200 ACGT
+";
+        // The literal rn7.chr3 doesn't match rn7xchr3 as a regex would.
+        let mapping = "# Cactus to UCSC names
+re:GCA_000002315\\.5\\.(.*) galGal6.$1
+rn7.chr3 rn7.chrX
+";
+        let mut renamer = Renamer::new(parse_mapping(mapping.as_bytes()));
+        let mut output = vec![];
+        rename(&mut maf.as_bytes(), &mut output, &mut renamer).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s galGal6.chr1 0 4 + 100 ACGT
+i galGal6.chr1 N 0 C 0
+s mm39.chr2 10 4 + 200 ACGT
+e rn7.chrX 10 4 + 200 I
+s rn7xchr3 0 4 + 200 ACGT
+
+"
+        );
+    }
+}
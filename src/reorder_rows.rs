@@ -0,0 +1,138 @@
+use maf_stream::tree::Tree;
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// The order to put rows in, after the reference.
+pub enum RowOrder {
+    /// By species, in this order; species that aren't listed go last.
+    Species(HashMap<String, usize>),
+    /// By sequence name.
+    Alphabetical,
+}
+
+impl RowOrder {
+    pub fn from_species_list<'a>(species: impl Iterator<Item = &'a str>) -> RowOrder {
+        let mut ranks = HashMap::new();
+        for s in species {
+            let rank = ranks.len();
+            ranks.entry(s.to_string()).or_insert(rank);
+        }
+        RowOrder::Species(ranks)
+    }
+
+    /// Species in the order their leaves appear in the tree.
+    pub fn from_tree(tree: &Tree) -> RowOrder {
+        let mut leaves: Vec<_> = tree.leaves().into_iter().collect();
+        leaves.sort_by_key(|(_, node)| *node);
+        RowOrder::from_species_list(leaves.iter().map(|(name, _)| name.as_str()))
+    }
+
+    fn sort_key(&self, seq: &str) -> (usize, String) {
+        match self {
+            RowOrder::Species(ranks) => {
+                let species = seq.split('.').next().unwrap();
+                (*ranks.get(species).unwrap_or(&ranks.len()), String::new())
+            }
+            RowOrder::Alphabetical => (0, seq.to_string()),
+        }
+    }
+}
+
+fn entry_seq(entry: &MAFBlockEntry) -> &str {
+    match entry {
+        MAFBlockEntry::AlignedEntry(e) => &e.seq,
+        MAFBlockEntry::UnalignedEntry(e) => &e.seq,
+    }
+}
+
+/// Reorder the rows of a block, keeping the reference row first. Rows
+/// that sort equally keep their original order.
+fn reorder_block(block: &mut MAFBlock, order: &RowOrder) {
+    let ref_idx = block
+        .entries
+        .iter()
+        .position(|e| matches!(e, MAFBlockEntry::AlignedEntry(_)));
+    let mut rest = std::mem::take(&mut block.entries);
+    let ref_entry = ref_idx.map(|i| rest.remove(i));
+    rest.sort_by_cached_key(|e| order.sort_key(entry_seq(e)));
+    block.entries = ref_entry.into_iter().chain(rest).collect();
+}
+
+pub fn reorder_rows(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    order: &RowOrder,
+) -> Result<(), MAFParseError> {
+    for_each_block(input, output, |mut block, output| {
+        reorder_block(&mut block, order);
+        output.write_block(&block);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s rn7.chr3 10 2 + 200 AC
+s mm39.chr2 10 2 + 200 AC
+e panTro6.chr1 10 2 + 200 I
+s hg38.chr1 10 2 + 100 AC
+s bosTau9.chr4 10 2 + 200 AC
+s hg38.chr5 10 2 + 100 AC
+";
+
+    fn seqs(order: &RowOrder) -> Vec<String> {
+        let mut output = vec![];
+        reorder_rows(&mut MAF.as_bytes(), &mut output, order).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .filter_map(|l| l.split(' ').nth(1).map(|s| s.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_reorder_rows() {
+        assert_eq!(
+            seqs(&RowOrder::from_species_list(
+                vec!["hg38", "panTro6", "mm39"].into_iter()
+            )),
+            vec![
+                "rn7.chr3",
+                "hg38.chr1",
+                "hg38.chr5",
+                "panTro6.chr1",
+                "mm39.chr2",
+                "bosTau9.chr4"
+            ]
+        );
+        assert_eq!(
+            seqs(&RowOrder::Alphabetical),
+            vec![
+                "rn7.chr3",
+                "bosTau9.chr4",
+                "hg38.chr1",
+                "hg38.chr5",
+                "mm39.chr2",
+                "panTro6.chr1"
+            ]
+        );
+        let tree = Tree::parse_newick("((bosTau9,(mm39,rn7)),(hg38,panTro6));");
+        assert_eq!(
+            seqs(&RowOrder::from_tree(&tree)),
+            vec![
+                "rn7.chr3",
+                "bosTau9.chr4",
+                "mm39.chr2",
+                "hg38.chr1",
+                "hg38.chr5",
+                "panTro6.chr1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::from_fasta::{build_block, coordinate_name, Coordinates};
+use crate::realign::{parse_fasta, regap, run_command};
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use tempfile::tempfile;
+
+/// Send a block through `command`, run with `sh -c`, as aligned FASTA
+/// with each row named by its source coordinates (as `from_fasta`
+/// reads them), and import the aligned FASTA it writes. Rows are
+/// matched up by name, so the command may reorder them, and each row's
+/// length is counted again from its bases. The command may move gaps
+/// and change case, but the ungapped sequences must be unchanged; the
+/// original bases (and their case and qualities) are kept. Rows with no
+/// bases aren't sent, and become all gaps. Fails, leaving the block
+/// alone, if the command fails, loses or adds rows, or changes a
+/// sequence.
+pub fn roundtrip_block(block: &mut MAFBlock, command: &str) -> Result<(), String> {
+    let mut fasta = tempfile().map_err(|e| e.to_string())?;
+    let mut names: Vec<Option<String>> = vec![];
+    let mut sizes = HashMap::new();
+    for entry in block.aligned_entries() {
+        if entry.aligned_length == 0 {
+            names.push(None);
+            continue;
+        }
+        let name = coordinate_name(entry);
+        if names.contains(&Some(name.clone())) {
+            return Err(format!("More than one row is {}", name));
+        }
+        writeln!(fasta, ">{}", name).map_err(|e| e.to_string())?;
+        fasta
+            .write_all(&entry.alignment)
+            .map_err(|e| e.to_string())?;
+        writeln!(fasta).map_err(|e| e.to_string())?;
+        sizes.insert(entry.seq.clone(), entry.sequence_size);
+        names.push(Some(name));
+    }
+    let records = parse_fasta(&run_command(command, fasta)?);
+    if records.is_empty() {
+        return Err(format!("{} gave no sequences", command));
+    }
+    for (name, _) in records.iter() {
+        if !names.contains(&Some(name.clone())) {
+            return Err(format!("{} gave an unknown sequence {}", command, name));
+        }
+    }
+    let num_named = names.iter().filter(|name| name.is_some()).count();
+    if records.len() != num_named {
+        return Err(format!(
+            "{} gave {} sequences for {} rows",
+            command,
+            records.len(),
+            num_named
+        ));
+    }
+    let imported = build_block(records, &mut Coordinates::Headers(sizes))?;
+    let mut imported: HashMap<String, _> = imported
+        .aligned_entries()
+        .map(|entry| (coordinate_name(entry), entry.clone()))
+        .collect();
+    let width = imported.values().next().unwrap().alignment.len();
+    let mut rows = vec![];
+    for (entry, name) in block.aligned_entries().zip(names.iter()) {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                rows.push((
+                    vec![b'-'; width],
+                    entry.qualities.as_ref().map(|_| vec![b'-'; width]),
+                    0,
+                ));
+                continue;
+            }
+        };
+        let new_entry = imported
+            .remove(name)
+            .ok_or_else(|| format!("{} lost sequence {}", command, name))?;
+        let (alignment, qualities) = regap(entry, &new_entry.alignment)
+            .ok_or_else(|| format!("{} changed sequence {}", command, name))?;
+        rows.push((alignment, qualities, new_entry.aligned_length));
+    }
+    let mut rows = rows.into_iter();
+    for entry in block.entries.iter_mut() {
+        if let MAFBlockEntry::AlignedEntry(entry) = entry {
+            let (alignment, qualities, aligned_length) = rows.next().unwrap();
+            entry.alignment = alignment;
+            entry.qualities = qualities;
+            entry.aligned_length = aligned_length;
+        }
+    }
+    Ok(())
+}
+
+/// Send each block through `command` with `roundtrip_block`. Blocks it
+/// fails on are written unchanged, with a warning. Returns the numbers
+/// of blocks round-tripped and failed.
+pub fn roundtrip(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    command: &str,
+) -> Result<(usize, usize), MAFParseError> {
+    let mut num_roundtripped = 0;
+    let mut num_failed = 0;
+    for_each_block(input, output, |mut block, output| {
+        match roundtrip_block(&mut block, command) {
+            Ok(()) => num_roundtripped += 1,
+            Err(e) => {
+                output.warn(&format!("Couldn't round-trip block: {}", e));
+                output.record_skipped(1);
+                num_failed += 1;
+            }
+        }
+        output.write_block(&block);
+    })?;
+    Ok((num_roundtripped, num_failed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let maf = "a
+s hg38.chr1 10 4 + 100 AC--GT
+s mm39.chr2 90 3 - 100 a-cG--
+s rn7.chr3 0 0 + 100 ------
+";
+        // A stand-in tool that reverses the order of the rows and
+        // right-aligns them in 5 columns, in upper case.
+        let command = "sed '/^>/!s/-//g' | awk '/^>/ { name = $0; next } \
+                       { rows[n++] = name \"\\n\" sprintf(\"%5s\", toupper($0)) } \
+                       END { while (n--) print rows[n] }' | tr ' ' -";
+        let mut output = vec![];
+        let counts = roundtrip(&mut maf.as_bytes(), &mut output, command).unwrap();
+        assert_eq!(counts, (1, 0));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 10 4 + 100 -ACGT
+s mm39.chr2 90 3 - 100 --acG
+s rn7.chr3 0 0 + 100 -----
+
+"
+        );
+
+        // Tools that change a sequence, lose a row, or add one are
+        // refused.
+        for command in ["sed 's/C/T/'", "head -n 2", "cat; echo '>extra'; echo A"].iter() {
+            let mut output = vec![];
+            let counts = roundtrip(&mut maf.as_bytes(), &mut output, command).unwrap();
+            assert_eq!(counts, (0, 1));
+            assert!(String::from_utf8(output).unwrap().contains("AC--GT"));
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Counting what goes in and out of a run, to pick its exit code and
+//! write a summary for workflow managers.
+use multiple_alignment_format::output::MAFValidationError;
+use multiple_alignment_format::parser::MAFParseError;
+use serde_json::json;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Bytes, blocks, and other lines seen passing through a stream.
+#[derive(Debug, Default)]
+pub struct StreamCounts {
+    pub bytes: u64,
+    /// Lines starting with "a".
+    pub blocks: u64,
+    /// Lines that aren't blank or comments.
+    pub content_lines: u64,
+    mid_line: bool,
+}
+
+impl StreamCounts {
+    fn scan(&mut self, bytes: &[u8]) {
+        self.bytes += bytes.len() as u64;
+        for &b in bytes {
+            if !self.mid_line {
+                if b == b'a' {
+                    self.blocks += 1;
+                }
+                if !matches!(b, b'#' | b'\n' | b'\r') {
+                    self.content_lines += 1;
+                }
+            }
+            self.mid_line = b != b'\n';
+        }
+    }
+}
+
+pub type SharedCounts = Rc<RefCell<StreamCounts>>;
+
+/// Counts what's read through it.
+pub struct CountingReader<R: BufRead> {
+    inner: R,
+    counts: SharedCounts,
+}
+
+impl<R: BufRead> CountingReader<R> {
+    pub fn new(inner: R, counts: SharedCounts) -> Self {
+        CountingReader { inner, counts }
+    }
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counts.borrow_mut().scan(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.counts.borrow_mut().scan(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Counts what's written through it.
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    counts: SharedCounts,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W, counts: SharedCounts) -> Self {
+        CountingWriter { inner, counts }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counts.borrow_mut().scan(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How a run went, with the process exit code for each outcome.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunStatus {
+    Success = 0,
+    /// An item of the input couldn't be parsed, so only the ones
+    /// before it were read.
+    ParseError = 2,
+    /// Blocks were read, but nothing but comments was written.
+    EmptyOutput = 3,
+    /// Some blocks couldn't be processed, and were dropped or left
+    /// alone.
+    Partial = 4,
+    /// The input ended partway through a block, so that only the
+    /// blocks before it were read.
+    Truncated = 5,
+    /// Strict output refused to write an invalid block, so the output
+    /// stops before it.
+    InvalidBlock = 6,
+}
+
+impl RunStatus {
+    fn name(self) -> &'static str {
+        match self {
+            RunStatus::Success => "success",
+            RunStatus::ParseError => "parse_error",
+            RunStatus::EmptyOutput => "empty_output",
+            RunStatus::Partial => "partial",
+            RunStatus::Truncated => "truncated",
+            RunStatus::InvalidBlock => "invalid_block",
+        }
+    }
+}
+
+pub struct RunSummary {
+    pub subcommand: String,
+    pub status: RunStatus,
+    /// The error that stopped the input from being read to its end,
+    /// which may be for a block the input ended partway through.
+    pub error: Option<MAFParseError>,
+    /// The error for the block strict output refused to write, if any.
+    pub refused: Option<MAFValidationError>,
+    /// Whether whatever was reading the output went away (as with
+    /// `| head`), so that the run stopped early.
+    output_closed: bool,
+    input: StreamCounts,
+    output: StreamCounts,
+    skipped: usize,
+    warnings: usize,
+    elapsed: Duration,
+}
+
+impl RunSummary {
+    /// Sum up a run from what went through its input and output, the
+    /// error that stopped the subcommand reading its input, if any, the
+    /// block strict output refused, if any, and whether the output was
+    /// closed early.
+    /// `writes_output` is false for subcommands that write their
+    /// results elsewhere, and `allow_truncated` is whether an input cut
+    /// off partway through a block is all right. `skipped` and
+    /// `warnings` are the numbers of blocks skipped and warnings given.
+    pub fn new(
+        subcommand: &str,
+        input: StreamCounts,
+        output: StreamCounts,
+        elapsed: Duration,
+        (error, refused, output_closed): (Option<MAFParseError>, Option<MAFValidationError>, bool),
+        (writes_output, allow_truncated): (bool, bool),
+        (skipped, warnings): (usize, usize),
+    ) -> Self {
+        let truncated = matches!(error, Some(MAFParseError::Truncated(_)));
+        let status = if refused.is_some() {
+            RunStatus::InvalidBlock
+        } else if error.is_some() && !truncated {
+            RunStatus::ParseError
+        } else if truncated && !allow_truncated {
+            RunStatus::Truncated
+        } else if skipped > 0 {
+            RunStatus::Partial
+        } else if writes_output && !output_closed && input.blocks > 0 && output.content_lines == 0 {
+            RunStatus::EmptyOutput
+        } else {
+            RunStatus::Success
+        };
+        RunSummary {
+            subcommand: subcommand.to_string(),
+            status,
+            error,
+            refused,
+            output_closed,
+            input,
+            output,
+            skipped,
+            warnings,
+            elapsed,
+        }
+    }
+
+    /// Whether the input ended partway through a block.
+    pub fn truncated(&self) -> bool {
+        matches!(self.error, Some(MAFParseError::Truncated(_)))
+    }
+
+    pub fn write_json(&self, output: &mut dyn Write) {
+        let summary = json!({
+            "subcommand": self.subcommand,
+            "status": self.status.name(),
+            "exit_code": self.status as i32,
+            "blocks_read": self.input.blocks,
+            "blocks_written": self.output.blocks,
+            "blocks_skipped": self.skipped,
+            "truncated": self.truncated(),
+            "output_closed": self.output_closed,
+            "warnings": self.warnings,
+            "bytes_read": self.input.bytes,
+            "bytes_written": self.output.bytes,
+            "seconds": self.elapsed.as_secs_f64(),
+        });
+        serde_json::to_writer_pretty(&mut *output, &summary).ok();
+        writeln!(output).ok();
+    }
+
+    pub fn write_json_file(&self, path: &str) {
+        let mut file = File::create(path).expect("Couldn't create summary file");
+        self.write_json(&mut file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(text: &str, read_all: bool) -> StreamCounts {
+        let counts = SharedCounts::default();
+        let mut reader = CountingReader::new(text.as_bytes(), counts.clone());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            line.clear();
+            if !read_all {
+                break;
+            }
+        }
+        counts.take()
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let maf = "##maf version=1\na\ns hg38.chr1 0 1 + 10 A\n\na\ns hg38.chr1 1 1 + 10 C\n";
+        let input = counts(maf, true);
+        assert_eq!((input.blocks, input.content_lines), (2, 4));
+
+        let output = counts("##maf version=1\n", true);
+        let summary = RunSummary::new(
+            "grep",
+            input,
+            output,
+            Duration::from_secs(1),
+            (None, None, false),
+            (true, false),
+            (0, 0),
+        );
+        assert_eq!(summary.status, RunStatus::EmptyOutput);
+        let mut json = vec![];
+        summary.write_json(&mut json);
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["blocks_read"], 2);
+        assert_eq!(json["exit_code"], 3);
+
+        let output = CountingWriter::new(vec![], SharedCounts::default());
+        let output_counts = output.counts.clone();
+        let mut output = output;
+        output.write_all(b"reference\tquery\n").unwrap();
+        let summary = RunSummary::new(
+            "coverage",
+            counts(maf, true),
+            output_counts.take(),
+            Duration::from_secs(1),
+            (None, None, false),
+            (true, false),
+            (0, 1),
+        );
+        assert_eq!(summary.status, RunStatus::Success);
+
+        let summary = RunSummary::new(
+            "grep",
+            counts(maf, false),
+            StreamCounts::default(),
+            Duration::from_secs(1),
+            (
+                Some(MAFParseError::BadLineType("x".to_string())),
+                None,
+                false,
+            ),
+            (true, false),
+            (1, 0),
+        );
+        assert_eq!(summary.status, RunStatus::ParseError);
+        assert!(summary.error.is_some());
+
+        let refused = MAFValidationError {
+            location: Some(("hg38.chr1".to_string(), 0)),
+            reason: "block has no s lines".to_string(),
+        };
+        let summary = RunSummary::new(
+            "grep",
+            counts(maf, true),
+            StreamCounts::default(),
+            Duration::from_secs(1),
+            (None, Some(refused), false),
+            (true, false),
+            (0, 0),
+        );
+        assert_eq!(summary.status, RunStatus::InvalidBlock);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let maf = "##maf version=1\n\na\ns hg38.chr1 0 4 + 100 ACGT\n\na score=2\ns mm39.";
+        let truncated = || Some(MAFParseError::Truncated("a score=2".to_string()));
+        let summary = RunSummary::new(
+            "grep",
+            counts(maf, true),
+            StreamCounts::default(),
+            Duration::from_secs(1),
+            (truncated(), None, false),
+            (false, false),
+            (0, 0),
+        );
+        assert_eq!(summary.status, RunStatus::Truncated);
+        assert_eq!(
+            summary.error.unwrap().to_string(),
+            "Input ends partway through the block starting \"a score=2\""
+        );
+
+        let summary = RunSummary::new(
+            "grep",
+            counts(maf, true),
+            StreamCounts::default(),
+            Duration::from_secs(1),
+            (truncated(), None, false),
+            (false, true),
+            (0, 0),
+        );
+        assert_eq!(summary.status, RunStatus::Success);
+        let mut json = vec![];
+        summary.write_json(&mut json);
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["truncated"], true);
+    }
+}
@@ -0,0 +1,198 @@
+//! Dropping rows by sequence name as the input is read.
+use crate::transform::BlockTransform;
+use crate::BLOCK_ID_KEY;
+use multiple_alignment_format::parser::{next_maf_item_raw, MAFParseError};
+use multiple_alignment_format::{MAFBlock, MAFBlockEntry, MAFItem};
+use regex::Regex;
+use std::io::{self, BufRead, Read};
+
+/// Which sequences to keep, by regexes searched for anywhere in the
+/// full sequence name (e.g. "hg38.chrUn_KI270302v1").
+pub struct SeqFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+impl SeqFilter {
+    pub fn new(include: Option<Regex>, exclude: Option<Regex>) -> Self {
+        SeqFilter { include, exclude }
+    }
+
+    pub fn keeps(&self, seq: &str) -> bool {
+        self.include.as_ref().is_none_or(|r| r.is_match(seq))
+            && !self.exclude.as_ref().is_some_and(|r| r.is_match(seq))
+    }
+
+    /// Drop the rows of sequences that aren't kept, or the whole block
+    /// if its reference (first) row isn't kept. Returns None if the
+    /// block is dropped.
+    pub fn filter_block(&self, mut block: MAFBlock) -> Option<MAFBlock> {
+        if let Some(reference) = block.aligned_entries().next() {
+            if !self.keeps(&reference.seq) {
+                return None;
+            }
+        }
+        block.entries.retain(|entry| match entry {
+            MAFBlockEntry::AlignedEntry(e) => self.keeps(&e.seq),
+            MAFBlockEntry::UnalignedEntry(e) => self.keeps(&e.seq),
+        });
+        Some(block)
+    }
+}
+
+impl BlockTransform for SeqFilter {
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+        self.filter_block(block).into_iter().collect()
+    }
+}
+
+/// Wraps a MAF input, handing on only what passes a `SeqFilter`, so
+/// that any subcommand can be run on the filtered alignment. Blocks
+/// that aren't changed are passed on byte-for-byte. Once a block has
+/// been dropped, the blocks after it are given their number in the
+/// input as their `id` (if they don't have one), so that reports give
+/// them the same IDs as without the filter. An item that can't be
+/// parsed is handed on as an I/O error wrapping its parse error.
+pub struct SeqFilterReader<R: BufRead> {
+    input: R,
+    filter: SeqFilter,
+    buf: Vec<u8>,
+    pos: usize,
+    num_blocks: usize,
+    num_dropped: usize,
+}
+
+impl<R: BufRead> SeqFilterReader<R> {
+    pub fn new(input: R, filter: SeqFilter) -> Self {
+        SeqFilterReader {
+            input,
+            filter,
+            buf: vec![],
+            pos: 0,
+            num_blocks: 0,
+            num_dropped: 0,
+        }
+    }
+
+    /// Refill the buffer with the next item that passes the filter,
+    /// leaving it empty at the end of the input.
+    fn refill(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        loop {
+            let (item, raw) = match next_maf_item_raw(&mut self.input) {
+                Ok(item) => item,
+                Err(MAFParseError::EOF) => return Ok(()),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            match item {
+                MAFItem::Comment(_) => {
+                    self.buf = raw.into_bytes();
+                    return Ok(());
+                }
+                MAFItem::Block(block) => {
+                    self.num_blocks += 1;
+                    let num_entries = block.entries.len();
+                    match self.filter.filter_block(block) {
+                        Some(mut block) => {
+                            let renumbered =
+                                self.num_dropped > 0 && !block.metadata.contains_key(BLOCK_ID_KEY);
+                            if renumbered {
+                                block
+                                    .metadata
+                                    .insert(BLOCK_ID_KEY.to_string(), self.num_blocks.to_string());
+                            }
+                            self.buf = if block.entries.len() == num_entries && !renumbered {
+                                raw.into_bytes()
+                            } else {
+                                format!("{}", block).into_bytes()
+                            };
+                            return Ok(());
+                        }
+                        None => self.num_dropped += 1,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for SeqFilterReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for SeqFilterReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.refill()?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAFItems;
+
+    #[test]
+    fn test_seq_filter_reader() {
+        let maf = "##maf version=1
+a score=1
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chrUn_1 5 4 - 20 ACGT
+e rn7.chr1_random 0 5 + 10 I
+s rn7.chr2 0 4 + 10 ACGT
+
+a
+s hg38.chrUn_2 0 2 + 10 AC
+s mm39.chr2 0 2 + 20 AC
+
+a
+s  hg38.chr2  0 2 + 10 AC
+";
+        let filter = SeqFilter::new(None, Some(Regex::new("chrUn|_random").unwrap()));
+        let mut output = String::new();
+        SeqFilterReader::new(maf.as_bytes(), filter)
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(
+            output,
+            "##maf version=1
+a score=1
+s hg38.chr1 10 4 + 100 ACGT
+s rn7.chr2 0 4 + 10 ACGT
+
+a id=3
+s hg38.chr2 0 2 + 10 AC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_seq_filter_reader_error() {
+        let maf = "a\ns hg38.chr1 0 2 + 10 AC\n\na\ns hg38.chr1 2 2 + 10 GT\ns mm39.";
+        let filter = SeqFilter::new(None, None);
+        let mut reader = SeqFilterReader::new(maf.as_bytes(), filter);
+        let mut items = MAFItems::new(&mut reader);
+        assert_eq!(items.by_ref().count(), 1);
+        assert!(matches!(items.finish(), Err(MAFParseError::Truncated(_))));
+    }
+
+    #[test]
+    fn test_include() {
+        let filter = SeqFilter::new(Some(Regex::new(r"\.chr\d+$").unwrap()), None);
+        assert!(filter.keeps("hg38.chr12"));
+        assert!(!filter.keeps("hg38.chrX"));
+        assert!(!filter.keeps("hg38.chr1_random"));
+    }
+}
@@ -0,0 +1,110 @@
+use maf_stream::{write_unmodified, MAFItems, MAFOutput, Rng};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use tempfile::tempfile;
+
+/// Write the blocks in a random order, the same for a given seed. Only
+/// the header (comments before the first block) is kept. Each block is
+/// first spooled to one of `num_buckets` temporary files at random, then
+/// each bucket in turn is read into memory and shuffled, so only about
+/// 1/`num_buckets` of the input is held in memory at once. Returns the
+/// number of blocks.
+pub fn shuffle(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    seed: u64,
+    num_buckets: usize,
+) -> Result<usize, MAFParseError> {
+    assert!(num_buckets > 0, "Need at least one bucket");
+    let mut rng = Rng::new(seed);
+    let mut buckets: Vec<Option<BufWriter<_>>> = (0..num_buckets).map(|_| None).collect();
+    let mut in_header = true;
+    let mut num_blocks = 0;
+    let mut items = MAFItems::new(input);
+    while let Some((item, raw)) = items.next_raw() {
+        match item {
+            MAFItem::Comment(_) => {
+                if in_header {
+                    write_unmodified(output, &item, &raw);
+                }
+            }
+            MAFItem::Block(_) => {
+                in_header = false;
+                num_blocks += 1;
+                let bucket =
+                    buckets[rng.below(num_buckets as u64) as usize].get_or_insert_with(|| {
+                        BufWriter::new(tempfile().expect("Couldn't open temporary file"))
+                    });
+                bucket.write_all(raw.as_bytes()).expect("failed to write");
+            }
+        }
+    }
+    let result = items.finish();
+    for bucket in buckets.into_iter().flatten() {
+        let mut bucket = bucket.into_inner().expect("failed to write");
+        bucket
+            .seek(SeekFrom::Start(0))
+            .expect("Couldn't rewind temporary file");
+        let mut bucket = BufReader::new(bucket);
+        let mut blocks = vec![];
+        let mut spooled = MAFItems::new(&mut bucket);
+        while let Some(block) = spooled.next_raw() {
+            blocks.push(block);
+        }
+        spooled
+            .finish()
+            .expect("Couldn't read back the temporary file");
+        // Fisher-Yates.
+        for i in (1..blocks.len()).rev() {
+            blocks.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        for (item, raw) in blocks {
+            write_unmodified(output, &item, &raw);
+        }
+    }
+    result?;
+    Ok(num_blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multiple_alignment_format::parser::next_maf_item;
+
+    fn starts(maf: &[u8]) -> Vec<u64> {
+        let mut starts = vec![];
+        let mut maf = maf;
+        while let Ok(item) = next_maf_item(&mut maf) {
+            if let MAFItem::Block(block) = item {
+                starts.push(block.aligned_entries().next().unwrap().start);
+            }
+        }
+        starts
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut maf = "##maf version=1\n".to_string();
+        for i in 0..20 {
+            maf.push_str(&format!("a\ns hg38.chr1 {} 2 + 100 AC\n\n", i * 2));
+        }
+        let run = |seed, num_buckets| {
+            let mut output = vec![];
+            assert_eq!(
+                shuffle(&mut maf.as_bytes(), &mut output, seed, num_buckets).unwrap(),
+                20
+            );
+            output
+        };
+        let output = run(1, 4);
+        assert!(output.starts_with(b"##maf version=1\n"));
+        let mut shuffled = starts(&output);
+        assert_ne!(shuffled, (0..20).map(|i| i * 2).collect::<Vec<_>>());
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, (0..20).map(|i| i * 2).collect::<Vec<_>>());
+        assert_eq!(run(1, 4), output);
+        assert_ne!(run(2, 4), output);
+        assert_eq!(starts(&run(1, 1)).len(), 20);
+    }
+}
@@ -0,0 +1,250 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{
+    AlignedContext, AlignedContextStatus, MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+
+/// Merged forward-strand intervals already covered, per sequence.
+#[derive(Default)]
+struct CoveredIntervals {
+    intervals: HashMap<String, BTreeMap<u64, u64>>,
+}
+
+impl CoveredIntervals {
+    fn contains(&self, seq: &str, pos: u64) -> bool {
+        self.intervals
+            .get(seq)
+            .and_then(|intervals| intervals.range(..=pos).next_back())
+            .is_some_and(|(_, end)| *end > pos)
+    }
+
+    fn insert(&mut self, seq: &str, mut start: u64, mut end: u64) {
+        let intervals = self.intervals.entry(seq.to_string()).or_default();
+        // Absorb any intervals touching this one.
+        let touching: Vec<(u64, u64)> = intervals
+            .range(..=end)
+            .rev()
+            .take_while(|(_, e)| **e >= start)
+            .map(|(s, e)| (*s, *e))
+            .collect();
+        for (s, e) in touching {
+            intervals.remove(&s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        intervals.insert(start, end);
+    }
+}
+
+/// Count `num_bases` trimmed off one side of a row as inserted between
+/// it and the neighboring block, where it was contiguous with or
+/// inserted next to that block. Other statuses still hold.
+fn trimmed_side(
+    (status, count): (AlignedContextStatus, u64),
+    num_bases: u64,
+) -> (AlignedContextStatus, u64) {
+    match status {
+        AlignedContextStatus::Contiguous | AlignedContextStatus::Insertion if num_bases > 0 => {
+            (AlignedContextStatus::Insertion, count + num_bases)
+        }
+        status => (status, count),
+    }
+}
+
+/// Trim a row down to its longest stretch of bases that haven't been
+/// covered yet, replacing the other bases (and their qualities) with
+/// gaps, and counting them in the row's context. Returns None if every
+/// base was already covered.
+fn trim_row(
+    entry: &MAFBlockAlignedEntry,
+    covered: &CoveredIntervals,
+) -> Option<MAFBlockAlignedEntry> {
+    let uncovered: Vec<bool> = entry
+        .column_positions()
+        .flatten()
+        .map(|pos| !covered.contains(&entry.seq, entry.forward_position(pos)))
+        .collect();
+    if uncovered.iter().all(|u| *u) {
+        return Some(entry.clone());
+    }
+    // Longest run of uncovered bases, as [start, end) base indices.
+    let (mut best_start, mut best_end) = (0, 0);
+    let mut run_start = 0;
+    for (i, u) in uncovered.iter().enumerate() {
+        if !u {
+            run_start = i + 1;
+        } else if i + 1 - run_start > best_end - best_start {
+            best_start = run_start;
+            best_end = i + 1;
+        }
+    }
+    if best_end == best_start {
+        return None;
+    }
+    let mut base_idx = 0;
+    let kept: Vec<bool> = entry
+        .alignment
+        .iter()
+        .map(|base| {
+            if is_gap(*base) {
+                return true;
+            }
+            base_idx += 1;
+            base_idx > best_start && base_idx <= best_end
+        })
+        .collect();
+    let alignment = entry
+        .alignment
+        .iter()
+        .zip(kept.iter())
+        .map(|(base, kept)| if *kept { *base } else { b'-' })
+        .collect();
+    let qualities = entry.qualities.as_ref().map(|qualities| {
+        qualities
+            .iter()
+            .zip(kept.iter())
+            .map(|(q, kept)| if *kept { *q } else { b'-' })
+            .collect()
+    });
+    let context = entry.context.as_ref().map(|context| {
+        let (left_status, left_count) = trimmed_side(
+            (context.left_status.clone(), context.left_count),
+            best_start as u64,
+        );
+        let (right_status, right_count) = trimmed_side(
+            (context.right_status.clone(), context.right_count),
+            (uncovered.len() - best_end) as u64,
+        );
+        AlignedContext {
+            left_status,
+            left_count,
+            right_status,
+            right_count,
+        }
+    });
+    Some(MAFBlockAlignedEntry {
+        seq: entry.seq.clone(),
+        start: entry.start + best_start as u64,
+        aligned_length: (best_end - best_start) as u64,
+        strand: entry.strand,
+        sequence_size: entry.sequence_size,
+        alignment,
+        context,
+        qualities,
+    })
+}
+
+/// Make every non-reference row cover only bases not covered by an
+/// earlier row, trimming rows to their longest uncovered stretch and
+/// dropping rows that are entirely covered.
+fn single_cov_block(block: &MAFBlock, covered: &mut CoveredIntervals) -> MAFBlock {
+    let mut entries = vec![];
+    let mut seen_ref = false;
+    for entry in block.entries.iter() {
+        match entry {
+            MAFBlockEntry::AlignedEntry(e) if seen_ref => {
+                if let Some(trimmed) = trim_row(e, covered) {
+                    let (start, end) = trimmed.forward_interval();
+                    covered.insert(&trimmed.seq, start, end);
+                    entries.push(MAFBlockEntry::AlignedEntry(trimmed));
+                }
+            }
+            MAFBlockEntry::AlignedEntry(_) => {
+                seen_ref = true;
+                entries.push(entry.clone());
+            }
+            MAFBlockEntry::UnalignedEntry(_) => entries.push(entry.clone()),
+        }
+    }
+    MAFBlock {
+        entries,
+        metadata: block.metadata.clone(),
+    }
+}
+
+/// Ensure each query (non-reference) position appears in at most one
+/// row across the whole file. Rows are claimed first-come,
+/// first-served, in file order.
+pub fn single_cov(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+) -> Result<(), MAFParseError> {
+    let mut covered = CoveredIntervals::default();
+    for_each_block(input, output, |block, output| {
+        write!(output, "{}", single_cov_block(&block, &mut covered)).ok();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_cov() {
+        let maf = "a
+s hg38.chr1 0 6 + 100 ACGTAC
+s mm39.chr2 10 6 + 200 ACGTAC
+s mm39.chr2 12 2 + 200 --GT--
+
+a
+s hg38.chr1 50 6 + 100 ACGTAC
+s mm39.chr2 14 4 + 200 AC--GT
+s rn7.chr3 186 4 - 200 AC--GT
+s rn7.chr3 10 4 + 200 AC--GT
+";
+        let mut output = vec![];
+        single_cov(&mut maf.as_bytes(), &mut output).unwrap();
+        // The second mm39 row in the first block is entirely covered by
+        // the first. In the second block, the mm39 row overlaps the end
+        // of the first block's row, and the second rn7 row is the
+        // reverse complement of the first.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 6 + 100 ACGTAC
+s mm39.chr2 10 6 + 200 ACGTAC
+
+a
+s hg38.chr1 50 6 + 100 ACGTAC
+s mm39.chr2 16 2 + 200 ----GT
+s rn7.chr3 186 4 - 200 AC--GT
+
+"
+        );
+    }
+
+    #[test]
+    fn test_single_cov_trims_qualities_and_context() {
+        let maf = "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+
+a
+s hg38.chr1 50 6 + 100 ACGTAC
+s mm39.chr2 12 4 + 200 AC--GT
+q mm39.chr2 12--34
+i mm39.chr2 C 0 I 3
+";
+        let mut output = vec![];
+        single_cov(&mut maf.as_bytes(), &mut output).unwrap();
+        // The two mm39 bases trimmed off the left are now between the
+        // row and the block before it.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 10 4 + 200 ACGT
+
+a
+s hg38.chr1 50 6 + 100 ACGTAC
+s mm39.chr2 14 2 + 200 ----GT
+q mm39.chr2 ----34
+i mm39.chr2 I 2 I 3
+
+"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use maf_stream::{chrom_part, range_contains_pos, MAFItems, Range};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+/// Write the aligned bases of every entry in each reference column
+/// covered by `positions`. The reference is the first entry of each
+/// block.
+fn output_block_columns(block: &MAFBlock, positions: &BTreeSet<Range>, output: &mut dyn Write) {
+    let ref_entry = match block.aligned_entries().next() {
+        Some(entry) => entry,
+        None => return,
+    };
+    let chrom = chrom_part(&ref_entry.seq);
+    for (i, ref_pos) in ref_entry.column_positions().enumerate() {
+        let ref_pos = match ref_pos {
+            Some(pos) => ref_entry.forward_position(pos),
+            // Gap in the reference
+            None => continue,
+        };
+        if !range_contains_pos(positions, &chrom, ref_pos) {
+            continue;
+        }
+        for entry in block.aligned_entries() {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}",
+                chrom,
+                ref_pos + 1,
+                entry.seq.split('.').next().unwrap(),
+                entry.alignment[i] as char,
+                if entry.strand == Strand::Positive {
+                    "+"
+                } else {
+                    "-"
+                },
+            )
+            .ok();
+        }
+    }
+}
+
+pub fn snps(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    positions: BTreeSet<Range>,
+) -> Result<(), MAFParseError> {
+    writeln!(output, "#chrom\tposition\tspecies\tbase\tstrand").ok();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            output_block_columns(&block, &positions, output);
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::parse_positions;
+
+    #[test]
+    fn test_snps() {
+        let maf = "a
+s       Gallus_gallus.chr1 4432333   5       +       157682039  CA-GTA
+s       Alca_torda.scaffold4709 42333   6       -       157682  TAGTAA
+
+a
+s       Gallus_gallus.chr1 20   2       -       100  CA
+s       Alca_torda.scaffold12 200   1       +       157682  -G
+";
+        let vcf = "##fileformat=VCFv4.2
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO
+chr1\t4432335\t.\tA\tG\t.\t.\t.
+chr1\t79\t.\tA\tG\t.\t.\t.
+";
+        let mut output = vec![];
+        snps(
+            &mut maf.as_bytes(),
+            &mut output,
+            parse_positions(vcf.as_bytes()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#chrom\tposition\tspecies\tbase\tstrand
+chr1\t4432335\tGallus_gallus\tA\t+
+chr1\t4432335\tAlca_torda\tA\t-
+chr1\t79\tGallus_gallus\tA\t-
+chr1\t79\tAlca_torda\tG\t+
+"
+        );
+    }
+}
@@ -1,7 +1,15 @@
+use crate::run_summary::{CountingWriter, SharedCounts};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use itertools::Itertools;
-use multiple_alignment_format::parser::next_maf_item;
+use maf_stream::tree::Tree;
+use maf_stream::{is_header, MAFItems, MAFOutput, OutputOptions};
+use multiple_alignment_format::output::MAFValidationError;
+use multiple_alignment_format::parser::MAFParseError;
 use multiple_alignment_format::{MAFBlock, MAFItem};
-use std::fs::File;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, File};
 use std::io::{BufRead, BufWriter, Write};
 use std::path::PathBuf;
 
@@ -10,78 +18,307 @@ struct MAFSplit {
     cur_chrom: Option<String>,
     /// Total length of blocks (in reference coordinates) within current file.
     cur_length: Option<u64>,
-    cur_file: Option<BufWriter<File>>,
+    cur_file: Option<Box<dyn Write>>,
     output_dir: PathBuf,
     /// Maximum aligned length (in reference) per file.
     max_length: u64,
+    /// If set, files are gzipped and split once this many compressed
+    /// bytes have been written, instead of by reference length.
+    max_compressed_bytes: Option<u64>,
+    /// Compressed bytes written to the current file so far. The encoder
+    /// holds back some output, so this lags a little behind the input.
+    cur_compressed: Option<SharedCounts>,
+    /// Summary of the current file, if writing a JSON sidecar for each
+    /// file.
+    cur_metadata: Option<ChunkMetadata>,
+    emit_metadata: bool,
+    /// Comments before the first block, which are copied into every
+    /// file.
+    header_comments: Vec<String>,
+    /// Species tree to prune to the species in each file, and write
+    /// next to it.
+    tree: Option<Tree>,
+    /// Path of the current (uncompressed) file, and the species in it,
+    /// if writing trees.
+    cur_species: Option<(PathBuf, BTreeSet<String>)>,
+    options: OutputOptions,
+}
+
+/// What a split file contains, written to a JSON file next to it so
+/// that workflows can decide what to do with each chunk without
+/// parsing it.
+struct ChunkMetadata {
+    path: PathBuf,
+    species: BTreeSet<String>,
+    chrom: String,
+    /// Forward-strand reference interval covered by the blocks.
+    start: u64,
+    end: u64,
+    num_blocks: u64,
+    num_columns: u64,
+}
+
+impl ChunkMetadata {
+    fn add_block(&mut self, block: &MAFBlock) {
+        for entry in block.aligned_entries() {
+            self.species
+                .insert(entry.seq.split('.').next().unwrap().to_string());
+        }
+        if let Some(ref_aln) = block.aligned_entries().next() {
+            let (start, end) = ref_aln.forward_interval();
+            self.start = self.start.min(start);
+            self.end = self.end.max(end);
+            self.num_columns += ref_aln.alignment.len() as u64;
+        }
+        self.num_blocks += 1;
+    }
+
+    fn write(&self) {
+        let metadata = json!({
+            "species": self.species,
+            "reference": {"chrom": self.chrom, "start": self.start, "end": self.end},
+            "blocks": self.num_blocks,
+            "columns": self.num_columns,
+        });
+        let f = File::create(self.path.with_extension("json")).expect("Couldn't create file");
+        serde_json::to_writer_pretty(BufWriter::new(f), &metadata).expect("failed to write");
+    }
 }
 
 impl MAFSplit {
-    fn new(output_dir: &str, max_length: u64) -> MAFSplit {
+    fn new(
+        output_dir: &str,
+        max_length: u64,
+        max_compressed_bytes: Option<u64>,
+        emit_metadata: bool,
+        tree: Option<Tree>,
+        options: OutputOptions,
+    ) -> MAFSplit {
         Self {
             cur_chrom: None,
             cur_length: None,
             cur_file: None,
             output_dir: PathBuf::from(output_dir),
             max_length,
+            max_compressed_bytes,
+            cur_compressed: None,
+            cur_metadata: None,
+            emit_metadata,
+            header_comments: vec![],
+            tree,
+            cur_species: None,
+            options,
         }
     }
 
     /// Outputs this block to the correct file, opening a new one if
-    /// needed.
-    fn output_block(&mut self, block: &MAFBlock) {
+    /// needed, unless strict output refuses it.
+    fn output_block(&mut self, block: &MAFBlock) -> Result<(), MAFValidationError> {
         let ref_line = block.aligned_entries().next();
         if let Some(ref_aln) = ref_line {
             let chr = ref_aln.seq.split('.').skip(1).join(".");
             // On any new reference chromosome, or if the file would grow too
             // large, we switch to a new file.
+            let full = match (self.max_compressed_bytes, self.cur_compressed.as_ref()) {
+                (Some(max), Some(compressed)) => compressed.borrow().bytes >= max,
+                _ => self.cur_length.unwrap_or(0) + ref_aln.aligned_length > self.max_length,
+            };
             if self.cur_chrom.is_none()
                 || self.cur_length.is_none()
                 || &chr != self.cur_chrom.as_ref().unwrap()
-                || self.cur_length.unwrap() + ref_aln.aligned_length > self.max_length
+                || full
             {
                 self.new_file(&chr, ref_aln.start);
             }
             self.cur_length = self.cur_length.map(|l| l + ref_aln.aligned_length);
         }
-        write!(self.cur_file.as_mut().unwrap(), "{}", block).expect("failed to write");
+        if let Some(metadata) = self.cur_metadata.as_mut() {
+            metadata.add_block(block);
+        }
+        if let Some((_, species)) = self.cur_species.as_mut() {
+            for entry in block.aligned_entries() {
+                species.insert(entry.seq.split('.').next().unwrap().to_string());
+            }
+        }
+        self.options
+            .writer
+            .write_block(self.cur_file.as_mut().unwrap(), block)
     }
 
     /// Starts a new file and flushes the old one.
     fn new_file(&mut self, chrom: &str, start_pos: u64) {
-        let f = File::create(self.output_dir.join(format!("{}.{}.maf", chrom, start_pos)))
-            .expect("Couldn't create file");
-        self.cur_file = Some(BufWriter::new(f));
+        self.finish_file();
+        let path = self.output_dir.join(format!("{}.{}.maf", chrom, start_pos));
+        // Dropping the old file first finishes its gzip stream.
+        self.cur_file = None;
+        if self.emit_metadata {
+            self.cur_metadata = Some(ChunkMetadata {
+                path: path.clone(),
+                species: BTreeSet::new(),
+                chrom: chrom.to_string(),
+                start: u64::MAX,
+                end: 0,
+                num_blocks: 0,
+                num_columns: 0,
+            });
+        }
+        if self.tree.is_some() {
+            self.cur_species = Some((path.clone(), BTreeSet::new()));
+        }
+        self.cur_file = Some(match self.max_compressed_bytes {
+            Some(_) => {
+                let f = File::create(path.with_extension("maf.gz")).expect("Couldn't create file");
+                let compressed = SharedCounts::default();
+                self.cur_compressed = Some(compressed.clone());
+                Box::new(GzEncoder::new(
+                    CountingWriter::new(BufWriter::new(f), compressed),
+                    Compression::default(),
+                ))
+            }
+            None => Box::new(BufWriter::new(
+                File::create(&path).expect("Couldn't create file"),
+            )),
+        });
         self.cur_length = Some(0);
         self.cur_chrom = Some(chrom.to_string());
         writeln!(self.cur_file.as_mut().unwrap(), "##maf version=1").expect("failed to write");
+        for comment in self.header_comments.iter() {
+            writeln!(self.cur_file.as_mut().unwrap(), "#{}", comment).expect("failed to write");
+        }
+    }
+
+    /// Outputs a comment to the current file, or to every file if it
+    /// comes before the first block. Each file gets its own "##maf"
+    /// header line, so the input's is dropped.
+    fn output_comment(&mut self, comment: &str) {
+        if is_header(comment) || self.options.strip_comments {
+            return;
+        }
+        match self.cur_file.as_mut() {
+            Some(file) => writeln!(file, "#{}", comment).expect("failed to write"),
+            None => self.header_comments.push(comment.to_string()),
+        }
+    }
+
+    /// Writes the JSON sidecar and the pruned tree for the current
+    /// file, if there are any.
+    fn finish_file(&mut self) {
+        if let Some(metadata) = self.cur_metadata.take() {
+            metadata.write();
+        }
+        if let (Some(tree), Some((path, species))) = (self.tree.as_ref(), self.cur_species.take()) {
+            if let Some(pruned) = tree.prune(|name| species.contains(name)) {
+                let mut file =
+                    File::create(path.with_extension("nwk")).expect("Couldn't create file");
+                writeln!(file, "{}", pruned.to_newick()).expect("failed to write");
+            }
+        }
     }
 }
 
-pub fn split_maf(input: &mut dyn BufRead, max_length: u64, output_dir: &str) {
-    let mut splitter = MAFSplit::new(output_dir, max_length);
+/// Split a MAF into files of at most `max_length` reference bases,
+/// each covering one reference chromosome. With `emit_metadata`, each
+/// file also gets a JSON file listing the species present, the
+/// reference interval covered, and the number of blocks and columns.
+/// With `max_compressed_bytes`, the files are gzipped, and a new one is
+/// started (on a block boundary) once about that many compressed bytes
+/// have been written, rather than after `max_length` reference bases.
+/// With `tree`, each file also gets the tree pruned to the species in
+/// it, as Newick. With `shard_species`, the blocks containing that species are split
+/// into the "with_<species>" subdirectory and the rest into
+/// "without_<species>", so work on one target species can skip the
+/// chunks it isn't in. The files are written with `output`'s options,
+/// and a block strict output refuses is passed on to it, stopping the
+/// split.
+#[allow(clippy::too_many_arguments)]
+pub fn split_maf(
+    input: &mut dyn BufRead,
+    max_length: u64,
+    max_compressed_bytes: Option<u64>,
+    output_dir: &str,
+    output: &mut dyn MAFOutput,
+    emit_metadata: bool,
+    tree: Option<&Tree>,
+    shard_species: Option<&str>,
+) -> Result<(), MAFParseError> {
+    let new_splitter = |dir: &str| {
+        MAFSplit::new(
+            dir,
+            max_length,
+            max_compressed_bytes,
+            emit_metadata,
+            tree.cloned(),
+            output.options(),
+        )
+    };
+    let mut splitters = match shard_species {
+        None => vec![new_splitter(output_dir)],
+        Some(species) => [format!("with_{}", species), format!("without_{}", species)]
+            .iter()
+            .map(|name| {
+                let dir = PathBuf::from(output_dir).join(name);
+                create_dir_all(&dir).expect("Couldn't create shard directory");
+                new_splitter(dir.to_str().unwrap())
+            })
+            .collect(),
+    };
+    // Which splitter the last block went to, for the comments after it.
+    let mut last: Option<usize> = None;
 
-    while let Ok(item) = next_maf_item(input) {
-        if let MAFItem::Block(block) = item {
-            splitter.output_block(&block);
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        match item {
+            MAFItem::Comment(comment) => match last {
+                Some(i) => splitters[i].output_comment(&comment),
+                None => splitters
+                    .iter_mut()
+                    .for_each(|splitter| splitter.output_comment(&comment)),
+            },
+            MAFItem::Block(block) => {
+                let i = match shard_species {
+                    Some(species)
+                        if !block
+                            .aligned_entries()
+                            .any(|entry| entry.seq.split('.').next() == Some(species)) =>
+                    {
+                        1
+                    }
+                    _ => 0,
+                };
+                if let Err(e) = splitters[i].output_block(&block) {
+                    output.refuse(e);
+                    break;
+                }
+                last = Some(i);
+            }
         }
     }
+    for splitter in splitters.iter_mut() {
+        splitter.finish_file();
+    }
+    items.finish()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::read_to_string;
+    use flate2::read::MultiGzDecoder;
+    use maf_stream::Rng;
+    use std::fs::{read_dir, read_to_string};
+    use std::io::Read;
     use std::path::Path;
     use tempfile::TempDir;
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn test_simple_split() {
         let input_maf = "##maf version=1
+# multiz.v11
 a
 s       Rhesus.chr21_chr20      0       54      +       19571763        AATTCTGTGAAGCTTCTTTGAGAGGCTTGGATTTATTTCACACATTCGAACATT
 s       Human.chr21     0       54      +       9688985 AGTTCTGAGAAGCTTCTTTGTGAGGCTTGGATTCATTTCACACATTTGAACAtt
 
+# between blocks
 a
 s       Rhesus.chr21_chr20      54      28      +       19571763        TGATTGAAGATTTGGAAACAGTCTTTTT
 s       Human.chr21     58      27      +       9688985 tgattgtagatctggaaacagtctt-tt
@@ -96,7 +333,17 @@ s       Human.chr21     217     32      +       9688985 aacctttcctttgctagagcactt
 ";
         let tempdir = TempDir::new().unwrap();
         let output_dir = tempdir.path().to_str().unwrap();
-        split_maf(&mut input_maf.as_bytes(), 84, &output_dir);
+        split_maf(
+            &mut input_maf.as_bytes(),
+            84,
+            None,
+            &output_dir,
+            &mut vec![],
+            false,
+            None,
+            None,
+        )
+        .unwrap();
 
         // The first two blocks should fit in one file, the third
         // should spill over into another file, and the fourth should
@@ -108,12 +355,14 @@ s       Human.chr21     217     32      +       9688985 aacctttcctttgctagagcactt
         assert!(Path::exists(&tempdir.path().join("chr22.193.maf")));
 
         assert_eq!(
-            read_to_string(&tempdir.path().join("chr21_chr20.0.maf")).unwrap(),
+            read_to_string(tempdir.path().join("chr21_chr20.0.maf")).unwrap(),
             "##maf version=1
+# multiz.v11
 a
 s Rhesus.chr21_chr20 0 54 + 19571763 AATTCTGTGAAGCTTCTTTGAGAGGCTTGGATTTATTTCACACATTCGAACATT
 s Human.chr21 0 54 + 9688985 AGTTCTGAGAAGCTTCTTTGTGAGGCTTGGATTCATTTCACACATTTGAACAtt
 
+# between blocks
 a
 s Rhesus.chr21_chr20 54 28 + 19571763 TGATTGAAGATTTGGAAACAGTCTTTTT
 s Human.chr21 58 27 + 9688985 tgattgtagatctggaaacagtctt-tt
@@ -122,8 +371,9 @@ s Human.chr21 58 27 + 9688985 tgattgtagatctggaaacagtctt-tt
         );
 
         assert_eq!(
-            read_to_string(&tempdir.path().join("chr21_chr20.82.maf")).unwrap(),
+            read_to_string(tempdir.path().join("chr21_chr20.82.maf")).unwrap(),
             "##maf version=1
+# multiz.v11
 a
 s Rhesus.chr21_chr20 82 16 + 19571763 TGTAAAATCTATAAAG
 s Human.chr21 85 16 + 9688985 tgtgaaatctataaag
@@ -132,8 +382,9 @@ s Human.chr21 85 16 + 9688985 tgtgaaatctataaag
         );
 
         assert_eq!(
-            read_to_string(&tempdir.path().join("chr22.193.maf")).unwrap(),
+            read_to_string(tempdir.path().join("chr22.193.maf")).unwrap(),
             "##maf version=1
+# multiz.v11
 a
 s Rhesus.chr22 193 32 + 19571763 aacctttcctttgctagagcactttggaaata
 s Human.chr21 217 32 + 9688985 aacctttcctttgctagagcactttgaaaata
@@ -141,4 +392,179 @@ s Human.chr21 217 32 + 9688985 aacctttcctttgctagagcactttgaaaata
 "
         );
     }
+
+    #[test]
+    fn test_split_metadata() {
+        let input_maf = "a
+s Rhesus.chr21 10 4 + 100 ACGT
+s Human.chr21 0 4 + 100 ACGT
+
+a
+s Rhesus.chr21 80 2 - 100 A-C
+s Mouse.chr2 5 3 + 100 ACC
+
+a
+s Rhesus.chr22 0 3 + 100 ACG
+";
+        let tempdir = TempDir::new().unwrap();
+        split_maf(
+            &mut input_maf.as_bytes(),
+            100,
+            None,
+            tempdir.path().to_str().unwrap(),
+            &mut vec![],
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+        let metadata: serde_json::Value =
+            serde_json::from_str(&read_to_string(tempdir.path().join("chr21.10.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            metadata,
+            json!({
+                "species": ["Human", "Mouse", "Rhesus"],
+                "reference": {"chrom": "chr21", "start": 10, "end": 20},
+                "blocks": 2,
+                "columns": 7,
+            })
+        );
+        assert!(Path::exists(&tempdir.path().join("chr22.0.json")));
+    }
+
+    #[test]
+    fn test_shard_by_species() {
+        let input_maf = "# header
+a
+s Rhesus.chr21 10 4 + 100 ACGT
+s Human.chr21 0 4 + 100 ACGT
+
+# after first block
+a
+s Rhesus.chr21 80 2 - 100 A-C
+s Mouse.chr2 5 3 + 100 ACC
+
+a
+s Rhesus.chr21 90 3 + 100 ACG
+s Human.chr2 5 3 + 100 ACC
+";
+        let tempdir = TempDir::new().unwrap();
+        split_maf(
+            &mut input_maf.as_bytes(),
+            100,
+            None,
+            tempdir.path().to_str().unwrap(),
+            &mut vec![],
+            false,
+            None,
+            Some("Human"),
+        )
+        .unwrap();
+        assert_eq!(
+            read_to_string(tempdir.path().join("with_Human/chr21.10.maf")).unwrap(),
+            "##maf version=1
+# header
+a
+s Rhesus.chr21 10 4 + 100 ACGT
+s Human.chr21 0 4 + 100 ACGT
+
+# after first block
+a
+s Rhesus.chr21 90 3 + 100 ACG
+s Human.chr2 5 3 + 100 ACC
+
+"
+        );
+        assert_eq!(
+            read_to_string(tempdir.path().join("without_Human/chr21.80.maf")).unwrap(),
+            "##maf version=1
+# header
+a
+s Rhesus.chr21 80 2 - 100 A-C
+s Mouse.chr2 5 3 + 100 ACC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_split_compressed() {
+        let mut rng = Rng::new(1);
+        let mut input_maf = String::new();
+        for i in 0..2000 {
+            let seq: String = (0..500)
+                .map(|_| ['A', 'C', 'G', 'T'][rng.below(4) as usize])
+                .collect();
+            input_maf += &format!("a\ns hg38.chr1 {} 500 + 10000000 {}\n\n", i * 500, seq);
+        }
+        let tempdir = TempDir::new().unwrap();
+        split_maf(
+            &mut input_maf.as_bytes(),
+            100,
+            Some(50_000),
+            tempdir.path().to_str().unwrap(),
+            &mut vec![],
+            true,
+            None,
+            None,
+        )
+        .unwrap();
+        let mut paths: Vec<_> = read_dir(tempdir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_str().unwrap().ends_with(".maf.gz"))
+            .collect();
+        paths.sort_by_key(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            name.split('.').nth(1).unwrap().parse::<u64>().unwrap()
+        });
+        // Random sequence compresses to about a quarter of its size, so
+        // there should be about five chunks, fewer depending on how
+        // much the encoder holds back.
+        assert!(paths.len() > 1 && paths.len() < 10, "{:?}", paths);
+        let mut decompressed = String::new();
+        for path in paths.iter() {
+            assert!(path.with_extension("").with_extension("json").exists());
+            let mut chunk = String::new();
+            MultiGzDecoder::new(File::open(path).unwrap())
+                .read_to_string(&mut chunk)
+                .unwrap();
+            decompressed += chunk.strip_prefix("##maf version=1\n").unwrap();
+        }
+        assert_eq!(decompressed, input_maf);
+    }
+
+    #[test]
+    fn test_split_trees() {
+        let input_maf = "a
+s Rhesus.chr21 10 4 + 100 ACGT
+s Human.chr21 0 4 + 100 ACGT
+
+a
+s Rhesus.chr22 0 3 + 100 ACG
+s Mouse.chr2 5 3 + 100 ACC
+";
+        let tree = Tree::parse_newick("((Human:0.125,Rhesus:0.125):0.125,Mouse:0.5);");
+        let tempdir = TempDir::new().unwrap();
+        split_maf(
+            &mut input_maf.as_bytes(),
+            100,
+            None,
+            tempdir.path().to_str().unwrap(),
+            &mut vec![],
+            false,
+            Some(&tree),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            read_to_string(tempdir.path().join("chr21.10.nwk")).unwrap(),
+            "(Human:0.125,Rhesus:0.125);\n"
+        );
+        assert_eq!(
+            read_to_string(tempdir.path().join("chr22.0.nwk")).unwrap(),
+            "(Rhesus:0.25,Mouse:0.5);\n"
+        );
+    }
 }
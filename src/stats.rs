@@ -0,0 +1,279 @@
+use maf_stream::alphabet::{is_gap, Alphabet};
+use maf_stream::{block_id, chrom_part, MAFItems, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFItem};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+/// Counts of the bases in a species' rows.
+#[derive(Debug, Default, PartialEq)]
+struct SpeciesStats {
+    blocks: usize,
+    bases: u64,
+    /// Unknown residues (N, or X for proteins).
+    unknown: u64,
+    /// Lowercase (soft-masked) residues.
+    masked: u64,
+}
+
+impl SpeciesStats {
+    fn add_row(&mut self, entry: &MAFBlockAlignedEntry, alphabet: &dyn Alphabet) {
+        let unknown = alphabet.unknown();
+        for c in entry.alignment.iter().filter(|c| !is_gap(**c)) {
+            self.bases += 1;
+            if c.to_ascii_uppercase() == unknown {
+                self.unknown += 1;
+            }
+            if c.is_ascii_lowercase() {
+                self.masked += 1;
+            }
+        }
+    }
+}
+
+/// The fraction of a row's bases that are unknown, or None if it has
+/// none.
+fn unknown_fraction(entry: &MAFBlockAlignedEntry, alphabet: &dyn Alphabet) -> Option<f64> {
+    let mut stats = SpeciesStats::default();
+    stats.add_row(entry, alphabet);
+    if stats.bases == 0 {
+        None
+    } else {
+        Some(stats.unknown as f64 / stats.bases as f64)
+    }
+}
+
+/// Write a block's row of the per-block TSV: its ID, its reference
+/// interval on the forward strand, its number of species and columns,
+/// the fraction of its rows' characters that are gaps, and its score.
+fn write_block_row(output: &mut dyn Write, block: &MAFBlock, number: usize) {
+    let ref_entry = match block.aligned_entries().next() {
+        Some(entry) => entry,
+        None => return,
+    };
+    let (start, end) = ref_entry.forward_interval();
+    let mut species = vec![];
+    let mut num_chars = 0;
+    let mut num_gaps = 0;
+    for entry in block.aligned_entries() {
+        let name = entry.seq.split('.').next().unwrap();
+        if !species.contains(&name) {
+            species.push(name);
+        }
+        num_chars += entry.alignment.len();
+        num_gaps += entry.alignment.iter().filter(|c| is_gap(**c)).count();
+    }
+    let gap_frac = if num_chars == 0 {
+        "NA".to_string()
+    } else {
+        format!("{:.4}", num_gaps as f64 / num_chars as f64)
+    };
+    writeln!(
+        output,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        block_id(block, number),
+        chrom_part(&ref_entry.seq),
+        start,
+        end,
+        species.len(),
+        ref_entry.alignment.len(),
+        gap_frac,
+        block
+            .metadata
+            .get("score")
+            .map_or("NA", |score| score.as_str())
+    )
+    .ok();
+}
+
+/// Write a TSV of, for each species, the number of blocks it's in, its
+/// number of bases, and the fractions of those that are unknown (the
+/// `alphabet`'s N or X) and soft-masked (lowercase). Blocks whose reference
+/// row has a greater fraction of unknown bases than `max_unknown_frac`
+/// are dropped, and left out of the statistics; the blocks that are
+/// kept are written to `kept`, if given.
+///
+/// If `per_block`, a row for each kept block (as `write_block_row`
+/// describes) is written as it's read instead, for plotting along the
+/// chromosomes; blocks are numbered from 1 counting dropped ones, so
+/// their IDs don't depend on the threshold. Returns the numbers of
+/// blocks kept and dropped.
+pub fn stats(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    max_unknown_frac: Option<f64>,
+    mut kept: Option<&mut dyn MAFOutput>,
+    per_block: bool,
+    alphabet: &dyn Alphabet,
+) -> Result<(usize, usize), MAFParseError> {
+    if per_block {
+        writeln!(
+            output,
+            "#id\tchrom\tstart\tend\tspecies\tcolumns\tgap_frac\tscore"
+        )
+        .ok();
+    }
+    let mut species_stats: BTreeMap<String, SpeciesStats> = BTreeMap::new();
+    let mut num_kept = 0;
+    let mut num_dropped = 0;
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(comment) => {
+                if let Some(kept) = kept.as_mut() {
+                    maf_stream::write_comment(*kept, &comment);
+                }
+                continue;
+            }
+        };
+        let ref_unknown = block
+            .aligned_entries()
+            .next()
+            .and_then(|entry| unknown_fraction(entry, alphabet));
+        if let (Some(max), Some(frac)) = (max_unknown_frac, ref_unknown) {
+            if frac > max {
+                num_dropped += 1;
+                continue;
+            }
+        }
+        num_kept += 1;
+        if per_block {
+            write_block_row(output, &block, num_kept + num_dropped);
+        }
+        let mut seen = vec![];
+        for entry in block.aligned_entries() {
+            let species = entry.seq.split('.').next().unwrap();
+            let stats = species_stats.entry(species.to_string()).or_default();
+            if !seen.contains(&species) {
+                stats.blocks += 1;
+                seen.push(species);
+            }
+            stats.add_row(entry, alphabet);
+        }
+        if let Some(kept) = kept.as_mut() {
+            kept.write_block(&block);
+        }
+    }
+    let fraction = |count: u64, total: u64| {
+        if total == 0 {
+            "NA".to_string()
+        } else {
+            format!("{:.4}", count as f64 / total as f64)
+        }
+    };
+    if !per_block {
+        writeln!(output, "#species\tblocks\tbases\tunknown_frac\tmasked_frac").ok();
+        for (species, stats) in species_stats.iter() {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}\t{}",
+                species,
+                stats.blocks,
+                stats.bases,
+                fraction(stats.unknown, stats.bases),
+                fraction(stats.masked, stats.bases)
+            )
+            .ok();
+        }
+    }
+    items.finish()?;
+    Ok((num_kept, num_dropped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::alphabet::Nucleotides;
+
+    #[test]
+    fn test_stats() {
+        let maf = "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACgt
+s mm39.chr2 0 4 + 100 ANNT
+s mm39.chr3 0 2 + 100 a--T
+
+a
+s hg38.chr1 4 4 + 100 NNNA
+s rn7.chr1 0 4 + 100 ACGT
+";
+        let mut output = vec![];
+        let counts = stats(
+            &mut maf.as_bytes(),
+            &mut output,
+            None,
+            None,
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(counts, (2, 0));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#species\tblocks\tbases\tunknown_frac\tmasked_frac
+hg38\t2\t8\t0.3750\t0.2500
+mm39\t1\t6\t0.3333\t0.1667
+rn7\t1\t4\t0.0000\t0.0000
+"
+        );
+
+        let mut output = vec![];
+        let mut kept = vec![];
+        let counts = stats(
+            &mut maf.as_bytes(),
+            &mut output,
+            Some(0.5),
+            Some(&mut kept),
+            false,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(counts, (1, 1));
+        assert!(!String::from_utf8(output).unwrap().contains("rn7"));
+        assert_eq!(
+            String::from_utf8(kept).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 0 4 + 100 ACgt
+s mm39.chr2 0 4 + 100 ANNT
+s mm39.chr3 0 2 + 100 a--T
+
+"
+        );
+    }
+
+    #[test]
+    fn test_stats_per_block() {
+        let maf = "a score=12.5
+s hg38.chr1 10 3 + 100 AC-g
+s panTro6.chr1 10 4 + 100 ACTG
+s panTro6.chr5 0 2 + 100 A--G
+
+a id=b2
+s hg38.chr1 84 2 - 100 NN
+
+a
+s hg38.chr2 0 2 + 100 AC
+s mm39.chr2 0 1 + 100 -C
+";
+        let mut output = vec![];
+        let counts = stats(
+            &mut maf.as_bytes(),
+            &mut output,
+            Some(0.5),
+            None,
+            true,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(counts, (2, 1));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "#id\tchrom\tstart\tend\tspecies\tcolumns\tgap_frac\tscore
+1\tchr1\t10\t13\t2\t4\t0.2500\t12.5
+3\tchr2\t0\t2\t2\t2\t0.2500\tNA
+"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use maf_stream::{write_unmodified, MAFItems, MAFOutput, Rng};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFItem;
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+/// Output the first `n` blocks, along with any comments preceding them.
+/// The header (comments before the first block) is always kept, so
+/// that even `n = 0` gives a valid MAF.
+pub fn head(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    n: usize,
+) -> Result<(), MAFParseError> {
+    let mut num_blocks = 0;
+    let mut items = MAFItems::new(input);
+    while let Some((item, raw)) = items.next_raw() {
+        if let MAFItem::Block(_) = item {
+            if num_blocks == n {
+                break;
+            }
+            num_blocks += 1;
+        }
+        write_unmodified(output, &item, &raw);
+        if num_blocks == n && n > 0 {
+            break;
+        }
+    }
+    items.finish()
+}
+
+/// Output the last `n` blocks. Only the header (comments before the
+/// first block) is kept.
+pub fn tail(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    n: usize,
+) -> Result<(), MAFParseError> {
+    let mut blocks: VecDeque<(MAFItem, String)> = VecDeque::with_capacity(n);
+    let mut in_header = true;
+    let mut items = MAFItems::new(input);
+    while let Some((item, raw)) = items.next_raw() {
+        match item {
+            MAFItem::Comment(_) => {
+                if in_header {
+                    write_unmodified(output, &item, &raw);
+                }
+            }
+            MAFItem::Block(_) => {
+                in_header = false;
+                if n == 0 {
+                    continue;
+                }
+                if blocks.len() == n {
+                    blocks.pop_front();
+                }
+                blocks.push_back((item, raw));
+            }
+        }
+    }
+    for (block, raw) in blocks {
+        write_unmodified(output, &block, &raw);
+    }
+    items.finish()
+}
+
+/// Output each block with probability `fraction`. Only the header
+/// (comments before the first block) is kept.
+pub fn sample(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    fraction: f64,
+    seed: u64,
+) -> Result<(), MAFParseError> {
+    let mut rng = Rng::new(seed);
+    let mut in_header = true;
+    let mut items = MAFItems::new(input);
+    while let Some((item, raw)) = items.next_raw() {
+        match item {
+            MAFItem::Comment(_) => {
+                if in_header {
+                    write_unmodified(output, &item, &raw);
+                }
+            }
+            MAFItem::Block(_) => {
+                in_header = false;
+                if rng.next_f64() < fraction {
+                    write_unmodified(output, &item, &raw);
+                }
+            }
+        }
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "##maf version=1
+a
+s       Rhesus.chr21    0       4       +       19571763        AATT
+
+# mid-file comment
+a
+s       Rhesus.chr21    4       4       +       19571763        CTGT
+
+a
+s       Rhesus.chr21    8       4       +       19571763        GAAG
+";
+
+    // Blocks are passed through byte-for-byte.
+    #[test]
+    fn test_head() {
+        let mut output = vec![];
+        head(&mut MAF.as_bytes(), &mut output, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s       Rhesus.chr21    0       4       +       19571763        AATT
+
+# mid-file comment
+a
+s       Rhesus.chr21    4       4       +       19571763        CTGT
+
+"
+        );
+
+        let mut output = vec![];
+        head(&mut MAF.as_bytes(), &mut output, 0).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "##maf version=1\n");
+    }
+
+    #[test]
+    fn test_tail() {
+        let mut output = vec![];
+        tail(&mut MAF.as_bytes(), &mut output, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s       Rhesus.chr21    4       4       +       19571763        CTGT
+
+a
+s       Rhesus.chr21    8       4       +       19571763        GAAG
+
+"
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut all = vec![];
+        sample(&mut MAF.as_bytes(), &mut all, 1.0, 0).unwrap();
+        let all = String::from_utf8(all).unwrap();
+        assert_eq!(all.lines().filter(|l| *l == "a").count(), 3);
+
+        let mut none = vec![];
+        sample(&mut MAF.as_bytes(), &mut none, 0.0, 0).unwrap();
+        assert_eq!(String::from_utf8(none).unwrap(), "##maf version=1\n");
+
+        // Same seed should give the same sample.
+        let mut first = vec![];
+        let mut second = vec![];
+        sample(&mut MAF.as_bytes(), &mut first, 0.5, 42).unwrap();
+        sample(&mut MAF.as_bytes(), &mut second, 0.5, 42).unwrap();
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,264 @@
+//! Generators for random but well-formed MAFs, for tests and
+//! benchmarks.
+
+use crate::{MAFOutput, Rng};
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry, Strand};
+use std::collections::BTreeMap;
+
+const BASES: &[u8; 4] = b"ACGT";
+
+/// Distribution of the number of reference bases in each block.
+#[derive(Debug, Clone, Copy)]
+pub enum LengthDistribution {
+    /// Uniform between `min` and `max` (inclusive), with `min <= max`.
+    Uniform { min: usize, max: usize },
+    /// Geometric with the given mean (at least 1).
+    Geometric { mean: f64 },
+}
+
+impl LengthDistribution {
+    fn sample(&self, rng: &mut Rng) -> usize {
+        match *self {
+            LengthDistribution::Uniform { min, max } => {
+                min + rng.below((max - min + 1) as u64) as usize
+            }
+            LengthDistribution::Geometric { mean } => {
+                if mean <= 1.0 {
+                    return 1;
+                }
+                let u = 1.0 - rng.next_f64();
+                1 + (u.ln() / (1.0 - 1.0 / mean).ln()).floor() as usize
+            }
+        }
+    }
+}
+
+/// Parameters for a synthetic MAF. Species are named `species0`,
+/// `species1`, ..., each with a sequence `chr1` holding its orthologous
+/// rows and a sequence `chrDup` holding any duplicated rows; `species0`
+/// is the reference and appears first in every block.
+#[derive(Debug, Clone)]
+pub struct SyntheticMaf {
+    pub num_species: usize,
+    pub num_blocks: usize,
+    pub block_length: LengthDistribution,
+    /// Chance of each non-reference base differing from the reference.
+    pub substitution_rate: f64,
+    /// Chance, per non-reference base, of an indel: half of the time a
+    /// deletion of the base, half an insertion of a new base after it.
+    pub indel_rate: f64,
+    /// Chance, per block and non-reference species, of an extra
+    /// (paralogous) row for that species.
+    pub duplication_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for SyntheticMaf {
+    fn default() -> Self {
+        SyntheticMaf {
+            num_species: 3,
+            num_blocks: 100,
+            block_length: LengthDistribution::Uniform { min: 10, max: 100 },
+            substitution_rate: 0.1,
+            indel_rate: 0.0,
+            duplication_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl SyntheticMaf {
+    /// Mutate the reference into a non-reference row. Deleted bases are
+    /// gaps; inserted bases are returned as the (0-based) reference
+    /// column they follow.
+    fn mutate(&self, reference: &[u8], rng: &mut Rng) -> (Vec<u8>, Vec<(usize, u8)>) {
+        let mut row = vec![];
+        let mut insertions = vec![];
+        for (i, base) in reference.iter().enumerate() {
+            let indel = rng.next_f64() < self.indel_rate;
+            if indel && rng.below(2) == 0 {
+                row.push(b'-');
+                continue;
+            }
+            if rng.next_f64() < self.substitution_rate {
+                row.push(BASES[rng.below(4) as usize]);
+            } else {
+                row.push(*base);
+            }
+            if indel {
+                insertions.push((i, BASES[rng.below(4) as usize]));
+            }
+        }
+        (row, insertions)
+    }
+
+    /// Generate the blocks. Each species' rows are colinear, on the
+    /// positive strand, and contiguous from block to block within each
+    /// sequence.
+    pub fn blocks(&self) -> Vec<MAFBlock> {
+        let mut rng = Rng::new(self.seed);
+        // Next position in chr1 and chrDup of each species.
+        let mut positions = vec![(0, 0); self.num_species];
+        let mut blocks = vec![];
+        for _ in 0..self.num_blocks {
+            let length = self.block_length.sample(&mut rng);
+            let reference: Vec<u8> = (0..length).map(|_| BASES[rng.below(4) as usize]).collect();
+            // (species, is duplicate, row, insertions)
+            let mut rows = vec![(0, false, reference.clone(), vec![])];
+            for species in 1..self.num_species {
+                let (row, insertions) = self.mutate(&reference, &mut rng);
+                rows.push((species, false, row, insertions));
+                if rng.next_f64() < self.duplication_rate {
+                    let (row, insertions) = self.mutate(&reference, &mut rng);
+                    rows.push((species, true, row, insertions));
+                }
+            }
+
+            // Lay out the columns, adding a column for every insertion.
+            let mut alignments: Vec<Vec<u8>> = vec![vec![]; rows.len()];
+            let mut next_insertion = vec![0; rows.len()];
+            for column in 0..length {
+                for (alignment, row) in alignments.iter_mut().zip(rows.iter()) {
+                    alignment.push(row.2[column]);
+                }
+                for (r, row) in rows.iter().enumerate() {
+                    let insertions = &row.3;
+                    while next_insertion[r] < insertions.len()
+                        && insertions[next_insertion[r]].0 == column
+                    {
+                        for (other, alignment) in alignments.iter_mut().enumerate() {
+                            alignment.push(if other == r {
+                                insertions[next_insertion[r]].1
+                            } else {
+                                b'-'
+                            });
+                        }
+                        next_insertion[r] += 1;
+                    }
+                }
+            }
+
+            let mut entries = vec![];
+            for ((species, duplicate, _, _), alignment) in rows.iter().zip(alignments) {
+                let aligned_length = alignment.iter().filter(|b| **b != b'-').count() as u64;
+                if aligned_length == 0 {
+                    continue;
+                }
+                let position = if *duplicate {
+                    &mut positions[*species].1
+                } else {
+                    &mut positions[*species].0
+                };
+                entries.push(MAFBlockEntry::AlignedEntry(MAFBlockAlignedEntry {
+                    seq: format!(
+                        "species{}.{}",
+                        species,
+                        if *duplicate { "chrDup" } else { "chr1" }
+                    ),
+                    start: *position,
+                    aligned_length,
+                    strand: Strand::Positive,
+                    // Filled in once all the blocks are generated.
+                    sequence_size: 0,
+                    alignment,
+                    context: None,
+                    qualities: None,
+                }));
+                *position += aligned_length;
+            }
+            blocks.push(MAFBlock {
+                entries,
+                metadata: BTreeMap::new(),
+            });
+        }
+
+        for block in blocks.iter_mut() {
+            for entry in block.entries.iter_mut() {
+                if let MAFBlockEntry::AlignedEntry(e) = entry {
+                    let species: usize = e.seq["species".len()..e.seq.find('.').unwrap()]
+                        .parse()
+                        .unwrap();
+                    let (size, dup_size) = positions[species];
+                    e.sequence_size = if e.seq.ends_with("chrDup") {
+                        dup_size
+                    } else {
+                        size
+                    };
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Write the MAF, header included.
+    pub fn write_maf(&self, output: &mut dyn MAFOutput) {
+        writeln!(output, "##maf version=1").ok();
+        for block in self.blocks() {
+            output.write_block(&block);
+        }
+    }
+
+    /// Generate the MAF as text, header included.
+    pub fn to_maf_string(&self) -> String {
+        let mut maf = vec![];
+        self.write_maf(&mut maf);
+        String::from_utf8(maf).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multiple_alignment_format::parser::next_maf_item;
+    use multiple_alignment_format::MAFItem;
+
+    #[test]
+    fn test_synthetic_maf_parses() {
+        let synthetic = SyntheticMaf {
+            num_species: 4,
+            num_blocks: 20,
+            ..Default::default()
+        };
+        let maf = synthetic.to_maf_string();
+        let mut input = maf.as_bytes();
+        let mut num_blocks = 0;
+        while let Ok(item) = next_maf_item(&mut input) {
+            if let MAFItem::Block(block) = item {
+                assert_eq!(block.aligned_entries().count(), 4);
+                num_blocks += 1;
+            }
+        }
+        assert_eq!(num_blocks, 20);
+        // Same seed, same MAF.
+        assert_eq!(maf, synthetic.to_maf_string());
+    }
+
+    #[test]
+    fn test_synthetic_indels_and_dups() {
+        let synthetic = SyntheticMaf {
+            num_species: 5,
+            num_blocks: 50,
+            block_length: LengthDistribution::Geometric { mean: 30.0 },
+            indel_rate: 0.2,
+            duplication_rate: 0.5,
+            ..Default::default()
+        };
+        let mut seen_dup = false;
+        for block in synthetic.blocks() {
+            let entries: Vec<_> = block.aligned_entries().collect();
+            let columns = entries[0].alignment.len();
+            for entry in entries.iter() {
+                assert_eq!(entry.alignment.len(), columns);
+                let bases = entry.alignment.iter().filter(|b| **b != b'-').count();
+                assert_eq!(bases as u64, entry.aligned_length);
+                assert!(entry.start + entry.aligned_length <= entry.sequence_size);
+                seen_dup |= entry.seq.ends_with("chrDup");
+            }
+            // No all-gap columns.
+            for column in 0..columns {
+                assert!(entries.iter().any(|e| e.alignment[column] != b'-'));
+            }
+        }
+        assert!(seen_dup);
+    }
+}
@@ -0,0 +1,69 @@
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use std::io::BufRead;
+
+/// Keep only the alignment columns at every `every`th reference
+/// position (those whose forward-strand position is a multiple of
+/// `every`, so the choice doesn't depend on where blocks break), each
+/// as a block of its own, since the columns between them are gone.
+/// Rows with a gap in a kept column are left out of its block, and
+/// columns where the reference has a gap are dropped. Returns the
+/// number of columns kept.
+pub fn thin(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    every: u64,
+) -> Result<usize, MAFParseError> {
+    let mut num_kept = 0;
+    for_each_block(input, output, |block, output| {
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => return,
+        };
+        for (column, pos) in ref_entry.column_positions().enumerate() {
+            if pos.is_some_and(|pos| ref_entry.forward_position(pos) % every == 0) {
+                if let Some(column) = block.slice_columns(column, column + 1) {
+                    output.write_block(&column);
+                    num_kept += 1;
+                }
+            }
+        }
+    })?;
+    Ok(num_kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thin() {
+        let maf = "##maf version=1
+a score=1
+s hg38.chr1 8 5 + 100 AC-GTA
+s mm39.chr2 0 5 + 100 A-CGTA
+
+a
+s hg38.chr1 88 3 - 100 ACG
+s mm39.chr2 5 3 + 100 ACG
+";
+        let mut output = vec![];
+        let num_kept = thin(&mut maf.as_bytes(), &mut output, 5).unwrap();
+        // Forward-strand position 10 is in both blocks: the second has
+        // it at 89 on the negative strand (100 - 1 - 89 = 10).
+        assert_eq!(num_kept, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a score=1
+s hg38.chr1 10 1 + 100 G
+s mm39.chr2 2 1 + 100 G
+
+a
+s hg38.chr1 89 1 - 100 C
+s mm39.chr2 6 1 + 100 C
+
+"
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, complement, MAFItems, MAFOutput};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFItem, Strand};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// A species' character in phast's tuples: the base, a gap, `N` for an
+/// ambiguous base, or `*` where the species isn't aligned.
+fn ss_char(c: u8) -> u8 {
+    match c.to_ascii_uppercase() {
+        c @ (b'A' | b'C' | b'G' | b'T') => c,
+        c if is_gap(c) => b'-',
+        _ => b'N',
+    }
+}
+
+/// The distinct column tuples, and, for the ordered format, which one
+/// each reference position has.
+struct Tuples {
+    index: HashMap<Vec<u8>, u32>,
+    tuples: Vec<(Vec<u8>, u64)>,
+    /// The first reference position, and the tuple of each one from it.
+    start: Option<u64>,
+    order: Vec<Option<u32>>,
+}
+
+impl Tuples {
+    /// Count `count` more columns with this tuple, returning its index.
+    fn add(&mut self, tuple: &[u8], count: u64) -> u32 {
+        let i = match self.index.get(tuple) {
+            Some(i) => *i,
+            None => {
+                self.tuples.push((tuple.to_vec(), 0));
+                self.index
+                    .insert(tuple.to_vec(), self.tuples.len() as u32 - 1);
+                self.tuples.len() as u32 - 1
+            }
+        };
+        self.tuples[i as usize].1 += count;
+        i
+    }
+}
+
+/// Write the columns of the alignment in phast's sufficient statistics
+/// (SS) format, as `msa_view --out-format SS` would: each distinct
+/// column tuple, with one character per species in the order given,
+/// and its count. Columns where the reference has a gap are dropped,
+/// and the rest are put on the reference's forward strand.
+///
+/// If `ordered`, the tuple at each reference position is also listed,
+/// as phastCons and phyloP's per-base scores need, from the first
+/// position covered (`IDX_OFFSET`) to the last; positions no block
+/// covers are all `*`, and ones an earlier block covered are skipped.
+/// This needs blocks on a single reference sequence, none starting
+/// before the first. Returns the number of tuples and columns.
+pub fn to_ss(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    species: &[&str],
+    ordered: bool,
+) -> Result<(usize, u64), MAFParseError> {
+    let mut tuples = Tuples {
+        index: HashMap::new(),
+        tuples: vec![],
+        start: None,
+        order: vec![],
+    };
+    let mut ref_chrom: Option<String> = None;
+    let mut num_overlapping = 0;
+    let mut tuple = vec![0; species.len()];
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        let block = match item {
+            MAFItem::Block(block) => block,
+            MAFItem::Comment(_) => continue,
+        };
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if ordered {
+            let chrom = chrom_part(&ref_entry.seq);
+            match &ref_chrom {
+                Some(ref_chrom) if *ref_chrom != chrom => panic!(
+                    "Ordered SS output needs a single reference sequence, but found {} and {}",
+                    ref_chrom, chrom
+                ),
+                Some(_) => (),
+                None => ref_chrom = Some(chrom),
+            }
+        }
+        let rows: Vec<Option<&Vec<u8>>> = species
+            .iter()
+            .map(|name| {
+                block
+                    .aligned_entries()
+                    .find(|entry| entry.seq.split('.').next() == Some(*name))
+                    .map(|entry| &entry.alignment)
+            })
+            .collect();
+        for (i, pos) in ref_entry.column_positions().enumerate() {
+            let pos = match pos {
+                Some(pos) => ref_entry.forward_position(pos),
+                None => continue,
+            };
+            for (c, row) in tuple.iter_mut().zip(rows.iter()) {
+                *c = row.map_or(b'*', |row| ss_char(row[i]));
+            }
+            if ref_entry.strand == Strand::Negative {
+                tuple.iter_mut().for_each(|c| *c = complement(*c));
+            }
+            if !ordered {
+                tuples.add(&tuple, 1);
+                continue;
+            }
+            let start = *tuples.start.get_or_insert(pos);
+            if pos < start {
+                panic!("Ordered SS output needs blocks sorted by reference position");
+            }
+            let offset = (pos - start) as usize;
+            if offset >= tuples.order.len() {
+                tuples.order.resize(offset + 1, None);
+            }
+            if tuples.order[offset].is_some() {
+                num_overlapping += 1;
+                continue;
+            }
+            tuples.order[offset] = Some(tuples.add(&tuple, 1));
+        }
+    }
+    if num_overlapping > 0 {
+        output.warn(&format!(
+            "Skipped {} reference positions covered by more than one block",
+            num_overlapping
+        ));
+    }
+    let num_uncovered = tuples.order.iter().filter(|i| i.is_none()).count() as u64;
+    if num_uncovered > 0 {
+        let missing = tuples.add(&vec![b'*'; species.len()], num_uncovered);
+        for i in tuples.order.iter_mut().filter(|i| i.is_none()) {
+            *i = Some(missing);
+        }
+    }
+    let length: u64 = tuples.tuples.iter().map(|(_, count)| count).sum();
+    writeln!(output, "NSEQS = {}", species.len()).ok();
+    writeln!(output, "LENGTH = {}", length).ok();
+    writeln!(output, "TUPLE_SIZE = 1").ok();
+    writeln!(output, "NTUPLES = {}", tuples.tuples.len()).ok();
+    writeln!(output, "NAMES = {}", species.join(",")).ok();
+    writeln!(output, "ALPHABET = ACGT").ok();
+    writeln!(output, "IDX_OFFSET = {}", tuples.start.unwrap_or(0)).ok();
+    writeln!(output, "NCATS = -1").ok();
+    writeln!(output).ok();
+    for (i, (tuple, count)) in tuples.tuples.iter().enumerate() {
+        writeln!(
+            output,
+            "{}\t{}\t{}",
+            i,
+            String::from_utf8_lossy(tuple),
+            count
+        )
+        .ok();
+    }
+    if ordered {
+        writeln!(output).ok();
+        for i in tuples.order.iter() {
+            writeln!(output, "{}", i.unwrap()).ok();
+        }
+    }
+    items.finish()?;
+    Ok((tuples.tuples.len(), length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s hg38.chr1 10 3 + 100 AC-g
+s panTro6.chr1 10 4 + 100 ACTG
+s mm39.chr2 0 3 + 100 AN-G
+
+a
+s hg38.chr1 84 2 - 100 GT
+s panTro6.chr1 20 2 + 100 GT
+";
+
+    #[test]
+    fn test_to_ss() {
+        let species = ["hg38", "panTro6", "mm39"];
+        let mut output = vec![];
+        let counts = to_ss(&mut MAF.as_bytes(), &mut output, &species, false).unwrap();
+        assert_eq!(counts, (5, 5));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "NSEQS = 3
+LENGTH = 5
+TUPLE_SIZE = 1
+NTUPLES = 5
+NAMES = hg38,panTro6,mm39
+ALPHABET = ACGT
+IDX_OFFSET = 0
+NCATS = -1
+
+0\tAAA\t1
+1\tCCN\t1
+2\tGGG\t1
+3\tCC*\t1
+4\tAA*\t1
+"
+        );
+
+        // The second block is at 14-16 on the forward strand, leaving
+        // position 13 uncovered.
+        let mut output = vec![];
+        let counts = to_ss(&mut MAF.as_bytes(), &mut output, &species, true).unwrap();
+        assert_eq!(counts, (6, 6));
+        assert!(String::from_utf8(output).unwrap().ends_with(
+            "IDX_OFFSET = 10
+NCATS = -1
+
+0\tAAA\t1
+1\tCCN\t1
+2\tGGG\t1
+3\tCC*\t1
+4\tAA*\t1
+5\t***\t1
+
+0
+1
+2
+5
+4
+3
+"
+        ));
+    }
+}
@@ -0,0 +1,187 @@
+use maf_stream::alphabet::{is_gap, Alphabet};
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFItem, Strand};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufWriter, Seek, SeekFrom, Write};
+use tempfile::tempfile;
+
+/// How the tensor is written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TensorFormat {
+    /// A NumPy .npy array, loadable with `numpy.load`.
+    Npy,
+    /// Just the bytes of the array, in C order.
+    Raw,
+}
+
+/// The header of a .npy file (version 1.0) holding a C-order uint8
+/// array of the given shape, padded so the data is 64-byte aligned.
+fn npy_header(shape: &[usize]) -> Vec<u8> {
+    let shape: Vec<String> = shape.iter().map(|n| n.to_string()).collect();
+    let mut dict = format!(
+        "{{'descr': '|u1', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
+    );
+    // Magic, version, and header length take 10 bytes, and the header
+    // ends with a newline.
+    while (10 + dict.len() + 1) % 64 != 0 {
+        dict.push(' ');
+    }
+    dict.push('\n');
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+/// Write windows of `width` reference bases, every `step` bases within
+/// each block, as a uint8 array of shape (windows, species, width,
+/// residues), one-hot encoding each species' residue (in the order of
+/// the alphabet: ACGT, or the amino acids with `--protein`) in each
+/// column. Columns where the reference has a gap are dropped, and
+/// gaps, unknown residues, ambiguity codes, and species missing from
+/// the block are all zeros. Only the first row of each species is
+/// used, and species not listed are ignored. A TSV of the forward-strand
+/// reference interval and strand of each window is written to
+/// `manifest`. The array is spooled to a temporary file until the
+/// number of windows is known. Returns the number of windows.
+#[allow(clippy::too_many_arguments)]
+pub fn to_tensor(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    manifest: &mut dyn Write,
+    species: &[String],
+    width: usize,
+    step: usize,
+    format: TensorFormat,
+    alphabet: &dyn Alphabet,
+) -> Result<usize, MAFParseError> {
+    assert!(width > 0 && step > 0, "Width and step must be positive");
+    let num_residues = alphabet.residues().len();
+    let species_idx: HashMap<&str, usize> = species
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.as_str(), i))
+        .collect();
+    let mut spool = BufWriter::new(tempfile().expect("Couldn't open temporary file"));
+    let mut num_windows = 0;
+    let mut window = vec![0u8; species.len() * width * num_residues];
+    writeln!(manifest, "#index\tchrom\tstart\tend\tstrand").ok();
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            let ref_entry = match block.aligned_entries().next() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let mut rows = vec![None; species.len()];
+            for entry in block.aligned_entries() {
+                if let Some(i) = species_idx.get(entry.seq.split('.').next().unwrap()) {
+                    rows[*i].get_or_insert(entry);
+                }
+            }
+            let columns: Vec<usize> = (0..ref_entry.alignment.len())
+                .filter(|i| !is_gap(ref_entry.alignment[*i]))
+                .collect();
+            let mut offset = 0;
+            while offset + width <= columns.len() {
+                window.iter_mut().for_each(|x| *x = 0);
+                for (s, row) in rows.iter().enumerate() {
+                    let row = match row {
+                        Some(row) => row,
+                        None => continue,
+                    };
+                    for (pos, column) in columns[offset..offset + width].iter().enumerate() {
+                        if let Some(residue) = alphabet.index(row.alignment[*column]) {
+                            window[(s * width + pos) * num_residues + residue] = 1;
+                        }
+                    }
+                }
+                spool.write_all(&window).expect("failed to write");
+                let (start, strand) = match ref_entry.strand {
+                    Strand::Positive => (ref_entry.start + offset as u64, '+'),
+                    Strand::Negative => (
+                        ref_entry.sequence_size - ref_entry.start - (offset + width) as u64,
+                        '-',
+                    ),
+                };
+                writeln!(
+                    manifest,
+                    "{}\t{}\t{}\t{}\t{}",
+                    num_windows,
+                    chrom_part(&ref_entry.seq),
+                    start,
+                    start + width as u64,
+                    strand
+                )
+                .ok();
+                num_windows += 1;
+                offset += step;
+            }
+        }
+    }
+    if format == TensorFormat::Npy {
+        let shape = [num_windows, species.len(), width, num_residues];
+        output.write_all(&npy_header(&shape)).ok();
+    }
+    let mut spool = spool.into_inner().expect("failed to write");
+    spool
+        .seek(SeekFrom::Start(0))
+        .expect("Couldn't rewind temporary file");
+    io::copy(&mut spool, output).expect("failed to write");
+    items.finish()?;
+    Ok(num_windows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maf_stream::alphabet::Nucleotides;
+
+    #[test]
+    fn test_to_tensor() {
+        let maf = "a
+s hg38.chr1 10 3 + 100 AC-G
+s mm39.chr2 0 4 + 100 ATTN
+s rn7.chr3 0 2 + 100 --GA
+
+a
+s hg38.chr1 20 1 + 100 A
+s mm39.chr2 10 1 + 100 A
+";
+        let species = vec!["hg38".to_string(), "mm39".to_string()];
+        let mut output = vec![];
+        let mut manifest = vec![];
+        let num_windows = to_tensor(
+            &mut maf.as_bytes(),
+            &mut output,
+            &mut manifest,
+            &species,
+            2,
+            1,
+            TensorFormat::Npy,
+            &Nucleotides,
+        )
+        .unwrap();
+        assert_eq!(num_windows, 2);
+        assert_eq!(
+            String::from_utf8(manifest).unwrap(),
+            "#index\tchrom\tstart\tend\tstrand\n0\tchr1\t10\t12\t+\n1\tchr1\t11\t13\t+\n"
+        );
+        let header_len = output.iter().position(|c| *c == b'\n').unwrap() + 1;
+        assert_eq!(header_len % 64, 0);
+        let header = String::from_utf8_lossy(&output[..header_len]);
+        assert!(header.contains("'shape': (2, 2, 2, 4)"));
+        #[rustfmt::skip]
+        let expected: Vec<u8> = vec![
+            // Window 0: hg38 AC, mm39 AT.
+            1, 0, 0, 0, 0, 1, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 1,
+            // Window 1: hg38 CG, mm39 TN (N is all zeros).
+            0, 1, 0, 0, 0, 0, 1, 0,
+            0, 0, 0, 1, 0, 0, 0, 0,
+        ];
+        assert_eq!(&output[header_len..], &expected[..]);
+    }
+}
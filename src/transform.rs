@@ -0,0 +1,226 @@
+//! Per-block transforms that can be chained and streamed over a MAF,
+//! so that new ones can be written outside this crate.
+use crate::seq_filter::SeqFilter;
+use crate::{chrom_part, for_each_block, range_contains_pos, MAFOutput, Range};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFBlockEntry};
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufRead;
+
+pub trait BlockTransform {
+    /// Turn a block into any number of blocks (none to drop it).
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock>;
+}
+
+/// Transforms applied one after another, each to every block the last
+/// one gave.
+#[derive(Default)]
+pub struct Chain<'a>(pub Vec<Box<dyn BlockTransform + 'a>>);
+
+impl<'a> Chain<'a> {
+    pub fn then(mut self, transform: impl BlockTransform + 'a) -> Self {
+        self.0.push(Box::new(transform));
+        self
+    }
+}
+
+impl BlockTransform for Chain<'_> {
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+        let mut blocks = vec![block];
+        for transform in self.0.iter_mut() {
+            blocks = blocks
+                .into_iter()
+                .flat_map(|block| transform.transform(block))
+                .collect();
+        }
+        blocks
+    }
+}
+
+/// Keeps only some rows, by sequence name, dropping whole blocks whose
+/// reference row isn't kept.
+pub struct Subset(pub SeqFilter);
+
+impl Subset {
+    /// Keep only the rows of the given species (the part of each
+    /// sequence name before the first '.'), and not matching `exclude`.
+    pub fn species<S: AsRef<str>>(
+        species: impl IntoIterator<Item = S>,
+        exclude: Option<Regex>,
+    ) -> Self {
+        let species: Vec<String> = species
+            .into_iter()
+            .map(|species| regex::escape(species.as_ref()))
+            .collect();
+        let include = Regex::new(&format!(r"^({})\.", species.join("|"))).unwrap();
+        Subset(SeqFilter::new(Some(include), exclude))
+    }
+}
+
+impl BlockTransform for Subset {
+    fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+        self.0.transform(block)
+    }
+}
+
+/// Normalizes the case of the bases: uppercases every base if asked,
+/// then lowercases the bases within each genome's repeat regions.
+#[derive(Default)]
+pub struct Normalize {
+    /// Uppercase every base first.
+    pub upper: bool,
+    /// Genome -> merged repeat regions, whose bases are lowercased.
+    pub repeats: HashMap<String, BTreeSet<Range>>,
+    /// The number of bases soft-masked so far.
+    pub num_masked: usize,
+}
+
+impl Normalize {
+    /// Recase a row, returning the number of bases soft-masked.
+    fn normalize_entry(&self, entry: &mut MAFBlockAlignedEntry) -> usize {
+        if self.upper {
+            entry.alignment.make_ascii_uppercase();
+        }
+        let repeats = match self.repeats.get(entry.seq.split('.').next().unwrap()) {
+            Some(repeats) => repeats,
+            None => return 0,
+        };
+        let chrom = chrom_part(&entry.seq);
+        let positions: Vec<_> = entry
+            .column_positions()
+            .map(|pos| pos.map(|pos| entry.forward_position(pos)))
+            .collect();
+        let mut num_masked = 0;
+        for (base, pos) in entry.alignment.iter_mut().zip(positions) {
+            if let Some(pos) = pos {
+                if range_contains_pos(repeats, &chrom, pos) {
+                    *base = base.to_ascii_lowercase();
+                    num_masked += 1;
+                }
+            }
+        }
+        num_masked
+    }
+}
+
+impl BlockTransform for Normalize {
+    fn transform(&mut self, mut block: MAFBlock) -> Vec<MAFBlock> {
+        for entry in block.entries.iter_mut() {
+            if let MAFBlockEntry::AlignedEntry(e) = entry {
+                self.num_masked += self.normalize_entry(e);
+            }
+        }
+        vec![block]
+    }
+}
+
+/// Write every block of the input through a transform, passing the
+/// header and comments through as they come.
+pub fn transform_blocks(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    transform: &mut dyn BlockTransform,
+) -> Result<(), MAFParseError> {
+    for_each_block(input, output, |block, output| {
+        for block in transform.transform(block) {
+            output.write_block(&block);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits blocks in half.
+    struct Halve;
+
+    impl BlockTransform for Halve {
+        fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+            let width = block
+                .aligned_entries()
+                .next()
+                .map_or(0, |e| e.alignment.len());
+            if width < 2 {
+                return vec![block];
+            }
+            vec![
+                block.slice_columns(0, width / 2),
+                block.slice_columns(width / 2, width),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        }
+    }
+
+    /// Drops blocks with fewer than 2 columns.
+    struct DropShort;
+
+    impl BlockTransform for DropShort {
+        fn transform(&mut self, block: MAFBlock) -> Vec<MAFBlock> {
+            let width = block
+                .aligned_entries()
+                .next()
+                .map_or(0, |e| e.alignment.len());
+            if width < 2 {
+                return vec![];
+            }
+            vec![block]
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let maf = "##maf version=1
+a
+s hg38.chr1 0 6 + 100 ACGTAC
+s mm39.chr2 0 5 + 100 ACG-AC
+";
+        let mut chain = Chain::default().then(Halve).then(Halve).then(DropShort);
+        let mut output = vec![];
+        transform_blocks(&mut maf.as_bytes(), &mut output, &mut chain).unwrap();
+        // 6 columns become 3 and 3, then 1, 2, 1, and 2, and the 1s go.
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "##maf version=1
+a
+s hg38.chr1 1 2 + 100 CG
+s mm39.chr2 1 2 + 100 CG
+
+a
+s hg38.chr1 4 2 + 100 AC
+s mm39.chr2 3 2 + 100 AC
+
+"
+        );
+    }
+
+    #[test]
+    fn test_subset_and_normalize() {
+        let maf = "a
+s hg38.chr1 0 2 + 100 ac
+s hg38x.chr1 0 2 + 100 AC
+s mm39.chr2 0 2 + 100 Ac
+s rn7.chr1 0 2 + 100 AC
+";
+        let normalize = Normalize {
+            upper: true,
+            ..Normalize::default()
+        };
+        let mut chain = Chain::default()
+            .then(Subset::species(vec!["hg38", "mm39"], None))
+            .then(normalize);
+        let mut output = vec![];
+        transform_blocks(&mut maf.as_bytes(), &mut output, &mut chain).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 0 2 + 100 AC
+s mm39.chr2 0 2 + 100 AC
+
+"
+        );
+    }
+}
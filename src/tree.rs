@@ -0,0 +1,260 @@
+//! Phylogenetic trees, read from and written as Newick.
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: Option<String>,
+    /// Length of the branch to the parent (0 if not given).
+    pub branch_length: f64,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+/// A rooted tree. Node 0 is the root, and every node comes after its
+/// parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    pub nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// Parse a Newick string, e.g. "((hg38:0.1,mm39:0.2):0.05,rn7:0.3);".
+    pub fn parse_newick(newick: &str) -> Tree {
+        let mut tree = Tree { nodes: vec![] };
+        let mut chars = newick.chars().peekable();
+        tree.parse_subtree(&mut chars, None);
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(';') {
+            panic!("Newick tree must end with ';'");
+        }
+        tree
+    }
+
+    fn parse_subtree(&mut self, chars: &mut Peekable<Chars>, parent: Option<usize>) -> usize {
+        let idx = self.nodes.len();
+        self.nodes.push(Node {
+            name: None,
+            branch_length: 0.0,
+            parent,
+            children: vec![],
+        });
+        skip_whitespace(chars);
+        if chars.peek() == Some(&'(') {
+            chars.next();
+            loop {
+                let child = self.parse_subtree(chars, Some(idx));
+                self.nodes[idx].children.push(child);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(')') => break,
+                    c => panic!("Unexpected {:?} in Newick tree", c),
+                }
+            }
+        }
+        let name = parse_label(chars);
+        if !name.is_empty() {
+            self.nodes[idx].name = Some(name);
+        }
+        skip_whitespace(chars);
+        if chars.peek() == Some(&':') {
+            chars.next();
+            let length = parse_label(chars);
+            self.nodes[idx].branch_length = length
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid branch length {} in Newick tree", length));
+        }
+        idx
+    }
+
+    pub fn root(&self) -> usize {
+        0
+    }
+
+    pub fn is_leaf(&self, node: usize) -> bool {
+        self.nodes[node].children.is_empty()
+    }
+
+    /// Leaf nodes, by name.
+    pub fn leaves(&self) -> HashMap<String, usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.children.is_empty())
+            .filter_map(|(i, node)| node.name.clone().map(|name| (name, i)))
+            .collect()
+    }
+
+    /// Every node, children before their parents.
+    pub fn postorder(&self) -> impl Iterator<Item = usize> {
+        (0..self.nodes.len()).rev()
+    }
+
+    fn write_newick(&self, node: usize, out: &mut String) {
+        let children = &self.nodes[node].children;
+        if !children.is_empty() {
+            out.push('(');
+            for (i, child) in children.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                self.write_newick(*child, out);
+            }
+            out.push(')');
+        }
+        if let Some(name) = &self.nodes[node].name {
+            if name
+                .chars()
+                .any(|c| "(),:;'".contains(c) || c.is_whitespace())
+            {
+                out.push_str(&format!("'{}'", name));
+            } else {
+                out.push_str(name);
+            }
+        }
+        if self.nodes[node].parent.is_some() {
+            out.push_str(&format!(":{}", self.nodes[node].branch_length));
+        }
+    }
+
+    /// The tree with only the leaves `keep` accepts, or None if it
+    /// accepts none. Nodes left with a single child are removed, joining
+    /// the branches above and below them.
+    pub fn prune(&self, keep: impl Fn(&str) -> bool) -> Option<Tree> {
+        let mut kept = vec![false; self.nodes.len()];
+        for node in self.postorder() {
+            kept[node] = if self.is_leaf(node) {
+                self.nodes[node].name.as_deref().is_some_and(&keep)
+            } else {
+                self.nodes[node].children.iter().any(|c| kept[*c])
+            };
+        }
+        if !kept[self.root()] {
+            return None;
+        }
+        let mut pruned = Tree { nodes: vec![] };
+        self.copy_pruned(self.root(), None, 0.0, &kept, &mut pruned);
+        Some(pruned)
+    }
+
+    fn copy_pruned(
+        &self,
+        node: usize,
+        parent: Option<usize>,
+        extra_length: f64,
+        kept: &[bool],
+        pruned: &mut Tree,
+    ) {
+        let branch_length = self.nodes[node].branch_length + extra_length;
+        let children: Vec<usize> = self.nodes[node]
+            .children
+            .iter()
+            .copied()
+            .filter(|c| kept[*c])
+            .collect();
+        if children.len() == 1 {
+            // The root's branch goes nowhere, so it isn't carried down.
+            let extra_length = if parent.is_some() { branch_length } else { 0.0 };
+            self.copy_pruned(children[0], parent, extra_length, kept, pruned);
+            return;
+        }
+        let idx = pruned.nodes.len();
+        pruned.nodes.push(Node {
+            name: self.nodes[node].name.clone(),
+            branch_length,
+            parent,
+            children: vec![],
+        });
+        if let Some(parent) = parent {
+            pruned.nodes[parent].children.push(idx);
+        }
+        for child in children {
+            self.copy_pruned(child, Some(idx), 0.0, kept, pruned);
+        }
+    }
+
+    pub fn to_newick(&self) -> String {
+        let mut out = String::new();
+        self.write_newick(self.root(), &mut out);
+        out.push(';');
+        out
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// A node name or branch length, possibly single-quoted.
+fn parse_label(chars: &mut Peekable<Chars>) -> String {
+    skip_whitespace(chars);
+    let mut label = String::new();
+    if chars.peek() == Some(&'\'') {
+        chars.next();
+        for c in chars.by_ref() {
+            if c == '\'' {
+                break;
+            }
+            label.push(c);
+        }
+        return label;
+    }
+    while let Some(c) = chars.peek() {
+        if "(),:;".contains(*c) || c.is_whitespace() {
+            break;
+        }
+        label.push(*c);
+        chars.next();
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newick_roundtrip() {
+        let tree = Tree::parse_newick("((hg38:0.1, mm39:0.2)anc:0.05,'rn 7':0.3);");
+        assert_eq!(tree.nodes.len(), 5);
+        let leaves = tree.leaves();
+        assert_eq!(leaves.len(), 3);
+        assert_eq!(tree.nodes[leaves["mm39"]].branch_length, 0.2);
+        assert_eq!(
+            tree.nodes[tree.nodes[leaves["hg38"]].parent.unwrap()].name,
+            Some("anc".to_string())
+        );
+        assert_eq!(
+            tree.to_newick(),
+            "((hg38:0.1,mm39:0.2)anc:0.05,'rn 7':0.3);"
+        );
+        let postorder: Vec<_> = tree.postorder().collect();
+        for node in 1..tree.nodes.len() {
+            let parent = tree.nodes[node].parent.unwrap();
+            let pos = |n| postorder.iter().position(|p| *p == n).unwrap();
+            assert!(pos(node) < pos(parent));
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let tree =
+            Tree::parse_newick("(((hg38:0.25,panTro6:0.25)hominid:0.5,mm39:0.3):0.1,bosTau9:0.4);");
+        let pruned = tree.prune(|name| name != "panTro6").unwrap();
+        assert_eq!(
+            pruned.to_newick(),
+            "((hg38:0.75,mm39:0.3):0.1,bosTau9:0.4);"
+        );
+        let pruned = tree.prune(|name| name == "hg38" || name == "mm39").unwrap();
+        assert_eq!(pruned.to_newick(), "(hg38:0.75,mm39:0.3);");
+        assert_eq!(
+            tree.prune(|name| name == "hg38").unwrap().to_newick(),
+            "hg38;"
+        );
+        assert!(tree.prune(|_| false).is_none());
+    }
+}
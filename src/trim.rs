@@ -0,0 +1,114 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::column_block::ColumnBlock;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFBlock;
+use std::io::BufRead;
+
+/// Fraction of the rows of a block that are gaps in each column.
+pub fn gap_fractions(block: &ColumnBlock) -> Vec<f64> {
+    block
+        .columns()
+        .map(|column| {
+            let gaps = column.iter().filter(|b| is_gap(**b)).count();
+            gaps as f64 / column.len() as f64
+        })
+        .collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// The columns [start, end) to keep: the block is cut back from each
+/// edge to the first column that is no more than `max_gap_fraction`
+/// gaps and starts (going inward) a window of columns that are no more
+/// than that on average. None if no column qualifies.
+fn trimmed_range(gaps: &[f64], max_gap_fraction: f64, window: usize) -> Option<(usize, usize)> {
+    let n = gaps.len();
+    let start = (0..n).find(|i| {
+        gaps[*i] <= max_gap_fraction && mean(&gaps[*i..n.min(i + window)]) <= max_gap_fraction
+    })?;
+    let end = (start..n).rev().find(|j| {
+        gaps[*j] <= max_gap_fraction
+            && mean(&gaps[(j + 1).saturating_sub(window).max(start)..=*j]) <= max_gap_fraction
+    })? + 1;
+    Some((start, end))
+}
+
+/// Trim the ragged edges of each block, removing leading and trailing
+/// columns where more than `max_gap_fraction` of rows are gaps (see
+/// `trimmed_range`). Blocks with nothing left are dropped. Returns the
+/// number of columns trimmed and the number of blocks dropped.
+pub fn trim(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    max_gap_fraction: f64,
+    window: usize,
+) -> Result<(usize, usize), MAFParseError> {
+    let mut num_trimmed = 0;
+    let mut num_dropped = 0;
+    for_each_block(input, output, |block, output| {
+        let columns = ColumnBlock::from(block);
+        let gaps = gap_fractions(&columns);
+        let block = MAFBlock::from(columns);
+        match trimmed_range(&gaps, max_gap_fraction, window.max(1)) {
+            Some((start, end)) => {
+                num_trimmed += gaps.len() - (end - start);
+                if end - start == gaps.len() {
+                    output.write_block(&block);
+                } else if let Some(trimmed) = block.slice_columns(start, end) {
+                    output.write_block(&trimmed);
+                } else {
+                    num_dropped += 1;
+                }
+            }
+            None => {
+                num_trimmed += gaps.len();
+                num_dropped += 1;
+            }
+        }
+    })?;
+    Ok((num_trimmed, num_dropped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trimmed_range() {
+        let gaps = [0.75, 0.25, 0.75, 0.0, 0.0, 0.25, 0.0, 0.5, 1.0];
+        assert_eq!(trimmed_range(&gaps, 0.5, 1), Some((1, 8)));
+        assert_eq!(trimmed_range(&gaps, 0.4, 1), Some((1, 7)));
+        // The window skips the lone good column at the left edge.
+        assert_eq!(trimmed_range(&gaps, 0.4, 2), Some((3, 7)));
+        assert_eq!(trimmed_range(&[1.0, 0.75], 0.5, 1), None);
+    }
+
+    #[test]
+    fn test_trim() {
+        let maf = "a score=3
+s hg38.chr1 10 6 + 100 --ACGTAC
+s mm39.chr2 5 6 - 50 -AACGTA-
+s rn7.chr3 0 4 + 10 T--CGT--
+
+a
+s hg38.chr1 20 1 + 100 A--
+s mm39.chr2 20 1 + 50 -G-
+s rn7.chr3 20 1 + 50 --T
+";
+        let mut output = vec![];
+        let counts = trim(&mut maf.as_bytes(), &mut output, 0.5, 1).unwrap();
+        assert_eq!(counts, (6, 1));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a score=3
+s hg38.chr1 10 5 + 100 ACGTA
+s mm39.chr2 6 5 - 50 ACGTA
+s rn7.chr3 1 3 + 10 -CGT-
+
+"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::trim::gap_fractions;
+use maf_stream::alphabet::{is_gap, Alphabet, Nucleotides};
+use maf_stream::{for_each_block, MAFOutput};
+use multiple_alignment_format::column_block::ColumnBlock;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::MAFBlock;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// Gblocks-style thresholds for which columns of a block to keep.
+pub struct ColumnTrimmer {
+    /// Columns need at least this fraction of rows to have a base.
+    pub min_present: f64,
+    /// Runs of kept columns are cut back until the columns at each end
+    /// have at least this fraction of rows sharing the same base.
+    pub min_flank_conservation: f64,
+    /// Runs of kept columns shorter than this are dropped.
+    pub min_run_length: usize,
+    /// The alphabet whose unknown residue doesn't count towards
+    /// conservation.
+    pub alphabet: &'static dyn Alphabet,
+}
+
+impl Default for ColumnTrimmer {
+    fn default() -> Self {
+        ColumnTrimmer {
+            min_present: 0.5,
+            min_flank_conservation: 0.5,
+            min_run_length: 5,
+            alphabet: &Nucleotides,
+        }
+    }
+}
+
+/// Fraction of all the rows of a block that share the most common
+/// base in each column.
+fn conservation(block: &ColumnBlock, alphabet: &dyn Alphabet) -> Vec<f64> {
+    let mut counts = HashMap::new();
+    block
+        .columns()
+        .map(|column| {
+            counts.clear();
+            for base in column.iter() {
+                let base = base.to_ascii_uppercase();
+                if !is_gap(base) && base != alphabet.unknown() {
+                    *counts.entry(base).or_insert(0) += 1;
+                }
+            }
+            *counts.values().max().unwrap_or(&0) as f64 / column.len() as f64
+        })
+        .collect()
+}
+
+impl ColumnTrimmer {
+    /// The runs of columns [start, end) to keep.
+    fn kept_runs(&self, block: &ColumnBlock) -> Vec<(usize, usize)> {
+        let gaps = gap_fractions(block);
+        let conserved = conservation(block, self.alphabet);
+        let mut runs = vec![];
+        let mut column = 0;
+        while column < gaps.len() {
+            if 1.0 - gaps[column] < self.min_present {
+                column += 1;
+                continue;
+            }
+            let mut start = column;
+            while column < gaps.len() && 1.0 - gaps[column] >= self.min_present {
+                column += 1;
+            }
+            let mut end = column;
+            while start < end && conserved[start] < self.min_flank_conservation {
+                start += 1;
+            }
+            while end > start && conserved[end - 1] < self.min_flank_conservation {
+                end -= 1;
+            }
+            if end - start >= self.min_run_length.max(1) {
+                runs.push((start, end));
+            }
+        }
+        runs
+    }
+}
+
+/// Remove poorly-aligned columns, for phylogenetics: columns where
+/// too many rows are gaps are removed, then each remaining run of
+/// columns is cut back to conserved columns at its flanks, and runs
+/// that are too short are removed. Each run is written as its own
+/// block, since rows can't skip the bases in the removed columns.
+/// If a report is given, a line is written to it for every input
+/// block with its reference row's forward-strand interval, its number
+/// of columns, and how many were removed. Returns the total number of
+/// columns removed.
+pub fn trim_columns(
+    input: &mut dyn BufRead,
+    output: &mut dyn MAFOutput,
+    trimmer: &ColumnTrimmer,
+    mut report: Option<&mut dyn Write>,
+) -> Result<usize, MAFParseError> {
+    if let Some(report) = report.as_mut() {
+        writeln!(report, "#seq\tstart\tend\tcolumns\tremoved").ok();
+    }
+    let mut total_removed = 0;
+    for_each_block(input, output, |block, output| {
+        let columns = ColumnBlock::from(block);
+        let runs = trimmer.kept_runs(&columns);
+        let num_columns = columns.num_columns();
+        let block = MAFBlock::from(columns);
+        let removed = num_columns - runs.iter().map(|(s, e)| e - s).sum::<usize>();
+        total_removed += removed;
+        if let (Some(report), Some(ref_entry)) = (report.as_mut(), block.aligned_entries().next()) {
+            let (start, end) = ref_entry.forward_interval();
+            writeln!(
+                report,
+                "{}\t{}\t{}\t{}\t{}",
+                ref_entry.seq, start, end, num_columns, removed
+            )
+            .ok();
+        }
+        if removed == 0 {
+            output.write_block(&block);
+        } else {
+            for slice in runs
+                .into_iter()
+                .filter_map(|(start, end)| block.slice_columns(start, end))
+            {
+                output.write_block(&slice);
+            }
+        }
+    })?;
+    Ok(total_removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_columns() {
+        let maf = "a
+s hg38.chr1 10 11 + 100 ACGTA--TCGTAC
+s mm39.chr2 5 10 - 50 TCGTA--CCGT-C
+s rn7.chr3 0 10 + 10 GCGTAG-GCGTA-
+s bosTau9.chr4 0 9 + 10 CCGT---CCGT-A
+";
+        let trimmer = ColumnTrimmer {
+            min_present: 0.5,
+            min_flank_conservation: 0.75,
+            min_run_length: 3,
+            alphabet: &Nucleotides,
+        };
+        let mut output = vec![];
+        let mut report: Vec<u8> = vec![];
+        let removed = trim_columns(
+            &mut maf.as_bytes(),
+            &mut output,
+            &trimmer,
+            Some(&mut report),
+        )
+        .unwrap();
+        // Columns 5 and 6 are mostly gaps, and 0, 7, 11, and 12 are
+        // unconserved flanks.
+        assert_eq!(removed, 6);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a
+s hg38.chr1 11 4 + 100 CGTA
+s mm39.chr2 6 4 - 50 CGTA
+s rn7.chr3 1 4 + 10 CGTA
+s bosTau9.chr4 1 3 + 10 CGT-
+
+a
+s hg38.chr1 16 3 + 100 CGT
+s mm39.chr2 11 3 - 50 CGT
+s rn7.chr3 7 3 + 10 CGT
+s bosTau9.chr4 5 3 + 10 CGT
+
+"
+        );
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "#seq\tstart\tend\tcolumns\tremoved\nhg38.chr1\t10\t21\t13\t6\n"
+        );
+    }
+}
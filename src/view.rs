@@ -0,0 +1,231 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::MAFItems;
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::io::{BufRead, Write};
+
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_MISMATCH: &str = "\x1b[1;31m";
+const ANSI_GAP: &str = "\x1b[2m";
+
+const HTML_HEADER: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<style>
+pre { font-family: monospace; }
+.ruler { color: #888; }
+.mismatch { color: #c00; font-weight: bold; }
+.gap { color: #bbb; }
+</style>
+</head>
+<body>
+";
+
+const HTML_FOOTER: &str = "</body>
+</html>
+";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ViewFormat {
+    /// Colored terminal output using ANSI escape codes.
+    Ansi,
+    /// A standalone HTML document.
+    Html,
+}
+
+#[derive(Debug, PartialEq)]
+enum BaseClass {
+    Match,
+    Mismatch,
+    Gap,
+}
+
+/// How a base compares to the reference base in the same column.
+fn classify(base: u8, ref_base: u8) -> BaseClass {
+    if is_gap(base) {
+        BaseClass::Gap
+    } else if base.eq_ignore_ascii_case(&ref_base) {
+        BaseClass::Match
+    } else {
+        BaseClass::Mismatch
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// A ruler numbering every 10th column (1-based), for the columns
+/// starting at `start`.
+fn ruler(start: usize, width: usize) -> String {
+    let mut ruler = String::new();
+    for column in start + 1..=start + width {
+        if column % 10 == 0 {
+            let label = column.to_string();
+            let len = ruler.len();
+            let keep = (len + 1).saturating_sub(label.len());
+            // Don't clobber the previous label.
+            if ruler[keep..].trim().is_empty() {
+                ruler.truncate(keep);
+                ruler.push_str(&label);
+                continue;
+            }
+            ruler.push('|');
+        } else {
+            ruler.push(' ');
+        }
+    }
+    ruler.trim_end().to_string()
+}
+
+/// Render a block, wrapping the alignment every `width` columns (or
+/// never if `width` is 0). The first entry is the reference that other
+/// entries are colored against.
+pub fn render_block(block: &MAFBlock, format: ViewFormat, width: usize) -> String {
+    let entries: Vec<_> = block.aligned_entries().collect();
+    let mut rendered = String::new();
+    let ref_entry = match entries.first() {
+        Some(entry) => entry,
+        None => return rendered,
+    };
+    let length = ref_entry.alignment.len();
+    let width = if width == 0 { length.max(1) } else { width };
+    let name_width = entries.iter().map(|e| e.seq.len()).max().unwrap_or(0);
+    let header = format!(
+        "{}:{}-{} ({})",
+        ref_entry.seq,
+        ref_entry.start,
+        ref_entry.start + ref_entry.aligned_length,
+        if ref_entry.strand == Strand::Positive {
+            "+"
+        } else {
+            "-"
+        }
+    );
+    match format {
+        ViewFormat::Ansi => rendered.push_str(&format!("{}\n", header)),
+        ViewFormat::Html => {
+            rendered.push_str(&format!("<h3>{}</h3>\n<pre>\n", html_escape(&header)))
+        }
+    }
+
+    for chunk_start in (0..length).step_by(width) {
+        let chunk_end = (chunk_start + width).min(length);
+        let ruler = format!(
+            "{:width$} {}",
+            "",
+            ruler(chunk_start, chunk_end - chunk_start),
+            width = name_width
+        )
+        .trim_end()
+        .to_string();
+        match format {
+            ViewFormat::Ansi => {
+                rendered.push_str(&format!("{}{}{}\n", ANSI_GAP, ruler, ANSI_RESET))
+            }
+            ViewFormat::Html => {
+                rendered.push_str(&format!("<span class=\"ruler\">{}</span>\n", ruler))
+            }
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let name = format!("{:width$} ", entry.seq, width = name_width);
+            match format {
+                ViewFormat::Ansi => rendered.push_str(&name),
+                ViewFormat::Html => rendered.push_str(&html_escape(&name)),
+            }
+            for column in chunk_start..chunk_end {
+                let base = entry.alignment[column];
+                let class = if i == 0 {
+                    BaseClass::Match
+                } else {
+                    classify(base, ref_entry.alignment[column])
+                };
+                let base = base as char;
+                match (format, class) {
+                    (_, BaseClass::Match) => rendered.push(base),
+                    (ViewFormat::Ansi, BaseClass::Mismatch) => {
+                        rendered.push_str(&format!("{}{}{}", ANSI_MISMATCH, base, ANSI_RESET))
+                    }
+                    (ViewFormat::Ansi, BaseClass::Gap) => {
+                        rendered.push_str(&format!("{}{}{}", ANSI_GAP, base, ANSI_RESET))
+                    }
+                    (ViewFormat::Html, BaseClass::Mismatch) => {
+                        rendered.push_str(&format!("<span class=\"mismatch\">{}</span>", base))
+                    }
+                    (ViewFormat::Html, BaseClass::Gap) => {
+                        rendered.push_str(&format!("<span class=\"gap\">{}</span>", base))
+                    }
+                }
+            }
+            rendered.push('\n');
+        }
+        rendered.push('\n');
+    }
+    if format == ViewFormat::Html {
+        rendered.push_str("</pre>\n");
+    }
+    rendered
+}
+
+pub fn view(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    format: ViewFormat,
+    width: usize,
+) -> Result<(), MAFParseError> {
+    if format == ViewFormat::Html {
+        write!(output, "{}", HTML_HEADER).ok();
+    }
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            write!(output, "{}", render_block(&block, format, width)).ok();
+        }
+    }
+    if format == ViewFormat::Html {
+        write!(output, "{}", HTML_FOOTER).ok();
+    }
+    items.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multiple_alignment_format::parser::next_maf_item;
+
+    #[test]
+    fn test_ruler() {
+        assert_eq!(ruler(0, 25), "        10        20");
+        assert_eq!(ruler(95, 10), "  100");
+        assert_eq!(ruler(0, 5), "");
+    }
+
+    #[test]
+    fn test_render_block() {
+        let block = "a
+s hg38.chr1 0 12 + 100 ACGTACGTACGT
+s mm39.chr2 10 11 + 200 ACcTACGTAC-T
+";
+        let item = next_maf_item(&mut block.as_bytes()).expect("Couldn't parse MAF block");
+        if let MAFItem::Block(block) = item {
+            assert_eq!(
+                render_block(&block, ViewFormat::Ansi, 8),
+                "hg38.chr1:0-12 (+)
+\x1b[2m\x1b[0m
+hg38.chr1 ACGTACGT
+mm39.chr2 AC\x1b[1;31mc\x1b[0mTACGT
+
+\x1b[2m          10\x1b[0m
+hg38.chr1 ACGT
+mm39.chr2 AC\x1b[2m-\x1b[0mT
+
+"
+            );
+        } else {
+            panic!("Got unexpected maf item {:?}", item);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use maf_stream::alphabet::is_gap;
+use maf_stream::{chrom_part, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFBlockAlignedEntry, MAFItem};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, Write};
+
+/// A value computed for each alignment column with a reference base.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnMetric {
+    /// The number of species with a base in the column, including the
+    /// reference.
+    SpeciesCount,
+    /// The fraction of the other rows with a gap in the column.
+    GapFraction,
+    /// The fraction of the other rows with a base in the column that
+    /// have the reference base.
+    Identity,
+    /// 1 if the reference base is G or C, 0 if it's A or T.
+    RefGc,
+}
+
+impl ColumnMetric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "species_count" => Some(ColumnMetric::SpeciesCount),
+            "gap_fraction" => Some(ColumnMetric::GapFraction),
+            "identity" => Some(ColumnMetric::Identity),
+            "gc" => Some(ColumnMetric::RefGc),
+            _ => None,
+        }
+    }
+
+    /// The metric for a column, or None if it has no value there (an
+    /// identity with no other bases, or the GC of an N).
+    pub fn value(
+        self,
+        block: &MAFBlock,
+        ref_entry: &MAFBlockAlignedEntry,
+        column: usize,
+    ) -> Option<f64> {
+        let ref_base = ref_entry.alignment[column];
+        let others = block
+            .aligned_entries()
+            .filter(|entry| !std::ptr::eq(*entry, ref_entry))
+            .map(|entry| entry.alignment[column]);
+        match self {
+            ColumnMetric::SpeciesCount => {
+                let species: HashSet<&str> = block
+                    .aligned_entries()
+                    .filter(|entry| !is_gap(entry.alignment[column]))
+                    .map(|entry| entry.seq.split('.').next().unwrap())
+                    .collect();
+                Some(species.len() as f64)
+            }
+            ColumnMetric::GapFraction => {
+                let (gaps, total) = others.fold((0, 0), |(gaps, total), c| {
+                    (gaps + is_gap(c) as usize, total + 1)
+                });
+                Some(if total == 0 {
+                    0.0
+                } else {
+                    gaps as f64 / total as f64
+                })
+            }
+            ColumnMetric::Identity => {
+                let (identical, total) =
+                    others
+                        .filter(|c| !is_gap(*c))
+                        .fold((0, 0), |(identical, total), c| {
+                            (
+                                identical + c.eq_ignore_ascii_case(&ref_base) as usize,
+                                total + 1,
+                            )
+                        });
+                if total == 0 {
+                    None
+                } else {
+                    Some(identical as f64 / total as f64)
+                }
+            }
+            ColumnMetric::RefGc => match ref_base.to_ascii_uppercase() {
+                b'G' | b'C' => Some(1.0),
+                b'A' | b'T' => Some(0.0),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// How the wiggle lines are laid out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WigStep {
+    /// A fixedStep section for every run of consecutive positions.
+    Fixed,
+    /// A variableStep section for every chromosome, with the position
+    /// on each line.
+    Variable,
+}
+
+/// Writes a metric's wiggle values for each block in turn, holding back
+/// those that a later block could still overlap.
+pub struct WigWriter<'a> {
+    output: &'a mut dyn Write,
+    metric: ColumnMetric,
+    step: WigStep,
+    chrom: String,
+    /// Values not yet written, by 0-based position.
+    pending: BTreeMap<u64, f64>,
+    /// The position after the last one written on this chromosome.
+    written_to: u64,
+    /// The position after the last one written, in the current fixedStep
+    /// section, if there is one.
+    section_end: Option<u64>,
+    num_overlapping: usize,
+}
+
+impl<'a> WigWriter<'a> {
+    pub fn new(output: &'a mut dyn Write, metric: ColumnMetric, step: WigStep) -> Self {
+        WigWriter {
+            output,
+            metric,
+            step,
+            chrom: String::new(),
+            pending: BTreeMap::new(),
+            written_to: 0,
+            section_end: None,
+            num_overlapping: 0,
+        }
+    }
+
+    /// Add the values of every reference base of a block, at their
+    /// forward-strand positions, writing those before the block, since
+    /// blocks come in order.
+    pub fn add_block(&mut self, block: &MAFBlock) {
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => return,
+        };
+        if ref_entry.aligned_length == 0 {
+            return;
+        }
+        let start = ref_entry
+            .forward_position(ref_entry.start)
+            .min(ref_entry.forward_position(ref_entry.start + ref_entry.aligned_length - 1));
+        let chrom = chrom_part(&ref_entry.seq);
+        if chrom != self.chrom {
+            self.flush_before(u64::MAX);
+            self.chrom = chrom;
+            self.written_to = 0;
+            self.section_end = None;
+            if self.step == WigStep::Variable {
+                writeln!(self.output, "variableStep chrom={}", self.chrom).ok();
+            }
+        } else {
+            self.flush_before(start);
+        }
+        for (column, pos) in ref_entry.column_positions().enumerate() {
+            if let Some(pos) = pos {
+                if let Some(value) = self.metric.value(block, ref_entry, column) {
+                    self.add(ref_entry.forward_position(pos), value);
+                }
+            }
+        }
+    }
+
+    /// Add a value, unless an earlier block already gave this position
+    /// one.
+    fn add(&mut self, pos: u64, value: f64) {
+        if pos < self.written_to || self.pending.contains_key(&pos) {
+            self.num_overlapping += 1;
+            return;
+        }
+        self.pending.insert(pos, value);
+    }
+
+    fn flush_before(&mut self, end: u64) {
+        let rest = self.pending.split_off(&end);
+        let done = std::mem::replace(&mut self.pending, rest);
+        for (pos, value) in done {
+            // Rounded so that counts come out as integers.
+            let value = (value * 10000.0).round() / 10000.0;
+            match self.step {
+                WigStep::Fixed => {
+                    if self.section_end != Some(pos) {
+                        writeln!(
+                            self.output,
+                            "fixedStep chrom={} start={} step=1",
+                            self.chrom,
+                            pos + 1
+                        )
+                        .ok();
+                    }
+                    writeln!(self.output, "{}", value).ok();
+                    self.section_end = Some(pos + 1);
+                }
+                WigStep::Variable => {
+                    writeln!(self.output, "{}\t{}", pos + 1, value).ok();
+                }
+            }
+            self.written_to = pos + 1;
+        }
+    }
+
+    /// Write the values held back, and return the number of positions
+    /// skipped for overlapping.
+    pub fn finish(mut self) -> usize {
+        self.flush_before(u64::MAX);
+        self.num_overlapping
+    }
+}
+
+/// Write a wiggle track of a metric for every reference base, at its
+/// forward-strand position (so negative-strand blocks are reversed).
+/// Blocks are expected to be sorted by reference position; where they
+/// overlap on the reference, the first block's value is kept, and
+/// positions without a value are left out. Returns the number of
+/// overlapping positions skipped.
+pub fn wig(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    metric: ColumnMetric,
+    step: WigStep,
+) -> Result<usize, MAFParseError> {
+    let mut writer = WigWriter::new(output, metric, step);
+    let mut items = MAFItems::new(input);
+    for item in items.by_ref() {
+        if let MAFItem::Block(block) = item {
+            writer.add_block(&block);
+        }
+    }
+    items.finish()?;
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a
+s hg38.chr1 10 4 + 100 ACG-T
+s mm39.chr2 20 5 + 200 ACGAT
+s mm39.chr3 30 4 + 200 AT-AT
+s rn7.chr3 30 2 + 200 ----C
+
+a
+s hg38.chr1 13 3 + 100 TGG
+s mm39.chr2 0 3 + 200 TCC
+
+a
+s hg38.chr1 80 2 - 100 AC
+s rn7.chr2 0 2 + 200 AA
+";
+
+    #[test]
+    fn test_wig() {
+        let mut output = vec![];
+        let overlapping = wig(
+            &mut MAF.as_bytes(),
+            &mut output,
+            ColumnMetric::SpeciesCount,
+            WigStep::Fixed,
+        )
+        .unwrap();
+        // Position 13 is in the first two blocks, and the second block
+        // carries straight on from the first.
+        assert_eq!(overlapping, 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "fixedStep chrom=chr1 start=11 step=1
+2
+2
+2
+3
+2
+2
+fixedStep chrom=chr1 start=19 step=1
+2
+2
+"
+        );
+        let mut output = vec![];
+        wig(
+            &mut MAF.as_bytes(),
+            &mut output,
+            ColumnMetric::Identity,
+            WigStep::Variable,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "variableStep chrom=chr1
+11\t1
+12\t0.5
+13\t1
+14\t0.6667
+15\t0
+16\t0
+19\t0
+20\t1
+"
+        );
+    }
+}
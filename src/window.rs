@@ -0,0 +1,388 @@
+//! Stitching blocks into one alignment per fixed-size reference window.
+use crate::alphabet::is_gap;
+use crate::{chrom_part, reverse_complement, write_alignment_file, MAFItems};
+use multiple_alignment_format::parser::MAFParseError;
+use multiple_alignment_format::{MAFBlock, MAFItem, Strand};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+/// The alignment of one reference window, stitched together from the
+/// blocks overlapping it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub chrom: String,
+    /// Forward-strand reference interval, 0-based and half-open.
+    pub start: u64,
+    pub end: u64,
+    pub ref_species: String,
+    /// Each species' row, all the same length, on the forward strand of
+    /// the reference. Reference bases outside every block are N, and
+    /// other species are gaps wherever they weren't aligned.
+    pub rows: BTreeMap<String, Vec<u8>>,
+}
+
+impl Window {
+    fn new(chrom: &str, start: u64, end: u64, ref_species: &str) -> Self {
+        Window {
+            chrom: chrom.to_string(),
+            start,
+            end,
+            ref_species: ref_species.to_string(),
+            rows: vec![(ref_species.to_string(), vec![])]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    pub fn num_columns(&self) -> usize {
+        self.rows[&self.ref_species].len()
+    }
+
+    /// Add a column, given each species' character in it; species
+    /// missing from it get a gap.
+    fn push_column(&mut self, column: &BTreeMap<&str, u8>) {
+        let length = self.num_columns();
+        for (species, c) in column.iter() {
+            self.rows
+                .entry(species.to_string())
+                .or_insert_with(|| vec![b'-'; length])
+                .push(*c);
+        }
+        for (species, row) in self.rows.iter_mut() {
+            if !column.contains_key(species.as_str()) {
+                row.push(b'-');
+            }
+        }
+    }
+
+    /// Fill reference positions from `from` to `to` that no block
+    /// covered.
+    fn fill(&mut self, from: u64, to: u64) {
+        let ref_species = self.ref_species.clone();
+        let column = vec![(ref_species.as_str(), b'N')].into_iter().collect();
+        for _ in from..to {
+            self.push_column(&column);
+        }
+    }
+}
+
+/// Iterates over the windows of `size` reference bases (starting at
+/// multiples of `size`) that any block overlaps, reading blocks as
+/// needed. Blocks must be sorted by reference position; columns at
+/// reference positions an earlier block already covered are skipped.
+/// Columns where the reference has a gap go with the reference base
+/// before them, and only the first row of each species in a block is
+/// used.
+pub struct Windows<'a> {
+    items: MAFItems<'a>,
+    size: u64,
+    current: Option<Window>,
+    /// The reference position after the last one added to the current
+    /// window.
+    covered_to: u64,
+    /// Length of the current reference sequence.
+    chrom_size: u64,
+    ready: VecDeque<Window>,
+    num_overlapping: usize,
+}
+
+impl<'a> Windows<'a> {
+    pub fn new(input: &'a mut dyn BufRead, size: u64) -> Self {
+        if size == 0 {
+            panic!("Window size must be positive");
+        }
+        Windows {
+            items: MAFItems::new(input),
+            size,
+            current: None,
+            covered_to: 0,
+            chrom_size: 0,
+            ready: VecDeque::new(),
+            num_overlapping: 0,
+        }
+    }
+
+    /// The number of reference positions skipped for being covered by
+    /// more than one block if the input was read to its end, or else
+    /// the error that stopped it.
+    pub fn finish(self) -> Result<usize, MAFParseError> {
+        self.items.finish()?;
+        Ok(self.num_overlapping)
+    }
+
+    /// Finish the current window, filling it out to its end.
+    fn finish_window(&mut self) {
+        if let Some(mut window) = self.current.take() {
+            window.fill(self.covered_to, window.end);
+            self.ready.push_back(window);
+        }
+    }
+
+    fn add_block(&mut self, block: &MAFBlock) {
+        let ref_entry = match block.aligned_entries().next() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let ref_species = ref_entry.seq.split('.').next().unwrap();
+        let chrom = chrom_part(&ref_entry.seq);
+        if self.current.as_ref().is_some_and(|w| w.chrom != chrom) {
+            self.finish_window();
+        }
+        self.chrom_size = ref_entry.sequence_size;
+        // The first row of each species, on the reference's forward
+        // strand.
+        let mut rows: Vec<(&str, Vec<u8>)> = vec![];
+        for entry in block.aligned_entries() {
+            let species = entry.seq.split('.').next().unwrap();
+            if rows.iter().any(|(s, _)| *s == species) {
+                continue;
+            }
+            let mut alignment = entry.alignment.clone();
+            if ref_entry.strand == Strand::Negative {
+                reverse_complement(&mut alignment);
+            }
+            rows.push((species, alignment));
+        }
+        let (mut pos, _) = ref_entry.forward_interval();
+        for i in 0..rows[0].1.len() {
+            let is_ref_base = !is_gap(rows[0].1[i]);
+            // Reference gaps go with the base before them, or with the
+            // first base of the block if there's none.
+            let anchor = if is_ref_base || i == 0 || pos == 0 {
+                pos
+            } else {
+                pos - 1
+            };
+            let within = self
+                .current
+                .as_ref()
+                .is_some_and(|w| w.chrom == chrom && anchor < w.end);
+            if within && anchor < self.covered_to && (is_ref_base || anchor + 1 < self.covered_to) {
+                self.num_overlapping += is_ref_base as usize;
+            } else {
+                if !within {
+                    self.finish_window();
+                    let start = anchor / self.size * self.size;
+                    let end = (start + self.size).min(self.chrom_size);
+                    self.current = Some(Window::new(&chrom, start, end, ref_species));
+                    self.covered_to = start;
+                }
+                let window = self.current.as_mut().unwrap();
+                if is_ref_base && pos > self.covered_to {
+                    window.fill(self.covered_to, pos);
+                    self.covered_to = pos;
+                }
+                let column = rows.iter().map(|(s, row)| (*s, row[i])).collect();
+                window.push_column(&column);
+                if is_ref_base {
+                    self.covered_to = pos + 1;
+                }
+            }
+            if is_ref_base {
+                pos += 1;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Windows<'a> {
+    type Item = Window;
+
+    fn next(&mut self) -> Option<Window> {
+        while self.ready.is_empty() {
+            match self.items.next() {
+                Some(MAFItem::Block(block)) => self.add_block(&block),
+                Some(MAFItem::Comment(_)) => continue,
+                None => {
+                    self.finish_window();
+                    break;
+                }
+            }
+        }
+        self.ready.pop_front()
+    }
+}
+
+impl Window {
+    /// The number of species with a base in the window.
+    pub fn num_species(&self) -> usize {
+        self.rows
+            .values()
+            .filter(|row| row.iter().any(|c| !is_gap(*c)))
+            .count()
+    }
+}
+
+/// Write the stitched alignment of each window with at least
+/// `min_species` species as FASTA, with the window's reference interval
+/// after each species' name, and a blank line between windows. Returns
+/// the number of windows written and the number of reference positions
+/// skipped for overlapping.
+pub fn write_windows(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    size: u64,
+    min_species: usize,
+) -> Result<(usize, usize), MAFParseError> {
+    let mut num_windows = 0;
+    let mut windows = Windows::new(input, size);
+    for window in windows.by_ref().filter(|w| w.num_species() >= min_species) {
+        if num_windows > 0 {
+            writeln!(output).ok();
+        }
+        for (species, row) in window.rows.iter() {
+            writeln!(
+                output,
+                ">{} {}:{}-{}",
+                species, window.chrom, window.start, window.end
+            )
+            .ok();
+            output.write_all(row).ok();
+            writeln!(output).ok();
+        }
+        num_windows += 1;
+    }
+    let num_overlapping = windows.finish()?;
+    Ok((num_windows, num_overlapping))
+}
+
+/// Write the stitched alignment of each window with at least
+/// `min_species` species to its own file in `output_dir`, as FASTA or
+/// PHYLIP, named `<chrom>.<start>-<end>.fa` (or `.phy`), for per-window
+/// tree inference with IQ-TREE and topology weighting with TWISST. A
+/// `manifest.tsv` lists each file with its window, number of species,
+/// and number of columns. Returns the number of windows written and the
+/// number of reference positions skipped for overlapping.
+pub fn export_windows(
+    input: &mut dyn BufRead,
+    output_dir: &str,
+    size: u64,
+    min_species: usize,
+    phylip: bool,
+) -> Result<(usize, usize), MAFParseError> {
+    let output_dir = Path::new(output_dir);
+    let mut manifest = BufWriter::new(
+        File::create(output_dir.join("manifest.tsv")).expect("Couldn't create manifest"),
+    );
+    writeln!(manifest, "#file\tchrom\tstart\tend\tspecies\tcolumns").expect("failed to write");
+    let mut num_windows = 0;
+    let mut windows = Windows::new(input, size);
+    for window in windows.by_ref() {
+        let num_species = window.num_species();
+        if num_species < min_species {
+            continue;
+        }
+        let extension = if phylip { "phy" } else { "fa" };
+        let name = format!(
+            "{}.{}-{}.{}",
+            window.chrom, window.start, window.end, extension
+        );
+        write_alignment_file(&output_dir.join(&name), &window.rows, phylip);
+        writeln!(
+            manifest,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            name,
+            window.chrom,
+            window.start,
+            window.end,
+            num_species,
+            window.num_columns()
+        )
+        .expect("failed to write");
+        num_windows += 1;
+    }
+    let num_overlapping = windows.finish()?;
+    Ok((num_windows, num_overlapping))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows() {
+        let maf = "a
+s hg38.chr1 2 4 + 12 AC-GT
+s mm39.chr2 0 5 + 100 ACTGT
+s mm39.chr3 0 5 + 100 AATGT
+
+a
+s hg38.chr1 3 2 - 12 GC
+s rn7.chr1 0 2 + 100 GA
+
+a
+s hg38.chr2 0 2 + 3 AC
+s mm39.chr1 0 2 + 100 AG
+";
+        let windows: Vec<Window> = Windows::new(&mut maf.as_bytes(), 5).collect();
+        let rows = |window: &Window| -> Vec<(String, String)> {
+            window
+                .rows
+                .iter()
+                .map(|(s, row)| (s.clone(), String::from_utf8(row.clone()).unwrap()))
+                .collect()
+        };
+        assert_eq!(windows.len(), 3);
+        assert_eq!((windows[0].start, windows[0].end), (0, 5));
+        // The reference insertion goes with the C before it.
+        assert_eq!(
+            rows(&windows[0]),
+            vec![
+                ("hg38".to_string(), "NNAC-G".to_string()),
+                ("mm39".to_string(), "--ACTG".to_string())
+            ]
+        );
+        // The second block is at 7-9 on the forward strand.
+        assert_eq!((windows[1].start, windows[1].end), (5, 10));
+        assert_eq!(
+            rows(&windows[1]),
+            vec![
+                ("hg38".to_string(), "TNGCN".to_string()),
+                ("mm39".to_string(), "T----".to_string()),
+                ("rn7".to_string(), "--TC-".to_string())
+            ]
+        );
+        // The last window stops at the end of chr2.
+        assert_eq!((windows[2].chrom.as_str(), windows[2].end), ("chr2", 3));
+        assert_eq!(windows[2].num_columns(), 3);
+    }
+
+    #[test]
+    fn test_export_windows() {
+        let maf = "a
+s hg38.chr1 0 4 + 100 ACGT
+s mm39.chr2 0 4 + 100 ACGA
+s rn7.chr2 0 4 + 100 AC-A
+
+a
+s hg38.chr1 10 2 + 100 AC
+s mm39.chr2 10 2 + 100 AG
+";
+        let dir = tempfile::tempdir().unwrap();
+        let (num_windows, _) = export_windows(
+            &mut maf.as_bytes(),
+            dir.path().to_str().unwrap(),
+            10,
+            3,
+            true,
+        )
+        .unwrap();
+        assert_eq!(num_windows, 1);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("manifest.tsv")).unwrap(),
+            "#file\tchrom\tstart\tend\tspecies\tcolumns
+chr1.0-10.phy\tchr1\t0\t10\t3\t10
+"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("chr1.0-10.phy")).unwrap(),
+            " 3 10
+hg38  ACGTNNNNNN
+mm39  ACGA------
+rn7  AC-A------
+"
+        );
+    }
+}
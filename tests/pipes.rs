@@ -0,0 +1,134 @@
+//! Running the binary with its input and output on pipes, as in shell
+//! pipelines and process substitution.
+#![cfg(unix)]
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use tempfile::tempdir;
+
+const MAF: &str = "##maf version=1
+a
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 20 4 + 200 ACGA
+
+a
+s hg38.chr1 20 2 + 100 AC
+s mm39.chr2 30 2 + 200 AG
+";
+
+fn maf_stream() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_maf_stream"))
+}
+
+/// Run with `input` written to stdin from another thread, returning
+/// stdout, and checking that the run succeeded.
+fn run_piped(command: &mut Command, input: String) -> String {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let output = run_piped(maf_stream().args(["head", "-n", "1"]), MAF.to_string());
+    assert_eq!(
+        output,
+        "##maf version=1
+a
+s hg38.chr1 10 4 + 100 ACGT
+s mm39.chr2 20 4 + 200 ACGA
+
+"
+    );
+}
+
+#[test]
+fn test_process_substitution() {
+    // Process substitution hands over a /dev/fd path to a pipe, which
+    // can't be seeked or mapped.
+    let output = run_piped(
+        maf_stream().args(["tail", "-n", "1", "/dev/stdin"]),
+        MAF.to_string(),
+    );
+    assert!(output.ends_with("s mm39.chr2 30 2 + 200 AG\n\n"));
+}
+
+#[test]
+fn test_named_pipes() {
+    let dir = tempdir().unwrap();
+    let input_path = dir.path().join("input.maf");
+    let output_path = dir.path().join("output.maf");
+    for path in [&input_path, &output_path].iter() {
+        assert!(Command::new("mkfifo").arg(path).status().unwrap().success());
+    }
+    let mut child = maf_stream()
+        .args(["annotate", "--set", "source=pipe"])
+        .arg(&input_path)
+        .arg(&output_path)
+        .spawn()
+        .unwrap();
+    let writer_path = input_path.clone();
+    let writer =
+        thread::spawn(move || File::create(writer_path).unwrap().write_all(MAF.as_bytes()));
+    let mut output = String::new();
+    File::open(&output_path)
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(output.matches("a source=pipe\n").count(), 2);
+}
+
+#[test]
+fn test_broken_pipe() {
+    let input: String = std::iter::once("##maf version=1\n".to_string())
+        .chain((0..20000).map(|i| format!("a\ns hg38.chr1 {} 4 + 1000000 ACGT\n\n", i * 4)))
+        .collect();
+    // Blocks are written from the worker threads' spool files all at
+    // once, with nothing to absorb a failed write.
+    let dir = tempdir().unwrap();
+    let bed_path = dir.path().join("all.bed");
+    File::create(&bed_path)
+        .unwrap()
+        .write_all(b"chr1\t0\t1000000\n")
+        .unwrap();
+    let summary_path = dir.path().join("summary.json");
+    let mut child = maf_stream()
+        .arg("--summary_json")
+        .arg(&summary_path)
+        .args(["filter", "--threads", "2", "--bed"])
+        .arg(&bed_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    // Once the output is closed, the rest of the input may never be
+    // read, so this is allowed to fail.
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()).ok());
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "##maf version=1\n");
+    // Like `| head -n 1`: stop reading, and close the pipe.
+    drop(stdout);
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    // The summary is still written, noting that the run stopped early.
+    let summary = std::fs::read_to_string(&summary_path).unwrap();
+    assert!(summary.contains("\"output_closed\": true"), "{}", summary);
+}